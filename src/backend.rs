@@ -6,13 +6,17 @@ mod jpeg;
 mod png;
 #[cfg(feature="webp")]
 mod webp;
+mod exif;
 
 use std::fs::Metadata;
-use std::io::Read;
+use std::io::{Cursor, Read, Seek};
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::SystemTime;
+use std::sync::atomic::{AtomicU64, Ordering};
 use image::DynamicImage;
 
-use super::{RusImg, Extension, RusimgError, ImgSize, Rect};
+use super::{RusImg, Extension, RusimgError, ImgSize, ImageMetadata, AnimationInfo, Rect, Anchor, PngOptimizeOptions, PngCompressOptions, RotateInterpolation, RusImgConfig, ResampleKernel, ToneMapMethod, CancelToken};
 
 /// BackendTrait is a trait for RusImg objects.
 /// This trait is used for image operations.
@@ -27,6 +31,7 @@ pub trait BackendTrait {
     /// 
     /// returns:
     /// - Self object
+    /// - ``InvalidImageBuffer`` error if the pixel buffer does not match the image dimensions (see ``check_image_buffer()``)
     fn import(image: Option<DynamicImage>, source_path: Option<PathBuf>, source_metadata: Option<Metadata>) -> Result<Self, RusimgError> where Self: Sized;
     /// Open an image from a image buffer.
     /// The ``path`` parameter is the file path of the image, but it is used for copying the file path to the object.
@@ -50,6 +55,33 @@ pub trait BackendTrait {
     /// returns:
     /// - Result object
     fn save(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError>;
+    /// Save the image like ``save()``, returning a ``Cancelled`` error if ``token`` is cancelled.
+    /// The built-in backends check the token between encoding, the oxipng pass (PNG) and writing the file; a cancelled save writes nothing.
+    /// By default, the token is checked only before saving.
+    /// 
+    /// args:
+    /// - path: file path for saving the image
+    /// - token: CancelToken object
+    /// 
+    /// returns:
+    /// - Result object
+    fn save_cancellable(&mut self, path: Option<PathBuf>, token: &CancelToken) -> Result<(), RusimgError> {
+        token.check()?;
+        self.save(path)
+    }
+    /// Save the image like ``save()``, but return a ``FileAlreadyExists`` error instead of replacing an existing file.
+    /// The built-in backends create the file only if it does not exist, in the same step as moving the written file into place,
+    /// so concurrent saves to the same path (e.g. from other processes of a batch) never overwrite each other.
+    /// By default, ``UnsupportedFeature`` is returned.
+    /// 
+    /// args:
+    /// - path: file path for saving the image
+    /// 
+    /// returns:
+    /// - Result object
+    fn save_no_overwrite(&mut self, _path: Option<PathBuf>) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
     /// Compress the image with the quality parameter.
     /// The quality parameter is a float value between 0.0 and 100.0.
     /// 
@@ -68,6 +100,99 @@ pub trait BackendTrait {
     /// returns:
     /// - ImgSize object
     fn resize(&mut self, resize_ratio: f32) -> Result<ImgSize, RusimgError>;
+    /// Resize the image with different horizontal and vertical ratios.
+    /// Each ratio is a percentage like the resize_ratio of ``resize()``.
+    /// 
+    /// args:
+    /// - x_ratio: horizontal resize ratio
+    /// - y_ratio: vertical resize ratio
+    /// 
+    /// returns:
+    /// - ImgSize object
+    fn resize_xy(&mut self, _x_ratio: f32, _y_ratio: f32) -> Result<ImgSize, RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+    /// Resize the image with the resize ratio, rounding the width and height up to the nearest even number.
+    /// Video encoders often require even dimensions.
+    /// This uses ``get_size()`` and ``resize_xy()``.
+    /// 
+    /// args:
+    /// - resize_ratio: resize ratio parameter
+    /// 
+    /// returns:
+    /// - ImgSize object
+    fn resize_even(&mut self, resize_ratio: f32) -> Result<ImgSize, RusimgError> {
+        let size = self.get_size()?;
+        let even = |n: usize| -> usize { n.div_ceil(2).max(1) * 2 };
+        let nwidth = even((size.width as f32 * (resize_ratio / 100.0)) as usize);
+        let nheight = even((size.height as f32 * (resize_ratio / 100.0)) as usize);
+        let (x_ratio, y_ratio) = exact_resize_ratios(size, nwidth, nheight);
+        self.resize_xy(x_ratio, y_ratio)
+    }
+    /// Resize the image with a resampling kernel, e.g. a Lanczos kernel with another window size.
+    /// This uses ``get_dynamic_image()`` and ``set_dynamic_image()``.
+    /// 
+    /// args:
+    /// - resize_ratio: resize ratio parameter
+    /// - kernel: resampling kernel
+    /// 
+    /// returns:
+    /// - ImgSize object
+    fn resize_with_kernel(&mut self, resize_ratio: f32, kernel: ResampleKernel) -> Result<ImgSize, RusimgError> {
+        self.resize_with_kernel_cancellable(resize_ratio, kernel, &CancelToken::new())
+    }
+    /// Resize the image with a resampling kernel like ``resize_with_kernel()``, checking ``token`` every few rows.
+    /// If the token is cancelled, a ``Cancelled`` error is returned and the image is left unchanged.
+    /// This uses ``get_dynamic_image()`` and ``set_dynamic_image()``.
+    /// 
+    /// args:
+    /// - resize_ratio: resize ratio parameter
+    /// - kernel: resampling kernel
+    /// - token: CancelToken object
+    /// 
+    /// returns:
+    /// - ImgSize object
+    fn resize_with_kernel_cancellable(&mut self, resize_ratio: f32, kernel: ResampleKernel, token: &CancelToken) -> Result<ImgSize, RusimgError> {
+        if !kernel.is_valid() {
+            return Err(RusimgError::InvalidFilterParameter);
+        }
+        if !resize_ratio.is_finite() || resize_ratio <= 0.0 {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+        let image = self.get_dynamic_image()?;
+        let nwidth = ((image.width() as f32 * (resize_ratio / 100.0)) as u32).max(1);
+        let nheight = ((image.height() as f32 * (resize_ratio / 100.0)) as u32).max(1);
+        self.set_dynamic_image(super::operations::resample(&image, nwidth, nheight, kernel, token)?)?;
+        Ok(ImgSize::new(nwidth as usize, nheight as usize))
+    }
+    /// Resize the image to the given width, scaling the height proportionally.
+    /// This uses ``get_size()`` and ``resize_xy()``.
+    /// 
+    /// args:
+    /// - width: new width
+    /// 
+    /// returns:
+    /// - ImgSize object
+    fn resize_to_width(&mut self, width: u32) -> Result<ImgSize, RusimgError> {
+        let size = self.get_size()?;
+        let nheight = ((size.height as f64 * width as f64 / size.width as f64).round() as usize).max(1);
+        let (x_ratio, y_ratio) = exact_resize_ratios(size, width as usize, nheight);
+        self.resize_xy(x_ratio, y_ratio)
+    }
+    /// Resize the image to the given height, scaling the width proportionally.
+    /// This uses ``get_size()`` and ``resize_xy()``.
+    /// 
+    /// args:
+    /// - height: new height
+    /// 
+    /// returns:
+    /// - ImgSize object
+    fn resize_to_height(&mut self, height: u32) -> Result<ImgSize, RusimgError> {
+        let size = self.get_size()?;
+        let nwidth = ((size.width as f64 * height as f64 / size.height as f64).round() as usize).max(1);
+        let (x_ratio, y_ratio) = exact_resize_ratios(size, nwidth, height as usize);
+        self.resize_xy(x_ratio, y_ratio)
+    }
     /// Trim the image with the trim parameter.
     /// The trim parameter is a Rect object.
     /// 
@@ -77,8 +202,266 @@ pub trait BackendTrait {
     /// returns:
     /// - ImgSize object
     fn trim(&mut self, trim: Rect) -> Result<ImgSize, RusimgError>;
+    /// Trim the image to the given size at the anchor position.
+    /// The trim area is computed from the anchor and the image size, and passed to ``trim()``.
+    /// 
+    /// args:
+    /// - anchor: anchor position (Anchor object)
+    /// - w: width of the trim area
+    /// - h: height of the trim area
+    /// 
+    /// returns:
+    /// - ImgSize object
+    fn trim_anchored(&mut self, anchor: Anchor, w: u32, h: u32) -> Result<ImgSize, RusimgError> {
+        let size = self.get_size()?;
+        self.trim(anchored_rect(size, anchor, w, h))
+    }
+    /// Trim the image to the trim area shrunk to multiples of ``multiple`` pixels (e.g. 16 for video encoders).
+    /// The trim area is first clipped to the image, then the width and height are rounded down to the nearest multiple,
+    /// and the shrunk area is centered within the clipped one.
+    /// 
+    /// args:
+    /// - trim: requested trim area (Rect object)
+    /// - multiple: the width and height must be multiples of this value (1 or more)
+    /// 
+    /// returns:
+    /// - ImgSize object
+    /// - ``InvalidTrimMultiple`` error if ``multiple`` is 0, and ``InvalidTrimXY`` error if the clipped area is smaller than ``multiple``
+    fn trim_multiple_of(&mut self, trim: Rect, multiple: u32) -> Result<ImgSize, RusimgError> {
+        let size = self.get_size()?;
+        self.trim(multiple_of_rect(size, &trim, multiple)?)
+    }
+    /// Rotate the image clockwise by an arbitrary angle around its center.
+    /// The canvas is expanded to fit the rotated image, and the uncovered corners are filled with the fill color.
+    /// 
+    /// args:
+    /// - degrees: rotation angle in degrees (clockwise)
+    /// - fill: RGBA color of the uncovered corners
+    /// - interpolation: interpolation used for sampling the source image (RotateInterpolation object)
+    /// 
+    /// returns:
+    /// - ImgSize object: the size of the rotated image
+    fn rotate_arbitrary(&mut self, degrees: f32, fill: [u8; 4], interpolation: RotateInterpolation) -> Result<ImgSize, RusimgError> {
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(super::operations::rotate(&image, degrees, fill, interpolation))?;
+        self.get_size()
+    }
+    /// Straighten a skewed document image.
+    /// The skew angle of the text lines is estimated within ±max_angle, and the image is rotated back by it with white corners.
+    /// 
+    /// args:
+    /// - max_angle: maximum skew angle to search in degrees
+    /// 
+    /// returns:
+    /// - Result<f32, RusimgError>: the detected skew angle in degrees (clockwise)
+    fn deskew(&mut self, max_angle: f32) -> Result<f32, RusimgError> {
+        let image = self.get_dynamic_image()?;
+        let angle = super::operations::skew_angle(&image.to_luma8(), max_angle);
+        if angle != 0.0 {
+            self.rotate_arbitrary(-angle, [255, 255, 255, 255], RotateInterpolation::Bilinear)?;
+        }
+        Ok(angle)
+    }
     /// Grayscale the image.
     fn grayscale(&mut self);
+    /// Grayscale the image, keeping 16-bit precision for 16-bit (and floating-point) sources.
+    /// 8-bit sources become 8-bit grayscale.
+    /// 
+    /// returns:
+    /// - Result object
+    fn grayscale_preserve_depth(&mut self) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(super::operations::grayscale(&image, true))
+    }
+    /// Remove the alpha channel of the image.
+    /// Grayscale images with alpha (LumaA) become grayscale (Luma), and RGBA images become RGB, keeping the bit depth.
+    /// 
+    /// returns:
+    /// - Result object
+    fn remove_alpha_channel(&mut self) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        if !image.color().has_alpha() {
+            return Ok(());
+        }
+        self.set_dynamic_image(super::operations::remove_alpha(&image))
+    }
+    /// Tone-map a floating-point (HDR) image to displayable 8-bit sRGB pixels with the tone-mapping operator.
+    /// Images without floating-point pixels are already displayable and are left unchanged.
+    /// 
+    /// args:
+    /// - method: tone-mapping operator (ToneMapMethod object)
+    /// 
+    /// returns:
+    /// - Result object
+    fn tonemap(&mut self, method: ToneMapMethod) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        if !matches!(image.color(), image::ColorType::Rgb32F | image::ColorType::Rgba32F) {
+            return Ok(());
+        }
+        self.set_dynamic_image(super::operations::tonemap(&image, method))
+    }
+    /// Drop the alpha channel of the image if every pixel is fully opaque, e.g. to save RGBA images as RGB.
+    /// 
+    /// returns:
+    /// - bool: true if the alpha channel was dropped, false if the image has no alpha channel or has transparent pixels
+    fn drop_redundant_alpha(&mut self) -> bool {
+        let image = match self.get_dynamic_image() {
+            Ok(image) => image,
+            Err(_) => return false,
+        };
+        if !image.color().has_alpha() || !super::operations::is_opaque(&image) {
+            return false;
+        }
+        self.set_dynamic_image(super::operations::remove_alpha(&image)).is_ok()
+    }
+    /// Convert the image to bilevel black and white.
+    /// The image is converted to grayscale, and each pixel is thresholded to 0 or 255 at the level.
+    /// 
+    /// args:
+    /// - level: threshold level (pixels at or above it become white)
+    /// 
+    /// returns:
+    /// - Result object
+    fn threshold(&mut self, level: u8) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(DynamicImage::ImageLuma8(super::operations::threshold(&image, level)))
+    }
+    /// Replace the image with its edge map.
+    /// The image is converted to grayscale, and each pixel is replaced with its Sobel gradient magnitude (clamped to 255).
+    /// 
+    /// returns:
+    /// - Result object
+    fn sobel_edges(&mut self) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(DynamicImage::ImageLuma8(super::operations::sobel_edges(&image)))
+    }
+    /// Equalize the histogram of the luminance, spreading the brightness levels over the full range.
+    /// The luminance is the Y channel of YCbCr, so the colors are kept.
+    /// 
+    /// returns:
+    /// - Result object
+    fn equalize_histogram(&mut self) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(super::operations::equalize_histogram(&image))
+    }
+    /// Apply a convolution kernel to the image (e.g. for blur, sharpen or emboss effects).
+    /// Each RGB channel becomes ``sum(kernel * pixels) / divisor + bias``, and the alpha channel is kept.
+    /// 
+    /// args:
+    /// - kernel: kernel values in row-major order (``kernel_width * kernel_height`` values)
+    /// - kernel_width: width of the kernel (1 or more)
+    /// - kernel_height: height of the kernel (1 or more)
+    /// - divisor: the weighted sum is divided by it (not 0, e.g. the sum of the kernel)
+    /// - bias: value added after dividing, in 8-bit units (e.g. 128 for emboss)
+    /// 
+    /// returns:
+    /// - Result object
+    /// - ``InvalidFilterParameter`` error if the kernel size does not match ``kernel.len()``, the divisor is 0, or a value is not finite
+    fn convolve(&mut self, kernel: &[f32], kernel_width: u32, kernel_height: u32, divisor: f32, bias: f32) -> Result<(), RusimgError> {
+        let size_matches = kernel_width > 0 && kernel_height > 0 && (kernel_width as u64 * kernel_height as u64) == kernel.len() as u64;
+        if !size_matches || divisor == 0.0 || !divisor.is_finite() || !bias.is_finite() || !kernel.iter().all(|v| v.is_finite()) {
+            return Err(RusimgError::InvalidFilterParameter);
+        }
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(super::operations::convolve(&image, kernel, kernel_width, kernel_height, divisor, bias))
+    }
+    /// Apply a function to each pixel of the image.
+    /// The image is converted to RGBA8, and each pixel is replaced with the result of the function.
+    /// The function is passed as a trait object so that ``BackendTrait`` stays object safe.
+    /// 
+    /// args:
+    /// - f: function taking and returning an RGBA pixel
+    /// 
+    /// returns:
+    /// - Result object
+    fn map_pixels(&mut self, f: &mut dyn FnMut([u8; 4]) -> [u8; 4]) -> Result<(), RusimgError> {
+        let mut buffer = self.get_dynamic_image()?.to_rgba8();
+        for pixel in buffer.pixels_mut() {
+            pixel.0 = f(pixel.0);
+        }
+        self.set_dynamic_image(DynamicImage::ImageRgba8(buffer))
+    }
+    /// Posterize the image by quantizing each RGB channel to the given number of levels.
+    /// 
+    /// args:
+    /// - levels_per_channel: number of levels per channel (2 or more)
+    /// 
+    /// returns:
+    /// - Result object
+    fn posterize(&mut self, levels_per_channel: u8) -> Result<(), RusimgError> {
+        if levels_per_channel < 2 {
+            return Err(RusimgError::InvalidPosterizeLevels);
+        }
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(super::operations::posterize(&image, levels_per_channel))
+    }
+    /// Multiply the alpha channel of the image by a factor, e.g. for fading overlays.
+    /// Images without an alpha channel are converted to RGBA first.
+    /// 
+    /// args:
+    /// - factor: opacity factor (0.0 - 1.0)
+    /// 
+    /// returns:
+    /// - Result object
+    fn set_opacity(&mut self, factor: f32) -> Result<(), RusimgError> {
+        if !(0.0..=1.0).contains(&factor) {
+            return Err(RusimgError::InvalidFilterParameter);
+        }
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(super::operations::scale_alpha(&image, factor))
+    }
+    /// Convert the image from straight to premultiplied alpha, multiplying the color channels by the alpha.
+    /// The image is converted to RGBA8.
+    /// 
+    /// returns:
+    /// - Result object
+    fn premultiply_alpha(&mut self) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(super::operations::premultiply_alpha(&image))
+    }
+    /// Convert the image from premultiplied to straight alpha, dividing the color channels by the alpha.
+    /// The image is converted to RGBA8, and fully transparent pixels become transparent black.
+    /// 
+    /// returns:
+    /// - Result object
+    fn unpremultiply_alpha(&mut self) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        self.set_dynamic_image(super::operations::unpremultiply_alpha(&image))
+    }
+    /// Make the pixels outside the circle inscribed in the image transparent, e.g. for avatars.
+    /// The circle is centered, and its diameter is the shorter side of the image.
+    /// 
+    /// returns:
+    /// - Result object
+    fn apply_circle_mask(&mut self) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        let (width, height) = (image.width() as f32, image.height() as f32);
+        let radius = width.min(height) / 2.0;
+        self.set_dynamic_image(super::operations::rounded_rect_mask(&image, (width / 2.0, height / 2.0), (radius, radius), radius))
+    }
+    /// Make the corners of the image transparent, rounding them with the radius.
+    /// 
+    /// args:
+    /// - radius: corner radius in pixels (limited to half the shorter side of the image)
+    /// 
+    /// returns:
+    /// - Result object
+    fn apply_rounded_corners(&mut self, radius: u32) -> Result<(), RusimgError> {
+        let image = self.get_dynamic_image()?;
+        let (width, height) = (image.width() as f32, image.height() as f32);
+        self.set_dynamic_image(super::operations::rounded_rect_mask(&image, (width / 2.0, height / 2.0), (width / 2.0, height / 2.0), radius as f32))
+    }
+    /// Convert the image to bilevel black and white with the threshold level chosen by Otsu's method.
+    /// The level is computed from the grayscale histogram and passed to ``threshold()``.
+    /// 
+    /// returns:
+    /// - Result<u8, RusimgError>: the chosen threshold level
+    fn threshold_otsu(&mut self) -> Result<u8, RusimgError> {
+        let image = self.get_dynamic_image()?;
+        let level = super::operations::otsu_level(&image.to_luma8());
+        self.threshold(level)?;
+        Ok(level)
+    }
     /// Set a image::DynamicImage to the image object.
     /// After setting the image, the image object will be updated.
     /// 
@@ -89,10 +472,11 @@ pub trait BackendTrait {
     /// - Result object
     fn set_dynamic_image(&mut self, image: DynamicImage) -> Result<(), RusimgError>;
     /// Get a image::DynamicImage from the image object.
+    /// This takes ``&self`` since 0.2.0 (it took ``&mut self`` in 0.1), so it must not change the backend.
     /// 
     /// returns:
     /// - DynamicImage object
-    fn get_dynamic_image(&mut self) -> Result<DynamicImage, RusimgError>;
+    fn get_dynamic_image(&self) -> Result<DynamicImage, RusimgError>;
     /// Get the source file path.
     /// 
     /// returns:
@@ -113,12 +497,317 @@ pub trait BackendTrait {
     /// returns:
     /// - Result<Option<Metadata>, RusimgError>
     fn get_metadata_dest(&self) -> Option<Metadata>;
+    /// Validate the image data by decoding it fully.
+    /// By default, ``source_bytes()`` is decoded with ``validate_image_bytes()``.
+    /// Images without source bytes were fully decoded when they were opened or imported, so they are valid.
+    /// 
+    /// returns:
+    /// - Result object: ``ImageTruncated`` if the data ends early, ``CorruptImage`` with the decoder message if the data is invalid
+    fn validate(&self) -> Result<(), RusimgError> {
+        match self.source_bytes() {
+            Some(source_bytes) => validate_image_bytes(source_bytes),
+            None => Ok(()),
+        }
+    }
+    /// Get the modification time of the source file.
+    /// This reads ``modified()`` of the metadata from ``get_metadata_src()``.
+    /// 
+    /// returns:
+    /// - Option<SystemTime>: the modification time, or None if there is no source file or the platform does not support it
+    fn source_modified(&self) -> Option<SystemTime> {
+        self.get_metadata_src().and_then(|metadata| metadata.modified().ok())
+    }
     /// Get the image size.
     /// 
     /// returns:
     /// - Result<ImgSize, RusimgError>
     fn get_size(&self) -> Result<ImgSize, RusimgError>;
 
+    /// Set the WebP encoding method (0=fast, 6=slowest but best compression).
+    /// This is only supported by the WebP backend; other backends return ``UnsupportedFeature``.
+    /// 
+    /// args:
+    /// - method: encoding method (0-6)
+    /// 
+    /// returns:
+    /// - Result object
+    fn set_webp_method(&mut self, _method: u8) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+
+    /// Compute a hash of the decoded image content.
+    /// The RGBA8 pixels and the dimensions are hashed with XXH3, so pixel-identical images have the same hash regardless of the format and the encoder.
+    /// 
+    /// returns:
+    /// - Result<u64, RusimgError>
+    fn content_hash(&self) -> Result<u64, RusimgError> {
+        let rgba = self.get_dynamic_image()?.to_rgba8();
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        hasher.update(&rgba.width().to_le_bytes());
+        hasher.update(&rgba.height().to_le_bytes());
+        hasher.update(rgba.as_raw());
+        Ok(hasher.digest())
+    }
+
+    /// Get the size in bytes of the image encoded with the quality, without saving it.
+    /// It is used for searching the quality giving a target file size.
+    /// This is only supported by the WebP backend; other backends return ``UnsupportedFeature``.
+    /// 
+    /// args:
+    /// - quality: quality parameter (0-100)
+    /// 
+    /// returns:
+    /// - Result<u64, RusimgError>: the encoded size in bytes
+    fn encoded_size(&self, _quality: f32) -> Result<u64, RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+    /// Encode the image in memory with the current settings, exactly as ``save()`` would write it.
+    /// The built-in backends support it; other backends return ``UnsupportedFeature`` by default.
+    /// 
+    /// returns:
+    /// - Result<Vec<u8>, RusimgError>: the encoded image file
+    fn encode_to_memory(&self) -> Result<Vec<u8>, RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+    /// Encode the image in memory like ``encode_to_memory()``, returning a ``Cancelled`` error if ``token`` is cancelled.
+    /// By default, the token is checked before and after encoding; the PNG backend also checks it before the oxipng pass.
+    /// 
+    /// args:
+    /// - token: CancelToken object
+    /// 
+    /// returns:
+    /// - Result<Vec<u8>, RusimgError>: the encoded image file
+    fn encode_to_memory_cancellable(&self, token: &CancelToken) -> Result<Vec<u8>, RusimgError> {
+        token.check()?;
+        let data = self.encode_to_memory()?;
+        token.check()?;
+        Ok(data)
+    }
+
+    /// Set the default settings of the operations (resize filter, default qualities).
+    /// Backends without configurable settings ignore it.
+    /// 
+    /// args:
+    /// - config: RusImgConfig object
+    fn set_config(&mut self, _config: &RusImgConfig) {}
+
+    /// Set whether to save the image with RLE8 compression.
+    /// This is only supported by the BMP backend; other backends return ``UnsupportedFeature``.
+    /// 
+    /// args:
+    /// - on: true to compress the image with RLE8 when saving
+    /// 
+    /// returns:
+    /// - Result object
+    fn set_bmp_rle(&mut self, _on: bool) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+
+    /// Set whether to save the image with indexed colors (a palette).
+    /// This is only supported by the PNG and WebP backends; other backends return ``UnsupportedFeature``.
+    /// PNG images are written with a PLTE chunk and WebP images are encoded losslessly, which uses the palette mode of WebP.
+    /// The image must have at most 256 colors for the palette to be used (see ``operations::quantize()``).
+    /// 
+    /// args:
+    /// - on: true to save the image with indexed colors
+    /// 
+    /// returns:
+    /// - Result object
+    fn set_indexed(&mut self, _on: bool) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+
+    /// Check whether the image format of the source is lossy.
+    /// Re-encoding an image from a lossy source adds generational quality loss.
+    /// Returns None if it is unknown (e.g. the backend cannot tell without the source file).
+    /// 
+    /// returns:
+    /// - Option<bool>: true if the source is lossy
+    fn source_is_lossy(&self) -> Option<bool> {
+        None
+    }
+
+    /// Get the original encoded bytes read from the file.
+    /// Returns None if the backend does not keep them or the image was not opened from a file.
+    /// 
+    /// returns:
+    /// - Option<&[u8]>
+    fn source_bytes(&self) -> Option<&[u8]> {
+        None
+    }
+    /// Get the number of frames of the image.
+    /// Animated formats count the frames from the container headers without decoding them.
+    /// Still images have 1 frame.
+    /// 
+    /// returns:
+    /// - Result<usize, RusimgError>
+    fn frame_count(&self) -> Result<usize, RusimgError> {
+        Ok(1)
+    }
+    /// Get the resolution of the image in dots per inch.
+    /// This is read from the format's density header (e.g. PNG pHYs, JPEG JFIF, BMP info header).
+    /// 
+    /// returns:
+    /// - Option<(f64, f64)>: horizontal and vertical DPI, or None if the image has no resolution
+    fn dpi(&self) -> Option<(f64, f64)> {
+        None
+    }
+    /// Get the EXIF orientation of the image.
+    /// By default, it is read from ``source_bytes()``.
+    /// 
+    /// returns:
+    /// - Option<u8>: EXIF orientation (1-8), or None if the image has no EXIF data
+    fn exif_orientation(&self) -> Option<u8> {
+        self.source_bytes().and_then(read_exif_orientation)
+    }
+    /// Get the GPS coordinates stored in the EXIF data of the image.
+    /// By default, they are read from ``source_bytes()``.
+    /// 
+    /// returns:
+    /// - Option<(f64, f64)>: latitude and longitude in decimal degrees (south and west are negative), or None if the image has no GPS data
+    fn gps_location(&self) -> Option<(f64, f64)> {
+        self.source_bytes().and_then(read_exif_gps)
+    }
+    /// Get the thumbnail embedded in the EXIF data of the image.
+    /// By default, it is read from ``source_bytes()``.
+    /// 
+    /// returns:
+    /// - Option<Vec<u8>>: the encoded JPEG thumbnail, or None if the image has no embedded thumbnail
+    fn embedded_thumbnail(&self) -> Option<Vec<u8>> {
+        self.source_bytes().and_then(read_exif_thumbnail)
+    }
+    /// Get the common metadata of the image in one call.
+    /// This uses ``get_dynamic_image()``, ``dpi()``, ``exif_orientation()`` and ``frame_count()``.
+    /// 
+    /// returns:
+    /// - ImageMetadata object
+    fn metadata(&self) -> ImageMetadata {
+        let mut metadata = ImageMetadata::from_image(&self.get_dynamic_image().unwrap_or_default());
+        metadata.dpi = self.dpi();
+        metadata.orientation = self.exif_orientation();
+        metadata.frame_count = self.frame_count().unwrap_or(1);
+        metadata
+    }
+    /// Get the animation information (loop count and the delay, disposal and blend method of each frame).
+    /// It is parsed from the container headers, so the frames are not decoded.
+    /// Still images return a single-frame descriptor (``AnimationInfo::still()``).
+    /// 
+    /// returns:
+    /// - Result<AnimationInfo, RusimgError>
+    fn animation_info(&self) -> Result<AnimationInfo, RusimgError> {
+        Ok(AnimationInfo::still())
+    }
+    /// Set the options for optimizing PNG images with oxipng.
+    /// They are used by ``compress()``.
+    /// This is only supported by the PNG backend; other backends return ``UnsupportedFeature``.
+    /// 
+    /// args:
+    /// - options: PngOptimizeOptions object
+    /// 
+    /// returns:
+    /// - Result object
+    fn set_png_optimize_options(&mut self, _options: PngOptimizeOptions) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+    /// Get the gamma of the image from the gAMA chunk (e.g. 0.45455 for a display gamma of 2.2).
+    /// The chunk is read when the file is opened and kept when the image is saved.
+    /// This is only supported by the PNG backend; other backends return None.
+    /// 
+    /// returns:
+    /// - Option<f32>: the gamma, or None if the image has no gAMA chunk
+    fn get_png_gamma(&self) -> Option<f32> {
+        None
+    }
+    /// Check whether the image has an sRGB chunk, i.e. it is in the sRGB color space.
+    /// The chunk is read when the file is opened and kept when the image is saved.
+    /// This is only supported by the PNG backend; other backends return false.
+    /// 
+    /// returns:
+    /// - bool
+    fn has_srgb_chunk(&self) -> bool {
+        false
+    }
+    /// Set the gamma written to the gAMA chunk when saving.
+    /// This is only supported by the PNG backend; other backends return ``UnsupportedFeature``.
+    /// 
+    /// args:
+    /// - gamma: gamma value (more than 0.0, e.g. 0.45455 for a display gamma of 2.2)
+    /// 
+    /// returns:
+    /// - Result object
+    fn set_png_gamma(&mut self, _gamma: f32) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+    /// Get the XMP packet of the image.
+    /// The packet is read when the file is opened (JPEG: APP1 segment, PNG: iTXt chunk, WebP: "XMP " chunk) or set by ``set_xmp()``.
+    /// Backends without XMP support return None.
+    /// 
+    /// returns:
+    /// - Option<String>: the XMP packet, or None if the image has no XMP packet
+    fn get_xmp(&self) -> Option<String> {
+        None
+    }
+    /// Set the XMP packet written to the image when saving, replacing the existing one.
+    /// This is supported by the JPEG, PNG and WebP backends; other backends return ``UnsupportedFeature``.
+    /// 
+    /// args:
+    /// - xmp: XMP packet
+    /// 
+    /// returns:
+    /// - Result object
+    fn set_xmp(&mut self, _xmp: &str) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+    /// Apply the strongest lossless compression settings of the format; the pixels are kept exactly.
+    /// PNG images are optimized with oxipng level 6 and the metadata chunks not affecting rendering are stripped,
+    /// and WebP images are encoded losslessly with the slowest method.
+    /// BMP and JPEG images return a ``CannotOptimize`` error with the reason, and the other backends return ``UnsupportedFeature``.
+    /// 
+    /// returns:
+    /// - Result object
+    fn optimize(&mut self) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+    /// Compress the image with an oxipng preset level directly, instead of the level converted from the quality by ``compress()``.
+    /// This is only supported by the PNG backend; other backends return ``UnsupportedFeature``.
+    /// 
+    /// args:
+    /// - level: oxipng preset level (0: fastest - 6: best compression)
+    /// 
+    /// returns:
+    /// - Result object
+    fn compress_png_level(&mut self, _level: u8) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+    /// Compress the image with prebuilt oxipng options instead of the ones built from the quality.
+    /// The options can be shared across many images, so batch runs do not rebuild them for each image.
+    /// This is only supported by the PNG backend; other backends return ``UnsupportedFeature``.
+    /// 
+    /// args:
+    /// - options: PngCompressOptions object
+    /// 
+    /// returns:
+    /// - Result object
+    fn compress_with_oxipng_options(&mut self, _options: &PngCompressOptions) -> Result<(), RusimgError> {
+        Err(RusimgError::UnsupportedFeature)
+    }
+
+    /// Get the background color stored in the image file as RGBA.
+    /// Returns None if the format or the file has no background color.
+    /// 
+    /// returns:
+    /// - Option<[u8; 4]>
+    fn get_background_color(&self) -> Option<[u8; 4]> {
+        None
+    }
+    /// Set the background color stored in the image file.
+    /// Formats without a background color ignore it.
+    /// 
+    /// args:
+    /// - color: background color (RGBA)
+    fn set_background_color(&mut self, _color: [u8; 4]) {
+    }
+
     /// Get a file path for saving an image.
     /// If the destination_filepath is None, the image will be saved to the source file path with the new extension.
     /// 
@@ -156,6 +845,264 @@ pub trait BackendTrait {
     }
 }
 
+// Compute the resize_xy() ratios giving exactly the target size.
+// The ratios aim at the middle of the target pixel so that the truncation in resize_xy() lands on it.
+fn exact_resize_ratios(size: ImgSize, nwidth: usize, nheight: usize) -> (f32, f32) {
+    let x_ratio = (nwidth as f32 + 0.5) / size.width as f32 * 100.0;
+    let y_ratio = (nheight as f32 + 0.5) / size.height as f32 * 100.0;
+    (x_ratio, y_ratio)
+}
+
+// Compute the trim area of ``trim_anchored()``: the area of w x h at the anchor position of an image of ``size``.
+pub(crate) fn anchored_rect(size: ImgSize, anchor: Anchor, w: u32, h: u32) -> Rect {
+    let free_w = (size.width as u32).saturating_sub(w);
+    let free_h = (size.height as u32).saturating_sub(h);
+    let x = match anchor {
+        Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0,
+        Anchor::Top | Anchor::Center | Anchor::Bottom => free_w / 2,
+        Anchor::TopRight | Anchor::Right | Anchor::BottomRight => free_w,
+    };
+    let y = match anchor {
+        Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0,
+        Anchor::Left | Anchor::Center | Anchor::Right => free_h / 2,
+        Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => free_h,
+    };
+    Rect { x, y, w, h }
+}
+
+// Compute the trim area of ``trim_multiple_of()`` in an image of ``size``.
+// The area is clipped to the image first, so that trim() does not shrink the rounded area again.
+pub(crate) fn multiple_of_rect(size: ImgSize, trim: &Rect, multiple: u32) -> Result<Rect, RusimgError> {
+    if multiple == 0 {
+        return Err(RusimgError::InvalidTrimMultiple);
+    }
+    let clipped_w = trim.w.min((size.width as u32).saturating_sub(trim.x));
+    let clipped_h = trim.h.min((size.height as u32).saturating_sub(trim.y));
+    let w = clipped_w - clipped_w % multiple;
+    let h = clipped_h - clipped_h % multiple;
+    if w == 0 || h == 0 {
+        return Err(RusimgError::InvalidTrimXY);
+    }
+    Ok(Rect { x: trim.x + (clipped_w - w) / 2, y: trim.y + (clipped_h - h) / 2, w, h })
+}
+
+/// Opener function for an external format backend.
+/// It receives the file path, the image buffer and the metadata of the file, and returns a BackendTrait object.
+/// The path and metadata are None for images opened from bytes in memory (e.g. ``RusImg::from_bytes()``).
+pub type BackendOpener = fn(Option<PathBuf>, Vec<u8>, Option<Metadata>) -> Result<Box<dyn BackendTrait>, RusimgError>;
+
+// Registered external format backends: (magic bytes, opener).
+static EXTERNAL_BACKENDS: Mutex<Vec<(Vec<u8>, BackendOpener)>> = Mutex::new(Vec::new());
+
+/// Register an external format backend.
+/// ``open_image()`` and ``open_image_bytes()`` use the ``opener`` for data starting with the ``magic`` bytes when no built-in backend can open them.
+/// The opened image gets ``Extension::ExternalFormat`` with the file extension of the path (empty without a path).
+pub fn register_backend(magic: &[u8], opener: BackendOpener) {
+    let mut backends = EXTERNAL_BACKENDS.lock().unwrap_or_else(|e| e.into_inner());
+    backends.push((magic.to_vec(), opener));
+}
+
+/// Encode a sequence of images into an animated WebP and return the encoded bytes.
+/// ``delays_ms`` is the display duration of each frame in milliseconds, and a ``loop_count`` of 0 means infinite looping.
+/// The frames must have the same size, and ``quality`` is the WebP quality (0-100).
+/// If the webp feature is not enabled, it will return an UnsupportedFileExtension error.
+pub fn encode_animated_webp(frames: &[RusImg], delays_ms: &[u32], loop_count: u16, quality: f32) -> Result<Vec<u8>, RusimgError> {
+    if frames.is_empty() {
+        return Err(RusimgError::ImageNotSpecified);
+    }
+    if delays_ms.len() != frames.len() {
+        return Err(RusimgError::FrameDelaysMismatch);
+    }
+    if !(0.0..=100.0).contains(&quality) {
+        return Err(RusimgError::InvalidCompressionLevel);
+    }
+    let images = frames.iter().map(|frame| frame.data.get_dynamic_image()).collect::<Result<Vec<DynamicImage>, RusimgError>>()?;
+    if images.iter().any(|image| image.width() != images[0].width() || image.height() != images[0].height()) {
+        return Err(RusimgError::FrameSizeMismatch);
+    }
+    encode_webp_animation(&images, delays_ms, loop_count, quality)
+}
+#[cfg(feature="webp")]
+fn encode_webp_animation(images: &[DynamicImage], delays_ms: &[u32], loop_count: u16, quality: f32) -> Result<Vec<u8>, RusimgError> {
+    webp::encode_animation(images, delays_ms, loop_count, quality)
+}
+#[cfg(not(feature="webp"))]
+fn encode_webp_animation(_images: &[DynamicImage], _delays_ms: &[u32], _loop_count: u16, _quality: f32) -> Result<Vec<u8>, RusimgError> {
+    Err(RusimgError::UnsupportedFileExtension)
+}
+
+/// Validate encoded image data by decoding it fully.
+/// This returns ``ImageTruncated`` if the data ends early, and ``CorruptImage`` with the decoder message (e.g. a CRC error of a PNG chunk) if the data is invalid.
+/// It can be used to quarantine bad files before opening them.
+pub fn validate_image_bytes(buf: &[u8]) -> Result<(), RusimgError> {
+    match image::load_from_memory(buf) {
+        Ok(_) => Ok(()),
+        Err(image::ImageError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => Err(RusimgError::ImageTruncated),
+        Err(image::ImageError::Decoding(e)) => Err(RusimgError::CorruptImage(e.to_string())),
+        Err(e) => Err(RusimgError::FailedToOpenImage(e.to_string())),
+    }
+}
+
+// Number of temporary files made by write_atomically(), to give each call its own temporary path.
+#[cfg(any(feature="bmp", feature="jpeg", feature="png", feature="webp"))]
+static TEMP_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Write a file atomically.
+/// ``write`` writes the file to a temporary file next to ``path``, which is synced to the disk and renamed to ``path`` only if it succeeds.
+/// So an interrupted or failed save never leaves a truncated file at ``path``; on failure, the temporary file is removed.
+/// The temporary path is unique to each call (also between threads), and keeps the extension of ``path``, so that the image format can still be inferred from it.
+/// Unless ``overwrite`` is set, the temporary file is hard-linked to ``path`` instead of renamed, which fails if ``path`` exists,
+/// so an existing file is never replaced (even by another process saving at the same time) and a ``FileAlreadyExists`` error is returned.
+#[cfg(any(feature="bmp", feature="jpeg", feature="png", feature="webp"))]
+pub(crate) fn write_atomically<F>(path: &Path, overwrite: bool, write: F) -> Result<(), RusimgError>
+where F: FnOnce(&mut std::fs::File) -> Result<(), RusimgError> {
+    let file_stem = path.file_stem().and_then(|s| s.to_str()).ok_or(RusimgError::FailedToGetFilename(path.to_path_buf()))?;
+    let id = format!("{}.{}", std::process::id(), TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed));
+    let temp_name = match path.extension().and_then(|s| s.to_str()) {
+        Some(extension) => format!(".{}.{}.tmp.{}", file_stem, id, extension),
+        None => format!(".{}.{}.tmp", file_stem, id),
+    };
+    let temp_path = path.with_file_name(temp_name);
+
+    let written = std::fs::File::create(&temp_path).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+        .and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all().map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+        });
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    if overwrite {
+        return std::fs::rename(&temp_path, path).map_err(|e| {
+            let _ = std::fs::remove_file(&temp_path);
+            RusimgError::FailedToSaveImage(e.to_string())
+        });
+    }
+    let linked = std::fs::hard_link(&temp_path, path);
+    let _ = std::fs::remove_file(&temp_path);
+    linked.map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => RusimgError::FileAlreadyExists(path.to_path_buf()),
+        _ => RusimgError::FailedToSaveImage(e.to_string()),
+    })
+}
+
+/// Check that the pixel buffer of an image is consistent with its dimensions.
+/// A ``DynamicImage`` built from a raw buffer with padded rows (a wrong stride) has more bytes than ``width * height * bytes per pixel``,
+/// and its pixels are read skewed, so such an image is rejected with an ``InvalidImageBuffer`` error.
+#[cfg(any(feature="bmp", feature="jpeg", feature="png", feature="webp"))]
+pub(crate) fn check_image_buffer(image: &DynamicImage) -> Result<(), RusimgError> {
+    let expected = (image.width() as usize).checked_mul(image.height() as usize)
+        .and_then(|n| n.checked_mul(image.color().bytes_per_pixel() as usize))
+        .unwrap_or(usize::MAX);
+    let actual = image.as_bytes().len();
+    if actual != expected {
+        return Err(RusimgError::InvalidImageBuffer(expected, actual));
+    }
+    Ok(())
+}
+
+// Read the EXIF orientation (1-8) of an encoded image.
+// Returns None if the image has no EXIF data.
+fn read_exif_orientation(buf: &[u8]) -> Option<u8> {
+    use image::ImageDecoder;
+    let mut decoder = image::ImageReader::new(Cursor::new(buf)).with_guessed_format().ok()?.into_decoder().ok()?;
+    decoder.exif_metadata().ok()??;
+    decoder.orientation().ok().map(|orientation| orientation.to_exif())
+}
+
+// Read the EXIF GPS coordinates (latitude, longitude) of an encoded image.
+// Returns None if the image has no EXIF data or no GPS coordinates in it.
+fn read_exif_gps(buf: &[u8]) -> Option<(f64, f64)> {
+    use image::ImageDecoder;
+    let mut decoder = image::ImageReader::new(Cursor::new(buf)).with_guessed_format().ok()?.into_decoder().ok()?;
+    exif::read_gps(&decoder.exif_metadata().ok()??)
+}
+
+// Read the JPEG thumbnail embedded in the EXIF data of an encoded image.
+// Returns None if the image has no EXIF data or no thumbnail in it.
+fn read_exif_thumbnail(buf: &[u8]) -> Option<Vec<u8>> {
+    use image::ImageDecoder;
+    let mut decoder = image::ImageReader::new(Cursor::new(buf)).with_guessed_format().ok()?.into_decoder().ok()?;
+    exif::read_thumbnail(&decoder.exif_metadata().ok()??)
+}
+
+// Open an image with the registered external format backends.
+// Returns None if no registered backend claims the image buffer.
+fn open_external_image(path: Option<&Path>, buf: Vec<u8>, metadata_input: Option<Metadata>) -> Option<Result<RusImg, RusimgError>> {
+    let opener = {
+        let backends = EXTERNAL_BACKENDS.lock().unwrap_or_else(|e| e.into_inner());
+        backends.iter().find(|(magic, _)| buf.starts_with(magic)).map(|(_, opener)| *opener)
+    }?;
+
+    let extension_str = path.and_then(|path| path.extension()).and_then(|s| s.to_str()).unwrap_or("").to_string();
+    Some(opener(path.map(Path::to_path_buf), buf, metadata_input).map(|data| RusImg::from_backend(Extension::ExternalFormat(extension_str), data)))
+}
+
+// Maximum number of pixels of an image to open (0 = no limit).
+static MAX_PIXELS: AtomicU64 = AtomicU64::new(0);
+
+/// Set the maximum number of pixels (width * height) of an image to open.
+/// Images exceeding the limit are rejected with ``ImageTooLarge`` before they are decoded.
+/// Set 0 to remove the limit (default).
+/// The limit is global to the process: it is shared by all threads and applies to every image opened afterwards
+/// (``RusImg::open()``, ``open_mmap()``, ``from_bytes()``, etc.) until it is set again.
+pub fn set_max_pixels(limit: u64) {
+    MAX_PIXELS.store(limit, Ordering::Relaxed);
+}
+
+/// Get the maximum number of pixels set by ``set_max_pixels()`` (0 = no limit).
+pub fn max_pixels() -> u64 {
+    MAX_PIXELS.load(Ordering::Relaxed)
+}
+
+// Global default config (None = RusImgConfig::default()).
+static DEFAULT_CONFIG: RwLock<Option<RusImgConfig>> = RwLock::new(None);
+
+/// Set the default config used by ``RusImg::open()`` and ``RusImg::new()``.
+/// It is shared by all threads; images that are already created keep their config.
+/// The config is checked first (see ``RusImgConfig::validate()``), and an invalid config is not set.
+pub fn set_default_config(config: RusImgConfig) -> Result<(), RusimgError> {
+    config.validate()?;
+    *DEFAULT_CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Some(config);
+    Ok(())
+}
+
+/// Get the default config set by ``set_default_config()``.
+pub fn default_config() -> RusImgConfig {
+    DEFAULT_CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone().unwrap_or_default()
+}
+
+// Check that a region is inside an image of the given size (``InvalidTrimXY``) and within the maximum number of pixels (``ImageTooLarge``).
+// A row of the whole image must be within the limit too, because the region decoders buffer whole rows.
+pub(crate) fn check_region(size: ImgSize, rect: &Rect) -> Result<(), RusimgError> {
+    let fits = |start: u32, len: u32, max: usize| len > 0 && (start as u64 + len as u64) <= max as u64;
+    if !fits(rect.x, rect.w, size.width) || !fits(rect.y, rect.h, size.height) {
+        return Err(RusimgError::InvalidTrimXY);
+    }
+    let limit = max_pixels();
+    if limit != 0 && (rect.w as u64 * rect.h as u64 > limit || size.width as u64 > limit) {
+        return Err(RusimgError::ImageTooLarge);
+    }
+    Ok(())
+}
+
+// Check the image dimensions in the header against the maximum number of pixels.
+// If the dimensions cannot be read from the header, the check is left to the decoder.
+fn check_max_pixels(image_buf: &[u8]) -> Result<(), RusimgError> {
+    let limit = max_pixels();
+    if limit == 0 {
+        return Ok(());
+    }
+    let reader = image::ImageReader::new(Cursor::new(image_buf)).with_guessed_format().map_err(|e| RusimgError::FailedToReadFile(e.to_string()))?;
+    if let Ok((width, height)) = reader.into_dimensions() {
+        if width as u64 * height as u64 > limit {
+            return Err(RusimgError::ImageTooLarge);
+        }
+    }
+    Ok(())
+}
+
 // Get image format from image buffer.
 fn guess_image_format(image_buf: &[u8]) -> Result<image::ImageFormat, RusimgError> {
     let format = image::guess_format(image_buf).map_err(|e| RusimgError::FailedToOpenImage(e.to_string()))?;
@@ -166,52 +1113,52 @@ fn guess_image_format(image_buf: &[u8]) -> Result<image::ImageFormat, RusimgErro
 /// If the bmp feature is enabled, it will open a BMP image.
 /// If not, it will return an UnsupportedFileExtension error.
 #[cfg(feature="bmp")]
-fn open_bmp_image(path: &Path, buf: Vec<u8>, metadata_input: Metadata) -> Result<RusImg, RusimgError> {
-    let image = bmp::BmpImage::open(Some(path.to_path_buf()), Some(buf), Some(metadata_input))?;
+fn open_bmp_image(path: Option<&Path>, buf: Vec<u8>, metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
+    let image = bmp::BmpImage::open(path.map(Path::to_path_buf), Some(buf), metadata_input)?;
     let data = Box::new(image);
-    Ok(RusImg { extension: Extension::Bmp, data: data })
+    Ok(RusImg::from_backend(Extension::Bmp, data))
 }
 #[cfg(not(feature="bmp"))]
-fn open_bmp_image(_path: &Path, _buf: Vec<u8>, _metadata_input: Metadata) -> Result<RusImg, RusimgError> {
+fn open_bmp_image(_path: Option<&Path>, _buf: Vec<u8>, _metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
     Err(RusimgError::UnsupportedFileExtension)
 }
 /// Open a jpeg image file and make a RusImg object.
 /// If the jpeg feature is enabled, it will open a JPEG image.
 /// If not, it will return an UnsupportedFileExtension error.
 #[cfg(feature="jpeg")]
-fn open_jpeg_image(path: &Path, buf: Vec<u8>, metadata_input: Metadata) -> Result<RusImg, RusimgError> {
-    let image = jpeg::JpegImage::open(Some(path.to_path_buf()), Some(buf), Some(metadata_input))?;
+fn open_jpeg_image(path: Option<&Path>, buf: Vec<u8>, metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
+    let image = jpeg::JpegImage::open(path.map(Path::to_path_buf), Some(buf), metadata_input)?;
     let data = Box::new(image);
-    Ok(RusImg { extension: Extension::Jpeg, data: data })
+    Ok(RusImg::from_backend(Extension::Jpeg, data))
 }
 #[cfg(not(feature="jpeg"))]
-fn open_jpeg_image(_path: &Path, _buf: Vec<u8>, _metadata_input: Metadata) -> Result<RusImg, RusimgError> {
+fn open_jpeg_image(_path: Option<&Path>, _buf: Vec<u8>, _metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
     Err(RusimgError::UnsupportedFileExtension)
 }
 /// Open a png image file and make a RusImg object.
 /// If the png feature is enabled, it will open a PNG image.
 /// If not, it will return an UnsupportedFileExtension error.
 #[cfg(feature="png")]
-fn open_png_image(path: &Path, buf: Vec<u8>, metadata_input: Metadata) -> Result<RusImg, RusimgError> {
-    let image = png::PngImage::open(Some(path.to_path_buf()), Some(buf), Some(metadata_input))?;
+fn open_png_image(path: Option<&Path>, buf: Vec<u8>, metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
+    let image = png::PngImage::open(path.map(Path::to_path_buf), Some(buf), metadata_input)?;
     let data = Box::new(image);
-    Ok(RusImg { extension: Extension::Png, data: data })
+    Ok(RusImg::from_backend(Extension::Png, data))
 }
 #[cfg(not(feature="png"))]
-fn open_png_image(_path: &Path, _buf: Vec<u8>, _metadata_input: Metadata) -> Result<RusImg, RusimgError> {
+fn open_png_image(_path: Option<&Path>, _buf: Vec<u8>, _metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
     Err(RusimgError::UnsupportedFileExtension)
 }
 /// Open a webp image file and make a RusImg object.
 /// If the webp feature is enabled, it will open a WebP image.
 /// If not, it will return an UnsupportedFileExtension error.
 #[cfg(feature="webp")]
-fn open_webp_image(path: &Path, buf: Vec<u8>, metadata_input: Metadata) -> Result<RusImg, RusimgError> {
-    let image = webp::WebpImage::open(Some(path.to_path_buf()), Some(buf), Some(metadata_input))?;
+fn open_webp_image(path: Option<&Path>, buf: Vec<u8>, metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
+    let image = webp::WebpImage::open(path.map(Path::to_path_buf), Some(buf), metadata_input)?;
     let data = Box::new(image);
-    Ok(RusImg { extension: Extension::Webp, data: data })
+    Ok(RusImg::from_backend(Extension::Webp, data))
 }
 #[cfg(not(feature="webp"))]
-fn open_webp_image(_path: &Path, _buf: Vec<u8>, _metadata_input: Metadata) -> Result<RusImg, RusimgError> {
+fn open_webp_image(_path: Option<&Path>, _buf: Vec<u8>, _metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
     Err(RusimgError::UnsupportedFileExtension)
 }
 
@@ -221,24 +1168,95 @@ pub fn open_image(path: &Path) -> Result<RusImg, RusimgError> {
     let mut buf = Vec::new();
     raw_data.read_to_end(&mut buf).map_err(|e| RusimgError::FailedToReadFile(e.to_string()))?;
     let metadata_input = raw_data.metadata().map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?;
+    check_max_pixels(&buf)?;
+    open_image_buffer(Some(path), buf, Some(metadata_input))
+}
+
+/// Open an image file by memory-mapping it instead of reading it into a buffer first.
+/// BMP images are decoded directly from the mapped file. The other backends keep the encoded bytes
+/// (e.g. to save unmodified files as they are), so the mapped file is copied for them and they are opened as with ``open_image()``.
+#[cfg(feature="mmap")]
+pub fn open_image_mmap(path: &Path) -> Result<RusImg, RusimgError> {
+    let file = std::fs::File::open(path).map_err(|e| RusimgError::FailedToOpenFile(e.to_string()))?;
+    let metadata_input = file.metadata().map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?;
+    // SAFETY: the mapping is only read while the image is opened; the file is not modified by this library meanwhile.
+    let mmap = unsafe { memmap2::Mmap::map(&file) }.map_err(|e| RusimgError::FailedToReadFile(e.to_string()))?;
+    check_max_pixels(&mmap)?;
+
+    match guess_image_format(&mmap) {
+        #[cfg(feature="bmp")]
+        Ok(image::ImageFormat::Bmp) => {
+            let image = bmp::BmpImage::open_from_slice(Some(path.to_path_buf()), &mmap, Some(metadata_input))?;
+            Ok(RusImg::from_backend(Extension::Bmp, Box::new(image)))
+        },
+        _ => open_image_buffer(Some(path), mmap.to_vec(), Some(metadata_input)),
+    }
+}
 
-    match guess_image_format(&buf)? {
-        image::ImageFormat::Bmp => {
+/// Open only a region of an image file, without decoding the whole image.
+/// Non-interlaced PNG files are decoded row by row down to the bottom of the region, and only the pixels of the region are read from uncompressed 24-bit BMP files.
+/// Returns None for the other files, which must be decoded whole (see ``RusImg::decode_region()``).
+/// Also returns the size of the whole image.
+pub fn open_image_region(path: &Path, rect: &Rect) -> Result<Option<(RusImg, ImgSize)>, RusimgError> {
+    let mut file = std::fs::File::open(path).map_err(|e| RusimgError::FailedToOpenFile(e.to_string()))?;
+    let metadata_input = file.metadata().map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?;
+    let mut signature = [0u8; 8];
+    if file.read_exact(&mut signature).is_err() {
+        return Ok(None);
+    }
+    file.rewind().map_err(|e| RusimgError::FailedToReadFile(e.to_string()))?;
+
+    match guess_image_format(&signature) {
+        #[cfg(feature="bmp")]
+        Ok(image::ImageFormat::Bmp) => {
+            let region = bmp::BmpImage::open_region(path.to_path_buf(), &mut file, metadata_input, rect)?;
+            Ok(region.map(|(image, size)| (RusImg::from_backend(Extension::Bmp, Box::new(image)), size)))
+        },
+        #[cfg(feature="png")]
+        Ok(image::ImageFormat::Png) => {
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf).map_err(|e| RusimgError::FailedToReadFile(e.to_string()))?;
+            let region = png::PngImage::open_region(path.to_path_buf(), &buf, metadata_input, rect)?;
+            Ok(region.map(|(image, size)| (RusImg::from_backend(Extension::Png, Box::new(image)), size)))
+        },
+        _ => {
+            #[cfg(not(any(feature="bmp", feature="png")))]
+            let _ = (rect, metadata_input);
+            Ok(None)
+        },
+    }
+}
+
+// Open an image from the encoded bytes, choosing the backend from the image format.
+// The path and metadata are those of the source file, if the bytes were read from one.
+fn open_image_buffer(path: Option<&Path>, buf: Vec<u8>, metadata_input: Option<Metadata>) -> Result<RusImg, RusimgError> {
+    match guess_image_format(&buf) {
+        Ok(image::ImageFormat::Bmp) => {
             open_bmp_image(path, buf, metadata_input)
         },
-        image::ImageFormat::Jpeg => {
+        Ok(image::ImageFormat::Jpeg) => {
             open_jpeg_image(path, buf, metadata_input)
         },
-        image::ImageFormat::Png => {
+        Ok(image::ImageFormat::Png) => {
             open_png_image(path, buf, metadata_input)
         },
-        image::ImageFormat::WebP => {
+        Ok(image::ImageFormat::WebP) => {
             open_webp_image(path, buf, metadata_input)
         },
-        _ => Err(RusimgError::UnsupportedFileExtension),
+        // Not a built-in format: try the registered external format backends.
+        Ok(_) => open_external_image(path, buf, metadata_input).unwrap_or(Err(RusimgError::UnsupportedFileExtension)),
+        Err(e) => open_external_image(path, buf, metadata_input).unwrap_or(Err(e)),
     }
 }
 
+/// Make a new RusImg object from encoded image bytes (e.g. downloaded or embedded data), choosing the backend from the image format.
+/// The bytes are opened by the backend like a file, so an unmodified image is saved as it is and its metadata (e.g. EXIF, XMP, PNG gamma, WebP animation) is kept,
+/// but it has no source file. Formats registered with ``register_backend()`` are opened too.
+pub fn open_image_bytes(buf: &[u8]) -> Result<RusImg, RusimgError> {
+    check_max_pixels(buf)?;
+    open_image_buffer(None, buf.to_vec(), None)
+}
+
 /// Make a new RusImg object from a DynamicImage object.
 pub fn new_image(extension: &Extension, image: DynamicImage) -> Result<RusImg, RusimgError> {
     match extension {
@@ -264,7 +1282,7 @@ pub fn new_image(extension: &Extension, image: DynamicImage) -> Result<RusImg, R
 fn new_bmp_image(image: DynamicImage) -> Result<RusImg, RusimgError> {
     let image_object = bmp::BmpImage::import(Some(image), None, None)?;
     let data = Box::new(image_object);
-    Ok(RusImg { extension: Extension::Bmp, data: data })
+    Ok(RusImg::from_backend(Extension::Bmp, data))
 }
 #[cfg(not(feature="bmp"))]
 fn new_bmp_image(_image: DynamicImage) -> Result<RusImg, RusimgError> {
@@ -274,7 +1292,7 @@ fn new_bmp_image(_image: DynamicImage) -> Result<RusImg, RusimgError> {
 fn new_jpeg_image(image: DynamicImage) -> Result<RusImg, RusimgError> {
     let image_object = jpeg::JpegImage::import(Some(image), None, None)?;
     let data = Box::new(image_object);
-    Ok(RusImg { extension: Extension::Jpg, data: data })
+    Ok(RusImg::from_backend(Extension::Jpg, data))
 }
 #[cfg(not(feature="jpeg"))]
 fn new_jpeg_image(_image: DynamicImage) -> Result<RusImg, RusimgError> {
@@ -284,7 +1302,7 @@ fn new_jpeg_image(_image: DynamicImage) -> Result<RusImg, RusimgError> {
 fn new_png_image(image: DynamicImage) -> Result<RusImg, RusimgError> {
     let image_object = png::PngImage::import(Some(image), None, None)?;
     let data = Box::new(image_object);
-    Ok(RusImg { extension: Extension::Png, data: data })
+    Ok(RusImg::from_backend(Extension::Png, data))
 }
 #[cfg(not(feature="png"))]
 fn new_png_image(_image: DynamicImage) -> Result<RusImg, RusimgError> {
@@ -294,7 +1312,7 @@ fn new_png_image(_image: DynamicImage) -> Result<RusImg, RusimgError> {
 fn new_webp_image(image: DynamicImage) -> Result<RusImg, RusimgError> {
     let image_object = webp::WebpImage::import(Some(image), None, None)?;
     let data = Box::new(image_object);
-    Ok(RusImg { extension: Extension::Webp, data: data })
+    Ok(RusImg::from_backend(Extension::Webp, data))
 }
 #[cfg(not(feature="webp"))]
 fn new_webp_image(_image: DynamicImage) -> Result<RusImg, RusimgError> {
@@ -1,62 +1,142 @@
+mod region;
+mod rle;
+
 use image::DynamicImage;
 
-use std::fs::Metadata;
+use std::fs::{File, Metadata};
+use std::io::{Cursor, Write};
 use std::path::PathBuf;
 
-use super::super::{ImgSize, RusimgError, BackendTrait, Rect};
+use super::super::{ImgSize, RusimgError, BackendTrait, Rect, RusImgConfig, CancelToken};
 
 #[derive(Debug, Clone)]
 pub struct BmpImage {
     pub image: DynamicImage,
     size: ImgSize,
+    dpi: Option<(f64, f64)>,
+    rle: bool,
+    config: RusImgConfig,
     pub metadata_input: Option<Metadata>,
     pub metadata_output: Option<Metadata>,
     pub filepath_input: Option<PathBuf>,
     pub filepath_output: Option<PathBuf>,
 }
 
-impl BackendTrait for BmpImage {
-    /// Import an image from a DynamicImage object.
-    fn import(image: Option<DynamicImage>, source_path: Option<PathBuf>, source_metadata: Option<Metadata>) -> Result<Self, RusimgError> {
-        let image = image.ok_or(RusimgError::ImageNotSpecified)?;
+// Read the resolution from the BMP info header in dots per inch.
+// Returns None if the header has no resolution.
+fn read_bmp_dpi(data: &[u8]) -> Option<(f64, f64)> {
+    // The pixels per meter fields exist in BITMAPINFOHEADER (40 bytes) and later versions.
+    let header_size = u32::from_le_bytes(data.get(14..18)?.try_into().ok()?);
+    if header_size < 40 {
+        return None;
+    }
+    let x = i32::from_le_bytes(data.get(38..42)?.try_into().ok()?);
+    let y = i32::from_le_bytes(data.get(42..46)?.try_into().ok()?);
+    if x <= 0 || y <= 0 {
+        return None;
+    }
+    Some((x as f64 * 0.0254, y as f64 * 0.0254))
+}
+
+impl BmpImage {
+    /// Open an image from a borrowed image buffer (e.g. a memory-mapped file).
+    /// The BMP backend does not keep the encoded bytes, so they do not need to be owned.
+    pub fn open_from_slice(path: Option<PathBuf>, image_buf: &[u8], metadata: Option<Metadata>) -> Result<Self, RusimgError> {
+        let image = image::load_from_memory(image_buf).map_err(|e| RusimgError::FailedToOpenImage(e.to_string()))?;
         let size = ImgSize { width: image.width() as usize, height: image.height() as usize };
+        let dpi = read_bmp_dpi(image_buf);
 
         Ok(Self {
             image,
             size,
-            metadata_input: source_metadata,
+            dpi,
+            rle: false,
+            config: RusImgConfig::default(),
+            metadata_input: metadata,
             metadata_output: None,
-            filepath_input: source_path,
+            filepath_input: path,
             filepath_output: None,
         })
     }
 
-    /// Open an image from a image buffer.
-    fn open(path: Option<PathBuf>, image_buf: Option<Vec<u8>>, metadata: Option<Metadata>) -> Result<Self, RusimgError> {
-        let image_buf = image_buf.ok_or(RusimgError::ImageNotSpecified)?; // If the image buffer is not specified, return an error.
-        let metadata = metadata.ok_or(RusimgError::ImageNotSpecified)?; // If the metadata is not specified, return an error.
+    /// Open only a region of an uncompressed 24-bit BMP file, reading just the pixels of the region from the file.
+    /// Returns None for the other kinds of BMP files. Also returns the size of the whole image.
+    pub fn open_region(path: PathBuf, file: &mut File, metadata: Metadata, rect: &Rect) -> Result<Option<(Self, ImgSize)>, RusimgError> {
+        let Some((image, header, size)) = region::decode_region(file, rect)? else {
+            return Ok(None);
+        };
+        let mut bmp = Self::import(Some(image), Some(path), Some(metadata))?;
+        bmp.dpi = read_bmp_dpi(&header);
+        Ok(Some((bmp, size)))
+    }
 
-        let image = image::load_from_memory(&image_buf).map_err(|e| RusimgError::FailedToOpenImage(e.to_string()))?;
+    // Save the image to a file, checking the token as save_cancellable() does.
+    // Unless ``overwrite`` is set, an existing file is not replaced and a ``FileAlreadyExists`` error is returned.
+    fn save_to_file(&mut self, path: Option<PathBuf>, token: &CancelToken, overwrite: bool) -> Result<(), RusimgError> {
+        let save_path = Self::get_save_filepath(&self, &self.filepath_input, path, &"bmp".to_string())?;
+        let data = self.encode_to_memory_cancellable(token)?;
+        token.check()?;
+        super::write_atomically(&save_path, overwrite, |file| {
+            file.write_all(&data).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+        })?;
+        self.metadata_output = Some(std::fs::metadata(&save_path).map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
+        self.filepath_output = Some(save_path);
+
+        Ok(())
+    }
+}
+
+impl BackendTrait for BmpImage {
+    /// Import an image from a DynamicImage object.
+    fn import(image: Option<DynamicImage>, source_path: Option<PathBuf>, source_metadata: Option<Metadata>) -> Result<Self, RusimgError> {
+        let image = image.ok_or(RusimgError::ImageNotSpecified)?;
+        super::check_image_buffer(&image)?;
         let size = ImgSize { width: image.width() as usize, height: image.height() as usize };
 
         Ok(Self {
             image,
             size,
-            metadata_input: Some(metadata),
+            dpi: None,
+            rle: false,
+            config: RusImgConfig::default(),
+            metadata_input: source_metadata,
             metadata_output: None,
-            filepath_input: path,
+            filepath_input: source_path,
             filepath_output: None,
         })
     }
 
+    /// Open an image from a image buffer.
+    /// The path and metadata of the source file are optional, e.g. for images decoded from bytes in memory.
+    fn open(path: Option<PathBuf>, image_buf: Option<Vec<u8>>, metadata: Option<Metadata>) -> Result<Self, RusimgError> {
+        let image_buf = image_buf.ok_or(RusimgError::ImageNotSpecified)?; // If the image buffer is not specified, return an error.
+        Self::open_from_slice(path, &image_buf, metadata)
+    }
+
     /// Save the image to a file.
     fn save(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError> {
-        let save_path = Self::get_save_filepath(&self, &self.filepath_input, path, &"bmp".to_string())?;
-        self.image.save(&save_path).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))?;
-        self.metadata_output = Some(std::fs::metadata(&save_path).map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
-        self.filepath_output = Some(save_path);
+        self.save_cancellable(path, &CancelToken::new())
+    }
 
-        Ok(())
+    /// Save the image to a file, checking the token before encoding and before writing the file.
+    fn save_cancellable(&mut self, path: Option<PathBuf>, token: &CancelToken) -> Result<(), RusimgError> {
+        self.save_to_file(path, token, true)
+    }
+
+    /// Save the image to a file, returning a ``FileAlreadyExists`` error instead of replacing an existing file.
+    fn save_no_overwrite(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError> {
+        self.save_to_file(path, &CancelToken::new(), false)
+    }
+
+    /// Encode the image in memory.
+    fn encode_to_memory(&self) -> Result<Vec<u8>, RusimgError> {
+        // Images that cannot be indexed are saved uncompressed even if RLE is enabled.
+        if let Some(rle_data) = self.rle.then(|| rle::encode_rle8(&self.image, self.dpi)).flatten() {
+            return Ok(rle_data);
+        }
+        let mut data = Vec::new();
+        self.image.write_to(&mut Cursor::new(&mut data), image::ImageFormat::Bmp).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))?;
+        Ok(data)
     }
 
     /// Compressing a BMP image is not supported because BMP is a lossless format.
@@ -66,10 +146,15 @@ impl BackendTrait for BmpImage {
 
     /// Resize the image.
     fn resize(&mut self, resize_ratio: f32) -> Result<ImgSize, RusimgError> {
-        let nwidth = (self.size.width as f32 * (resize_ratio as f32 / 100.0)) as usize;
-        let nheight = (self.size.height as f32 * (resize_ratio as f32 / 100.0)) as usize;
-        
-        self.image = self.image.resize(nwidth as u32, nheight as u32, image::imageops::FilterType::Lanczos3);
+        self.resize_xy(resize_ratio, resize_ratio)
+    }
+
+    /// Resize the image with different horizontal and vertical ratios.
+    fn resize_xy(&mut self, x_ratio: f32, y_ratio: f32) -> Result<ImgSize, RusimgError> {
+        let nwidth = (self.size.width as f32 * (x_ratio / 100.0)) as usize;
+        let nheight = (self.size.height as f32 * (y_ratio / 100.0)) as usize;
+
+        self.image = self.image.resize_exact(nwidth as u32, nheight as u32, self.config.resize_filter.filter_type());
 
         self.size.width = nwidth;
         self.size.height = nheight;
@@ -92,6 +177,10 @@ impl BackendTrait for BmpImage {
                 return Err(RusimgError::InvalidTrimXY);
             }
         }
+        // A zero width or height would produce an empty image.
+        if w == 0 || h == 0 {
+            return Err(RusimgError::InvalidTrimXY);
+        }
 
         self.image = self.image.crop(trim.x, trim.y, w, h);
 
@@ -101,22 +190,40 @@ impl BackendTrait for BmpImage {
         Ok(self.size)
     }
 
-    /// Convert the image to grayscale.
+    /// Convert the image to grayscale, keeping the bit depth of 16-bit sources.
+    /// 8-bit RGB images are converted with ``for_each_pixel()``, so the parallel feature applies.
     fn grayscale(&mut self) {
-        self.image = self.image.grayscale();
+        self.image = super::super::operations::grayscale(&self.image, true);
     }
 
     /// Set the image to a DynamicImage object.
     fn set_dynamic_image(&mut self, image: DynamicImage) -> Result<(), RusimgError> {
+        self.size = ImgSize { width: image.width() as usize, height: image.height() as usize };
         self.image = image;
         Ok(())
     }
     
     /// Get the DynamicImage object.
-    fn get_dynamic_image(&mut self) -> Result<DynamicImage, RusimgError> {
+    fn get_dynamic_image(&self) -> Result<DynamicImage, RusimgError> {
         Ok(self.image.clone())
     }
 
+    /// Set whether to save the image with RLE8 compression.
+    fn set_bmp_rle(&mut self, on: bool) -> Result<(), RusimgError> {
+        self.rle = on;
+        Ok(())
+    }
+
+    /// BMP has no lossless compression worth optimizing, so converting to PNG is suggested.
+    fn optimize(&mut self) -> Result<(), RusimgError> {
+        Err(RusimgError::CannotOptimize("BMP images cannot be optimized; convert them to PNG for lossless compression".to_string()))
+    }
+
+    /// Get the resolution read from the info header.
+    fn dpi(&self) -> Option<(f64, f64)> {
+        self.dpi
+    }
+
     /// Get the source file path.
     fn get_source_filepath(&self) -> Option<PathBuf> {
         self.filepath_input.clone()
@@ -137,6 +244,16 @@ impl BackendTrait for BmpImage {
         self.metadata_output.clone()
     }
 
+    /// Check whether the format is lossy. BMP is uncompressed, so it is lossless.
+    fn source_is_lossy(&self) -> Option<bool> {
+        Some(false)
+    }
+
+    /// Set the default settings of the operations.
+    fn set_config(&mut self, config: &RusImgConfig) {
+        self.config = config.clone();
+    }
+
     /// Get the image size.
     fn get_size(&self) -> Result<ImgSize, RusimgError> {
         Ok(self.size)
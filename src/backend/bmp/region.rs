@@ -0,0 +1,48 @@
+// Region decoder for BMP images.
+// Uncompressed 24-bit BMP files store the rows at fixed offsets, so only the part of each row in the region is read from the file.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use image::{DynamicImage, ImageBuffer};
+
+use super::super::super::{ImgSize, Rect, RusimgError};
+
+const HEADER_SIZE: usize = 54;     // file header (14 bytes) + BITMAPINFOHEADER (40 bytes)
+const BI_RGB: u32 = 0;
+
+/// Read the pixels of a region of an uncompressed 24-bit BMP file, seeking to each row of the region.
+/// Returns None for the other kinds of BMP files (palette, 32-bit, compressed, etc.).
+/// Also returns the header (for the resolution) and the size of the whole image.
+pub fn decode_region(file: &mut File, rect: &Rect) -> Result<Option<(DynamicImage, [u8; HEADER_SIZE], ImgSize)>, RusimgError> {
+    let mut header = [0u8; HEADER_SIZE];
+    if file.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    let u32_at = |pos: usize| u32::from_le_bytes([header[pos], header[pos + 1], header[pos + 2], header[pos + 3]]);
+    let (offset, header_size, compression) = (u32_at(10), u32_at(14), u32_at(30));
+    let (width, height) = (u32_at(18) as i32, u32_at(22) as i32);
+    let bits_per_pixel = u16::from_le_bytes([header[28], header[29]]);
+    if header_size < 40 || bits_per_pixel != 24 || compression != BI_RGB || width <= 0 || height == 0 || height == i32::MIN {
+        return Ok(None);
+    }
+    let size = ImgSize::new(width as usize, height.unsigned_abs() as usize);
+    super::super::check_region(size, rect)?;
+
+    // Each row is padded to a multiple of 4 bytes.
+    let stride = (width as u64 * 3 + 3) & !3;
+    let row_bytes = rect.w as usize * 3;
+    let mut pixels = vec![0u8; row_bytes * rect.h as usize];
+    for (i, row) in pixels.chunks_exact_mut(row_bytes).enumerate() {
+        let y = rect.y as u64 + i as u64;
+        // The rows are stored bottom-up, unless the height is negative.
+        let file_row = if height > 0 { size.height as u64 - 1 - y } else { y };
+        file.seek(SeekFrom::Start(offset as u64 + file_row * stride + rect.x as u64 * 3)).map_err(|e| RusimgError::FailedToReadFile(e.to_string()))?;
+        file.read_exact(row).map_err(|e| RusimgError::FailedToReadFile(e.to_string()))?;
+        // BGR to RGB
+        for pixel in row.chunks_exact_mut(3) {
+            pixel.swap(0, 2);
+        }
+    }
+    let image = ImageBuffer::from_raw(rect.w, rect.h, pixels).map(DynamicImage::ImageRgb8);
+    Ok(image.map(|image| (image, header, size)))
+}
@@ -0,0 +1,115 @@
+// BMP RLE8 encoder.
+// The image crate decodes RLE-compressed BMP files but only writes uncompressed ones, so RLE8 files are written here directly.
+
+use image::DynamicImage;
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+const BI_RLE8: u32 = 1;
+
+/// Build a palette of the image colors and the palette index of each pixel.
+/// Returns None if the image has more than 256 colors or transparent pixels.
+fn to_indexed(image: &DynamicImage) -> Option<(Vec<[u8; 3]>, Vec<u8>)> {
+    let rgba = image.to_rgba8();
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut indices = Vec::with_capacity(rgba.pixels().len());
+    for pixel in rgba.pixels() {
+        if pixel[3] != 255 {
+            return None;
+        }
+        let color = [pixel[0], pixel[1], pixel[2]];
+        let index = match palette.iter().position(|c| *c == color) {
+            Some(index) => index,
+            None => {
+                if palette.len() == 256 {
+                    return None;
+                }
+                palette.push(color);
+                palette.len() - 1
+            },
+        };
+        indices.push(index as u8);
+    }
+    Some((palette, indices))
+}
+
+// Encode a row of palette indices.
+// Runs of the same index use the encoded mode (count, index), and sequences of 3 or more differing indices use the absolute mode.
+fn encode_row(row: &[u8], output: &mut Vec<u8>) {
+    let run_length = |start: usize| -> usize {
+        row[start..].iter().take(255).take_while(|&&v| v == row[start]).count()
+    };
+    let mut pos = 0;
+    while pos < row.len() {
+        let run = run_length(pos);
+        if run >= 2 {
+            output.extend_from_slice(&[run as u8, row[pos]]);
+            pos += run;
+            continue;
+        }
+        // Collect the differing indices up to the next run.
+        let mut end = pos + 1;
+        while end < row.len() && end - pos < 255 && run_length(end) < 2 {
+            end += 1;
+        }
+        let literal = &row[pos..end];
+        if literal.len() >= 3 {
+            output.extend_from_slice(&[0, literal.len() as u8]);
+            output.extend_from_slice(literal);
+            if literal.len() & 1 == 1 {
+                output.push(0);     // absolute runs are padded to a 16-bit boundary
+            }
+        }
+        else {
+            for index in literal {
+                output.extend_from_slice(&[1, *index]);
+            }
+        }
+        pos = end;
+    }
+    output.extend_from_slice(&[0, 0]);     // end of line
+}
+
+/// Encode an image as an RLE8-compressed 8-bit indexed BMP file.
+/// ``dpi`` is written to the info header if specified.
+/// Returns None if the image cannot be indexed (more than 256 colors or transparent pixels).
+pub fn encode_rle8(image: &DynamicImage, dpi: Option<(f64, f64)>) -> Option<Vec<u8>> {
+    let (palette, indices) = to_indexed(image)?;
+    let (width, height) = (image.width() as usize, image.height() as usize);
+
+    // BMP rows are stored from the bottom to the top.
+    let mut pixel_data = Vec::new();
+    for row in indices.chunks_exact(width.max(1)).rev() {
+        encode_row(row, &mut pixel_data);
+    }
+    pixel_data.extend_from_slice(&[0, 1]);     // end of bitmap
+
+    let pixel_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + palette.len() * 4;
+    let file_size = pixel_offset + pixel_data.len();
+    let (x_ppm, y_ppm) = dpi.map(|(x, y)| ((x / 0.0254).round() as i32, (y / 0.0254).round() as i32)).unwrap_or((0, 0));
+
+    let mut output = Vec::with_capacity(file_size);
+    // BITMAPFILEHEADER
+    output.extend_from_slice(b"BM");
+    output.extend_from_slice(&(file_size as u32).to_le_bytes());
+    output.extend_from_slice(&0u32.to_le_bytes());
+    output.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+    // BITMAPINFOHEADER
+    output.extend_from_slice(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+    output.extend_from_slice(&(width as i32).to_le_bytes());
+    output.extend_from_slice(&(height as i32).to_le_bytes());
+    output.extend_from_slice(&1u16.to_le_bytes());      // planes
+    output.extend_from_slice(&8u16.to_le_bytes());      // bits per pixel
+    output.extend_from_slice(&BI_RLE8.to_le_bytes());
+    output.extend_from_slice(&(pixel_data.len() as u32).to_le_bytes());
+    output.extend_from_slice(&x_ppm.to_le_bytes());
+    output.extend_from_slice(&y_ppm.to_le_bytes());
+    output.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+    output.extend_from_slice(&0u32.to_le_bytes());
+    // Palette (BGR0)
+    for [r, g, b] in palette {
+        output.extend_from_slice(&[b, g, r, 0]);
+    }
+    output.extend_from_slice(&pixel_data);
+    Some(output)
+}
@@ -0,0 +1,113 @@
+// EXIF (TIFF structure) helpers.
+// The image crate only interprets the orientation tag of the EXIF data, so the other tags are read here directly.
+
+const GPS_IFD_POINTER: u16 = 0x8825;
+const GPS_LATITUDE_REF: u16 = 1;
+const GPS_LATITUDE: u16 = 2;
+const GPS_LONGITUDE_REF: u16 = 3;
+const GPS_LONGITUDE: u16 = 4;
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+
+// Byte order reader of a TIFF structure.
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..4)? {
+            [0x49, 0x49, 42, 0] => true,
+            [0x4d, 0x4d, 0, 42] => false,
+            _ => return None,
+        };
+        Some(Self { data, little_endian })
+    }
+
+    fn u16_at(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    // Find an entry of the IFD at the offset and return the offset of its entry.
+    fn find_entry(&self, ifd_offset: usize, tag: u16) -> Option<usize> {
+        let count = self.u16_at(ifd_offset)? as usize;
+        (0..count).map(|i| ifd_offset + 2 + i * 12).find(|&entry| self.u16_at(entry) == Some(tag))
+    }
+
+    // Read the value of a SHORT or LONG entry.
+    fn integer(&self, ifd_offset: usize, tag: u16) -> Option<u32> {
+        let entry = self.find_entry(ifd_offset, tag)?;
+        match self.u16_at(entry + 2)? {
+            3 => self.u16_at(entry + 8).map(|v| v as u32),
+            4 => self.u32_at(entry + 8),
+            _ => None,
+        }
+    }
+
+    // Read the first character of an ASCII entry (e.g. 'N' or 'S').
+    fn ascii_char(&self, ifd_offset: usize, tag: u16) -> Option<u8> {
+        let entry = self.find_entry(ifd_offset, tag)?;
+        self.data.get(entry + 8).copied()
+    }
+
+    // Read an entry of 3 RATIONAL values (degrees, minutes, seconds) as decimal degrees.
+    fn degrees(&self, ifd_offset: usize, tag: u16) -> Option<f64> {
+        let entry = self.find_entry(ifd_offset, tag)?;
+        if self.u16_at(entry + 2)? != 5 || self.u32_at(entry + 4)? != 3 {
+            return None;
+        }
+        let values = self.u32_at(entry + 8)? as usize;
+        let rational = |i: usize| -> Option<f64> {
+            let denominator = self.u32_at(values + i * 8 + 4)?;
+            if denominator == 0 {
+                return None;
+            }
+            Some(self.u32_at(values + i * 8)? as f64 / denominator as f64)
+        };
+        Some(rational(0)? + rational(1)? / 60.0 + rational(2)? / 3600.0)
+    }
+}
+
+/// Read the GPS latitude and longitude from EXIF data (a TIFF structure) in decimal degrees.
+/// South latitudes and west longitudes are negative.
+/// Returns None if the data has no GPS coordinates.
+pub fn read_gps(data: &[u8]) -> Option<(f64, f64)> {
+    let tiff = Tiff::new(data)?;
+    let ifd0 = tiff.u32_at(4)? as usize;
+    let gps_ifd = tiff.u32_at(tiff.find_entry(ifd0, GPS_IFD_POINTER)? + 8)? as usize;
+
+    let mut latitude = tiff.degrees(gps_ifd, GPS_LATITUDE)?;
+    let mut longitude = tiff.degrees(gps_ifd, GPS_LONGITUDE)?;
+    if tiff.ascii_char(gps_ifd, GPS_LATITUDE_REF) == Some(b'S') {
+        latitude = -latitude;
+    }
+    if tiff.ascii_char(gps_ifd, GPS_LONGITUDE_REF) == Some(b'W') {
+        longitude = -longitude;
+    }
+    Some((latitude, longitude))
+}
+
+/// Read the embedded JPEG thumbnail from EXIF data (a TIFF structure).
+/// The thumbnail is stored in IFD1, which follows IFD0.
+/// Returns None if the data has no thumbnail or it is not a JPEG stream.
+pub fn read_thumbnail(data: &[u8]) -> Option<Vec<u8>> {
+    let tiff = Tiff::new(data)?;
+    let ifd0 = tiff.u32_at(4)? as usize;
+    let ifd1 = tiff.u32_at(ifd0 + 2 + tiff.u16_at(ifd0)? as usize * 12)? as usize;
+    if ifd1 == 0 {
+        return None;
+    }
+    let offset = tiff.integer(ifd1, JPEG_INTERCHANGE_FORMAT)? as usize;
+    let length = tiff.integer(ifd1, JPEG_INTERCHANGE_FORMAT_LENGTH)? as usize;
+    let thumbnail = data.get(offset..offset.checked_add(length)?)?;
+    if !thumbnail.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    Some(thumbnail.to_vec())
+}
@@ -1,36 +1,99 @@
+mod segment;
+
 use jpeg_encoder::{Encoder, ColorType};
 use image::DynamicImage;
 
 use std::fs::Metadata;
+use std::io::Write;
 use std::path::PathBuf;
 
-use super::super::{BackendTrait, RusimgError, ImgSize, Rect};
+use super::super::{BackendTrait, RusimgError, ImgSize, Rect, RusImgConfig, CancelToken};
 
 #[derive(Debug, Clone)]
 pub struct JpegImage {
     pub image: DynamicImage,
+    source_data: Option<Vec<u8>>,
     size: ImgSize,
     operations_count: u32,
     extension_str: String,
     required_quality: Option<f32>,
+    dpi: Option<(f64, f64)>,
+    orientation: Option<u8>,
+    gps: Option<(f64, f64)>,
+    thumbnail: Option<Vec<u8>>,
+    xmp: Option<String>,
+    config: RusImgConfig,
     pub metadata_input: Option<Metadata>,
     pub metadata_output: Option<Metadata>,
     pub filepath_input: Option<PathBuf>,
     pub filepath_output: Option<PathBuf>,
 }
 
+// Convert a quality value (0.0 - 100.0) to the jpeg_encoder quality (1 - 100).
+// The value is rounded instead of truncated, so that e.g. 99.9 is mapped to the maximum quality 100.
+fn encoder_quality(quality: f32) -> u8 {
+    quality.round().clamp(1.0, 100.0) as u8
+}
+
+// Read the resolution from the JFIF APP0 segment in dots per inch.
+// Returns None if there is no JFIF segment or it only specifies the aspect ratio.
+fn read_jfif_dpi(data: &[u8]) -> Option<(f64, f64)> {
+    if data.get(0..4)? != [0xFF, 0xD8, 0xFF, 0xE0] || data.get(6..11)? != b"JFIF\0" {
+        return None;
+    }
+    let x = u16::from_be_bytes(data.get(14..16)?.try_into().ok()?) as f64;
+    let y = u16::from_be_bytes(data.get(16..18)?.try_into().ok()?) as f64;
+    match data.get(13)? {
+        1 => Some((x, y)),                  // dots per inch
+        2 => Some((x * 2.54, y * 2.54)),    // dots per cm
+        _ => None,
+    }
+}
+
+impl JpegImage {
+    // Write the XMP packet to the APP1 segment of an encoded JPEG stream.
+    fn write_xmp(&self, data: &[u8], xmp: &str) -> Result<Vec<u8>, RusimgError> {
+        segment::write_xmp(data, xmp.as_bytes()).ok_or(RusimgError::FailedToSaveImage("failed to write the XMP segment".to_string()))
+    }
+
+    // Save the image to a file, checking the token as save_cancellable() does.
+    // Unless ``overwrite`` is set, an existing file is not replaced and a ``FileAlreadyExists`` error is returned.
+    fn save_to_file(&mut self, path: Option<PathBuf>, token: &CancelToken, overwrite: bool) -> Result<(), RusimgError> {
+        let save_path = Self::get_save_filepath(&self, &self.filepath_input, path, &self.extension_str)?;
+
+        let data = self.encode_to_memory_cancellable(token)?;
+        token.check()?;
+        super::write_atomically(&save_path, overwrite, |file| {
+            file.write_all(&data).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+        })?;
+        self.metadata_output = Some(std::fs::metadata(&save_path).map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
+
+        self.filepath_output = Some(save_path);
+
+        Ok(())
+    }
+}
+
 impl BackendTrait for JpegImage {
     /// Import an image from a DynamicImage object.
     fn import(image: Option<DynamicImage>, source_path: Option<PathBuf>, source_metadata: Option<Metadata>) -> Result<Self, RusimgError> {
         let image = image.ok_or(RusimgError::ImageNotSpecified)?;
+        super::check_image_buffer(&image)?;
         let size = ImgSize { width: image.width() as usize, height: image.height() as usize };
 
         Ok(Self {
             image,
+            source_data: None,
             size,
             operations_count: 0,
             extension_str: "jpg".to_string(),
             required_quality: None,
+            dpi: None,
+            orientation: None,
+            gps: None,
+            thumbnail: None,
+            xmp: None,
+            config: RusImgConfig::default(),
             metadata_input: source_metadata,
             metadata_output: None,
             filepath_input: source_path,
@@ -39,46 +102,87 @@ impl BackendTrait for JpegImage {
     }
 
     /// Open an image from a image buffer.
+    /// The path and metadata of the source file are optional, e.g. for images decoded from bytes in memory.
     fn open(path: Option<PathBuf>, image_buf: Option<Vec<u8>>, metadata: Option<Metadata>) -> Result<Self, RusimgError> {
-        let path = path.ok_or(RusimgError::ImageNotSpecified)?; // If the image path is not specified, return an error.
         let image_buf = image_buf.ok_or(RusimgError::ImageNotSpecified)?; // If the image buffer is not specified, return an error.
-        let metadata = metadata.ok_or(RusimgError::ImageNotSpecified)?; // If the metadata is not specified, return an error.
         
         let image = image::load_from_memory(&image_buf).map_err(|e| RusimgError::FailedToOpenImage(e.to_string()))?;
         let size = ImgSize { width: image.width() as usize, height: image.height() as usize };
 
-        let extension_str = path.extension().and_then(|s| s.to_str()).unwrap_or("").to_string();
+        let extension_str = match &path {
+            Some(path) => path.extension().and_then(|s| s.to_str()).unwrap_or("").to_string(),
+            None => "jpg".to_string(),
+        };
+        let dpi = read_jfif_dpi(&image_buf);
+        let orientation = super::read_exif_orientation(&image_buf);
+        let gps = super::read_exif_gps(&image_buf);
+        let thumbnail = super::read_exif_thumbnail(&image_buf);
+        let xmp = segment::read_xmp(&image_buf).and_then(|xmp| String::from_utf8(xmp.to_vec()).ok());
         
         Ok(Self {
             image,
+            source_data: Some(image_buf),
             size,
             operations_count: 0,
             extension_str,
             required_quality: None,
-            metadata_input: Some(metadata),
+            dpi,
+            orientation,
+            gps,
+            thumbnail,
+            xmp,
+            config: RusImgConfig::default(),
+            metadata_input: metadata,
             metadata_output: None,
-            filepath_input: Some(path),
+            filepath_input: path,
             filepath_output: None,
         })
     }
 
     /// Save the image to a file.
     fn save(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError> {
-        let save_path = Self::get_save_filepath(&self, &self.filepath_input, path, &self.extension_str)?;
+        self.save_cancellable(path, &CancelToken::new())
+    }
+
+    /// Save the image to a file, checking the token before encoding and before writing the file.
+    fn save_cancellable(&mut self, path: Option<PathBuf>, token: &CancelToken) -> Result<(), RusimgError> {
+        self.save_to_file(path, token, true)
+    }
+
+    /// Save the image to a file, returning a ``FileAlreadyExists`` error instead of replacing an existing file.
+    fn save_no_overwrite(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError> {
+        self.save_to_file(path, &CancelToken::new(), false)
+    }
+
+    /// Encode the image in memory.
+    fn encode_to_memory(&self) -> Result<Vec<u8>, RusimgError> {
+        // If the image is not modified, use the source file as it is instead of re-encoding it (which would degrade it).
+        // The XMP segment is rewritten only if it has been changed by set_xmp().
+        if let (0, Some(source_data)) = (self.operations_count, &self.source_data) {
+            return match &self.xmp {
+                Some(xmp) if segment::read_xmp(source_data) != Some(xmp.as_bytes()) => self.write_xmp(source_data, xmp),
+                _ => Ok(source_data.clone()),
+            };
+        }
 
-        // If compression is not specified, set the default quality to 75.0
+        // If compression is not specified, use the default quality of the config
         let quality = if let Some(quality) = self.required_quality {
             quality
         } else {
-            100.0
+            self.config.jpeg_quality
         };
-        let encoder = Encoder::new_file(&save_path, quality as u8).map_err(|e| RusimgError::FailedToCreateFile(e.to_string()))?;
-        encoder.encode(&self.image.to_rgb8(), self.size.width as u16, self.size.height as u16, ColorType::Rgb).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))?;
-        self.metadata_output = Some(std::fs::metadata(&save_path).map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
-
-        self.filepath_output = Some(save_path);
-
-        Ok(())
+        // JPEG has no alpha channel, so grayscale images (with or without alpha) are encoded as grayscale and the others as RGB.
+        let (data, color_type) = match self.image.color() {
+            image::ColorType::L8 | image::ColorType::La8 | image::ColorType::L16 | image::ColorType::La16 => (self.image.to_luma8().into_raw(), ColorType::Luma),
+            _ => (self.image.to_rgb8().into_raw(), ColorType::Rgb),
+        };
+        let mut encoded = Vec::new();
+        let encoder = Encoder::new(&mut encoded, encoder_quality(quality));
+        encoder.encode(&data, self.size.width as u16, self.size.height as u16, color_type).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))?;
+        match &self.xmp {
+            Some(xmp) => self.write_xmp(&encoded, xmp),
+            None => Ok(encoded),
+        }
     }
 
     /// Compress the image.
@@ -86,7 +190,10 @@ impl BackendTrait for JpegImage {
     /// Because the jpeg_encoder crate compresses the image when saving it, the compress() method does not need to do anything.
     /// So this method only sets the quality value.
     fn compress(&mut self, quality: Option<f32>) -> Result<(), RusimgError> {
-        let quality = quality.unwrap_or(100.0);  // default quality: 75.0
+        let quality = quality.unwrap_or(self.config.jpeg_quality);  // default quality of the config
+        if !(0.0..=100.0).contains(&quality) {
+            return Err(RusimgError::InvalidCompressionLevel);
+        }
         self.required_quality = Some(quality);
         self.operations_count += 1;
         Ok(())
@@ -94,10 +201,15 @@ impl BackendTrait for JpegImage {
 
     /// Resize the image.
     fn resize(&mut self, resize_ratio: f32) -> Result<ImgSize, RusimgError> {
-        let nwidth = (self.size.width as f32 * (resize_ratio as f32 / 100.0)) as usize;
-        let nheight = (self.size.height as f32 * (resize_ratio as f32 / 100.0)) as usize;
-        
-        self.image = self.image.resize(nwidth as u32, nheight as u32, image::imageops::FilterType::Lanczos3);
+        self.resize_xy(resize_ratio, resize_ratio)
+    }
+
+    /// Resize the image with different horizontal and vertical ratios.
+    fn resize_xy(&mut self, x_ratio: f32, y_ratio: f32) -> Result<ImgSize, RusimgError> {
+        let nwidth = (self.size.width as f32 * (x_ratio / 100.0)) as usize;
+        let nheight = (self.size.height as f32 * (y_ratio / 100.0)) as usize;
+
+        self.image = self.image.resize_exact(nwidth as u32, nheight as u32, self.config.resize_filter.filter_type());
 
         self.size.width = nwidth;
         self.size.height = nheight;
@@ -121,32 +233,65 @@ impl BackendTrait for JpegImage {
                 return Err(RusimgError::InvalidTrimXY);
             }
         }
+        // A zero width or height would produce an empty image.
+        if w == 0 || h == 0 {
+            return Err(RusimgError::InvalidTrimXY);
+        }
 
         self.image = self.image.crop(trim.x, trim.y, w, h);
 
         self.size.width = w as usize;
         self.size.height = h as usize;
 
+        self.operations_count += 1;
         Ok(self.size)
     }
 
-    /// Convert the image to grayscale.
+    /// Convert the image to grayscale, keeping the bit depth of 16-bit sources.
+    /// 8-bit RGB images are converted with ``for_each_pixel()``, so the parallel feature applies.
     fn grayscale(&mut self) {
-        self.image = self.image.grayscale();
+        self.image = super::super::operations::grayscale(&self.image, true);
         self.operations_count += 1;
     }
 
     /// Set the image to a DynamicImage object.
     fn set_dynamic_image(&mut self, image: DynamicImage) -> Result<(), RusimgError> {
+        self.size = ImgSize { width: image.width() as usize, height: image.height() as usize };
         self.image = image;
+        self.operations_count += 1;
         Ok(())
     }
 
     /// Get the DynamicImage object.
-    fn get_dynamic_image(&mut self) -> Result<DynamicImage, RusimgError> {
+    fn get_dynamic_image(&self) -> Result<DynamicImage, RusimgError> {
         Ok(self.image.clone())
     }
 
+    /// Get the resolution read from the JFIF segment.
+    fn dpi(&self) -> Option<(f64, f64)> {
+        self.dpi
+    }
+
+    /// Get the EXIF orientation read from the file.
+    fn exif_orientation(&self) -> Option<u8> {
+        self.orientation
+    }
+
+    /// Get the EXIF GPS coordinates read from the file.
+    fn gps_location(&self) -> Option<(f64, f64)> {
+        self.gps
+    }
+
+    /// Get the original encoded bytes read from the file.
+    fn source_bytes(&self) -> Option<&[u8]> {
+        self.source_data.as_deref()
+    }
+
+    /// Get the EXIF thumbnail read from the file.
+    fn embedded_thumbnail(&self) -> Option<Vec<u8>> {
+        self.thumbnail.clone()
+    }
+
     /// Get the source file path.
     fn get_source_filepath(&self) -> Option<PathBuf> {
         self.filepath_input.clone()
@@ -167,6 +312,37 @@ impl BackendTrait for JpegImage {
         self.metadata_output.clone()
     }
 
+    /// Check whether the format is lossy. JPEG is lossy.
+    fn source_is_lossy(&self) -> Option<bool> {
+        Some(true)
+    }
+
+    /// Lossless JPEG optimization rewrites the Huffman tables of the DCT coefficients (like jpegtran),
+    /// but the encoder can only encode decoded pixels, which re-quantizes them, so it is not supported.
+    fn optimize(&mut self) -> Result<(), RusimgError> {
+        Err(RusimgError::CannotOptimize("JPEG images cannot be optimized without re-quantizing them".to_string()))
+    }
+
+    /// Get the XMP packet read from the APP1 segment or set by ``set_xmp()``.
+    fn get_xmp(&self) -> Option<String> {
+        self.xmp.clone()
+    }
+
+    /// Set the XMP packet written to the APP1 segment when saving.
+    /// A packet larger than a segment (about 64 KB) returns ``MetadataTooLarge``.
+    fn set_xmp(&mut self, xmp: &str) -> Result<(), RusimgError> {
+        if xmp.len() > segment::MAX_XMP_LEN {
+            return Err(RusimgError::MetadataTooLarge);
+        }
+        self.xmp = Some(xmp.to_string());
+        Ok(())
+    }
+
+    /// Set the default settings of the operations.
+    fn set_config(&mut self, config: &RusImgConfig) {
+        self.config = config.clone();
+    }
+
     /// Get the image size.
     fn get_size(&self) -> Result<ImgSize, RusimgError> {
         Ok(self.size)
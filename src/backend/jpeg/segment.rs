@@ -0,0 +1,82 @@
+// JPEG segment helpers.
+// jpeg_encoder only writes the image data, so the metadata segments are read and written here directly.
+
+/// Namespace that starts the APP1 segment of an XMP packet.
+const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const SOS: u8 = 0xDA;
+
+/// The maximum size of an XMP packet embedded in a single APP1 segment.
+pub const MAX_XMP_LEN: usize = 0xFFFF - 2 - XMP_NAMESPACE.len();
+
+/// A segment in a JPEG stream: (marker, segment data without the length).
+pub type Segment<'a> = (u8, &'a [u8]);
+
+/// Split the header of a JPEG stream into segments, up to (but not including) the SOS segment.
+/// This also returns the offset of the SOS segment, where the image data starts.
+/// Returns None if the data is not a JPEG stream or a segment is truncated.
+pub fn read_segments(data: &[u8]) -> Option<(Vec<Segment<'_>>, usize)> {
+    if data.get(0..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut pos = 2;
+    loop {
+        if *data.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *data.get(pos + 1)?;
+        if marker == 0xFF {
+            pos += 1;       // fill byte
+            continue;
+        }
+        if marker == SOS {
+            return Some((segments, pos));
+        }
+        let length = u16::from_be_bytes(data.get(pos + 2..pos + 4)?.try_into().ok()?) as usize;
+        segments.push((marker, data.get(pos + 4..pos + 2 + length)?));
+        pos += 2 + length;  // marker + length (including the length field itself)
+    }
+}
+
+/// Read the XMP packet from the APP1 segment with the XMP namespace.
+pub fn read_xmp(data: &[u8]) -> Option<&[u8]> {
+    read_segments(data)?.0.into_iter()
+        .find(|(marker, d)| *marker == APP1 && d.starts_with(XMP_NAMESPACE))
+        .map(|(_, d)| &d[XMP_NAMESPACE.len()..])
+}
+
+/// Write an XMP packet to an APP1 segment, replacing the existing one.
+/// The new segment is placed after the leading APP0 (JFIF) and APP1 (EXIF) segments.
+/// Returns None if the data is not a JPEG stream or the packet does not fit in a segment.
+pub fn write_xmp(data: &[u8], xmp: &[u8]) -> Option<Vec<u8>> {
+    if xmp.len() > MAX_XMP_LEN {
+        return None;
+    }
+    let (segments, sos) = read_segments(data)?;
+    let mut output = vec![0xFF, 0xD8];
+    let mut inserted = false;
+    for (marker, d) in segments {
+        if marker == APP1 && d.starts_with(XMP_NAMESPACE) {
+            continue;
+        }
+        if !inserted && marker != APP0 && marker != APP1 {
+            write_segment(&mut output, APP1, &[XMP_NAMESPACE, xmp].concat());
+            inserted = true;
+        }
+        write_segment(&mut output, marker, d);
+    }
+    if !inserted {
+        write_segment(&mut output, APP1, &[XMP_NAMESPACE, xmp].concat());
+    }
+    output.extend_from_slice(&data[sos..]);
+    Some(output)
+}
+
+// Write a segment with its marker and length.
+fn write_segment(output: &mut Vec<u8>, marker: u8, segment_data: &[u8]) {
+    output.extend_from_slice(&[0xFF, marker]);
+    output.extend_from_slice(&((segment_data.len() + 2) as u16).to_be_bytes());
+    output.extend_from_slice(segment_data);
+}
@@ -0,0 +1,231 @@
+// PNG chunk helpers.
+// The image crate and oxipng do not give access to ancillary chunks, so they are read and written here directly.
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// Keyword (with the null separator) of the iTXt chunk holding an XMP packet.
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp\0";
+
+/// A chunk in a PNG stream: (chunk type, chunk data).
+pub type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Split a PNG stream into chunks.
+/// Returns None if the data is not a PNG stream or a chunk is truncated.
+pub fn read_chunks(data: &[u8]) -> Option<Vec<Chunk<'_>>> {
+    if !data.starts_with(&PNG_SIGNATURE) {
+        return None;
+    }
+    let mut chunks = Vec::new();
+    let mut pos = PNG_SIGNATURE.len();
+    while pos < data.len() {
+        let length = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let chunk_type: [u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let chunk_data = data.get(pos + 8..pos + 8 + length)?;
+        chunks.push((chunk_type, chunk_data));
+        pos += 12 + length;     // length + type + data + crc
+    }
+    Some(chunks)
+}
+
+/// Find the first chunk of the given type.
+pub fn find_chunk<'a>(data: &'a [u8], chunk_type: &[u8; 4]) -> Option<&'a [u8]> {
+    read_chunks(data)?.into_iter().find(|(t, _)| t == chunk_type).map(|(_, d)| d)
+}
+
+/// Replace all chunks of the given type with a new chunk.
+/// The new chunk is placed just before the first IDAT chunk.
+/// Returns None if the data is not a PNG stream.
+pub fn replace_chunk(data: &[u8], chunk_type: &[u8; 4], chunk_data: &[u8]) -> Option<Vec<u8>> {
+    replace_chunk_before(data, chunk_type, chunk_data, &[b"IDAT"])
+}
+
+// Replace all chunks of the given type with a new chunk placed just before the first chunk of the types in ``before``.
+// Some chunks must precede PLTE as well as IDAT (e.g. gAMA and sRGB).
+fn replace_chunk_before(data: &[u8], chunk_type: &[u8; 4], chunk_data: &[u8], before: &[&[u8; 4]]) -> Option<Vec<u8>> {
+    replace_chunks_where(data, |(t, _)| t == chunk_type, chunk_type, chunk_data, before)
+}
+
+// Remove the chunks matching ``remove`` and insert a new chunk just before the first chunk of the types in ``before``.
+// Chunk types that may appear multiple times (e.g. iTXt) are told apart by their data.
+fn replace_chunks_where<F>(data: &[u8], remove: F, chunk_type: &[u8; 4], chunk_data: &[u8], before: &[&[u8; 4]]) -> Option<Vec<u8>>
+where F: Fn(&Chunk) -> bool {
+    let chunks = read_chunks(data)?;
+    let mut output = PNG_SIGNATURE.to_vec();
+    let mut inserted = false;
+    for (t, d) in chunks {
+        if remove(&(t, d)) {
+            continue;
+        }
+        if before.contains(&&t) && !inserted {
+            write_chunk(&mut output, chunk_type, chunk_data);
+            inserted = true;
+        }
+        write_chunk(&mut output, &t, d);
+    }
+    Some(output)
+}
+
+// Write a chunk with its length and CRC.
+fn write_chunk(output: &mut Vec<u8>, chunk_type: &[u8; 4], chunk_data: &[u8]) {
+    output.extend_from_slice(&(chunk_data.len() as u32).to_be_bytes());
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(chunk_data);
+    let crc = crc32(chunk_type.iter().chain(chunk_data.iter()));
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+// CRC-32 used by PNG (ISO 3309).
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Read the bit depth and color type from the IHDR chunk.
+pub fn read_ihdr(data: &[u8]) -> Option<(u8, u8)> {
+    let ihdr = find_chunk(data, b"IHDR")?;
+    Some((*ihdr.get(8)?, *ihdr.get(9)?))
+}
+
+/// Read the background color from the bKGD chunk as RGBA.
+/// PNG has no background alpha, so the alpha value is always 255.
+pub fn read_background_color(data: &[u8]) -> Option<[u8; 4]> {
+    let (bit_depth, color_type) = read_ihdr(data)?;
+    let bkgd = find_chunk(data, b"bKGD")?;
+    // Scale a sample of the image bit depth to 8 bits.
+    let max = ((1u32 << bit_depth) - 1) as f32;
+    let to_u8 = |v: &[u8]| -> Option<u8> {
+        let v = u16::from_be_bytes(v.try_into().ok()?) as f32;
+        Some((v / max * 255.0).round() as u8)
+    };
+    match color_type {
+        // grayscale, grayscale with alpha
+        0 | 4 => {
+            let gray = to_u8(bkgd.get(0..2)?)?;
+            Some([gray, gray, gray, 255])
+        },
+        // truecolor, truecolor with alpha
+        2 | 6 => Some([to_u8(bkgd.get(0..2)?)?, to_u8(bkgd.get(2..4)?)?, to_u8(bkgd.get(4..6)?)?, 255]),
+        // indexed color
+        3 => {
+            let index = *bkgd.first()? as usize;
+            let palette = find_chunk(data, b"PLTE")?;
+            let rgb = palette.get(index * 3..index * 3 + 3)?;
+            Some([rgb[0], rgb[1], rgb[2], 255])
+        },
+        _ => None,
+    }
+}
+
+/// Write the background color to the bKGD chunk.
+/// The color is stored in the color type of the PNG stream; for indexed images the closest palette entry is used.
+/// Returns None if the data is not a PNG stream.
+pub fn write_background_color(data: &[u8], color: [u8; 4]) -> Option<Vec<u8>> {
+    let (bit_depth, color_type) = read_ihdr(data)?;
+    let max = ((1u32 << bit_depth) - 1) as f32;
+    let from_u8 = |v: u8| -> [u8; 2] { ((v as f32 / 255.0 * max).round() as u16).to_be_bytes() };
+    let bkgd = match color_type {
+        0 | 4 => {
+            let gray = (0.299 * color[0] as f32 + 0.587 * color[1] as f32 + 0.114 * color[2] as f32).round() as u8;
+            from_u8(gray).to_vec()
+        },
+        2 | 6 => [from_u8(color[0]), from_u8(color[1]), from_u8(color[2])].concat(),
+        3 => {
+            let palette = find_chunk(data, b"PLTE")?;
+            let distance = |rgb: &[u8]| -> i32 {
+                (0..3).map(|i| (rgb[i] as i32 - color[i] as i32).pow(2)).sum()
+            };
+            let index = palette.chunks_exact(3).enumerate().min_by_key(|(_, rgb)| distance(rgb))?.0;
+            vec![index as u8]
+        },
+        _ => return None,
+    };
+    replace_chunk(data, b"bKGD", &bkgd)
+}
+
+/// Read the resolution from the pHYs chunk in dots per inch.
+/// Returns None if the chunk is missing or its unit is not the meter (i.e. it only specifies the aspect ratio).
+pub fn read_dpi(data: &[u8]) -> Option<(f64, f64)> {
+    let phys = find_chunk(data, b"pHYs")?;
+    let x = u32::from_be_bytes(phys.get(0..4)?.try_into().ok()?);
+    let y = u32::from_be_bytes(phys.get(4..8)?.try_into().ok()?);
+    if *phys.get(8)? != 1 {
+        return None;
+    }
+    Some((x as f64 * 0.0254, y as f64 * 0.0254))
+}
+
+/// Read the gamma from the gAMA chunk.
+/// The chunk stores the gamma multiplied by 100000 (e.g. 45455 for 1/2.2).
+pub fn read_gamma(data: &[u8]) -> Option<f32> {
+    let gama = find_chunk(data, b"gAMA")?;
+    let gamma = u32::from_be_bytes(gama.get(0..4)?.try_into().ok()?);
+    Some(gamma as f32 / 100000.0)
+}
+
+/// Write the gamma to the gAMA chunk.
+/// Returns None if the data is not a PNG stream.
+pub fn write_gamma(data: &[u8], gamma: f32) -> Option<Vec<u8>> {
+    let gama = ((gamma as f64 * 100000.0).round() as u32).to_be_bytes();
+    replace_chunk_before(data, b"gAMA", &gama, &[b"PLTE", b"IDAT"])
+}
+
+/// Read the rendering intent from the sRGB chunk.
+pub fn read_srgb_intent(data: &[u8]) -> Option<u8> {
+    find_chunk(data, b"sRGB")?.first().copied()
+}
+
+/// Write the rendering intent to the sRGB chunk.
+/// Returns None if the data is not a PNG stream.
+pub fn write_srgb_intent(data: &[u8], intent: u8) -> Option<Vec<u8>> {
+    replace_chunk_before(data, b"sRGB", &[intent], &[b"PLTE", b"IDAT"])
+}
+
+/// Read the XMP packet from the iTXt chunk with the "XML:com.adobe.xmp" keyword.
+/// Returns None if there is no such chunk, or its text is compressed or not valid UTF-8.
+pub fn read_xmp(data: &[u8]) -> Option<String> {
+    read_chunks(data)?.into_iter()
+        .filter(|(t, _)| t == b"iTXt")
+        .find_map(|(_, itxt)| {
+            let text = itxt.strip_prefix(XMP_KEYWORD)?;
+            // compression flag, compression method, language tag and translated keyword (both null-terminated)
+            if *text.first()? != 0 {
+                return None;
+            }
+            let mut fields = text.get(2..)?.splitn(3, |b| *b == 0);
+            let (_, _, xmp) = (fields.next()?, fields.next()?, fields.next()?);
+            String::from_utf8(xmp.to_vec()).ok()
+        })
+}
+
+/// Write the XMP packet to an uncompressed iTXt chunk with the "XML:com.adobe.xmp" keyword, replacing the existing one.
+/// The other iTXt chunks are kept.
+/// Returns None if the data is not a PNG stream.
+pub fn write_xmp(data: &[u8], xmp: &str) -> Option<Vec<u8>> {
+    let itxt = [XMP_KEYWORD, &[0, 0, 0, 0], xmp.as_bytes()].concat();
+    replace_chunks_where(data, |(t, d)| t == b"iTXt" && d.starts_with(XMP_KEYWORD), b"iTXt", &itxt, &[b"IDAT"])
+}
+
+/// Read the number of frames and the number of plays from the acTL chunk (APNG).
+pub fn read_actl(data: &[u8]) -> Option<(u32, u32)> {
+    let actl = find_chunk(data, b"acTL")?;
+    let num_frames = u32::from_be_bytes(actl.get(0..4)?.try_into().ok()?);
+    let num_plays = u32::from_be_bytes(actl.get(4..8)?.try_into().ok()?);
+    Some((num_frames, num_plays))
+}
+
+/// Read the delay (numerator, denominator), dispose_op and blend_op of each fcTL chunk (APNG).
+pub fn read_fctl(data: &[u8]) -> Vec<(u16, u16, u8, u8)> {
+    read_chunks(data).unwrap_or_default().iter()
+        .filter(|(chunk_type, _)| chunk_type == b"fcTL")
+        .filter_map(|(_, fctl)| {
+            let delay_num = u16::from_be_bytes(fctl.get(20..22)?.try_into().ok()?);
+            let delay_den = u16::from_be_bytes(fctl.get(22..24)?.try_into().ok()?);
+            Some((delay_num, delay_den, *fctl.get(24)?, *fctl.get(25)?))
+        })
+        .collect()
+}
@@ -1,28 +1,117 @@
-use std::io::{Write, Cursor};
+mod chunk;
+mod region;
+
+use std::io::{Cursor, Write};
 use std::fs::Metadata;
 use std::path::PathBuf;
 use image::DynamicImage;
 
-use super::super::{BackendTrait, RusimgError, ImgSize, Rect};
+use super::super::{BackendTrait, RusimgError, ImgSize, Rect, PngOptimizeOptions, PngCompressOptions, AnimationInfo, FrameInfo, FrameDisposal, FrameBlend, RusImgConfig, CancelToken};
 
 #[derive(Debug, Clone)]
 pub struct PngImage {
     binary_data: Vec<u8>,
+    binary_data_is_source: bool,
     pub image: DynamicImage,
-    image_bytes: Option<Vec<u8>>,
+    oxipng_options: Option<oxipng::Options>,
     width: usize,
     height: usize,
     operations_count: u32,
+    background_color: Option<[u8; 4]>,
+    gamma: Option<f32>,
+    srgb_intent: Option<u8>,
+    xmp: Option<String>,
+    indexed: bool,
+    optimize_options: PngOptimizeOptions,
+    config: RusImgConfig,
     pub metadata_input: Option<Metadata>,
     pub metadata_output: Option<Metadata>,
     pub filepath_input: Option<PathBuf>,
     pub filepath_output: Option<PathBuf>,
 }
 
+// Convert an oxipng error to a RusimgError.
+fn oxipng_error(e: oxipng::PngError) -> RusimgError {
+    let message = match e {
+        oxipng::PngError::DeflatedDataTooLong(s) => format!("(oxipng) deflated data too long: {}", s),
+        oxipng::PngError::TimedOut => "(oxipng) timed out".to_string(),
+        oxipng::PngError::NotPNG => "(oxipng) not png".to_string(),
+        oxipng::PngError::APNGNotSupported => "(oxipng) apng not supported".to_string(),
+        oxipng::PngError::InvalidData => "(oxipng) invalid data".to_string(),
+        oxipng::PngError::TruncatedData => "(oxipng) truncated data".to_string(),
+        oxipng::PngError::ChunkMissing(s) => format!("(oxipng) chunk missing: {}", s),
+        oxipng::PngError::Other(s) => format!("(oxipng) other: {}", s),
+        _ => "unknown error".to_string(),
+    };
+    RusimgError::FailedToCompressImage(Some(message))
+}
+
+// Check if the image is a bilevel grayscale image, which has only black (0) and white (255) pixels.
+fn is_bilevel(image: &DynamicImage) -> bool {
+    match image {
+        DynamicImage::ImageLuma8(gray) => gray.pixels().all(|p| p[0] == 0 || p[0] == 255),
+        _ => false,
+    }
+}
+
+// Encode a bilevel grayscale image as a 1-bit PNG.
+// oxipng reduces the bit depth of the 8-bit grayscale data to 1 bit, because the image has only two values.
+fn encode_bilevel(image: &DynamicImage) -> Result<Vec<u8>, RusimgError> {
+    let gray = image.to_luma8();
+    let raw = oxipng::RawImage::new(gray.width(), gray.height(), oxipng::ColorType::Grayscale { transparent_shade: None }, oxipng::BitDepth::Eight, gray.into_raw())
+        .map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))?;
+    raw.create_optimized_png(&oxipng::Options::from_preset(1)).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+}
+
+// Encode an image with indexed colors.
+// oxipng writes a paletted PNG (with a PLTE chunk) if the image has at most 256 colors,
+// unless another color type (e.g. grayscale) is smaller.
+fn encode_indexed(image: &DynamicImage) -> Result<Vec<u8>, RusimgError> {
+    let rgba = image.to_rgba8();
+    let raw = oxipng::RawImage::new(rgba.width(), rgba.height(), oxipng::ColorType::RGBA, oxipng::BitDepth::Eight, rgba.into_raw())
+        .map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))?;
+    raw.create_optimized_png(&oxipng::Options::from_preset(2)).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+}
+
+impl PngImage {
+    /// Open only a region of a non-interlaced PNG image, decoding the rows down to the bottom of the region one by one.
+    /// The encoded bytes are read whole, but only the region is kept decoded. The ancillary chunks are read as ``open()`` does.
+    /// Returns None for interlaced images. Also returns the size of the whole image.
+    pub fn open_region(path: PathBuf, image_buf: &[u8], metadata: Metadata, rect: &Rect) -> Result<Option<(Self, ImgSize)>, RusimgError> {
+        let Some((image, size)) = region::decode_region(image_buf, rect)? else {
+            return Ok(None);
+        };
+        let mut png = Self::import(Some(image), Some(path), Some(metadata))?;
+        png.background_color = chunk::read_background_color(image_buf);
+        png.gamma = chunk::read_gamma(image_buf);
+        png.srgb_intent = chunk::read_srgb_intent(image_buf);
+        png.xmp = chunk::read_xmp(image_buf);
+        Ok(Some((png, size)))
+    }
+
+    // Save the image to a file, checking the token as save_cancellable() does.
+    // Unless ``overwrite`` is set, an existing file is not replaced and a ``FileAlreadyExists`` error is returned.
+    fn save_to_file(&mut self, path: Option<PathBuf>, token: &CancelToken, overwrite: bool) -> Result<(), RusimgError> {
+        let save_path = Self::get_save_filepath(&self, &self.filepath_input, path, &"png".to_string())?;
+        
+        let data = self.encode_to_memory_cancellable(token)?;
+        token.check()?;
+        super::write_atomically(&save_path, overwrite, |file| {
+            file.write_all(&data).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+        })?;
+        self.metadata_output = Some(std::fs::metadata(&save_path).map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
+
+        self.filepath_output = Some(save_path);
+
+        Ok(())
+    }
+}
+
 impl BackendTrait for PngImage {
     /// Import an image from a DynamicImage object.
     fn import(image: Option<DynamicImage>, source_path: Option<PathBuf>, source_metadata: Option<Metadata>) -> Result<Self, RusimgError> {
         let image = image.ok_or(RusimgError::ImageNotSpecified)?;
+        super::check_image_buffer(&image)?;
         let (width, height) = (image.width() as usize, image.height() as usize);
 
         let mut new_binary_data = Vec::new();
@@ -31,11 +120,19 @@ impl BackendTrait for PngImage {
 
         Ok(Self {
             binary_data: new_binary_data,
+            binary_data_is_source: false,
             image,
-            image_bytes: None,
+            oxipng_options: None,
             width,
             height,
             operations_count: 0,
+            background_color: None,
+            gamma: None,
+            srgb_intent: None,
+            xmp: None,
+            indexed: false,
+            optimize_options: PngOptimizeOptions::default(),
+            config: RusImgConfig::default(),
             metadata_input: source_metadata,
             metadata_output: None,
             filepath_input: source_path,
@@ -44,52 +141,106 @@ impl BackendTrait for PngImage {
     }
 
     /// Open an image from a image buffer.
+    /// The path and metadata of the source file are optional, e.g. for images decoded from bytes in memory.
     fn open(path: Option<PathBuf>, image_buf: Option<Vec<u8>>, metadata: Option<Metadata>) -> Result<Self, RusimgError> {
-        let path = path.ok_or(RusimgError::ImageNotSpecified)?; // If the image path is not specified, return an error.
         let image_buf = image_buf.ok_or(RusimgError::ImageNotSpecified)?; // If the image buffer is not specified, return an error.
-        let metadata = metadata.ok_or(RusimgError::ImageNotSpecified)?; // If the metadata is not specified, return an error.
         
         let image = image::load_from_memory(&image_buf).map_err(|e| RusimgError::FailedToOpenImage(e.to_string()))?;
         let (width, height) = (image.width() as usize, image.height() as usize);
+        let background_color = chunk::read_background_color(&image_buf);
+        let gamma = chunk::read_gamma(&image_buf);
+        let srgb_intent = chunk::read_srgb_intent(&image_buf);
+        let xmp = chunk::read_xmp(&image_buf);
 
         Ok(Self {
             binary_data: image_buf,
+            binary_data_is_source: true,
             image,
-            image_bytes: None,
+            oxipng_options: None,
             width,
             height,
             operations_count: 0,
-            metadata_input: Some(metadata),
+            background_color,
+            gamma,
+            srgb_intent,
+            xmp,
+            indexed: false,
+            optimize_options: PngOptimizeOptions::default(),
+            config: RusImgConfig::default(),
+            metadata_input: metadata,
             metadata_output: None,
-            filepath_input: Some(path),
+            filepath_input: path,
             filepath_output: None,
         })
     }
 
     /// Save the image to a file.
     fn save(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError> {
-        let save_path = Self::get_save_filepath(&self, &self.filepath_input, path, &"png".to_string())?;
-        
-        // If image_bytes == None, save DynamicImage
-        if self.image_bytes.is_none() {
-            self.image.save(&save_path).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))?;
-            self.metadata_output = Some(std::fs::metadata(&save_path).map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
+        self.save_cancellable(path, &CancelToken::new())
+    }
+
+    /// Save the image to a file, checking the token between encoding, the oxipng pass and writing the file.
+    fn save_cancellable(&mut self, path: Option<PathBuf>, token: &CancelToken) -> Result<(), RusimgError> {
+        self.save_to_file(path, token, true)
+    }
+
+    /// Save the image to a file, returning a ``FileAlreadyExists`` error instead of replacing an existing file.
+    fn save_no_overwrite(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError> {
+        self.save_to_file(path, &CancelToken::new(), false)
+    }
+
+    /// Encode the image in memory.
+    fn encode_to_memory(&self) -> Result<Vec<u8>, RusimgError> {
+        self.encode_to_memory_cancellable(&CancelToken::new())
+    }
+
+    /// Encode the image in memory, checking the token before encoding and before and after the oxipng pass.
+    fn encode_to_memory_cancellable(&self, token: &CancelToken) -> Result<Vec<u8>, RusimgError> {
+        token.check()?;
+        // If the image is not modified, use the source file as it is instead of re-encoding it;
+        // otherwise encode the DynamicImage.
+        let unmodified = self.operations_count == 0 && self.binary_data_is_source;
+        let mut data = if unmodified {
+            self.binary_data.clone()
+        }
+        else if is_bilevel(&self.image) {
+            encode_bilevel(&self.image)?
+        }
+        else if self.indexed {
+            encode_indexed(&self.image)?
         }
-        // If image_bytes != None, save the compressed binary data with oxipng
         else {
-            let mut file = std::fs::File::create(&save_path).map_err(|e| RusimgError::FailedToCreateFile(e.to_string()))?;
-            file.write_all(&self.image_bytes.as_ref().unwrap()).map_err(|e| RusimgError::FailedToWriteFIle(e.to_string()))?;
-            self.metadata_output = Some(file.metadata().map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
+            let mut buf = Vec::new();
+            self.image.write_to(&mut Cursor::new(&mut buf), image::ImageFormat::Png).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))?;
+            buf
+        };
+        // Compress with oxipng if compress() has been called.
+        // This is done here so that the compression applies to the current image, not the one at the time of compress().
+        if let Some(options) = &self.oxipng_options {
+            token.check()?;
+            data = oxipng::optimize_from_memory(&data, options).map_err(oxipng_error)?;
+            token.check()?;
         }
-
-        self.filepath_output = Some(save_path);
-
-        Ok(())
+        // Add the ancillary chunks
+        if let Some(color) = self.background_color {
+            data = chunk::write_background_color(&data, color).ok_or(RusimgError::FailedToSaveImage("failed to write the bKGD chunk".to_string()))?;
+        }
+        if let Some(gamma) = self.gamma {
+            data = chunk::write_gamma(&data, gamma).ok_or(RusimgError::FailedToSaveImage("failed to write the gAMA chunk".to_string()))?;
+        }
+        if let Some(intent) = self.srgb_intent {
+            data = chunk::write_srgb_intent(&data, intent).ok_or(RusimgError::FailedToSaveImage("failed to write the sRGB chunk".to_string()))?;
+        }
+        if let Some(xmp) = &self.xmp {
+            data = chunk::write_xmp(&data, xmp).ok_or(RusimgError::FailedToSaveImage("failed to write the iTXt chunk".to_string()))?;
+        }
+        Ok(data)
     }
 
     /// Compress the image.
     /// quality: Option<f32> 0.0 - 100.0
-    /// Because oxipng supports only 6 levels of compression, the quality value is converted to a level value.
+    /// Because oxipng supports only 6 levels of compression, the quality value is converted to a level value:
+    /// 0-17 -> 1, 17-34 -> 2, 34-51 -> 3, 51-68 -> 4, 68-85 -> 5, 85-100 -> 6.
     fn compress(&mut self, quality: Option<f32>) -> Result<(), RusimgError> {
         // Set the level according to the value of quality
         let level = if let Some(q) = quality {
@@ -113,38 +264,44 @@ impl BackendTrait for PngImage {
             }
         }
         else {
-            5       // default
+            self.config.png_level.clamp(1, 6)       // default (5 by default)
         };
 
-        match oxipng::optimize_from_memory(&self.binary_data, &oxipng::Options::from_preset(level)) {
-            Ok(data) => {
-                self.image_bytes = Some(data);
-                self.operations_count += 1;
-                Ok(())
-            },
-            Err(e) => {
-                let oxipng_err = match e {
-                    oxipng::PngError::DeflatedDataTooLong(s) => Err(format!("(oxipng) deflated data too long: {}", s)),
-                    oxipng::PngError::TimedOut => Err("(oxipng) timed out".to_string()),
-                    oxipng::PngError::NotPNG => Err("(oxipng) not png".to_string()),
-                    oxipng::PngError::APNGNotSupported => Err("(oxipng) apng not supported".to_string()),
-                    oxipng::PngError::InvalidData => Err("(oxipng) invalid data".to_string()),
-                    oxipng::PngError::TruncatedData => Err("(oxipng) truncated data".to_string()),
-                    oxipng::PngError::ChunkMissing(s) => Err(format!("(oxipng) chunk missing: {}", s)),
-                    oxipng::PngError::Other(s) => Err(format!("(oxipng) other: {}", s)),
-                    _ => Err("unknown error".to_string()),
-                };
-                Err(RusimgError::FailedToCompressImage(oxipng_err.unwrap()))
-            }
+        self.compress_png_level(level)
+    }
+
+    /// Compress the image with the oxipng preset level (0 - 6) directly.
+    fn compress_png_level(&mut self, level: u8) -> Result<(), RusimgError> {
+        if level > 6 {
+            return Err(RusimgError::InvalidCompressionLevel);
         }
+        let options = PngCompressOptions::from_preset(level).with_filters(&self.optimize_options.filters);
+        self.compress_with_oxipng_options(&options)
+    }
+
+    /// Optimize the image with oxipng level 6, stripping the metadata chunks that do not affect rendering.
+    fn optimize(&mut self) -> Result<(), RusimgError> {
+        self.compress_with_oxipng_options(&PngCompressOptions::from_preset(6).strip_metadata())
+    }
+
+    /// Compress the image with prebuilt oxipng options.
+    /// The compression is applied when the image is encoded.
+    fn compress_with_oxipng_options(&mut self, options: &PngCompressOptions) -> Result<(), RusimgError> {
+        self.oxipng_options = Some(options.oxipng.clone());
+        Ok(())
     }
 
     /// Resize the image.
     fn resize(&mut self, resize_ratio: f32) -> Result<ImgSize, RusimgError> {
-        let nwidth = (self.width as f32 * (resize_ratio as f32 / 100.0)) as usize;
-        let nheight = (self.height as f32 * (resize_ratio as f32 / 100.0)) as usize;
+        self.resize_xy(resize_ratio, resize_ratio)
+    }
 
-        self.image = self.image.resize(nwidth as u32, nheight as u32, image::imageops::FilterType::Lanczos3);
+    /// Resize the image with different horizontal and vertical ratios.
+    fn resize_xy(&mut self, x_ratio: f32, y_ratio: f32) -> Result<ImgSize, RusimgError> {
+        let nwidth = (self.width as f32 * (x_ratio / 100.0)) as usize;
+        let nheight = (self.height as f32 * (y_ratio / 100.0)) as usize;
+
+        self.image = self.image.resize_exact(nwidth as u32, nheight as u32, self.config.resize_filter.filter_type());
 
         self.width = nwidth;
         self.height = nheight;
@@ -168,29 +325,37 @@ impl BackendTrait for PngImage {
                 return Err(RusimgError::InvalidTrimXY);
             }
         }
+        // A zero width or height would produce an empty image.
+        if w == 0 || h == 0 {
+            return Err(RusimgError::InvalidTrimXY);
+        }
 
         self.image = self.image.crop(trim.x, trim.y, w, h);
 
         self.width = w as usize;
         self.height = h as usize;
 
+        self.operations_count += 1;
         Ok(ImgSize::new(self.width, self.height))
     }
 
-    /// Convert the image to grayscale.
+    /// Convert the image to grayscale, keeping the bit depth of 16-bit sources.
+    /// 8-bit RGB images are converted with ``for_each_pixel()``, so the parallel feature applies.
     fn grayscale(&mut self) {
-        self.image = self.image.grayscale();
+        self.image = super::super::operations::grayscale(&self.image, true);
         self.operations_count += 1;
     }
 
     /// Set the image to a DynamicImage object.
     fn set_dynamic_image(&mut self, image: DynamicImage) -> Result<(), RusimgError> {
+        (self.width, self.height) = (image.width() as usize, image.height() as usize);
         self.image = image;
+        self.operations_count += 1;
         Ok(())
     }
 
     /// Get the DynamicImage object.
-    fn get_dynamic_image(&mut self) -> Result<DynamicImage, RusimgError> {
+    fn get_dynamic_image(&self) -> Result<DynamicImage, RusimgError> {
         Ok(self.image.clone())
     }
 
@@ -214,8 +379,119 @@ impl BackendTrait for PngImage {
         self.metadata_output.clone()
     }
 
+    /// Check whether the format is lossy. PNG is lossless.
+    fn source_is_lossy(&self) -> Option<bool> {
+        Some(false)
+    }
+
+    /// Set the default settings of the operations.
+    fn set_config(&mut self, config: &RusImgConfig) {
+        self.config = config.clone();
+    }
+
     /// Get the image size.
     fn get_size(&self) -> Result<ImgSize, RusimgError> {
         Ok(ImgSize::new(self.width, self.height))
     }
+
+    /// Set the options for optimizing the image with oxipng.
+    fn set_png_optimize_options(&mut self, options: PngOptimizeOptions) -> Result<(), RusimgError> {
+        self.optimize_options = options;
+        Ok(())
+    }
+
+    /// Get the resolution from the pHYs chunk.
+    fn dpi(&self) -> Option<(f64, f64)> {
+        self.source_bytes().and_then(chunk::read_dpi)
+    }
+
+    /// Get the original encoded bytes read from the file.
+    fn source_bytes(&self) -> Option<&[u8]> {
+        if self.binary_data_is_source {
+            Some(&self.binary_data)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Get the number of frames from the acTL chunk.
+    /// Non-animated PNG files have 1 frame.
+    fn frame_count(&self) -> Result<usize, RusimgError> {
+        let num_frames = self.source_bytes().and_then(chunk::read_actl).map(|(num_frames, _)| num_frames as usize).unwrap_or(1);
+        Ok(num_frames.max(1))
+    }
+
+    /// Get the animation information from the acTL and fcTL chunks (APNG).
+    fn animation_info(&self) -> Result<AnimationInfo, RusimgError> {
+        let source_bytes = match self.source_bytes() {
+            Some(source_bytes) => source_bytes,
+            None => return Ok(AnimationInfo::still()),
+        };
+        let num_plays = match chunk::read_actl(source_bytes) {
+            Some((_, num_plays)) => num_plays,
+            None => return Ok(AnimationInfo::still()),
+        };
+        let frames = chunk::read_fctl(source_bytes).into_iter()
+            .map(|(delay_num, delay_den, dispose_op, blend_op)| FrameInfo {
+                // A denominator of 0 means 1/100 second.
+                delay_ms: delay_num as u32 * 1000 / if delay_den == 0 { 100 } else { delay_den as u32 },
+                disposal: match dispose_op {
+                    1 => FrameDisposal::Background,
+                    2 => FrameDisposal::Previous,
+                    _ => FrameDisposal::None,
+                },
+                blend: if blend_op == 1 { FrameBlend::Over } else { FrameBlend::Source },
+            })
+            .collect();
+        Ok(AnimationInfo { loop_count: num_plays, frames })
+    }
+
+    /// Get the background color from the bKGD chunk.
+    fn get_background_color(&self) -> Option<[u8; 4]> {
+        self.background_color
+    }
+
+    /// Set the background color written to the bKGD chunk when saving.
+    /// PNG has no background alpha, so the alpha value is ignored.
+    fn set_background_color(&mut self, color: [u8; 4]) {
+        self.background_color = Some([color[0], color[1], color[2], 255]);
+    }
+
+    /// Get the gamma read from the gAMA chunk or set by ``set_png_gamma()``.
+    fn get_png_gamma(&self) -> Option<f32> {
+        self.gamma
+    }
+
+    /// Check whether the source file has an sRGB chunk.
+    fn has_srgb_chunk(&self) -> bool {
+        self.srgb_intent.is_some()
+    }
+
+    /// Set the gamma written to the gAMA chunk when saving.
+    fn set_png_gamma(&mut self, gamma: f32) -> Result<(), RusimgError> {
+        if !gamma.is_finite() || gamma <= 0.0 {
+            return Err(RusimgError::InvalidFilterParameter);
+        }
+        self.gamma = Some(gamma);
+        Ok(())
+    }
+
+    /// Get the XMP packet read from the iTXt chunk or set by ``set_xmp()``.
+    fn get_xmp(&self) -> Option<String> {
+        self.xmp.clone()
+    }
+
+    /// Set the XMP packet written to the iTXt chunk when saving.
+    fn set_xmp(&mut self, xmp: &str) -> Result<(), RusimgError> {
+        self.xmp = Some(xmp.to_string());
+        Ok(())
+    }
+
+    /// Set whether to save the image as a paletted PNG.
+    fn set_indexed(&mut self, on: bool) -> Result<(), RusimgError> {
+        self.indexed = on;
+        self.operations_count += 1;
+        Ok(())
+    }
 }
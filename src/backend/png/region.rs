@@ -0,0 +1,57 @@
+// Region decoder for PNG images.
+// The image crate decodes whole images, so the rows of a region are decoded here with the png crate, one row at a time.
+
+use std::io::Cursor;
+use image::{DynamicImage, ImageBuffer};
+
+use super::super::super::{ImgSize, Rect, RusimgError};
+
+/// Decode the rows of a non-interlaced PNG image down to the bottom of the region, keeping only the pixels in the region.
+/// Palette and low bit depth images are expanded as the image crate does, and 16-bit images stay 16-bit.
+/// Returns None for interlaced images, whose rows cannot be decoded one by one.
+/// Also returns the size of the whole image.
+pub fn decode_region(data: &[u8], rect: &Rect) -> Result<Option<(DynamicImage, ImgSize)>, RusimgError> {
+    let mut decoder = dep_png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(dep_png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|e| RusimgError::FailedToOpenImage(e.to_string()))?;
+    if reader.info().interlaced {
+        return Ok(None);
+    }
+    let size = ImgSize::new(reader.info().width as usize, reader.info().height as usize);
+    super::super::check_region(size, rect)?;
+
+    let (color_type, bit_depth) = reader.output_color_type();
+    let sixteen = bit_depth == dep_png::BitDepth::Sixteen;
+    let pixel_bytes = color_type.samples() * if sixteen { 2 } else { 1 };
+    let (start, row_bytes) = (rect.x as usize * pixel_bytes, rect.w as usize * pixel_bytes);
+    let mut pixels = Vec::with_capacity(row_bytes * rect.h as usize);
+    for y in 0..rect.y + rect.h {
+        let row = reader.next_row().map_err(|e| RusimgError::FailedToOpenImage(e.to_string()))?
+            .ok_or_else(|| RusimgError::FailedToOpenImage("image data ended before the region".to_string()))?;
+        if y >= rect.y {
+            pixels.extend_from_slice(&row.data()[start..start + row_bytes]);
+        }
+    }
+
+    let (w, h) = (rect.w, rect.h);
+    let image = if sixteen {
+        // PNG stores 16-bit samples in big-endian order.
+        let samples: Vec<u16> = pixels.chunks_exact(2).map(|s| u16::from_be_bytes([s[0], s[1]])).collect();
+        match color_type {
+            dep_png::ColorType::Grayscale => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageLuma16),
+            dep_png::ColorType::GrayscaleAlpha => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageLumaA16),
+            dep_png::ColorType::Rgb => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageRgb16),
+            dep_png::ColorType::Rgba => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageRgba16),
+            dep_png::ColorType::Indexed => None,
+        }
+    } else {
+        match color_type {
+            dep_png::ColorType::Grayscale => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLuma8),
+            dep_png::ColorType::GrayscaleAlpha => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageLumaA8),
+            dep_png::ColorType::Rgb => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageRgb8),
+            dep_png::ColorType::Rgba => ImageBuffer::from_raw(w, h, pixels).map(DynamicImage::ImageRgba8),
+            dep_png::ColorType::Indexed => None,
+        }
+    };
+    Ok(image.map(|image| (image, size)))
+}
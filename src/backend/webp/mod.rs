@@ -1,10 +1,12 @@
+mod riff;
+
 use image::{DynamicImage, EncodableLayout};
 
 use std::fs::Metadata;
 use std::io::Write;
 use std::path::{PathBuf, Path};
 
-use super::super::{BackendTrait, RusimgError, ImgSize, Rect};
+use super::super::{BackendTrait, RusimgError, ImgSize, Rect, AnimationInfo, FrameInfo, FrameDisposal, FrameBlend, RusImgConfig, CancelToken};
 
 #[derive(Debug, Clone)]
 pub struct WebpImage {
@@ -14,16 +16,96 @@ pub struct WebpImage {
     height: usize,
     operations_count: u32,
     required_quality: Option<f32>,
+    method: Option<u8>,
+    background_color: Option<[u8; 4]>,
+    exif: Option<Vec<u8>>,
+    xmp: Option<Vec<u8>>,
+    lossless: bool,
+    frame_count: usize,
+    config: RusImgConfig,
     pub metadata_input: Option<Metadata>,
     pub metadata_output: Option<Metadata>,
     pub filepath_input: Option<PathBuf>,
     pub filepath_output: Option<PathBuf>,
 }
 
+// Decode a WebP image.
+// Animated WebP images are not supported by dep_webp::Decoder, so they are decoded to their first frame.
+// If dep_webp cannot decode the data, the WebP decoder of the image crate is tried before giving up.
+fn decode_webp(image_buf: &[u8]) -> Result<DynamicImage, RusimgError> {
+    if let Some(decoded) = dep_webp::Decoder::new(image_buf).decode() {
+        return Ok(decoded.to_image());
+    }
+    if let Ok(animation) = dep_webp::AnimDecoder::new(image_buf).decode() {
+        if let Some(first_frame) = animation.get_frame(0) {
+            return Ok((&first_frame).into());
+        }
+    }
+    image::load_from_memory_with_format(image_buf, image::ImageFormat::WebP).map_err(|_| RusimgError::FailedToDecodeWebp)
+}
+
+/// Encode frames into an animated WebP.
+/// The frames must have the same size, and each frame is shown for its delay in milliseconds.
+/// A loop count of 0 means infinite looping.
+pub fn encode_animation(frames: &[DynamicImage], delays_ms: &[u32], loop_count: u16, quality: f32) -> Result<Vec<u8>, RusimgError> {
+    let (width, height) = frames.first().map(|f| (f.width(), f.height())).ok_or(RusimgError::ImageNotSpecified)?;
+
+    let mut config = dep_webp::WebPConfig::new().map_err(|_| RusimgError::FailedToEncodeWebp("failed to initialize the encoder config".to_string()))?;
+    config.quality = quality;
+
+    let rgba_frames: Vec<image::RgbaImage> = frames.iter().map(|f| f.to_rgba8()).collect();
+    let mut encoder = dep_webp::AnimEncoder::new(width, height, &config);
+    encoder.set_loop_count(loop_count as i32);
+    let mut timestamp = 0i32;
+    for (rgba_frame, delay) in rgba_frames.iter().zip(delays_ms) {
+        encoder.add_frame(dep_webp::AnimFrame::from_rgba(rgba_frame, width, height, timestamp));
+        timestamp = timestamp.saturating_add(*delay as i32);
+    }
+    let encoded = encoder.try_encode().map_err(|e| RusimgError::FailedToEncodeWebp(format!("{:?}", e)))?;
+
+    // The encoder cannot set the duration of the last frame, so write all the durations to the ANMF chunks.
+    riff::write_frame_durations(&encoded, delays_ms).ok_or(RusimgError::FailedToEncodeWebp("failed to write the frame durations".to_string()))
+}
+
+impl WebpImage {
+    // Encode the image to WebP in memory with the quality and the method set by set_webp_method().
+    fn encode(&self, quality: f32) -> Result<Vec<u8>, RusimgError> {
+        let mut config = dep_webp::WebPConfig::new().map_err(|_| RusimgError::FailedToEncodeWebp("failed to initialize the encoder config".to_string()))?;
+        config.quality = quality;
+        if let Some(method) = self.method {
+            config.method = method as i32;
+        }
+        if self.lossless {
+            config.lossless = 1;    // the lossless encoder uses the palette mode for images with at most 256 colors
+        }
+        let encoded_webp = dep_webp::Encoder::from_rgba(&self.image.to_rgba8(), self.image.width(), self.image.height()).encode_advanced(&config)
+            .map_err(|e| RusimgError::FailedToEncodeWebp(format!("{:?}", e)))?;
+        Ok(encoded_webp.as_bytes().to_vec())
+    }
+
+    // Save the image to a file, checking the token as save_cancellable() does.
+    // Unless ``overwrite`` is set, an existing file is not replaced and a ``FileAlreadyExists`` error is returned.
+    fn save_to_file(&mut self, path: Option<PathBuf>, token: &CancelToken, overwrite: bool) -> Result<(), RusimgError> {
+        let save_path = Self::get_save_filepath(&self, &self.filepath_input, path, &"webp".to_string())?;
+
+        let data = self.encode_to_memory_cancellable(token)?;
+        token.check()?;
+        super::write_atomically(&save_path, overwrite, |file| {
+            file.write_all(&data).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+        })?;
+
+        self.metadata_output = Some(std::fs::metadata(&save_path).map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
+        self.filepath_output = Some(save_path);
+
+        Ok(())
+    }
+}
+
 impl BackendTrait for WebpImage {
     /// Import an image from a DynamicImage object.
     fn import(image: Option<DynamicImage>, source_path: Option<PathBuf>, source_metadata: Option<Metadata>) -> Result<Self, RusimgError> {
         let image = image.ok_or(RusimgError::ImageNotSpecified)?;
+        super::check_image_buffer(&image)?;
         let (width, height) = (image.width() as usize, image.height() as usize);
 
         Ok(Self {
@@ -33,6 +115,13 @@ impl BackendTrait for WebpImage {
             height,
             operations_count: 0,
             required_quality: None,
+            method: None,
+            background_color: None,
+            exif: None,
+            xmp: None,
+            lossless: false,
+            frame_count: 1,
+            config: RusImgConfig::default(),
             metadata_input: source_metadata,
             metadata_output: None,
             filepath_input: source_path,
@@ -41,52 +130,74 @@ impl BackendTrait for WebpImage {
     }
 
     /// Open an image from a image buffer.
+    /// The path and metadata of the source file are optional, e.g. for images decoded from bytes in memory.
     fn open(path: Option<PathBuf>, image_buf: Option<Vec<u8>>, metadata: Option<Metadata>) -> Result<Self, RusimgError> {
-        let path = path.ok_or(RusimgError::ImageNotSpecified)?; // If the image path is not specified, return an error.
         let image_buf = image_buf.ok_or(RusimgError::ImageNotSpecified)?; // If the image buffer is not specified, return an error.
-        let metadata = metadata.ok_or(RusimgError::ImageNotSpecified)?; // If the metadata is not specified, return an error.
         
-        let webp_decoder = dep_webp::Decoder::new(&image_buf).decode();
-        if let Some(webp_decoder) = webp_decoder {
-            let image = webp_decoder.to_image();
-            let (width, height) = (image.width() as usize, image.height() as usize);
-
-            Ok(Self {
-                image,
-                image_bytes: Some(image_buf),
-                width,
-                height,
-                operations_count: 0,
-                required_quality: None,
-                metadata_input: Some(metadata),
-                metadata_output: None,
-                filepath_input: Some(path),
-                filepath_output: None,
-            })
-        }
-        else {
-            return Err(RusimgError::FailedToDecodeWebp);
-        }
+        let image = decode_webp(&image_buf)?;
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let background_color = riff::read_background_color(&image_buf);
+        let exif = riff::find_chunk(&image_buf, b"EXIF").map(|d| d.to_vec());
+        let xmp = riff::find_chunk(&image_buf, b"XMP ").map(|d| d.to_vec());
+        let frame_count = riff::frame_count(&image_buf);
+
+        Ok(Self {
+            image,
+            image_bytes: Some(image_buf),
+            width,
+            height,
+            operations_count: 0,
+            required_quality: None,
+            method: None,
+            background_color,
+            exif,
+            xmp,
+            lossless: false,
+            frame_count,
+            config: RusImgConfig::default(),
+            metadata_input: metadata,
+            metadata_output: None,
+            filepath_input: path,
+            filepath_output: None,
+        })
     }
 
     /// Save the image to a file.
     fn save(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError> {
-        let save_path = Self::get_save_filepath(&self, &self.filepath_input, path, &"webp".to_string())?;
+        self.save_cancellable(path, &CancelToken::new())
+    }
+
+    /// Save the image to a file, checking the token before encoding and before writing the file.
+    fn save_cancellable(&mut self, path: Option<PathBuf>, token: &CancelToken) -> Result<(), RusimgError> {
+        self.save_to_file(path, token, true)
+    }
 
+    /// Save the image to a file, returning a ``FileAlreadyExists`` error instead of replacing an existing file.
+    fn save_no_overwrite(&mut self, path: Option<PathBuf>) -> Result<(), RusimgError> {
+        self.save_to_file(path, &CancelToken::new(), false)
+    }
+
+    /// Encode the image in memory.
+    fn encode_to_memory(&self) -> Result<Vec<u8>, RusimgError> {
         // If the source image is webp and the number of operations is 0, do not encode it.
+        // Images opened from bytes without a path have only their webp bytes as the source.
         let source_is_webp = if let Some(filepath_input) = &self.filepath_input {
             Path::new(filepath_input).extension().and_then(|s| s.to_str()).unwrap_or("").to_string() == "webp"
         } else {
-            false
+            self.image_bytes.is_some()
         };
-        if source_is_webp && self.operations_count == 0 && self.image_bytes.is_some() {
-            let mut file = std::fs::File::create(&save_path).map_err(|e| RusimgError::FailedToCreateFile(e.to_string()))?;
-            file.write_all(self.image_bytes.as_ref().unwrap()).map_err(|e| RusimgError::FailedToWriteFIle(e.to_string()))?;
-
-            self.metadata_output = Some(file.metadata().map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
-            self.filepath_output = Some(save_path);
-
-            return Ok(());
+        if let (true, 0, Some(image_bytes)) = (source_is_webp, self.operations_count, &self.image_bytes) {
+            // Update the animation background color if the file has one.
+            let data = match self.background_color.and_then(|color| riff::write_background_color(image_bytes, color)) {
+                Some(updated) => updated,
+                None => image_bytes.clone(),
+            };
+            // Rewrite the XMP chunk if it has been changed by set_xmp().
+            if self.xmp.is_none() || riff::find_chunk(&data, b"XMP ") == self.xmp.as_deref() {
+                return Ok(data);
+            }
+            return riff::write_metadata(&data, self.image.width(), self.image.height(), self.image.color().has_alpha(), self.exif.as_deref(), self.xmp.as_deref())
+                .ok_or(RusimgError::FailedToEncodeWebp("failed to write the EXIF and XMP chunks".to_string()));
         }
 
         // quality
@@ -94,19 +205,20 @@ impl BackendTrait for WebpImage {
             q       // If the quality is specified, use it.
         }
         else {
-            75.0    // If the quality is not specified, use the default value.
+            self.config.webp_quality    // If the quality is not specified, use the default value (75.0 by default).
         };
-       
-        // Compress and save the image
-        let encoded_webp = dep_webp::Encoder::from_rgba(&self.image.to_rgba8(), self.image.width(), self.image.height()).encode(quality);
-
-        let mut file = std::fs::File::create(&save_path).map_err(|e| RusimgError::FailedToCreateFile(e.to_string()))?;
-        file.write_all(&encoded_webp.as_bytes()).map_err(|e| RusimgError::FailedToWriteFIle(e.to_string()))?;
-
-        self.metadata_output = Some(file.metadata().map_err(|e| RusimgError::FailedToGetMetadata(e.to_string()))?);
-        self.filepath_output = Some(save_path);
+        let encoded = self.encode(quality)?;
+        // Embed the EXIF and XMP metadata of the source file again.
+        if self.exif.is_none() && self.xmp.is_none() {
+            return Ok(encoded);
+        }
+        riff::write_metadata(&encoded, self.image.width(), self.image.height(), self.image.color().has_alpha(), self.exif.as_deref(), self.xmp.as_deref())
+            .ok_or(RusimgError::FailedToEncodeWebp("failed to write the EXIF and XMP chunks".to_string()))
+    }
 
-        Ok(())
+    /// Get the size of the image encoded with the quality, without saving it.
+    fn encoded_size(&self, quality: f32) -> Result<u64, RusimgError> {
+        Ok(self.encode(quality)?.len() as u64)
     }
 
     /// Compress the image.
@@ -122,10 +234,15 @@ impl BackendTrait for WebpImage {
 
     /// Resize the image.
     fn resize(&mut self, resize_ratio: f32) -> Result<ImgSize, RusimgError> {
-        let nwidth = (self.width as f32 * (resize_ratio as f32 / 100.0)) as usize;
-        let nheight = (self.height as f32 * (resize_ratio as f32 / 100.0)) as usize;
+        self.resize_xy(resize_ratio, resize_ratio)
+    }
+
+    /// Resize the image with different horizontal and vertical ratios.
+    fn resize_xy(&mut self, x_ratio: f32, y_ratio: f32) -> Result<ImgSize, RusimgError> {
+        let nwidth = (self.width as f32 * (x_ratio / 100.0)) as usize;
+        let nheight = (self.height as f32 * (y_ratio / 100.0)) as usize;
 
-        self.image = self.image.resize(nwidth as u32, nheight as u32, image::imageops::FilterType::Lanczos3);
+        self.image = self.image.resize_exact(nwidth as u32, nheight as u32, self.config.resize_filter.filter_type());
 
         self.width = nwidth;
         self.height = nheight;
@@ -148,29 +265,37 @@ impl BackendTrait for WebpImage {
                 return Err(RusimgError::InvalidTrimXY);
             }
         }
+        // A zero width or height would produce an empty image.
+        if w == 0 || h == 0 {
+            return Err(RusimgError::InvalidTrimXY);
+        }
 
         self.image = self.image.crop(trim.x, trim.y, w, h);
 
         self.width = w as usize;
         self.height = h as usize;
 
+        self.operations_count += 1;
         Ok(ImgSize::new(self.width, self.height))
     }
 
-    /// Convert the image to grayscale.
+    /// Convert the image to grayscale, keeping the bit depth of 16-bit sources.
+    /// 8-bit RGB images are converted with ``for_each_pixel()``, so the parallel feature applies.
     fn grayscale(&mut self) {
-        self.image = self.image.grayscale();
+        self.image = super::super::operations::grayscale(&self.image, true);
         self.operations_count += 1;
     }
 
     /// Set the image to a DynamicImage object.
     fn set_dynamic_image(&mut self, image: DynamicImage) -> Result<(), RusimgError> {
+        (self.width, self.height) = (image.width() as usize, image.height() as usize);
         self.image = image;
+        self.operations_count += 1;
         Ok(())
     }
 
     /// Get the DynamicImage object.
-    fn get_dynamic_image(&mut self) -> Result<DynamicImage, RusimgError> {
+    fn get_dynamic_image(&self) -> Result<DynamicImage, RusimgError> {
         Ok(self.image.clone())
     }
 
@@ -194,8 +319,97 @@ impl BackendTrait for WebpImage {
         self.metadata_output.clone()
     }
 
+    /// Set the default settings of the operations.
+    fn set_config(&mut self, config: &RusImgConfig) {
+        self.config = config.clone();
+    }
+
     /// Get the image size.
     fn get_size(&self) -> Result<ImgSize, RusimgError> {
         Ok(ImgSize::new(self.width, self.height))
     }
+
+    /// Get the original encoded bytes read from the file.
+    fn source_bytes(&self) -> Option<&[u8]> {
+        self.image_bytes.as_deref()
+    }
+
+    /// Check whether the source file is lossy WebP (VP8) or lossless WebP (VP8L).
+    fn source_is_lossy(&self) -> Option<bool> {
+        riff::is_lossy(self.image_bytes.as_deref()?)
+    }
+
+    /// Get the number of frames of the source file.
+    /// It is counted from the ANMF chunks, so the animation is not decoded.
+    fn frame_count(&self) -> Result<usize, RusimgError> {
+        Ok(self.frame_count)
+    }
+
+    /// Get the animation information from the ANIM and ANMF chunks.
+    fn animation_info(&self) -> Result<AnimationInfo, RusimgError> {
+        let image_bytes = match &self.image_bytes {
+            Some(image_bytes) => image_bytes,
+            None => return Ok(AnimationInfo::still()),
+        };
+        let frames: Vec<FrameInfo> = riff::frame_params(image_bytes).into_iter()
+            .map(|(duration, flags)| FrameInfo {
+                delay_ms: duration,
+                disposal: if flags & 0x01 != 0 { FrameDisposal::Background } else { FrameDisposal::None },
+                blend: if flags & 0x02 != 0 { FrameBlend::Source } else { FrameBlend::Over },
+            })
+            .collect();
+        match (riff::loop_count(image_bytes), frames.is_empty()) {
+            (Some(loop_count), false) => Ok(AnimationInfo { loop_count: loop_count as u32, frames }),
+            _ => Ok(AnimationInfo::still()),
+        }
+    }
+
+    /// Get the animation background color from the ANIM chunk.
+    fn get_background_color(&self) -> Option<[u8; 4]> {
+        self.background_color
+    }
+
+    /// Set the animation background color.
+    /// It is written to the ANIM chunk, so only animated WebP files keep it.
+    fn set_background_color(&mut self, color: [u8; 4]) {
+        self.background_color = Some(color);
+    }
+
+    /// Get the XMP packet read from the "XMP " chunk or set by ``set_xmp()``.
+    fn get_xmp(&self) -> Option<String> {
+        self.xmp.as_ref().and_then(|xmp| String::from_utf8(xmp.clone()).ok())
+    }
+
+    /// Set the XMP packet written to the "XMP " chunk when saving.
+    fn set_xmp(&mut self, xmp: &str) -> Result<(), RusimgError> {
+        self.xmp = Some(xmp.as_bytes().to_vec());
+        Ok(())
+    }
+
+    /// Set the encoding method used when saving.
+    /// method: 0 (fast) - 6 (slowest, best compression)
+    fn set_webp_method(&mut self, method: u8) -> Result<(), RusimgError> {
+        if method > 6 {
+            return Err(RusimgError::InvalidWebpMethod);
+        }
+        self.method = Some(method);
+        self.operations_count += 1;
+        Ok(())
+    }
+
+    /// Set whether to encode the image losslessly with the palette mode when saving.
+    fn set_indexed(&mut self, on: bool) -> Result<(), RusimgError> {
+        self.lossless = on;
+        self.operations_count += 1;
+        Ok(())
+    }
+
+    /// Encode the image losslessly with the slowest method and the highest effort when saving.
+    fn optimize(&mut self) -> Result<(), RusimgError> {
+        self.lossless = true;
+        self.method = Some(6);
+        self.required_quality = Some(100.0);    // the quality is the compression effort in the lossless mode
+        self.operations_count += 1;
+        Ok(())
+    }
 }
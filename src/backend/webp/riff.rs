@@ -0,0 +1,177 @@
+// WebP RIFF container helpers.
+// dep_webp only exposes the image data, so the container chunks are read and written here directly.
+
+/// A chunk in a WebP RIFF container: (FourCC, chunk data).
+pub type Chunk<'a> = ([u8; 4], &'a [u8]);
+
+/// Split a WebP RIFF container into chunks.
+/// Returns None if the data is not a WebP file or a chunk is truncated.
+pub fn read_chunks(data: &[u8]) -> Option<Vec<Chunk<'_>>> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return None;
+    }
+    read_chunk_sequence(&data[12..])
+}
+
+// Split a sequence of chunks (the body of the container or of an ANMF chunk).
+fn read_chunk_sequence(data: &[u8]) -> Option<Vec<Chunk<'_>>> {
+    let mut chunks = Vec::new();
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let fourcc: [u8; 4] = data[pos..pos + 4].try_into().ok()?;
+        let size = u32::from_le_bytes(data[pos + 4..pos + 8].try_into().ok()?) as usize;
+        let chunk_data = data.get(pos + 8..pos + 8 + size)?;
+        chunks.push((fourcc, chunk_data));
+        pos += 8 + size + (size & 1);   // chunks are padded to an even size
+    }
+    Some(chunks)
+}
+
+/// Find the first chunk with the given FourCC.
+pub fn find_chunk<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> Option<&'a [u8]> {
+    read_chunks(data)?.into_iter().find(|(f, _)| f == fourcc).map(|(_, d)| d)
+}
+
+/// Assemble a WebP RIFF container from chunks.
+pub fn write_chunks(chunks: &[Chunk]) -> Vec<u8> {
+    let mut body = b"WEBP".to_vec();
+    for (fourcc, chunk_data) in chunks {
+        body.extend_from_slice(fourcc);
+        body.extend_from_slice(&(chunk_data.len() as u32).to_le_bytes());
+        body.extend_from_slice(chunk_data);
+        if chunk_data.len() & 1 == 1 {
+            body.push(0);
+        }
+    }
+    let mut output = b"RIFF".to_vec();
+    output.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    output.extend_from_slice(&body);
+    output
+}
+
+/// Read the animation background color from the ANIM chunk as RGBA.
+pub fn read_background_color(data: &[u8]) -> Option<[u8; 4]> {
+    let anim = find_chunk(data, b"ANIM")?;
+    let bgra = anim.get(0..4)?;
+    Some([bgra[2], bgra[1], bgra[0], bgra[3]])
+}
+
+/// Write the animation background color to the ANIM chunk.
+/// Returns None if the data has no ANIM chunk (i.e. it is not an animated WebP).
+pub fn write_background_color(data: &[u8], color: [u8; 4]) -> Option<Vec<u8>> {
+    let chunks = read_chunks(data)?;
+    let anim = find_chunk(data, b"ANIM")?;
+    let mut new_anim = vec![color[2], color[1], color[0], color[3]];
+    new_anim.extend_from_slice(anim.get(4..)?);
+    let chunks: Vec<Chunk> = chunks.into_iter()
+        .map(|(fourcc, d)| if &fourcc == b"ANIM" { (fourcc, new_anim.as_slice()) } else { (fourcc, d) })
+        .collect();
+    Some(write_chunks(&chunks))
+}
+
+/// Embed EXIF and XMP metadata in the EXIF and "XMP " chunks of a WebP file.
+/// They need the extended format, so a VP8X chunk is added to simple files (with the canvas size and the alpha flag),
+/// and the flags of an existing one are updated. Existing EXIF and XMP chunks are replaced.
+/// Returns None if the data is not a WebP file.
+pub fn write_metadata(data: &[u8], width: u32, height: u32, has_alpha: bool, exif: Option<&[u8]>, xmp: Option<&[u8]>) -> Option<Vec<u8>> {
+    const ALPHA_FLAG: u8 = 0x10;
+    const EXIF_FLAG: u8 = 0x08;
+    const XMP_FLAG: u8 = 0x04;
+
+    let chunks = read_chunks(data)?;
+    let mut vp8x = match find_chunk(data, b"VP8X") {
+        Some(vp8x) if vp8x.len() >= 10 => vp8x.to_vec(),
+        _ => {
+            let mut vp8x = vec![if has_alpha { ALPHA_FLAG } else { 0 }, 0, 0, 0];
+            vp8x.extend_from_slice(&(width.max(1) - 1).to_le_bytes()[0..3]);
+            vp8x.extend_from_slice(&(height.max(1) - 1).to_le_bytes()[0..3]);
+            vp8x
+        },
+    };
+    vp8x[0] &= !(EXIF_FLAG | XMP_FLAG);
+    if exif.is_some() {
+        vp8x[0] |= EXIF_FLAG;
+    }
+    if xmp.is_some() {
+        vp8x[0] |= XMP_FLAG;
+    }
+
+    // VP8X comes first, and EXIF and XMP come after the image data.
+    let mut new_chunks: Vec<Chunk> = vec![(*b"VP8X", vp8x.as_slice())];
+    new_chunks.extend(chunks.into_iter().filter(|(fourcc, _)| !matches!(fourcc, b"VP8X" | b"EXIF" | b"XMP ")));
+    if let Some(exif) = exif {
+        new_chunks.push((*b"EXIF", exif));
+    }
+    if let Some(xmp) = xmp {
+        new_chunks.push((*b"XMP ", xmp));
+    }
+    Some(write_chunks(&new_chunks))
+}
+
+/// Count the animation frames (ANMF chunks).
+/// Non-animated WebP files have 1 frame.
+pub fn frame_count(data: &[u8]) -> usize {
+    let anmf_count = read_chunks(data).map(|chunks| chunks.iter().filter(|(fourcc, _)| fourcc == b"ANMF").count()).unwrap_or(0);
+    anmf_count.max(1)
+}
+
+/// Set the duration (in milliseconds) of each animation frame in the ANMF chunks.
+/// Durations are 24-bit values, so they are capped at 0xFFFFFF.
+/// Returns None if the data is not a WebP file or an ANMF chunk is truncated.
+pub fn write_frame_durations(data: &[u8], durations: &[u32]) -> Option<Vec<u8>> {
+    let chunks = read_chunks(data)?;
+    let mut new_anmf = Vec::new();
+    let mut durations = durations.iter();
+    for (fourcc, chunk_data) in &chunks {
+        if fourcc == b"ANMF" {
+            let mut anmf = chunk_data.to_vec();
+            if let Some(duration) = durations.next() {
+                anmf.get_mut(12..15)?.copy_from_slice(&duration.min(&0xFFFFFF).to_le_bytes()[0..3]);
+            }
+            new_anmf.push(anmf);
+        }
+    }
+    let mut new_anmf = new_anmf.iter();
+    let chunks: Vec<Chunk> = chunks.iter()
+        .map(|(fourcc, d)| if fourcc == b"ANMF" { (*fourcc, new_anmf.next().map(|a| a.as_slice()).unwrap_or(d)) } else { (*fourcc, *d) })
+        .collect();
+    Some(write_chunks(&chunks))
+}
+
+/// Read the loop count from the ANIM chunk.
+pub fn loop_count(data: &[u8]) -> Option<u16> {
+    let anim = find_chunk(data, b"ANIM")?;
+    Some(u16::from_le_bytes(anim.get(4..6)?.try_into().ok()?))
+}
+
+/// Read the duration (in milliseconds) and the flags byte (bit 1: no blending, bit 0: dispose to background) of each ANMF chunk.
+pub fn frame_params(data: &[u8]) -> Vec<(u32, u8)> {
+    read_chunks(data).unwrap_or_default().iter()
+        .filter(|(fourcc, _)| fourcc == b"ANMF")
+        .filter_map(|(_, anmf)| {
+            let duration = anmf.get(12..15)?;
+            Some((u32::from_le_bytes([duration[0], duration[1], duration[2], 0]), *anmf.get(15)?))
+        })
+        .collect()
+}
+
+/// Check whether the image data is lossy (VP8) or lossless (VP8L).
+/// The frames of an animated WebP are checked too, and the image is lossy if any frame is.
+/// Returns None if the data is not a WebP file or has no image data chunk.
+pub fn is_lossy(data: &[u8]) -> Option<bool> {
+    let mut lossy = None;
+    for (fourcc, chunk_data) in read_chunks(data)? {
+        let frame_chunks = match &fourcc {
+            b"ANMF" => read_chunk_sequence(chunk_data.get(16..)?)?,
+            _ => vec![(fourcc, chunk_data)],
+        };
+        for (fourcc, _) in frame_chunks {
+            match &fourcc {
+                b"VP8 " => return Some(true),
+                b"VP8L" => lossy = Some(false),
+                _ => {},
+            }
+        }
+    }
+    lossy
+}
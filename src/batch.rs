@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+
+use super::{RusImg, Extension, RusimgError, SaveStatus};
+
+/// Scan a directory recursively and return the paths of image files.
+/// Only files with a built-in image extension (bmp, jpg, jpeg, png, webp) are returned.
+/// The paths are sorted so that the result is stable.
+pub fn scan_directory(dir: &Path) -> Result<Vec<PathBuf>, RusimgError> {
+    let mut files = Vec::new();
+    let entries = std::fs::read_dir(dir).map_err(|e| RusimgError::FailedToReadDirectory(e.to_string()))?;
+    for entry in entries {
+        let path = entry.map_err(|e| RusimgError::FailedToReadDirectory(e.to_string()))?.path();
+        if path.is_dir() {
+            files.extend(scan_directory(&path)?);
+        }
+        else if Extension::from_path(&path).is_some() {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Iterate over the images in a directory.
+/// The paths are collected by ``scan_directory()``, and each image is opened only when the iterator reaches it,
+/// so only one image is held in memory at a time.
+pub fn iter_images(dir: &Path) -> Result<impl Iterator<Item = Result<RusImg, RusimgError>>, RusimgError> {
+    let files = scan_directory(dir)?;
+    Ok(files.into_iter().map(|path| RusImg::open(&path)))
+}
+
+/// Convert all images in a directory to another format.
+/// Every image found by ``scan_directory()`` in ``src`` is opened, converted to ``to`` and saved to ``dst``, preserving the relative paths.
+/// If ``quality`` is specified, the converted images are compressed with it.
+/// If ``only`` is specified, only the images whose source format is in the list are converted.
+/// This returns the result for each image.
+pub fn convert_directory(src: &Path, dst: &Path, to: &Extension, quality: Option<f32>, only: Option<&[Extension]>) -> Vec<Result<SaveStatus, RusimgError>> {
+    let files = match scan_directory(src) {
+        Ok(files) => files,
+        Err(e) => return vec![Err(e)],
+    };
+
+    files.iter()
+        .filter(|path| {
+            match (only, Extension::from_path(path)) {
+                (Some(only), Some(extension)) => only.iter().any(|e| e.is_same_format(&extension)),
+                _ => true,
+            }
+        })
+        .map(|path| convert_file(path, src, dst, to, quality))
+        .collect()
+}
+
+// Convert an image file in ``src`` and save it to the same relative path in ``dst``.
+fn convert_file(path: &Path, src: &Path, dst: &Path, to: &Extension, quality: Option<f32>) -> Result<SaveStatus, RusimgError> {
+    let relative_path = path.strip_prefix(src).map_err(|_| RusimgError::FailedToGetFilename(path.to_path_buf()))?;
+    let output_path = dst.join(relative_path).with_extension(to.to_string());
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| RusimgError::FailedToCreateFile(e.to_string()))?;
+    }
+
+    let mut image = RusImg::open(path)?;
+    image.convert(to)?;
+    if quality.is_some() {
+        image.compress(quality)?;
+    }
+    image.save_image(Some(output_path.to_str().ok_or(RusimgError::FailedToConvertPathToString)?))
+}
+
+/// Aggregate of the results of a batch run, e.g. for a progress bar or a summary line.
+/// Results are added one by one with ``add()`` (or all at once with ``from_results()``).
+/// Only the successful results that have both the file size before and after saving are counted in the byte totals,
+/// so that the savings compare the same files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    total_input_bytes: u64,
+    total_output_bytes: u64,
+    succeeded: usize,
+    failed: usize,
+}
+impl BatchReport {
+    /// Make a report from the results of a batch run (e.g. of ``convert_directory()``).
+    pub fn from_results(results: &[Result<SaveStatus, RusimgError>]) -> Self {
+        let mut report = Self::default();
+        results.iter().for_each(|result| report.add(result));
+        report
+    }
+
+    /// Add the result of an image.
+    pub fn add(&mut self, result: &Result<SaveStatus, RusimgError>) {
+        match result {
+            Ok(status) => {
+                self.succeeded += 1;
+                if let (Some(before), Some(after)) = (status.before_filesize, status.after_filesize) {
+                    self.total_input_bytes += before;
+                    self.total_output_bytes += after;
+                }
+            },
+            Err(_) => self.failed += 1,
+        }
+    }
+
+    /// Total size of the input files in bytes.
+    pub fn total_input_bytes(&self) -> u64 {
+        self.total_input_bytes
+    }
+
+    /// Total size of the output files in bytes.
+    pub fn total_output_bytes(&self) -> u64 {
+        self.total_output_bytes
+    }
+
+    /// Number of saved images.
+    pub fn succeeded(&self) -> usize {
+        self.succeeded
+    }
+
+    /// Number of failed images.
+    pub fn failed(&self) -> usize {
+        self.failed
+    }
+
+    /// Number of processed images (succeeded and failed).
+    pub fn processed(&self) -> usize {
+        self.succeeded + self.failed
+    }
+
+    /// Saved bytes (input - output). This is negative if the output files are larger.
+    pub fn savings_bytes(&self) -> i64 {
+        self.total_input_bytes as i64 - self.total_output_bytes as i64
+    }
+
+    /// Saved bytes as a percentage of the input size (e.g. 72.0 for 72% smaller).
+    /// Returns None if no input size is counted.
+    pub fn savings_percent(&self) -> Option<f64> {
+        if self.total_input_bytes == 0 {
+            return None;
+        }
+        Some(self.savings_bytes() as f64 / self.total_input_bytes as f64 * 100.0)
+    }
+}
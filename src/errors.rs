@@ -1,6 +1,8 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fmt;
 
+use super::Extension;
+
 /// Error type for Rusimg.
 /// This error type is used in Rusimg functions.
 /// Some error types have a string parameter to store the error message.
@@ -8,11 +10,17 @@ use std::fmt;
 pub enum RusimgError {
     FailedToOpenFile(String),
     FailedToReadFile(String),
+    FailedToReadDirectory(String),
     FailedToGetMetadata(String),
     FailedToOpenImage(String),
+    ImageTruncated,
+    CorruptImage(String),
+    InvalidDataUrl(String),
     FailedToSaveImage(String),
+    VerificationFailed(String),
     FailedToCopyBinaryData(String),
     FailedToGetFilename(PathBuf),
+    FileAlreadyExists(PathBuf),
     FailedToCreateFile(String),
     FailedToWriteFIle(String),
     FailedToDecodeWebp,
@@ -21,12 +29,61 @@ pub enum RusimgError {
     FailedToConvertPathToString,
     InvalidCompressionLevel,
     InvalidTrimXY,
+    InvalidTrimMultiple,
+    OriginalCoordinatesUnavailable,
     InvalidResizeRatio,
+    InvalidWebpMethod,
+    InvalidBufferLength(usize, usize),
+    InvalidImageBuffer(usize, usize),
+    InvalidPosterizeLevels,
+    InvalidPaletteSize,
+    InvalidRotationAngle,
+    InvalidTargetSize,
+    InvalidTileGrid,
+    InvalidBandHeight,
+    ContentNotFound,
+    InvalidFilterParameter,
+    FrameSizeMismatch,
+    ImageSizeMismatch,
+    FrameDelaysMismatch,
+    ImageTooLarge,
+    MetadataTooLarge,
     ImageFormatCannotBeCompressed,
+    CannotOptimize(String),
     UnsupportedFileExtension,
     UnsupportedFeature,
+    ExtensionMismatch(Extension, Extension),
     ImageNotSpecified,
+    Cancelled,
     DestinationPathMustBeSpecified,
+    /// An error of opening or saving the file at the path.
+    WithPath { path: PathBuf, source: Box<RusimgError> },
+}
+impl RusimgError {
+    /// Attach the file path that caused the error.
+    /// Errors that already have a path are returned as they are.
+    pub fn with_path(self, path: &Path) -> RusimgError {
+        match self {
+            RusimgError::WithPath { .. } => self,
+            _ => RusimgError::WithPath { path: path.to_path_buf(), source: Box::new(self) },
+        }
+    }
+
+    /// Get the file path attached to the error, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            RusimgError::WithPath { path, .. } => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Get the error without the attached file path (e.g. for matching the error kind).
+    pub fn without_path(&self) -> &RusimgError {
+        match self {
+            RusimgError::WithPath { source, .. } => source,
+            _ => self,
+        }
+    }
 }
 /// Implement Display trait for RusimgError.
 impl fmt::Display for RusimgError {
@@ -34,11 +91,17 @@ impl fmt::Display for RusimgError {
         match self {
             RusimgError::FailedToOpenFile(s) => write!(f, "Failed to open file: \n\t{}", s),
             RusimgError::FailedToReadFile(s) => write!(f, "Failed to read file: \n\t{}", s),
+            RusimgError::FailedToReadDirectory(s) => write!(f, "Failed to read directory: \n\t{}", s),
             RusimgError::FailedToGetMetadata(s) => write!(f, "Failed to get metadata: \n\t{}", s),
             RusimgError::FailedToOpenImage(s) => write!(f, "Failed to open image: \n\t{}", s),
+            RusimgError::ImageTruncated => write!(f, "Image data is truncated (unexpected end of file)"),
+            RusimgError::CorruptImage(s) => write!(f, "Image data is corrupt: \n\t{}", s),
+            RusimgError::InvalidDataUrl(s) => write!(f, "Invalid data URL: \n\t{}", s),
             RusimgError::FailedToSaveImage(s) => write!(f, "Failed to save image: \n\t{}", s),
+            RusimgError::VerificationFailed(s) => write!(f, "The saved image failed verification: \n\t{}", s),
             RusimgError::FailedToCopyBinaryData(s) => write!(f, "Failed to copy binary data to memory: \n\t{}", s),
             RusimgError::FailedToGetFilename(s) => write!(f, "Failed to get filename: \n\t{}", s.display()),
+            RusimgError::FileAlreadyExists(s) => write!(f, "File already exists: \n\t{}", s.display()),
             RusimgError::FailedToCreateFile(s) => write!(f, "Failed to create file: \n\t{}", s),
             RusimgError::FailedToWriteFIle(s) => write!(f, "Failed to write file: \n\t{}", s),
             RusimgError::FailedToDecodeWebp => write!(f, "Failed to decode webp"),
@@ -54,12 +117,34 @@ impl fmt::Display for RusimgError {
             RusimgError::FailedToConvertPathToString => write!(f, "Failed to convert path to string"),
             RusimgError::InvalidCompressionLevel => write!(f, "Invalid compression level"),
             RusimgError::InvalidTrimXY => write!(f, "Invalid trim XY"),
+            RusimgError::InvalidTrimMultiple => write!(f, "Invalid trim multiple (must be 1 or more)"),
+            RusimgError::OriginalCoordinatesUnavailable => write!(f, "The original coordinates cannot be mapped to the image after rotating or replacing it"),
             RusimgError::InvalidResizeRatio => write!(f, "Invalid resize ratio"),
+            RusimgError::InvalidWebpMethod => write!(f, "Invalid webp method (must be 0-6)"),
+            RusimgError::InvalidBufferLength(expected, actual) => write!(f, "Invalid buffer length: expected {} bytes, but got {} bytes", expected, actual),
+            RusimgError::InvalidImageBuffer(expected, actual) => write!(f, "Invalid image buffer: the image dimensions need {} bytes, but the buffer has {} bytes (wrong stride?)", expected, actual),
+            RusimgError::InvalidPosterizeLevels => write!(f, "Invalid posterize levels (must be 2 or more)"),
+            RusimgError::InvalidPaletteSize => write!(f, "Invalid palette size (must be 1 to 256 colors)"),
+            RusimgError::InvalidRotationAngle => write!(f, "Invalid rotation angle (must be a finite number)"),
+            RusimgError::InvalidTargetSize => write!(f, "Invalid target size (must be more than 0 bytes, with a tolerance in [0, 1))"),
+            RusimgError::ContentNotFound => write!(f, "No content found (the whole image is background)"),
+            RusimgError::InvalidTileGrid => write!(f, "Invalid tile grid (the number of columns and rows must be 1 to the image width and height)"),
+            RusimgError::InvalidBandHeight => write!(f, "Invalid band height (must be 1 or more)"),
+            RusimgError::InvalidFilterParameter => write!(f, "Invalid filter parameter"),
+            RusimgError::FrameSizeMismatch => write!(f, "All animation frames must have the same size"),
+            RusimgError::ImageSizeMismatch => write!(f, "The images must have the same size"),
+            RusimgError::FrameDelaysMismatch => write!(f, "The number of frame delays must match the number of frames"),
+            RusimgError::ImageTooLarge => write!(f, "Image is too large (exceeds the maximum number of pixels)"),
+            RusimgError::MetadataTooLarge => write!(f, "The metadata is too large to embed in the image"),
             RusimgError::ImageFormatCannotBeCompressed => write!(f, "this image format cannot be compressed"),
+            RusimgError::CannotOptimize(s) => write!(f, "Cannot optimize the image: {}", s),
             RusimgError::UnsupportedFileExtension => write!(f, "Unsupported file extension"),
             RusimgError::UnsupportedFeature => write!(f, "Unsupported feature"),
+            RusimgError::ExtensionMismatch(image, path) => write!(f, "The output file extension ({}) does not match the image format ({})", path, image),
             RusimgError::ImageNotSpecified => write!(f, "Image not specified"),
+            RusimgError::Cancelled => write!(f, "The operation was cancelled"),
             RusimgError::DestinationPathMustBeSpecified => write!(f, "Destination path must be specified"),
+            RusimgError::WithPath { path, source } => write!(f, "{}: {}", path.display(), source),
         }
     }
 }
@@ -1,3 +1,4 @@
+use std::path::Path;
 use std::fmt;
 
 /// Image extension object.
@@ -24,3 +25,40 @@ impl fmt::Display for Extension {
         }
     }
 }
+impl Extension {
+    /// Get the built-in extension from a file path.
+    /// The file extension is compared case-insensitively.
+    /// Returns None if the path has no extension or the extension is not a built-in format.
+    pub fn from_path(path: &Path) -> Option<Extension> {
+        let extension_str = path.extension()?.to_str()?.to_lowercase();
+        match extension_str.as_str() {
+            "bmp" => Some(Extension::Bmp),
+            "jpg" => Some(Extension::Jpg),
+            "jpeg" => Some(Extension::Jpeg),
+            "png" => Some(Extension::Png),
+            "webp" => Some(Extension::Webp),
+            _ => None,
+        }
+    }
+
+    /// Get the MIME type of the built-in extension (e.g. ``image/png``).
+    /// Returns None for external formats.
+    pub fn mime_type(&self) -> Option<&'static str> {
+        match self {
+            Extension::Bmp => Some("image/bmp"),
+            Extension::Jpg | Extension::Jpeg => Some("image/jpeg"),
+            Extension::Png => Some("image/png"),
+            Extension::Webp => Some("image/webp"),
+            Extension::ExternalFormat(_) => None,
+        }
+    }
+
+    /// Check if two extensions refer to the same image format.
+    /// ``Extension::Jpg`` and ``Extension::Jpeg`` are the same format.
+    pub fn is_same_format(&self, other: &Extension) -> bool {
+        match (self, other) {
+            (Extension::Jpg | Extension::Jpeg, Extension::Jpg | Extension::Jpeg) => true,
+            _ => self == other,
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 use image::DynamicImage;
 
+use logging::{log_debug, log_info, Timer};
+
 pub mod backend;
 pub use backend::*;
 pub mod structs;
@@ -9,12 +12,21 @@ pub mod errors;
 pub use errors::*;
 pub mod extension;
 pub use extension::*;
+pub mod batch;
+pub use batch::*;
+mod operations;
+mod logging;
 
 /// RusImg object.
 /// This object contains an image object and its metadata.
 pub struct RusImg {
     extension: Extension,
     data: Box<(dyn BackendTrait)>,
+    linear_resize: bool,
+    extension_mismatch_policy: ExtensionMismatchPolicy,
+    strict_trim: bool,
+    coordinate_space: Option<CoordinateSpace>,
+    config: RusImgConfig,
 }
 
 /// RusImg object implementation.
@@ -24,28 +36,162 @@ impl RusImg {
     /// This function will open an image file and return a RusImg object.
     /// The image file will be opened based on the file extension.
     pub fn open(path: &Path) -> Result<Self, RusimgError> {
-        backend::open_image(path)
+        Self::open_with_config(path, &backend::default_config())
+    }
+
+    /// Open an image file with the config instead of the default config.
+    /// The config sets the defaults of the later operations, such as the resize filter and the quality used by ``compress()``.
+    pub fn open_with_config(path: &Path, config: &RusImgConfig) -> Result<Self, RusimgError> {
+        let timer = Timer::start();
+        let mut image = backend::open_image(path).map_err(|e| e.with_path(path))?;
+        image.set_config(config)?;
+        log_info!("opened {} ({}, {:?}) in {:.1} ms", path.display(), image.extension, image.data.get_size().unwrap_or_default(), timer.elapsed_ms());
+        Ok(image)
+    }
+
+    /// Open an image file by memory-mapping it, which avoids reading very large files into memory first.
+    /// BMP images are decoded directly from the mapped file; the other formats keep the encoded bytes, so they are copied as with ``open()``.
+    /// This requires the ``mmap`` feature.
+    #[cfg(feature="mmap")]
+    pub fn open_mmap(path: &Path) -> Result<Self, RusimgError> {
+        let timer = Timer::start();
+        let mut image = backend::open_image_mmap(path).map_err(|e| e.with_path(path))?;
+        image.set_config(&backend::default_config())?;
+        log_info!("opened {} ({}, {:?}) in {:.1} ms", path.display(), image.extension, image.data.get_size().unwrap_or_default(), timer.elapsed_ms());
+        Ok(image)
+    }
+
+    /// Open only a region of an image file, e.g. to make tiles of a huge image.
+    /// The region must be inside the image, otherwise an ``InvalidTrimXY`` error is returned.
+    /// Non-interlaced PNG files are decoded row by row and uncompressed 24-bit BMP files are read row by row, keeping only the pixels of the region,
+    /// so the decoded memory is that of the region. The maximum number of pixels (see ``set_max_pixels()``) applies to the region for them.
+    /// The other files (JPEG, WebP, interlaced PNG, compressed BMP, etc.) are decoded whole and then trimmed, with the same result.
+    /// The image keeps its source file and ``coordinate_space()`` maps the whole image to the region.
+    /// This uses ``open_image_region()``, or ``open()`` and ``trim_rect()`` for the other files.
+    pub fn decode_region(path: &Path, rect: Rect) -> Result<Self, RusimgError> {
+        // An invalid region is not an error of the file, so it is returned without the path as with ``trim_rect()``.
+        let region = backend::open_image_region(path, &rect).map_err(|e| match e {
+            RusimgError::InvalidTrimXY => e,
+            _ => e.with_path(path),
+        })?;
+        if let Some((mut image, size)) = region {
+            image.set_config(&backend::default_config())?;
+            image.coordinate_space = Some(CoordinateSpace::new(size).trimmed(rect.x, rect.y));
+            return Ok(image);
+        }
+        let mut image = Self::open(path)?;
+        backend::check_region(image.data.get_size()?, &rect)?;
+        image.trim_rect(rect)?;
+        Ok(image)
+    }
+
+    /// Open an image file from anything path-like (``&str``, ``String``, ``PathBuf``, etc.).
+    /// This is the same as ``open()``, but the caller does not need to build a ``Path`` object.
+    pub fn open_path<P: AsRef<Path>>(path: P) -> Result<Self, RusimgError> {
+        Self::open(path.as_ref())
     }
 
     /// New image object.
     /// This function will create a new image object based on the file extension.
     /// It will return a RusImg object.
     pub fn new(extension: &Extension, image: DynamicImage) -> Result<Self, RusimgError> {
-        backend::new_image(extension, image)
+        let mut new_img = backend::new_image(extension, image)?;
+        new_img.set_config(&backend::default_config())?;
+        Ok(new_img)
+    }
+
+    /// Create a new image object from encoded image bytes (e.g. a downloaded file), detecting the format from the data.
+    /// The bytes are opened by the backend like a file, so the metadata (EXIF, XMP, PNG gamma, WebP animation, etc.) is kept
+    /// and an unmodified image is saved as it is. Formats registered with ``register_backend()`` are opened too.
+    /// The image has no source file, so a destination path must be given to save it.
+    /// This is also available as ``RusImg::try_from(bytes)``.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, RusimgError> {
+        let mut new_img = backend::open_image_bytes(bytes)?;
+        new_img.set_config(&backend::default_config())?;
+        Ok(new_img)
+    }
+
+    /// Create a new image object from a ``data:image/...;base64,...`` URL (e.g. made by ``to_data_url()`` or sent by a web page).
+    /// The base64 data is decoded and opened with ``from_bytes()``, so the format is detected from the data rather than the media type.
+    /// A string that is not a base64 image data URL returns an ``InvalidDataUrl`` error.
+    /// This requires the ``base64`` feature.
+    #[cfg(feature="base64")]
+    pub fn from_data_url(s: &str) -> Result<Self, RusimgError> {
+        use base64::Engine;
+        let s = s.trim();
+        let rest = s.get(..5).filter(|scheme| scheme.eq_ignore_ascii_case("data:")).map(|_| &s[5..])
+            .ok_or(RusimgError::InvalidDataUrl("missing \"data:\" prefix".to_string()))?;
+        let (header, data) = rest.split_once(',').ok_or(RusimgError::InvalidDataUrl("missing \",\" before the data".to_string()))?;
+        let media_type = header.strip_suffix(";base64").ok_or(RusimgError::InvalidDataUrl("the data is not base64-encoded".to_string()))?;
+        if !media_type.to_ascii_lowercase().starts_with("image/") {
+            return Err(RusimgError::InvalidDataUrl(format!("not an image media type: {:?}", media_type)));
+        }
+        let bytes = base64::engine::general_purpose::STANDARD.decode(data)
+            .map_err(|e| RusimgError::InvalidDataUrl(e.to_string()))?;
+        Self::from_bytes(&bytes)
+    }
+
+    /// Create a new image object from a buffer of RGBA8 pixels.
+    /// The buffer must contain ``width * height * 4`` bytes in row-major order, otherwise an ``InvalidBufferLength`` error is returned.
+    /// The image is imported into the backend of the extension, like ``new()``.
+    pub fn from_rgba8(bytes: Vec<u8>, width: u32, height: u32, extension: &Extension) -> Result<Self, RusimgError> {
+        let expected = (width as usize).checked_mul(height as usize).and_then(|n| n.checked_mul(4)).unwrap_or(usize::MAX);
+        let actual = bytes.len();
+        let buffer = image::RgbaImage::from_raw(width, height, bytes).filter(|_| actual == expected)
+            .ok_or(RusimgError::InvalidBufferLength(expected, actual))?;
+        Self::new(extension, DynamicImage::ImageRgba8(buffer))
     }
 
     /// Create a new RusImg object from an Extension and a BaclendTrait object.
     /// This function is for external formats.
     /// It will return a RusImg object.
     pub fn assemble(extension: &Extension, data: Box<(dyn BackendTrait)>) -> Result<Self, RusimgError> {
-        let mut new_img = RusImg {
-            extension: extension.clone(),
-            data,
-        };
-        new_img.extension = extension.clone();
+        let mut new_img = RusImg::from_backend(extension.clone(), data);
+        new_img.set_config(&backend::default_config())?;
         Ok(new_img)
     }
 
+    // Wrap a backend object with the default settings: no linear-light resizing, converting on extension mismatch, lenient trimming and the default config.
+    // The backends use this for the images they open or create.
+    pub(crate) fn from_backend(extension: Extension, data: Box<dyn BackendTrait>) -> Self {
+        let coordinate_space = data.get_size().ok().map(CoordinateSpace::new);
+        RusImg {
+            extension,
+            data,
+            linear_resize: false,
+            extension_mismatch_policy: ExtensionMismatchPolicy::Convert,
+            strict_trim: false,
+            coordinate_space,
+            config: RusImgConfig::default(),
+        }
+    }
+
+    /// Get the animation information of an image: the loop count and the delay, disposal and blend method of each frame.
+    /// Still images return a single-frame descriptor.
+    /// This uses the ``animation_info()`` function from ``BackendTrait``.
+    pub fn animation_info(&self) -> Result<AnimationInfo, RusimgError> {
+        let info = self.data.animation_info()?;
+        Ok(info)
+    }
+
+    /// Validate the image data by decoding it fully.
+    /// This uses the ``validate()`` function from ``BackendTrait``.
+    pub fn validate(&self) -> Result<(), RusimgError> {
+        self.data.validate()
+    }
+
+    /// Get the modification time of the source file.
+    /// This uses the ``source_modified()`` function from ``BackendTrait``.
+    pub fn source_modified(&self) -> Option<SystemTime> {
+        self.data.source_modified()
+    }
+
+    /// Get the common metadata of an image: dimensions, color type, bit depth, DPI, EXIF orientation, alpha and frame count.
+    /// This uses the ``metadata()`` function from ``BackendTrait``.
+    pub fn metadata(&self) -> ImageMetadata {
+        self.data.metadata()
+    }
+
     /// Get image size.
     /// This uses the ``get_size()`` function from ``BackendTrait``.
     pub fn get_image_size(&self) -> Result<ImgSize, RusimgError> {
@@ -58,543 +204,4156 @@ impl RusImg {
     /// Set ratio to 100 to keep the original size.
     /// This uses the ``resize()`` function from ``BackendTrait``.
     pub fn resize(&mut self, ratio: f32) -> Result<ImgSize, RusimgError> {
-        if ratio <= 0.0 {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+
+        let size = self.logged("resize", |img| img.resize_in_linear_light(|data| data.resize(ratio)))?;
+        Ok(size)
+    }
+
+    /// Resize an image like ``resize()``, returning a ``Cancelled`` error if ``token`` is cancelled while resizing.
+    /// The image is resampled with the kernel of the configured resize filter, checking the token every few rows;
+    /// the pixels may differ slightly from ``resize()``, which uses the resampler of the image crate.
+    /// A cancelled resize leaves the image unchanged.
+    /// This uses the ``resize_with_kernel_cancellable()`` function from ``BackendTrait``.
+    pub fn resize_cancellable(&mut self, ratio: f32, token: &CancelToken) -> Result<ImgSize, RusimgError> {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+
+        let kernel = self.config.resize_filter.resample_kernel();
+        let size = self.logged("resize_cancellable", |img| img.resize_in_linear_light(|data| data.resize_with_kernel_cancellable(ratio, kernel, token)))?;
+        Ok(size)
+    }
+
+    /// Resize an image with different horizontal and vertical ratios.
+    /// It must be called after open_image().
+    /// Set both ratios to 100 to keep the original size.
+    /// This uses the ``resize_xy()`` function from ``BackendTrait``.
+    pub fn resize_xy(&mut self, x_ratio: f32, y_ratio: f32) -> Result<ImgSize, RusimgError> {
+        if !x_ratio.is_finite() || !y_ratio.is_finite() || x_ratio <= 0.0 || y_ratio <= 0.0 {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+
+        let size = self.logged("resize_xy", |img| img.resize_in_linear_light(|data| data.resize_xy(x_ratio, y_ratio)))?;
+        Ok(size)
+    }
+
+    /// Resize an image, rounding the width and height up to the nearest even number.
+    /// It must be called after open_image().
+    /// This uses the ``resize_even()`` function from ``BackendTrait``.
+    pub fn resize_even(&mut self, ratio: f32) -> Result<ImgSize, RusimgError> {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+
+        let size = self.logged("resize_even", |img| img.resize_in_linear_light(|data| data.resize_even(ratio)))?;
+        Ok(size)
+    }
+
+    /// Resize an image with a resampling kernel (e.g. ``ResampleKernel::Lanczos { a: 5 }``), for comparing the resampling quality.
+    /// An ``InvalidFilterParameter`` error is returned if the kernel parameter is out of range.
+    /// It must be called after open_image().
+    /// This uses the ``resize_with_kernel()`` function from ``BackendTrait``.
+    pub fn resize_with_kernel(&mut self, ratio: f32, kernel: ResampleKernel) -> Result<ImgSize, RusimgError> {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+
+        let size = self.logged("resize_with_kernel", |img| img.resize_in_linear_light(|data| data.resize_with_kernel(ratio, kernel)))?;
+        Ok(size)
+    }
+
+    /// Resize an image to the given width, keeping the aspect ratio.
+    /// It must be called after open_image().
+    /// This uses the ``resize_to_width()`` function from ``BackendTrait``.
+    pub fn resize_to_width(&mut self, width: u32) -> Result<ImgSize, RusimgError> {
+        if width == 0 {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+
+        let size = self.logged("resize_to_width", |img| img.resize_in_linear_light(|data| data.resize_to_width(width)))?;
+        Ok(size)
+    }
+
+    /// Resize an image to the largest width in ``breakpoints`` that does not exceed the current width, keeping the aspect ratio.
+    /// Images are never upscaled: if no breakpoint fits, an ``InvalidResizeRatio`` error is returned, and if the largest one equals the current width, the image is left as it is.
+    /// This returns the new size.
+    /// This uses ``resize_to_width()``.
+    pub fn resize_to_breakpoint(&mut self, breakpoints: &[u32]) -> Result<ImgSize, RusimgError> {
+        let size = self.data.get_size()?;
+        let width = breakpoints.iter().copied()
+            .filter(|&w| w > 0 && w as usize <= size.width)
+            .max()
+            .ok_or(RusimgError::InvalidResizeRatio)?;
+        if width as usize == size.width {
+            return Ok(size);
+        }
+        self.resize_to_width(width)
+    }
+
+    /// Resize an image to the given height, keeping the aspect ratio.
+    /// It must be called after open_image().
+    /// This uses the ``resize_to_height()`` function from ``BackendTrait``.
+    pub fn resize_to_height(&mut self, height: u32) -> Result<ImgSize, RusimgError> {
+        if height == 0 {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+
+        let size = self.logged("resize_to_height", |img| img.resize_in_linear_light(|data| data.resize_to_height(height)))?;
+        Ok(size)
+    }
+
+    /// Shrink an image to fit within the given number of megapixels, keeping the aspect ratio.
+    /// If the image already fits, it is left unchanged.
+    /// The limit must be a positive finite number, otherwise an ``InvalidResizeRatio`` error is returned.
+    /// This returns the resulting image size.
+    /// This uses the ``resize_xy()`` function from ``BackendTrait``.
+    pub fn limit_megapixels(&mut self, max_mp: f32) -> Result<ImgSize, RusimgError> {
+        if !max_mp.is_finite() || max_mp <= 0.0 {
             return Err(RusimgError::InvalidResizeRatio);
         }
 
-        let size = self.data.resize(ratio)?;
+        let size = self.data.get_size()?;
+        let pixels = (size.width * size.height) as f64;
+        let max_pixels = max_mp as f64 * 1_000_000.0;
+        if pixels <= max_pixels {
+            return Ok(size);
+        }
+
+        // Scale both sides by the same factor, and aim at the middle of the target pixel so that the truncation in resize_xy() lands on it.
+        let scale = (max_pixels / pixels).sqrt();
+        let nwidth = ((size.width as f64 * scale) as usize).max(1);
+        let nheight = ((size.height as f64 * scale) as usize).max(1);
+        let x_ratio = (nwidth as f32 + 0.5) / size.width as f32 * 100.0;
+        let y_ratio = (nheight as f32 + 0.5) / size.height as f32 * 100.0;
+        self.resize_xy(x_ratio, y_ratio)
+    }
+
+    /// Set whether to resize images in linear light.
+    /// If enabled, ``resize()``, ``resize_xy()`` and ``resize_even()`` convert the image from sRGB to linear RGB before resizing and back to sRGB afterward.
+    /// Resizing in gamma-encoded sRGB darkens downscaled images slightly, so this gives perceptually correct results.
+    /// It is disabled by default.
+    pub fn set_linear_resize(&mut self, on: bool) {
+        self.linear_resize = on;
+    }
+
+    /// Set the defaults of the later operations, such as the resize filter and the quality used by ``compress()``.
+    /// The config is kept when the image is converted to another format.
+    /// The config is checked first (see ``RusImgConfig::validate()``), and an invalid config is not applied.
+    /// This uses the ``set_config()`` function from ``BackendTrait``.
+    pub fn set_config(&mut self, config: &RusImgConfig) -> Result<(), RusimgError> {
+        config.validate()?;
+        self.config = config.clone();
+        self.data.set_config(config);
+        Ok(())
+    }
+
+    /// Get the config of the image.
+    pub fn config(&self) -> &RusImgConfig {
+        &self.config
+    }
+
+    // Run a resize function of the backend, in linear light if linear resizing is enabled.
+    fn resize_in_linear_light<F>(&mut self, resize: F) -> Result<ImgSize, RusimgError>
+    where F: FnOnce(&mut dyn BackendTrait) -> Result<ImgSize, RusimgError> {
+        let before = self.data.get_size()?;
+        let size = if !self.linear_resize {
+            resize(self.data.as_mut())?
+        }
+        else {
+            let image = self.data.get_dynamic_image()?;
+            self.data.set_dynamic_image(operations::srgb_to_linear(&image))?;
+            let size = match resize(self.data.as_mut()) {
+                Ok(size) => size,
+                Err(e) => {
+                    self.data.set_dynamic_image(image)?;
+                    return Err(e);
+                }
+            };
+            let resized = self.data.get_dynamic_image()?;
+            self.data.set_dynamic_image(operations::linear_to_srgb(&resized, image.color()))?;
+            size
+        };
+        self.coordinate_space = self.coordinate_space.and_then(|space| space.resized(before, size));
         Ok(size)
     }
 
+    // Follow a trim at (x, y) of the current image in the coordinate space.
+    fn track_trim(&mut self, x: u32, y: u32) {
+        self.coordinate_space = self.coordinate_space.map(|space| space.trimmed(x, y));
+    }
+
+    // Run an operation and log its duration and the resulting image size (with the logging feature).
+    fn logged<T, F>(&mut self, operation: &str, f: F) -> Result<T, RusimgError>
+    where F: FnOnce(&mut Self) -> Result<T, RusimgError> {
+        let timer = Timer::start();
+        let result = f(self);
+        match &result {
+            Ok(_) => log_debug!("{}: {:?} in {:.1} ms", operation, self.data.get_size().unwrap_or_default(), timer.elapsed_ms()),
+            Err(e) => log_debug!("{} failed: {}", operation, e),
+        }
+        result
+    }
+
     /// Trim an image. Set the trim area with four u32 values: x, y, w, h.
     /// It must be called after open_image().
     /// The values will be assigned to a Rect object.
     /// This uses the ``trim()`` function from ``BackendTrait``.
     pub fn trim(&mut self, trim_x: u32, trim_y: u32, trim_w: u32, trim_h: u32) -> Result<ImgSize, RusimgError> {
-        let size = self.data.trim(Rect{x: trim_x, y: trim_y, w: trim_w, h: trim_h})?;
+        self.check_strict_trim(&Rect{x: trim_x, y: trim_y, w: trim_w, h: trim_h})?;
+        let size = self.logged("trim", |img| img.data.trim(Rect{x: trim_x, y: trim_y, w: trim_w, h: trim_h}))?;
+        self.track_trim(trim_x, trim_y);
         Ok(size)
     }
     /// Trim an image. Set the trim area with a rusimg::Rect object.
     /// It must be called after open_image().
     /// This uses the ``trim()`` function from ``BackendTrait``.
     pub fn trim_rect(&mut self, trim_area: Rect) -> Result<ImgSize, RusimgError> {
-        let size = self.data.trim(trim_area)?;
+        self.check_strict_trim(&trim_area)?;
+        let (x, y) = (trim_area.x, trim_area.y);
+        let size = self.logged("trim_rect", |img| img.data.trim(trim_area))?;
+        self.track_trim(x, y);
         Ok(size)
     }
 
-    /// Grayscale an image.
-    /// It must be called after open_image().
-    /// This uses the ``grayscale()`` function from ``BackendTrait``.
-    pub fn grayscale(&mut self) -> Result<(), RusimgError> {
-        self.data.grayscale();
+    /// Set whether the trim functions reject trim areas that do not fit in the image.
+    /// By default (``false``), a width or height reaching past the image is shrunk to fit, and only an x or y outside the image is an ``InvalidTrimXY`` error.
+    /// In strict mode, any area reaching past the image is an ``InvalidTrimXY`` error, and the image is left untouched.
+    /// It applies to ``trim()``, ``trim_rect()``, ``trim_anchored()``, ``trim_multiple_of()``, ``trim_original_coords()`` and ``split_tiles()``.
+    pub fn set_strict_trim(&mut self, strict: bool) {
+        self.strict_trim = strict;
+    }
+
+    // In strict trim mode, check that the trim area fits in the image.
+    fn check_strict_trim(&self, rect: &Rect) -> Result<(), RusimgError> {
+        if !self.strict_trim {
+            return Ok(());
+        }
+        let size = self.data.get_size()?;
+        if rect.x as u64 + rect.w as u64 > size.width as u64 || rect.y as u64 + rect.h as u64 > size.height as u64 {
+            return Err(RusimgError::InvalidTrimXY);
+        }
         Ok(())
     }
 
-    /// Compress an image.
+    /// Get the mapping from the coordinates of the original image (when it was opened or created) to the current image.
+    /// It follows resizing and trimming, and is used by ``trim_original_coords()``.
+    /// Returns None after an operation that it cannot follow: rotating by an arbitrary angle, deskewing, or replacing the image with one of another size.
+    pub fn coordinate_space(&self) -> Option<CoordinateSpace> {
+        self.coordinate_space
+    }
+
+    /// Trim an image with a trim area given in the coordinates of the original image (the image when it was opened or created).
+    /// The area is mapped through ``coordinate_space()``, so the same region is cropped after resizing and trimming.
+    /// The mapped area is rounded outwards to whole pixels. Like ``trim()``, the part outside the current image is cut off, unless strict trim mode is set.
+    /// An ``OriginalCoordinatesUnavailable`` error is returned if the mapping has been lost (see ``coordinate_space()``).
     /// It must be called after open_image().
-    /// Set quality to 100 to keep the original quality.
-    /// This uses the ``compress()`` function from ``BackendTrait``.
-    pub fn compress(&mut self, quality: Option<f32>) -> Result<(), RusimgError> {
-        if quality.is_some() && (quality.unwrap() < 0.0 || quality.unwrap() > 100.0) {
-            return Err(RusimgError::InvalidCompressionLevel);
+    /// This uses the ``trim()`` function from ``BackendTrait``.
+    pub fn trim_original_coords(&mut self, rect: Rect) -> Result<ImgSize, RusimgError> {
+        let space = self.coordinate_space.ok_or(RusimgError::OriginalCoordinatesUnavailable)?;
+        let current = self.data.get_size()?;
+        if rect.w == 0 || rect.h == 0 || current.width == 0 || current.height == 0 {
+            return Err(RusimgError::InvalidTrimXY);
+        }
+        // Round outwards, ignoring the floating-point error of the mapping.
+        let (left, top) = space.to_current(rect.x as f64, rect.y as f64);
+        let (right, bottom) = space.to_current(rect.x as f64 + rect.w as f64, rect.y as f64 + rect.h as f64);
+        let (left, top) = ((left + 1e-9).floor(), (top + 1e-9).floor());
+        let (right, bottom) = ((right - 1e-9).ceil(), (bottom - 1e-9).ceil());
+        let (width, height) = (current.width as f64, current.height as f64);
+        if self.strict_trim && (left < 0.0 || top < 0.0 || right > width || bottom > height) {
+            return Err(RusimgError::InvalidTrimXY);
         }
+        let (left, top, right, bottom) = (left.max(0.0), top.max(0.0), right.min(width), bottom.min(height));
+        if right <= left || bottom <= top {
+            return Err(RusimgError::InvalidTrimXY);
+        }
+        let (x, y) = (left as u32, top as u32);
+        let size = self.logged("trim_original_coords", |img| img.data.trim(Rect { x, y, w: (right - left) as u32, h: (bottom - top) as u32 }))?;
+        self.track_trim(x, y);
+        Ok(size)
+    }
 
-        self.data.compress(quality)?;
-        Ok(())
+    /// Trim an image to the given size at the anchor position.
+    /// For example, ``Anchor::Top`` with the image width keeps the top ``h`` pixels.
+    /// It must be called after open_image().
+    /// This uses the ``trim_anchored()`` function from ``BackendTrait``.
+    pub fn trim_anchored(&mut self, anchor: Anchor, w: u32, h: u32) -> Result<ImgSize, RusimgError> {
+        self.check_strict_trim(&Rect { x: 0, y: 0, w, h })?;
+        let size = self.logged("trim_anchored", |img| {
+            let area = backend::anchored_rect(img.data.get_size()?, anchor, w, h);
+            let size = img.data.trim_anchored(anchor, w, h)?;
+            img.track_trim(area.x, area.y);
+            Ok(size)
+        })?;
+        Ok(size)
     }
 
-    /// Convert an image to another format.
-    /// And replace the original image with the new one.
+    /// Trim an image to the area of ``rect`` shrunk to multiples of ``multiple`` pixels, keeping it centered within ``rect``.
+    /// For example, a 100x100 area with ``multiple`` 16 is trimmed to the 96x96 area at its center, e.g. for video thumbnails.
     /// It must be called after open_image().
-    /// This uses the ``get_dynamic_image()`` function to get the DynamicImage object, ``get_metadata_src()`` to get the metadata, and ``compress()`` to compress the image.
-    pub fn convert(&mut self, new_extension: &Extension) -> Result<(), RusimgError> {
-        let dynamic_image = self.data.get_dynamic_image()?;
-        let filepath = self.data.get_source_filepath();
-        let metadata = self.data.get_metadata_src();
+    /// This uses the ``trim_multiple_of()`` function from ``BackendTrait``.
+    pub fn trim_multiple_of(&mut self, rect: Rect, multiple: u32) -> Result<ImgSize, RusimgError> {
+        self.check_strict_trim(&rect)?;
+        let size = self.logged("trim_multiple_of", |img| {
+            let area = backend::multiple_of_rect(img.data.get_size()?, &rect, multiple)?;
+            let size = img.data.trim_multiple_of(rect, multiple)?;
+            img.track_trim(area.x, area.y);
+            Ok(size)
+        })?;
+        Ok(size)
+    }
 
-        let new_image: Box<(dyn BackendTrait)> = match new_extension {
-            Extension::Bmp => {
-                backend::convert_to_bmp_image(dynamic_image, filepath, metadata)?
-            },
-            Extension::Jpeg => {
-                backend::convert_to_jpeg_image(dynamic_image, filepath, metadata)?
-            },
-            Extension::Jpg => {
-                backend::convert_to_jpeg_image(dynamic_image, filepath, metadata)?
-            },
-            Extension::Png => {
-                backend::convert_to_png_image(dynamic_image, filepath, metadata)?
-            },
-            Extension::Webp => {
-                backend::convert_to_webp_image(dynamic_image, filepath, metadata)?
-            },
-            Extension::ExternalFormat(_) => return Err(RusimgError::UnsupportedFileExtension),
-        };
+    /// Rotate an image clockwise by an arbitrary angle (e.g. to deskew a scanned document).
+    /// The canvas is expanded to fit the rotated image, and the corners are filled with ``fill`` (RGBA).
+    /// Bilinear interpolation is used; use ``rotate_arbitrary_with()`` to choose another one.
+    /// It must be called after open_image().
+    /// This uses the ``rotate_arbitrary()`` function from ``BackendTrait``.
+    pub fn rotate_arbitrary(&mut self, degrees: f32, fill: [u8; 4]) -> Result<ImgSize, RusimgError> {
+        self.rotate_arbitrary_with(degrees, fill, RotateInterpolation::default())
+    }
 
-        self.extension = new_extension.clone();
-        self.data = new_image;
+    /// Rotate an image clockwise by an arbitrary angle with the given interpolation.
+    /// It must be called after open_image().
+    /// This uses the ``rotate_arbitrary()`` function from ``BackendTrait``.
+    pub fn rotate_arbitrary_with(&mut self, degrees: f32, fill: [u8; 4], interpolation: RotateInterpolation) -> Result<ImgSize, RusimgError> {
+        if !degrees.is_finite() {
+            return Err(RusimgError::InvalidRotationAngle);
+        }
+        let size = self.logged("rotate_arbitrary", |img| img.data.rotate_arbitrary(degrees, fill, interpolation))?;
+        self.coordinate_space = None;
+        Ok(size)
+    }
 
-        Ok(())
+    /// Straighten a skewed document image (e.g. for OCR preprocessing).
+    /// The skew angle of the text lines is detected within ±``max_angle`` degrees (up to 45) and corrected with ``rotate_arbitrary()``.
+    /// The uncovered corners are filled with white. This returns the detected skew angle in degrees (clockwise).
+    /// It must be called after open_image().
+    /// This uses the ``deskew()`` function from ``BackendTrait``.
+    pub fn deskew(&mut self, max_angle: f32) -> Result<f32, RusimgError> {
+        if !max_angle.is_finite() || max_angle <= 0.0 || max_angle > 45.0 {
+            return Err(RusimgError::InvalidRotationAngle);
+        }
+        let angle = self.logged("deskew", |img| img.data.deskew(max_angle))?;
+        if angle != 0.0 {
+            self.coordinate_space = None;
+        }
+        Ok(angle)
     }
 
-    /// Set a ``image::DynamicImage`` to an RusImg.
-    /// After setting the image, the image object will be updated.
-    /// This uses the ``set_dynamic_image()`` function from ``BackendTrait``.
-    pub fn set_dynamic_image(&mut self, image: DynamicImage) -> Result<(), RusimgError> {
-        self.data.set_dynamic_image(image)?;
-        Ok(())
+    /// Crop an image to the given size, keeping the most detailed region.
+    /// Every crop window of the size is scored by its edge density and the highest-scoring one is kept.
+    /// It must be called after open_image().
+    /// This uses the ``get_dynamic_image()`` function to find the window and ``trim()`` to crop it.
+    pub fn smart_crop(&mut self, target_w: u32, target_h: u32) -> Result<ImgSize, RusimgError> {
+        let dynamic_image = self.data.get_dynamic_image()?;
+        let trim_area = operations::salient_window(&dynamic_image, target_w, target_h).ok_or(RusimgError::InvalidTrimXY)?;
+        let (x, y) = (trim_area.x, trim_area.y);
+        let size = self.logged("smart_crop", |img| img.data.trim(trim_area))?;
+        self.track_trim(x, y);
+        Ok(size)
     }
 
-    /// Get a ``image::DynamicImage`` from an RusImg.
+    /// Find the bounding box of the content of an image, i.e. the pixels that differ from the background, without modifying the image.
+    /// The background is the most common color of the border pixels, and pixels within ``tolerance`` of it in every channel are background.
+    /// The result can be inspected before passing it to ``trim_rect()``. A ``ContentNotFound`` error is returned if the whole image is background.
     /// This uses the ``get_dynamic_image()`` function from ``BackendTrait``.
-    pub fn get_dynamic_image(&mut self) -> Result<DynamicImage, RusimgError> {
+    pub fn content_bounds(&self, tolerance: u8) -> Result<Rect, RusimgError> {
         let dynamic_image = self.data.get_dynamic_image()?;
-        Ok(dynamic_image)
+        operations::content_bounds(&dynamic_image, tolerance).ok_or(RusimgError::ContentNotFound)
     }
 
-    /// Get file extension.
-    /// This returns the file extension of the image.
-    pub fn get_extension(&self) -> Extension {
-        self.extension.clone()
+    /// Grayscale an image.
+    /// The built-in backends keep the bit depth of 16-bit sources, and convert 8-bit RGB images on multiple threads with the ``parallel`` feature.
+    /// It must be called after open_image().
+    /// This uses the ``grayscale()`` function from ``BackendTrait``.
+    pub fn grayscale(&mut self) -> Result<(), RusimgError> {
+        self.logged("grayscale", |img| {
+            img.data.grayscale();
+            Ok(())
+        })
     }
 
-    /// Get input file path.
-    /// This returns the file path of the image.
-    pub fn get_input_filepath(&self) -> Result<PathBuf, RusimgError> {
-        self.data.get_source_filepath().ok_or(RusimgError::DestinationPathMustBeSpecified)
+    /// Grayscale an image, choosing the bit depth of the result.
+    /// With ``preserve_depth``, 16-bit sources (e.g. 16-bit PNG) become 16-bit grayscale; otherwise the result is always 8-bit grayscale.
+    /// It must be called after open_image().
+    /// This uses the ``grayscale_preserve_depth()`` function from ``BackendTrait``, or ``set_dynamic_image()`` for the 8-bit output.
+    pub fn grayscale_with_depth(&mut self, preserve_depth: bool) -> Result<(), RusimgError> {
+        self.logged("grayscale_with_depth", |img| {
+            if preserve_depth {
+                img.data.grayscale_preserve_depth()
+            }
+            else {
+                let image = img.data.get_dynamic_image()?;
+                img.data.set_dynamic_image(operations::grayscale(&image, false))
+            }
+        })
     }
 
-    /// Save an image to a file.
-    /// If path is None, the original file will be overwritten.
-    /// This uses the ``get_destination_filepath()`` to get the destination file path, ``get_metadata_src()`` to get the source file size, and ``get_metadata_dest()`` to get the destination file size, and ``save()`` to save the image.
-    pub fn save_image(&mut self, path: Option<&str>) -> Result<SaveStatus, RusimgError> {
-        let path_buf = match path {
-            Some(p) => Some(PathBuf::from(p)),
-            None => None,
-        };
-        self.data.save(path_buf)?;
-
-        let ret = SaveStatus {
-            output_path: self.data.get_destination_filepath()?.clone().or(None),
-            before_filesize: if let Some(m) = self.data.get_metadata_src() {
-                Some(m.len())
-            } else {
-                None
-            },
-            after_filesize: if let Some(m) = self.data.get_metadata_dest() {
-                Some(m.len())
-            } else {
-                None
-            },
-        };
-        Ok(ret)
+    /// Apply a function to each pixel of an image.
+    /// The image is converted to RGBA8, and each pixel is replaced with the result of the function.
+    /// It must be called after open_image().
+    /// This uses the ``map_pixels()`` function from ``BackendTrait``.
+    pub fn map_pixels<F>(&mut self, mut f: F) -> Result<(), RusimgError>
+    where F: FnMut([u8; 4]) -> [u8; 4] {
+        self.logged("map_pixels", |img| img.data.map_pixels(&mut f))?;
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::Path;
-    use image::{ImageBuffer, Rgb};
+    /// Posterize an image by quantizing each RGB channel to the given number of levels (2 or more).
+    /// It must be called after open_image().
+    /// This uses the ``posterize()`` function from ``BackendTrait``.
+    pub fn posterize(&mut self, levels_per_channel: u8) -> Result<(), RusimgError> {
+        self.logged("posterize", |img| img.data.posterize(levels_per_channel))?;
+        Ok(())
+    }
 
-    // Generate a test image with the specified filename, width, and height.
-    fn generate_test_image(filename: &str, width: u32, height: u32) {
-        let mut img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
-        for x in 0..width {
-            for y in 0..height {
-                let r = (x * 3) as u8;
-                let g = (y * 5) as u8;
-                let b = (x * y) as u8;
-                img.put_pixel(x, y, Rgb([r, g, b]));
-            }
+    /// Reduce an image to at most ``max_colors`` colors (1 - 256) and save it with indexed colors, which is much smaller for graphics with few colors.
+    /// PNG images are saved with a palette (PLTE chunk), and WebP images are encoded losslessly, which uses the palette mode of WebP.
+    /// The colors are chosen by ``operations::quantize()``; the alpha values are kept, so images with many alpha values may be saved without a palette.
+    /// Only PNG and WebP images support it; other formats return an ``UnsupportedFeature`` error and are left untouched.
+    /// This uses the ``set_indexed()``, ``get_dynamic_image()`` and ``set_dynamic_image()`` functions from ``BackendTrait``.
+    pub fn to_indexed(&mut self, max_colors: u16) -> Result<(), RusimgError> {
+        if !(1..=256).contains(&max_colors) {
+            return Err(RusimgError::InvalidPaletteSize);
         }
-        let mut test_image = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(img.clone())).unwrap();
-        test_image.save_image(Some(filename)).unwrap();
+        self.logged("to_indexed", |img| {
+            img.data.set_indexed(true)?;
+            let image = img.data.get_dynamic_image()?;
+            img.data.set_dynamic_image(operations::quantize(&image, max_colors as usize))
+        })?;
+        Ok(())
     }
 
-    #[test]
-    fn test_open_image() {
-        let filename = "test_image1.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let result = RusImg::open(path);
-        assert!(result.is_ok());
-        std::fs::remove_file(filename).unwrap();
+    /// Multiply the alpha channel of an image by ``factor`` (0.0 - 1.0), e.g. for fading overlays.
+    /// RGB images are converted to RGBA first. JPEG and BMP images cannot store the alpha channel, so save the result as PNG or WebP.
+    /// It must be called after open_image().
+    /// This uses the ``set_opacity()`` function from ``BackendTrait``.
+    pub fn set_opacity(&mut self, factor: f32) -> Result<(), RusimgError> {
+        self.logged("set_opacity", |img| img.data.set_opacity(factor))?;
+        Ok(())
     }
 
-    #[test]
-    fn test_get_image_size() {
-        let filename = "test_image2.png";
-        let width = 100;
+    /// Convert an image from straight to premultiplied alpha (e.g. before compositing it).
+    /// The image is converted to RGBA8.
+    /// It must be called after open_image().
+    /// This uses the ``premultiply_alpha()`` function from ``BackendTrait``.
+    pub fn premultiply_alpha(&mut self) -> Result<(), RusimgError> {
+        self.logged("premultiply_alpha", |img| img.data.premultiply_alpha())?;
+        Ok(())
+    }
+
+    /// Convert an image from premultiplied to straight alpha.
+    /// The image is converted to RGBA8, and fully transparent pixels become transparent black.
+    /// It must be called after open_image().
+    /// This uses the ``unpremultiply_alpha()`` function from ``BackendTrait``.
+    pub fn unpremultiply_alpha(&mut self) -> Result<(), RusimgError> {
+        self.logged("unpremultiply_alpha", |img| img.data.unpremultiply_alpha())?;
+        Ok(())
+    }
+
+    /// Make the pixels outside the circle inscribed in an image transparent (e.g. for avatars).
+    /// The circle is centered, and its diameter is the shorter side of the image. The result is RGBA, so save it as PNG or WebP.
+    /// It must be called after open_image().
+    /// This uses the ``apply_circle_mask()`` function from ``BackendTrait``.
+    pub fn apply_circle_mask(&mut self) -> Result<(), RusimgError> {
+        self.logged("apply_circle_mask", |img| img.data.apply_circle_mask())?;
+        Ok(())
+    }
+
+    /// Round the corners of an image with the radius (in pixels), making the pixels outside them transparent.
+    /// The result is RGBA, so save it as PNG or WebP.
+    /// It must be called after open_image().
+    /// This uses the ``apply_rounded_corners()`` function from ``BackendTrait``.
+    pub fn apply_rounded_corners(&mut self, radius: u32) -> Result<(), RusimgError> {
+        self.logged("apply_rounded_corners", |img| img.data.apply_rounded_corners(radius))?;
+        Ok(())
+    }
+
+    /// Remove the alpha channel of an image.
+    /// LumaA images become Luma, and RGBA images become RGB.
+    /// It must be called after open_image().
+    /// This uses the ``remove_alpha_channel()`` function from ``BackendTrait``.
+    pub fn remove_alpha_channel(&mut self) -> Result<(), RusimgError> {
+        self.logged("remove_alpha_channel", |img| img.data.remove_alpha_channel())?;
+        Ok(())
+    }
+
+    /// Drop the alpha channel of an image if every pixel is fully opaque, which makes e.g. RGBA PNG files about 25% smaller as RGB.
+    /// This returns whether the alpha channel was dropped; images with transparent pixels are left as they are.
+    /// It must be called after open_image().
+    /// This uses the ``drop_redundant_alpha()`` function from ``BackendTrait``.
+    pub fn drop_redundant_alpha(&mut self) -> bool {
+        self.data.drop_redundant_alpha()
+    }
+
+    /// Convert an image to bilevel black and white with the threshold level.
+    /// PNG images converted by this are saved as 1-bit PNG.
+    /// It must be called after open_image().
+    /// This uses the ``threshold()`` function from ``BackendTrait``.
+    pub fn threshold(&mut self, level: u8) -> Result<(), RusimgError> {
+        self.logged("threshold", |img| img.data.threshold(level))?;
+        Ok(())
+    }
+
+    /// Convert an image to bilevel black and white with the threshold level chosen automatically by Otsu's method.
+    /// This returns the chosen level.
+    /// It must be called after open_image().
+    /// This uses the ``threshold_otsu()`` function from ``BackendTrait``.
+    pub fn threshold_otsu(&mut self) -> Result<u8, RusimgError> {
+        let level = self.logged("threshold_otsu", |img| img.data.threshold_otsu())?;
+        Ok(level)
+    }
+
+    /// Replace an image with its edge map (e.g. for masks and image analysis).
+    /// The image is converted to grayscale, and the edges become bright by the Sobel filter.
+    /// It must be called after open_image().
+    /// This uses the ``sobel_edges()`` function from ``BackendTrait``.
+    pub fn sobel_edges(&mut self) -> Result<(), RusimgError> {
+        self.logged("sobel_edges", |img| img.data.sobel_edges())?;
+        Ok(())
+    }
+
+    /// Tone-map an HDR (floating-point) image to 8-bit pixels, e.g. before saving it in an 8-bit format.
+    /// Converting it directly clamps the values above 1.0 and blows out the highlights; the operator compresses them instead.
+    /// Images without floating-point pixels are left unchanged.
+    /// It must be called after open_image().
+    /// This uses the ``tonemap()`` function from ``BackendTrait``.
+    pub fn tonemap(&mut self, method: ToneMapMethod) -> Result<(), RusimgError> {
+        self.logged("tonemap", |img| img.data.tonemap(method))?;
+        Ok(())
+    }
+
+    /// Equalize the histogram of an image's luminance, e.g. to bring out the details of low-contrast scanned or medical images.
+    /// Unlike a simple contrast stretch, the brightness levels are spread by how many pixels use them.
+    /// The luminance is equalized in YCbCr, so the colors are kept.
+    /// It must be called after open_image().
+    /// This uses the ``equalize_histogram()`` function from ``BackendTrait``.
+    pub fn equalize_histogram(&mut self) -> Result<(), RusimgError> {
+        self.logged("equalize_histogram", |img| img.data.equalize_histogram())?;
+        Ok(())
+    }
+
+    /// Apply a convolution kernel of ``kernel_width`` x ``kernel_height`` to an image, e.g. for blur, sharpen or emboss effects.
+    /// Each RGB channel becomes ``sum(kernel * pixels) / divisor + bias`` (``bias`` in 8-bit units), and the alpha channel is kept.
+    /// For example, a 3x3 box blur is ``convolve(&[1.0; 9], 3, 3, 9.0, 0.0)``.
+    /// It must be called after open_image().
+    /// This uses the ``convolve()`` function from ``BackendTrait``.
+    pub fn convolve(&mut self, kernel: &[f32], kernel_width: u32, kernel_height: u32, divisor: f32, bias: f32) -> Result<(), RusimgError> {
+        self.logged("convolve", |img| img.data.convolve(kernel, kernel_width, kernel_height, divisor, bias))?;
+        Ok(())
+    }
+
+    /// Compress an image.
+    /// It must be called after open_image().
+    /// Set quality to 100 to keep the original quality.
+    /// This uses the ``compress()`` function from ``BackendTrait``.
+    pub fn compress(&mut self, quality: Option<f32>) -> Result<(), RusimgError> {
+        if quality.is_some() && (quality.unwrap() < 0.0 || quality.unwrap() > 100.0) {
+            return Err(RusimgError::InvalidCompressionLevel);
+        }
+
+        self.logged("compress", |img| img.data.compress(quality))?;
+        Ok(())
+    }
+
+    /// Compress an image like ``compress()``, unless ``token`` has been cancelled.
+    /// ``compress()`` only records the quality, and the encoders (including oxipng) run when the image is saved,
+    /// so use ``save_image_cancellable()`` with the same token to cancel the compression itself.
+    pub fn compress_cancellable(&mut self, quality: Option<f32>, token: &CancelToken) -> Result<(), RusimgError> {
+        token.check()?;
+        self.compress(quality)
+    }
+
+    /// Make an image smaller losslessly with the strongest settings of its format, keeping the pixels exactly.
+    /// A fully opaque alpha channel is dropped by ``drop_redundant_alpha()``.
+    /// PNG images are optimized with oxipng level 6 with the metadata stripped (the bKGD, gAMA and sRGB chunks are kept), and WebP images are encoded losslessly.
+    /// BMP images return a ``CannotOptimize`` error suggesting converting them to PNG,
+    /// and so do JPEG images, because the JPEG encoder cannot optimize them without re-quantizing.
+    /// This uses the ``optimize()`` function from ``BackendTrait``.
+    pub fn optimize(&mut self) -> Result<(), RusimgError> {
+        self.logged("optimize", |img| {
+            img.data.optimize()?;
+            img.data.drop_redundant_alpha();
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// Compress a PNG image with an oxipng preset level (0 - 6) for exact control.
+    /// ``compress()`` maps the quality 0 - 100 onto the levels 1 - 6 (e.g. every quality above 85 is level 6), so many qualities give the same output;
+    /// this takes the level directly. Higher levels are slower and give smaller files.
+    /// Only PNG images support it.
+    /// This uses the ``compress_png_level()`` function from ``BackendTrait``.
+    pub fn compress_png_level(&mut self, level: u8) -> Result<(), RusimgError> {
+        self.logged("compress_png_level", |img| img.data.compress_png_level(level))?;
+        Ok(())
+    }
+
+    /// Set the WebP encoding method.
+    /// 0 is the fastest and 6 is the slowest but gives the best compression.
+    /// It must be called after open_image(), and only WebP images support it.
+    /// This uses the ``set_webp_method()`` function from ``BackendTrait``.
+    pub fn set_webp_method(&mut self, method: u8) -> Result<(), RusimgError> {
+        self.data.set_webp_method(method)?;
+        Ok(())
+    }
+
+    /// Set whether to save a BMP image with RLE8 compression.
+    /// RLE8 stores the image as 8-bit palette indices, so it is only used for images with at most 256 colors and no transparency;
+    /// other images are saved uncompressed. RLE-compressed BMP files are decoded on open as usual.
+    /// Only BMP images support it.
+    /// This uses the ``set_bmp_rle()`` function from ``BackendTrait``.
+    pub fn set_bmp_rle(&mut self, on: bool) -> Result<(), RusimgError> {
+        self.data.set_bmp_rle(on)?;
+        Ok(())
+    }
+
+    /// Get the GPS coordinates where the photo was taken, read from the EXIF data.
+    /// This returns the latitude and longitude in decimal degrees (south and west are negative), or None if the image has no GPS data.
+    /// This uses the ``gps_location()`` function from ``BackendTrait``.
+    pub fn gps_location(&self) -> Option<(f64, f64)> {
+        self.data.gps_location()
+    }
+
+    /// Get the JPEG thumbnail embedded in the EXIF data of the image, if any.
+    /// It is much cheaper to decode than the full image, e.g. for instant previews in a gallery.
+    /// This uses the ``embedded_thumbnail()`` function from ``BackendTrait``.
+    pub fn embedded_thumbnail(&self) -> Option<Vec<u8>> {
+        self.data.embedded_thumbnail()
+    }
+
+    /// Check whether the source of the image is lossy.
+    /// JPEG returns true, PNG and BMP return false, and WebP checks whether the source file is lossy (VP8) or lossless (VP8L).
+    /// This returns None if it is unknown, e.g. for WebP images not opened from a file.
+    /// This uses the ``source_is_lossy()`` function from ``BackendTrait``.
+    pub fn source_is_lossy(&self) -> Option<bool> {
+        self.data.source_is_lossy()
+    }
+
+    /// Get the original encoded bytes read from the file.
+    /// PNG and WebP images opened from a file keep them; otherwise this returns None.
+    /// This uses the ``source_bytes()`` function from ``BackendTrait``.
+    pub fn source_bytes(&self) -> Option<&[u8]> {
+        self.data.source_bytes()
+    }
+
+    /// Get the number of frames of the image.
+    /// Animated WebP images return the number of frames in the file, and still images return 1.
+    /// This uses the ``frame_count()`` function from ``BackendTrait``.
+    pub fn frame_count(&self) -> Result<usize, RusimgError> {
+        self.data.frame_count()
+    }
+
+    /// Get the background color stored in the image file as RGBA.
+    /// PNG stores it in the bKGD chunk and animated WebP in the ANIM chunk.
+    /// This uses the ``get_background_color()`` function from ``BackendTrait``.
+    pub fn get_background_color(&self) -> Option<[u8; 4]> {
+        self.data.get_background_color()
+    }
+
+    /// Set the background color stored in the image file.
+    /// It is written when the image is saved. Formats without a background color ignore it.
+    /// This uses the ``set_background_color()`` function from ``BackendTrait``.
+    pub fn set_background_color(&mut self, color: [u8; 4]) -> Result<(), RusimgError> {
+        self.data.set_background_color(color);
+        Ok(())
+    }
+
+    /// Set the options for optimizing PNG images with oxipng.
+    /// The options are used by ``compress()``, so call this before it.
+    /// Only PNG images support it.
+    /// This uses the ``set_png_optimize_options()`` function from ``BackendTrait``.
+    pub fn set_png_optimize_options(&mut self, options: PngOptimizeOptions) -> Result<(), RusimgError> {
+        self.data.set_png_optimize_options(options)?;
+        Ok(())
+    }
+
+    /// Get the gamma of a PNG image from its gAMA chunk (e.g. 0.45455 for a display gamma of 2.2), for color-managed rendering.
+    /// Returns None if the image has no gAMA chunk or is not a PNG image.
+    /// This uses the ``get_png_gamma()`` function from ``BackendTrait``.
+    pub fn get_png_gamma(&self) -> Option<f32> {
+        self.data.get_png_gamma()
+    }
+
+    /// Check whether a PNG image has an sRGB chunk, i.e. it is in the sRGB color space.
+    /// This uses the ``has_srgb_chunk()`` function from ``BackendTrait``.
+    pub fn has_srgb_chunk(&self) -> bool {
+        self.data.has_srgb_chunk()
+    }
+
+    /// Set the gamma written to the gAMA chunk of a PNG image when saving.
+    /// Only PNG images support it.
+    /// This uses the ``set_png_gamma()`` function from ``BackendTrait``.
+    pub fn set_png_gamma(&mut self, gamma: f32) -> Result<(), RusimgError> {
+        self.data.set_png_gamma(gamma)?;
+        Ok(())
+    }
+
+    /// Get the XMP packet of an image (e.g. for licensing or content credentials).
+    /// Returns None if the image has no XMP packet or its format does not support XMP.
+    /// This uses the ``get_xmp()`` function from ``BackendTrait``.
+    pub fn get_xmp(&self) -> Option<String> {
+        self.data.get_xmp()
+    }
+
+    /// Set the XMP packet written to an image when saving, replacing the existing one.
+    /// JPEG, PNG and WebP images support it.
+    /// This uses the ``set_xmp()`` function from ``BackendTrait``.
+    pub fn set_xmp(&mut self, xmp: &str) -> Result<(), RusimgError> {
+        self.data.set_xmp(xmp)?;
+        Ok(())
+    }
+
+    /// Compress a PNG image with prebuilt options.
+    /// Build the options once (e.g. ``PngCompressOptions::from_preset(4)``) and pass them to every image in a batch,
+    /// instead of letting ``compress()`` rebuild them for each image.
+    /// Only PNG images support it.
+    /// This uses the ``compress_with_oxipng_options()`` function from ``BackendTrait``.
+    pub fn compress_with_options(&mut self, options: &PngCompressOptions) -> Result<(), RusimgError> {
+        self.logged("compress_with_options", |img| img.data.compress_with_oxipng_options(options))?;
+        Ok(())
+    }
+
+    /// Convert an image to another format.
+    /// And replace the original image with the new one.
+    /// It must be called after open_image().
+    /// This uses the ``get_dynamic_image()`` function to get the DynamicImage object, ``get_metadata_src()`` to get the metadata, and ``compress()`` to compress the image.
+    pub fn convert(&mut self, new_extension: &Extension) -> Result<(), RusimgError> {
+        let timer = Timer::start();
+        let new_image = self.convert_backend(new_extension)?;
+        log_debug!("convert: {} -> {} in {:.1} ms", self.extension, new_extension, timer.elapsed_ms());
+
+        self.extension = new_extension.clone();
+        self.data = new_image;
+
+        Ok(())
+    }
+
+    /// Convert an image to another format and return it as a new RusImg object.
+    /// Unlike ``convert()``, the original image is left untouched.
+    /// This uses the same functions as ``convert()``.
+    pub fn converted(&self, new_extension: &Extension) -> Result<RusImg, RusimgError> {
+        let new_image = self.convert_backend(new_extension)?;
+
+        Ok(RusImg {
+            extension: new_extension.clone(),
+            data: new_image,
+            linear_resize: self.linear_resize,
+            extension_mismatch_policy: self.extension_mismatch_policy,
+            strict_trim: self.strict_trim,
+            coordinate_space: self.coordinate_space,
+            config: self.config.clone(),
+        })
+    }
+
+    /// Crop a region of an image into a new RusImg object, leaving the original untouched.
+    /// The region must be inside the image, otherwise an ``InvalidTrimXY`` error is returned.
+    /// The new image has the same format and settings, but no source file, so a destination path must be given to save it.
+    /// This uses the ``get_dynamic_image()`` function from ``BackendTrait``.
+    pub fn crop_region(&self, rect: Rect) -> Result<RusImg, RusimgError> {
+        let size = self.data.get_size()?;
+        let fits = |start: u32, len: u32, max: usize| len > 0 && (start as u64 + len as u64) <= max as u64;
+        if !fits(rect.x, rect.w, size.width) || !fits(rect.y, rect.h, size.height) {
+            return Err(RusimgError::InvalidTrimXY);
+        }
+        let dynamic_image = self.data.get_dynamic_image()?;
+        self.derived(dynamic_image.crop_imm(rect.x, rect.y, rect.w, rect.h))
+    }
+
+    /// Split an image into a grid of ``cols`` x ``rows`` tiles, returned in row-major order.
+    /// Each tile has ``width / cols`` x ``height / rows`` pixels, and the tiles of the last column and row also take the remainder pixels.
+    /// The tiles are independent RusImg objects cropped like ``crop_region()``, and the original is left untouched.
+    pub fn split_tiles(&self, cols: u32, rows: u32) -> Result<Vec<RusImg>, RusimgError> {
+        let size = self.data.get_size()?;
+        if cols == 0 || rows == 0 || cols as usize > size.width || rows as usize > size.height {
+            return Err(RusimgError::InvalidTileGrid);
+        }
+        let (tile_w, tile_h) = (size.width as u32 / cols, size.height as u32 / rows);
+        // The image is fetched once and each tile is cropped from it.
+        let dynamic_image = self.data.get_dynamic_image()?;
+        let mut tiles = Vec::with_capacity(cols as usize * rows as usize);
+        for row in 0..rows {
+            for col in 0..cols {
+                let (x, y) = (col * tile_w, row * tile_h);
+                let w = if col == cols - 1 { size.width as u32 - x } else { tile_w };
+                let h = if row == rows - 1 { size.height as u32 - y } else { tile_h };
+                let rect = Rect { x, y, w, h };
+                self.check_strict_trim(&rect)?;
+                tiles.push(self.derived(dynamic_image.crop_imm(rect.x, rect.y, rect.w, rect.h))?);
+            }
+        }
+        Ok(tiles)
+    }
+
+    /// Apply ``f`` to successive horizontal bands of ``band_height`` rows, from the top (e.g. for per-row adjustments of a large image).
+    /// The image is copied out of the backend once, and each band of the copy is converted to an RGBA8 buffer for ``f`` and written back before the next one.
+    /// So the memory needed is one copy of the image in its own color type plus one RGBA8 band, instead of an RGBA8 copy of the whole image.
+    /// The last band has the remaining rows. ``f`` must keep the size of the band.
+    /// The image keeps its color type. Only the bands changed by ``f`` are written back, so with more than 8 bits per channel,
+    /// the changed bands are rounded to 8 bits and the others keep their full depth.
+    /// This uses the ``get_dynamic_image()`` and ``set_dynamic_image()`` functions from ``BackendTrait``.
+    pub fn process_in_bands<F>(&mut self, band_height: u32, mut f: F) -> Result<(), RusimgError>
+    where F: FnMut(&mut image::RgbaImage) {
+        use image::GenericImage;
+        if band_height == 0 {
+            return Err(RusimgError::InvalidBandHeight);
+        }
+        self.logged("process_in_bands", |img| {
+            let mut image = img.data.get_dynamic_image()?;
+            let (width, height) = (image.width(), image.height());
+            for y in (0..height).step_by(band_height as usize) {
+                let rows = band_height.min(height - y);
+                let original = image.crop_imm(0, y, width, rows).to_rgba8();
+                let mut band = original.clone();
+                f(&mut band);
+                if band.dimensions() != (width, rows) {
+                    return Err(RusimgError::FailedToCopyBinaryData("the size of a band was changed".to_string()));
+                }
+                if band != original {
+                    image.copy_from(&band, 0, y).map_err(|e| RusimgError::FailedToCopyBinaryData(e.to_string()))?;
+                }
+            }
+            img.data.set_dynamic_image(image)
+        })
+    }
+
+    // Make a new RusImg object of the same format and settings from an image derived from this one.
+    fn derived(&self, image: DynamicImage) -> Result<RusImg, RusimgError> {
+        let mut new_img = backend::new_image(&self.extension, image)?;
+        new_img.linear_resize = self.linear_resize;
+        new_img.extension_mismatch_policy = self.extension_mismatch_policy;
+        new_img.strict_trim = self.strict_trim;
+        new_img.set_config(&self.config)?;
+        Ok(new_img)
+    }
+
+    // Make a new backend object of the new extension from the current image.
+    fn convert_backend(&self, new_extension: &Extension) -> Result<Box<dyn BackendTrait>, RusimgError> {
+        let dynamic_image = self.data.get_dynamic_image()?;
+        let filepath = self.data.get_source_filepath();
+        let metadata = self.data.get_metadata_src();
+
+        let mut new_image: Box<(dyn BackendTrait)> = match new_extension {
+            Extension::Bmp => {
+                backend::convert_to_bmp_image(dynamic_image, filepath, metadata)?
+            },
+            Extension::Jpeg => {
+                backend::convert_to_jpeg_image(dynamic_image, filepath, metadata)?
+            },
+            Extension::Jpg => {
+                backend::convert_to_jpeg_image(dynamic_image, filepath, metadata)?
+            },
+            Extension::Png => {
+                backend::convert_to_png_image(dynamic_image, filepath, metadata)?
+            },
+            Extension::Webp => {
+                backend::convert_to_webp_image(dynamic_image, filepath, metadata)?
+            },
+            Extension::ExternalFormat(_) => return Err(RusimgError::UnsupportedFileExtension),
+        };
+        new_image.set_config(&self.config);
+        Ok(new_image)
+    }
+
+    /// Set a ``image::DynamicImage`` to an RusImg.
+    /// After setting the image, the image object will be updated.
+    /// This uses the ``set_dynamic_image()`` function from ``BackendTrait``.
+    pub fn set_dynamic_image(&mut self, image: DynamicImage) -> Result<(), RusimgError> {
+        let before = self.data.get_size()?;
+        self.data.set_dynamic_image(image)?;
+        if self.data.get_size()? != before {
+            self.coordinate_space = None;
+        }
+        Ok(())
+    }
+
+    /// Get a ``image::DynamicImage`` from an RusImg.
+    /// This uses the ``get_dynamic_image()`` function from ``BackendTrait``.
+    pub fn get_dynamic_image(&mut self) -> Result<DynamicImage, RusimgError> {
+        let dynamic_image = self.data.get_dynamic_image()?;
+        Ok(dynamic_image)
+    }
+
+    /// Get file extension.
+    /// This returns the file extension of the image.
+    pub fn get_extension(&self) -> Extension {
+        self.extension.clone()
+    }
+
+    /// Get input file path.
+    /// This returns the file path of the image.
+    pub fn get_input_filepath(&self) -> Result<PathBuf, RusimgError> {
+        self.data.get_source_filepath().ok_or(RusimgError::DestinationPathMustBeSpecified)
+    }
+
+    /// Set what ``save_image()`` does when the extension of the output path differs from the image format.
+    /// By default, the image is converted to the format of the output path.
+    pub fn set_extension_mismatch_policy(&mut self, policy: ExtensionMismatchPolicy) {
+        self.extension_mismatch_policy = policy;
+    }
+
+    /// Save an image to a file.
+    /// If path is None, the original file will be overwritten.
+    /// If the extension of the path is a different image format from the image, the image is converted to it or an error is returned, depending on ``set_extension_mismatch_policy()``.
+    /// This uses the ``get_destination_filepath()`` to get the destination file path, ``get_metadata_src()`` to get the source file size, and ``get_metadata_dest()`` to get the destination file size, and ``save()`` to save the image.
+    pub fn save_image(&mut self, path: Option<&str>) -> Result<SaveStatus, RusimgError> {
+        self.save_image_with(path, |data, path_buf| data.save(path_buf))
+    }
+
+    // Save an image with ``save``, which is ``save()``, ``save_cancellable()`` or ``save_no_overwrite()`` of the backend.
+    fn save_image_with<F>(&mut self, path: Option<&str>, save: F) -> Result<SaveStatus, RusimgError>
+    where F: FnOnce(&mut dyn BackendTrait, Option<PathBuf>) -> Result<(), RusimgError> {
+        let path_buf = match path {
+            Some(p) => Some(PathBuf::from(p)),
+            None => None,
+        };
+        if let Some(path_extension) = path_buf.as_deref().and_then(Extension::from_path) {
+            if !path_extension.is_same_format(&self.extension) {
+                match self.extension_mismatch_policy {
+                    ExtensionMismatchPolicy::Convert => self.convert(&path_extension)?,
+                    ExtensionMismatchPolicy::Error => return Err(RusimgError::ExtensionMismatch(self.extension.clone(), path_extension)),
+                }
+            }
+        }
+        let timer = Timer::start();
+        if let Err(e) = save(self.data.as_mut(), path_buf) {
+            // Attach the destination file path to the error if it can be resolved (a cancellation is not a failure of the file,
+            // and ``FileAlreadyExists`` has the path already).
+            return Err(match self.resolve_destination_filepath(path) {
+                Ok(destination) if e != RusimgError::Cancelled && !matches!(e, RusimgError::FileAlreadyExists(_)) => e.with_path(&destination),
+                _ => e,
+            });
+        }
+
+        let ret = SaveStatus {
+            output_path: self.data.get_destination_filepath()?.clone().or(None),
+            before_filesize: if let Some(m) = self.data.get_metadata_src() {
+                Some(m.len())
+            } else {
+                None
+            },
+            after_filesize: if let Some(m) = self.data.get_metadata_dest() {
+                Some(m.len())
+            } else {
+                None
+            },
+            source_modified: self.data.source_modified(),
+        };
+        log_info!("saved {:?} ({:?} -> {:?} bytes) in {:.1} ms", ret.output_path, ret.before_filesize, ret.after_filesize, timer.elapsed_ms());
+        Ok(ret)
+    }
+
+    /// Save an image like ``save_image()``, then read the output file back and check that it decodes to the same dimensions.
+    /// This catches files broken by the disk or the encoder, e.g. for archival jobs; if the check fails, a ``VerificationFailed`` error with the output path is returned.
+    /// The output file is left as written either way.
+    /// This uses ``save_image()`` to save the image.
+    pub fn save_image_verified(&mut self, path: Option<&str>) -> Result<SaveStatus, RusimgError> {
+        let status = self.save_image(path)?;
+        let output_path = status.output_path.clone().ok_or(RusimgError::DestinationPathMustBeSpecified)?;
+        let verify = || -> Result<(), String> {
+            let buf = std::fs::read(&output_path).map_err(|e| e.to_string())?;
+            let decoded = image::load_from_memory(&buf).map_err(|e| e.to_string())?;
+            let size = self.data.get_size().map_err(|e| e.to_string())?;
+            if (decoded.width() as usize, decoded.height() as usize) != (size.width, size.height) {
+                return Err(format!("decoded to {}x{} instead of {}x{}", decoded.width(), decoded.height(), size.width, size.height));
+            }
+            Ok(())
+        };
+        verify().map_err(|message| RusimgError::VerificationFailed(message).with_path(&output_path))?;
+        Ok(status)
+    }
+
+    /// Save an image like ``save_image()``, returning a ``Cancelled`` error if ``token`` is cancelled while saving.
+    /// The built-in backends check the token between encoding, the oxipng pass (PNG) and writing the file, and a cancelled save writes nothing.
+    /// Each stage itself (e.g. a single oxipng run) is not interrupted, so cancelling takes effect when the running stage ends.
+    /// This uses the ``save_cancellable()`` function from ``BackendTrait``.
+    pub fn save_image_cancellable(&mut self, path: Option<&str>, token: &CancelToken) -> Result<SaveStatus, RusimgError> {
+        token.check()?;
+        self.save_image_with(path, |data, path_buf| data.save_cancellable(path_buf, token))
+    }
+
+    /// Encode an image in the format of ``extension`` and return it as a ``data:`` URL (e.g. for embedding it in HTML).
+    /// ``quality`` is passed to ``compress()`` before encoding if specified. The image itself is left untouched.
+    /// This requires the ``base64`` feature.
+    /// This uses the ``encode_to_memory()`` function from ``BackendTrait``.
+    #[cfg(feature="base64")]
+    pub fn to_data_url(&self, extension: &Extension, quality: Option<f32>) -> Result<String, RusimgError> {
+        use base64::Engine;
+        let mime_type = extension.mime_type().ok_or(RusimgError::UnsupportedFileExtension)?;
+        let data = if extension.is_same_format(&self.extension) && quality.is_none() {
+            self.data.encode_to_memory()?
+        }
+        else {
+            let mut target = self.converted(extension)?;
+            if quality.is_some() {
+                target.compress(quality)?;
+            }
+            target.data.encode_to_memory()?
+        };
+        Ok(format!("data:{};base64,{}", mime_type, base64::engine::general_purpose::STANDARD.encode(data)))
+    }
+
+    /// Encode an image in the format of ``extension`` with each quality, e.g. for drawing a quality/size tradeoff chart.
+    /// This returns (quality, encoded size in bytes, PSNR in dB against the current image) for each quality; lossless results have an infinite PSNR.
+    /// It is meaningful for lossy formats (JPEG and WebP). The image itself is left untouched.
+    /// This uses the ``compress()`` and ``encode_to_memory()`` functions from ``BackendTrait``.
+    pub fn quality_sweep(&self, extension: &Extension, qualities: &[f32]) -> Result<Vec<(f32, u64, f64)>, RusimgError> {
+        if qualities.iter().any(|q| !(0.0..=100.0).contains(q)) {
+            return Err(RusimgError::InvalidCompressionLevel);
+        }
+        let original = self.data.get_dynamic_image()?;
+        qualities.iter().map(|&quality| {
+            let mut target = self.converted(extension)?;
+            target.compress(Some(quality))?;
+            let encoded = target.data.encode_to_memory()?;
+            let decoded = image::load_from_memory(&encoded).map_err(|e| RusimgError::FailedToOpenImage(e.to_string()))?;
+            Ok((quality, encoded.len() as u64, operations::psnr(&original, &decoded)))
+        }).collect()
+    }
+
+    /// Save an image as WebP with a file size close to ``target_bytes``.
+    /// The WebP quality is binary-searched by encoding the image in memory until the size is within ``tolerance`` (e.g. 0.1 for ±10%) of the target.
+    /// If no quality reaches the tolerance, the quality giving the closest size is used.
+    /// Images of other formats are converted to WebP first.
+    /// This uses the ``encoded_size()`` function from ``BackendTrait`` for the search, and ``save_image()`` to save the image.
+    pub fn save_webp_target_size(&mut self, path: &str, target_bytes: u64, tolerance: f32) -> Result<SaveStatus, RusimgError> {
+        if target_bytes == 0 || !(0.0..1.0).contains(&tolerance) {
+            return Err(RusimgError::InvalidTargetSize);
+        }
+        if !self.extension.is_same_format(&Extension::Webp) {
+            self.convert(&Extension::Webp)?;
+        }
+
+        let distance = |size: u64| -> f64 { (size as f64 - target_bytes as f64).abs() / target_bytes as f64 };
+        let (mut low, mut high) = (0.0f32, 100.0f32);
+        let mut best: Option<(f32, f64)> = None;
+        // 8 steps narrow the quality down to less than 0.5.
+        for _ in 0..8 {
+            let quality = (low + high) / 2.0;
+            let size = self.data.encoded_size(quality)?;
+            if best.map_or(true, |(_, d)| distance(size) < d) {
+                best = Some((quality, distance(size)));
+            }
+            if distance(size) <= tolerance as f64 {
+                break;
+            }
+            if size > target_bytes {
+                high = quality;
+            }
+            else {
+                low = quality;
+            }
+        }
+
+        let quality = best.map(|(q, _)| q).unwrap_or(75.0);
+        self.compress(Some(quality))?;
+        self.save_image(Some(path))
+    }
+
+    /// Save an image to a file and set the modification time of the output file to that of the source file.
+    /// If the image has no source file metadata, the modification time is left as it is.
+    /// This uses ``save_image()`` to save the image, and the ``source_modified()`` function from ``BackendTrait``.
+    pub fn save_preserving_mtime(&mut self, path: Option<&str>) -> Result<SaveStatus, RusimgError> {
+        let status = self.save_image(path)?;
+        if let (Some(output_path), Some(modified)) = (&status.output_path, status.source_modified) {
+            filetime::set_file_mtime(output_path, filetime::FileTime::from_system_time(modified))
+                .map_err(|e| RusimgError::FailedToWriteFIle(e.to_string()))?;
+        }
+        Ok(status)
+    }
+
+    /// Save an image to a file, creating the missing parent directories of the path first.
+    /// Unlike ``save_image()``, the destination file path must be specified.
+    /// This uses ``save_image()`` to save the image.
+    pub fn save_image_mkdir(&mut self, path: &str) -> Result<SaveStatus, RusimgError> {
+        if let Some(parent) = Path::new(path).parent() {
+            std::fs::create_dir_all(parent).map_err(|e| RusimgError::FailedToCreateFile(e.to_string()))?;
+        }
+        self.save_image(Some(path))
+    }
+
+    /// Save resized copies of an image for the ``srcset`` attribute of HTML, one file per width in ``widths``.
+    /// The files are saved in ``out_dir`` (created if missing) in the format of ``extension`` and named ``<source file stem>-<width>w.<extension>`` (e.g. ``photo-640w.webp``); images without a source file are named ``image-<width>w.<extension>``.
+    /// Widths larger than the image are skipped, as images are never upscaled. A width of 0 is rejected with an ``InvalidResizeRatio`` error before any file is written.
+    /// ``quality`` is passed to ``compress()`` if specified.
+    /// The copies are resized with the ``resize_filter`` of the config (see ``set_config()``), so one setting chooses between fast and high-quality resampling for the whole set.
+    /// The image itself is left untouched. This returns the status of each saved file in the order of ``widths``.
+    /// This uses ``converted()``, ``resize_to_width()`` and ``save_image_mkdir()``.
+    pub fn generate_srcset(&self, widths: &[u32], extension: &Extension, quality: Option<f32>, out_dir: &Path) -> Result<Vec<SaveStatus>, RusimgError> {
+        let size = self.data.get_size()?;
+        let stem = self.data.get_source_filepath()
+            .and_then(|source| Some(source.file_stem()?.to_str()?.to_string()))
+            .unwrap_or_else(|| "image".to_string());
+        if widths.contains(&0) {
+            return Err(RusimgError::InvalidResizeRatio);
+        }
+
+        let mut statuses = Vec::new();
+        for &width in widths.iter().filter(|&&w| w as usize <= size.width) {
+            let mut target = self.converted(extension)?;
+            if width as usize != size.width {
+                target.resize_to_width(width)?;
+            }
+            if quality.is_some() {
+                target.compress(quality)?;
+            }
+            let path = out_dir.join(format!("{}-{}w.{}", stem, width, extension));
+            statuses.push(target.save_image_mkdir(path.to_str().ok_or(RusimgError::FailedToConvertPathToString)?)?);
+        }
+        Ok(statuses)
+    }
+
+    /// Save an image to a file without overwriting an existing file.
+    /// The destination file path is resolved in the same way as ``save_image()``, and if the file already exists, a ``FileAlreadyExists`` error is returned.
+    /// The file is created only if it does not exist when the written image is moved into place, so concurrent saves (e.g. from other processes of a batch) never overwrite each other.
+    /// This uses the ``save_no_overwrite()`` function from ``BackendTrait``.
+    pub fn save_image_no_overwrite(&mut self, path: Option<&str>) -> Result<SaveStatus, RusimgError> {
+        self.save_image_with(path, |data, path_buf| data.save_no_overwrite(path_buf))
+    }
+
+    // Resolve the destination file path of save_image().
+    // Without a path, the backend saves the image next to the source file, keeping the source file extension if it is the same format.
+    fn resolve_destination_filepath(&self, path: Option<&str>) -> Result<PathBuf, RusimgError> {
+        let source_filepath = self.data.get_source_filepath();
+        let extension_str = match source_filepath.as_deref().and_then(|source| Some((Extension::from_path(source)?, source.extension()?.to_str()?))) {
+            Some((source_extension, source_extension_str)) if source_extension.is_same_format(&self.extension) => source_extension_str.to_string(),
+            _ => self.extension.to_string(),
+        };
+        self.data.get_save_filepath(&source_filepath, path.map(PathBuf::from), &extension_str)
+    }
+
+    /// Make a new image of the per-pixel absolute difference from ``other``, multiplied by ``amplify`` and clamped.
+    /// This visualizes compression artifacts, e.g. between an image and its re-encoded version.
+    /// Both images must have the same size; the result has the format and settings of this image.
+    /// This uses the ``get_dynamic_image()`` function from ``BackendTrait``.
+    pub fn difference_image(&self, other: &RusImg, amplify: f32) -> Result<RusImg, RusimgError> {
+        if !amplify.is_finite() || amplify < 0.0 {
+            return Err(RusimgError::InvalidFilterParameter);
+        }
+        let (image, other_image) = (self.data.get_dynamic_image()?, other.data.get_dynamic_image()?);
+        if (image.width(), image.height()) != (other_image.width(), other_image.height()) {
+            return Err(RusimgError::ImageSizeMismatch);
+        }
+        self.derived(operations::difference_image(&image, &other_image, amplify))
+    }
+
+    /// Find the ``count`` most prominent colors of an image (e.g. for theming a UI from cover art), sorted by coverage.
+    /// The colors are found by k-means clustering on a downsampled copy of the image.
+    /// Fewer colors are returned if the image has fewer distinct colors.
+    /// This uses the ``get_dynamic_image()`` function from ``BackendTrait``.
+    pub fn dominant_colors(&self, count: usize) -> Result<Vec<[u8; 3]>, RusimgError> {
+        let dynamic_image = self.data.get_dynamic_image()?;
+        Ok(operations::dominant_colors(&dynamic_image, count))
+    }
+
+    /// Compute a sharpness score of an image (e.g. for culling blurry photos): the variance of the Laplacian of the grayscale image.
+    /// Higher means sharper. The score depends on the content and the size, so compare it between similar images or against a threshold tuned for them.
+    /// This uses the ``get_dynamic_image()`` function from ``BackendTrait``.
+    pub fn sharpness_score(&self) -> Result<f64, RusimgError> {
+        let dynamic_image = self.data.get_dynamic_image()?;
+        Ok(operations::laplacian_variance(&dynamic_image))
+    }
+
+    /// Count the distinct RGBA colors of an image, e.g. to decide whether to save it with indexed colors (see ``to_indexed()``).
+    /// With ``cap``, counting stops once more than ``cap`` colors are found and ``cap`` is returned, which keeps it fast on photos;
+    /// so a result equal to ``cap`` means "``cap`` or more".
+    /// This uses the ``get_dynamic_image()`` function from ``BackendTrait``.
+    pub fn unique_color_count(&self, cap: Option<usize>) -> Result<usize, RusimgError> {
+        let dynamic_image = self.data.get_dynamic_image()?;
+        Ok(operations::unique_color_count(&dynamic_image, cap))
+    }
+
+    /// Compute a hash of the decoded pixels, e.g. for cache keys.
+    /// Unlike a hash of the file, it does not depend on the format or the encoder, so a PNG image and its BMP conversion share it.
+    /// This uses the ``content_hash()`` function from ``BackendTrait``.
+    pub fn content_hash(&self) -> Result<u64, RusimgError> {
+        self.data.content_hash()
+    }
+
+    /// Compute a perceptual hash (dHash) of the image.
+    /// The image is shrunk to 9x8 grayscale and each pixel is compared with its right neighbor, giving 64 bits.
+    /// Similar images have hashes with a small Hamming distance, see ``hamming_distance()``.
+    /// This uses the ``get_dynamic_image()`` function from ``BackendTrait``.
+    pub fn perceptual_hash(&self) -> Result<u64, RusimgError> {
+        let dynamic_image = self.data.get_dynamic_image()?;
+        let small = dynamic_image.resize_exact(9, 8, image::imageops::FilterType::Triangle).to_luma8();
+
+        let mut hash = 0u64;
+        for y in 0..8 {
+            for x in 0..8 {
+                hash <<= 1;
+                if small.get_pixel(x, y)[0] > small.get_pixel(x + 1, y)[0] {
+                    hash |= 1;
+                }
+            }
+        }
+        Ok(hash)
+    }
+}
+
+/// Decode encoded image bytes with ``RusImg::from_bytes()``.
+impl TryFrom<&[u8]> for RusImg {
+    type Error = RusimgError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_bytes(bytes)
+    }
+}
+
+/// Import a DynamicImage into the PNG backend, which keeps the pixels losslessly.
+/// Use ``RusImg::new()`` to choose another format.
+impl TryFrom<DynamicImage> for RusImg {
+    type Error = RusimgError;
+
+    fn try_from(image: DynamicImage) -> Result<Self, Self::Error> {
+        Self::new(&Extension::Png, image)
+    }
+}
+
+/// Count the number of differing bits between two perceptual hashes.
+/// A small distance means the images are visually similar.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Merge runs of near-duplicate frames of an animation (e.g. of a screen recording) before encoding it with ``encode_animated_webp()``.
+/// A frame is merged into the first frame of the current run if the ``hamming_distance()`` of their ``perceptual_hash()`` is below ``threshold``,
+/// and the merged frame shows for the sum of their delays. So a ``threshold`` of 0 keeps every frame, and 1 merges only frames with the same hash.
+/// Frames whose hash cannot be computed are never merged. ``frames`` and ``delays`` must have the same length, otherwise ``FrameDelaysMismatch`` is returned.
+pub fn dedupe_frames(frames: Vec<RusImg>, delays: Vec<u32>, threshold: u64) -> Result<(Vec<RusImg>, Vec<u32>), RusimgError> {
+    if frames.len() != delays.len() {
+        return Err(RusimgError::FrameDelaysMismatch);
+    }
+    let mut kept_frames: Vec<RusImg> = Vec::new();
+    let mut kept_delays: Vec<u32> = Vec::new();
+    let mut run_hash: Option<u64> = None;
+    for (frame, delay) in frames.into_iter().zip(delays) {
+        let hash = frame.perceptual_hash().ok();
+        let duplicate = match (run_hash, hash, kept_delays.last_mut()) {
+            (Some(a), Some(b), Some(last_delay)) if (hamming_distance(a, b) as u64) < threshold => {
+                *last_delay = last_delay.saturating_add(delay);
+                true
+            },
+            _ => false,
+        };
+        if !duplicate {
+            kept_frames.push(frame);
+            kept_delays.push(delay);
+            run_hash = hash;
+        }
+    }
+    Ok((kept_frames, kept_delays))
+}
+
+/// Get the built-in image formats enabled by the crate features (e.g. to list them in the help of a CLI).
+/// JPEG is listed as both ``Extension::Jpg`` and ``Extension::Jpeg``.
+/// External formats registered with ``register_backend()`` are not included.
+pub fn supported_extensions() -> Vec<Extension> {
+    let formats = [
+        (cfg!(feature="bmp"), vec![Extension::Bmp]),
+        (cfg!(feature="jpeg"), vec![Extension::Jpg, Extension::Jpeg]),
+        (cfg!(feature="png"), vec![Extension::Png]),
+        (cfg!(feature="webp"), vec![Extension::Webp]),
+    ];
+    formats.into_iter().filter(|(enabled, _)| *enabled).flat_map(|(_, extensions)| extensions).collect()
+}
+
+/// Get the quality range accepted by ``compress()`` for a format, as ``(min, max, default)``.
+/// The default is taken from the config set by ``set_default_config()``.
+/// Returns ``None`` for lossless formats (PNG, BMP) and external formats, whose quality does not trade off image quality.
+pub fn quality_range(ext: &Extension) -> Option<(f32, f32, f32)> {
+    let config = default_config();
+    match ext {
+        Extension::Jpg | Extension::Jpeg => Some((0.0, 100.0, config.jpeg_quality)),
+        Extension::Webp => Some((0.0, 100.0, config.webp_quality)),
+        Extension::Png | Extension::Bmp | Extension::ExternalFormat(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use image::{ImageBuffer, Rgb};
+
+    // Generate a test image with the specified filename, width, and height.
+    fn generate_test_image(filename: &str, width: u32, height: u32) {
+        let mut img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
+        for x in 0..width {
+            for y in 0..height {
+                let r = (x * 3) as u8;
+                let g = (y * 5) as u8;
+                let b = (x * y) as u8;
+                img.put_pixel(x, y, Rgb([r, g, b]));
+            }
+        }
+        let mut test_image = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(img.clone())).unwrap();
+        test_image.save_image(Some(filename)).unwrap();
+    }
+
+    // Generate an animated WebP with the specified filename, size, and number of frames.
+    // Each frame is a solid color and is shown for 100 ms.
+    fn generate_test_animation(filename: &str, width: u32, height: u32, frames: u32) {
+        let config = dep_webp::WebPConfig::new().unwrap();
+        let mut encoder = dep_webp::AnimEncoder::new(width, height, &config);
+        let frame_images: Vec<Vec<u8>> = (0..frames)
+            .map(|i| [(i * 80) as u8, 255 - (i * 80) as u8, 128, 255].repeat((width * height) as usize))
+            .collect();
+        for (i, frame_image) in frame_images.iter().enumerate() {
+            encoder.add_frame(dep_webp::AnimFrame::from_rgba(frame_image, width, height, i as i32 * 100));
+        }
+        std::fs::write(filename, &*encoder.encode()).unwrap();
+    }
+
+    #[test]
+    fn test_open_image() {
+        let filename = "test_image1.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let result = RusImg::open(path);
+        assert!(result.is_ok());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_get_image_size() {
+        let filename = "test_image2.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let img = RusImg::open(path).unwrap();
+        let size = img.get_image_size().unwrap();
+        assert_eq!(size.width, 100);
+        assert_eq!(size.height, 100);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_resize_image() {
+        let filename = "test_image3.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let size = img.resize(50.0).unwrap();
+        assert_eq!(size.width, 50);
+        assert_eq!(size.height, 50);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_trim_image() {
+        let filename = "test_image4.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let size = img.trim(10, 10, 50, 50).unwrap();
+        assert_eq!(size.width, 50);
+        assert_eq!(size.height, 50);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_trim_rect_image() {
+        let filename = "test_image5.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let rect = Rect { x: 10, y: 10, w: 50, h: 50 };
+        let size = img.trim_rect(rect).unwrap();
+        assert_eq!(size.width, 50);
+        assert_eq!(size.height, 50);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_grayscale_image() {
+        let filename = "test_image6.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result = img.grayscale();
+        assert!(result.is_ok());
+        // color check
+        let dynamic_image = img.get_dynamic_image().unwrap();
+        let img_data = dynamic_image.to_rgb8();
+        for pixel in img_data.pixels() {
+            assert_eq!(pixel[0], pixel[1]);
+            assert_eq!(pixel[1], pixel[2]);
+        }
+        std::fs::remove_file(filename).unwrap();
+
+        // The result matches the image crate's conversion, keeping the bit depth of 16-bit sources.
+        let rgb = DynamicImage::ImageRgb8(ImageBuffer::from_fn(64, 48, |x, y| Rgb([(x * 4) as u8, (y * 5) as u8, (x ^ y) as u8])));
+        for source in [rgb.clone(), DynamicImage::ImageRgba8(rgb.to_rgba8()), DynamicImage::ImageRgb16(rgb.to_rgb16()), DynamicImage::ImageLumaA8(rgb.to_luma_alpha8())] {
+            for extension in [Extension::Bmp, Extension::Png, Extension::Webp] {
+                let mut img = RusImg::new(&extension, source.clone()).unwrap();
+                img.grayscale().unwrap();
+                assert_eq!(img.get_dynamic_image().unwrap(), source.grayscale(), "{:?} {}", source.color(), extension);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_image() {
+        let filename = "test_image7.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result = img.compress(Some(30.0));
+        assert!(result.is_ok());
+        // size check
+        img.save_image(None).unwrap();
+        let before_size = img.data.get_metadata_src().unwrap().len();
+        let after_size = img.data.get_metadata_dest().unwrap().len();
+        assert!(after_size < before_size);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_convert_image() {
+        let file_names = vec![
+            "test_image8.bmp",
+            "test_image8.jpeg",
+            "test_image8.jpg",
+            "test_image8.png",
+            "test_image8.webp",
+        ];
+        for filename in &file_names {
+            let width = 100;
+            let height = 100;
+            generate_test_image(filename, width, height);
+            let path = Path::new(filename);
+            let mut img = RusImg::open(path).unwrap();
+            let result = img.convert(&Extension::Webp);
+            assert!(result.is_ok());
+            // file types
+            let rusimg_extensions = vec![Extension::Bmp, Extension::Jpeg, Extension::Jpg, Extension::Png, Extension::Webp];
+            let image_extensions = vec![image::ImageFormat::Bmp, image::ImageFormat::Jpeg, image::ImageFormat::Jpeg, image::ImageFormat::Png, image::ImageFormat::WebP];
+            for (ext, image_ext) in rusimg_extensions.iter().zip(image_extensions.iter()) {
+                // Convert the image to the new format.
+                let new_filename = filename.replace(format!(".{}", filename.split('.').last().unwrap()).as_str(), format!("_output.{}", ext.to_string()).as_str());
+                let new_path = Path::new(&new_filename);
+                let mut image_cloned = RusImg::open(&PathBuf::from(filename)).unwrap();
+                image_cloned.convert(&ext).unwrap();
+                image_cloned.save_image(new_path.to_str()).unwrap();
+                // Check if the file extension is correct.
+                let output_image_binary = std::fs::read(new_path).unwrap();
+                let guessed_format = image::guess_format(&output_image_binary).unwrap();
+                assert_eq!(guessed_format, *image_ext);
+                // Clean up the test image file.
+                std::fs::remove_file(new_path).unwrap();
+            }
+            std::fs::remove_file(filename).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_set_dynamic_image() {
+        let filename = "test_image9.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let dynamic_image = image::open(path).unwrap();
+        let result = img.set_dynamic_image(dynamic_image);
+        assert!(result.is_ok());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_get_dynamic_image() {
+        let filename = "test_image10.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result = img.get_dynamic_image();
+        assert!(result.is_ok());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_get_extension() {
+        let filename = "test_image12.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let img = RusImg::open(path).unwrap();
+        let extension = img.get_extension();
+        assert_eq!(extension, Extension::Png);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_get_input_filepath() {
+        let filename = "test_image13.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let img = RusImg::open(path).unwrap();
+        let input_filepath = img.get_input_filepath().unwrap();
+        assert_eq!(input_filepath, Path::new(filename));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_save_image() {
+        let filename = "test_image14.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result = img.save_image(Some("test_image_saved.png"));
+        assert!(result.is_ok());
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file("test_image_saved.png").unwrap();
+    }
+
+    #[test]
+    fn test_err_failed_to_open_file() {
+        let path = Path::new("non_existent_file.png");
+        let result = RusImg::open(path);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            if let RusimgError::FailedToOpenFile(_) = e.without_path() {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+    }
+
+    #[test]
+    fn test_err_failed_to_open_image() {
+        // Not supported image format
+        let path = Path::new("test_image1.txt");
+        // Create a dummy text file
+        std::fs::write(path, "This is a test file.").unwrap();
+        // Attempt to open the text file as an image
+        let result = RusImg::open(path);
+        // Remove the dummy text file
+        std::fs::remove_file(path).unwrap();
+        // Check if the error is as expected
+        assert!(result.is_err());
+        if let Err(e) = result {
+            if let RusimgError::FailedToOpenImage(_) = e.without_path() {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+    }
+
+    #[test]
+    fn test_err_failed_to_save_image() {
+        let filename = "test_image15.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result = img.save_image(Some("test_image/invalid_path/test_image_saved.png"));
+        assert!(result.is_err());
+        if let Err(e) = result {
+            if let RusimgError::FailedToSaveImage(_) = e.without_path() {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+        // Clean up the test image file
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_err_invalid_compression_level() {
+        let filename = "test_image16.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result1 = img.compress(Some(150.0));
+        let result2 = img.compress(Some(-10.0));
+        assert!(result1.is_err());
+        assert!(result2.is_err());
+        if let Err(e) = result1 {
+            if let RusimgError::InvalidCompressionLevel = e {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+        if let Err(e) = result2 {
+            if let RusimgError::InvalidCompressionLevel = e {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_err_invalid_trim_xy() {
+        let filename = "test_image17.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result = img.trim(150, 150, 50, 50);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            if let RusimgError::InvalidTrimXY = e {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_err_invalid_resize_ratio() {
+        let filename = "test_image18.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result = img.resize(0.0);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            if let RusimgError::InvalidResizeRatio = e {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_err_image_format_cannot_be_compressed() {
+        let filename = "test_image19.bmp";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let result = img.compress(Some(50.0));
+        assert!(result.is_err());
+        if let Err(e) = result {
+            if let RusimgError::ImageFormatCannotBeCompressed = e {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_err_source_path_must_be_specified() {
+        let filename = "test_image20.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let img = RusImg::open(path).unwrap();
+        let result = img.get_input_filepath();
+        assert!(result.is_ok());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_perceptual_hash() {
+        let filename = "test_image21.png";
+        let recompressed_filename = "test_image21_recompressed.jpg";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let hash = img.perceptual_hash().unwrap();
+        // Slightly recompressed JPEG version of the same image
+        img.convert(&Extension::Jpg).unwrap();
+        img.compress(Some(90.0)).unwrap();
+        img.save_image(Some(recompressed_filename)).unwrap();
+        let recompressed = RusImg::open(Path::new(recompressed_filename)).unwrap();
+        let recompressed_hash = recompressed.perceptual_hash().unwrap();
+        assert!(hamming_distance(hash, recompressed_hash) <= 4);
+        // Unrelated image
+        let mut unrelated: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::new(width, height);
+        for (x, y, pixel) in unrelated.enumerate_pixels_mut() {
+            let v = if (x / 10 + y / 25) % 2 == 0 { 255 } else { 0 };
+            *pixel = Rgb([v, v, v]);
+        }
+        let unrelated = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(unrelated)).unwrap();
+        let unrelated_hash = unrelated.perceptual_hash().unwrap();
+        assert!(hamming_distance(hash, unrelated_hash) >= 16);
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(recompressed_filename).unwrap();
+    }
+
+    #[test]
+    fn test_err_invalid_trim_zero_area() {
+        let filename = "test_image22.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let rect = Rect { x: 10, y: 10, w: 0, h: 50 };
+        let result = img.trim_rect(rect);
+        assert!(result.is_err());
+        if let Err(e) = result {
+            if let RusimgError::InvalidTrimXY = e {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+        // The image must be left untouched
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(100, 100));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_set_webp_method() {
+        let filename = "test_image23.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        for method in [0, 6] {
+            let mut img = RusImg::open(path).unwrap();
+            img.convert(&Extension::Webp).unwrap();
+            img.compress(Some(75.0)).unwrap();
+            img.set_webp_method(method).unwrap();
+            let output_filename = format!("test_image23_method{}.webp", method);
+            img.save_image(Some(&output_filename)).unwrap();
+            let output_image_binary = std::fs::read(&output_filename).unwrap();
+            assert_eq!(image::guess_format(&output_image_binary).unwrap(), image::ImageFormat::WebP);
+            assert!(RusImg::open(Path::new(&output_filename)).is_ok());
+            std::fs::remove_file(&output_filename).unwrap();
+        }
+        // Out of range
+        let mut img = RusImg::open(path).unwrap();
+        img.convert(&Extension::Webp).unwrap();
+        assert_eq!(img.set_webp_method(7), Err(RusimgError::InvalidWebpMethod));
+        // Not a WebP image
+        let mut img = RusImg::open(path).unwrap();
+        assert_eq!(img.set_webp_method(4), Err(RusimgError::UnsupportedFeature));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    // Trivial external format backend for testing register_backend().
+    struct TestExternalImage {
+        image: DynamicImage,
+        filepath_input: Option<PathBuf>,
+        metadata_input: Option<std::fs::Metadata>,
+    }
+    impl BackendTrait for TestExternalImage {
+        fn import(image: Option<DynamicImage>, source_path: Option<PathBuf>, source_metadata: Option<std::fs::Metadata>) -> Result<Self, RusimgError> {
+            let image = image.ok_or(RusimgError::ImageNotSpecified)?;
+            Ok(Self { image, filepath_input: source_path, metadata_input: source_metadata })
+        }
+        fn open(path: Option<PathBuf>, _image_buf: Option<Vec<u8>>, metadata: Option<std::fs::Metadata>) -> Result<Self, RusimgError> {
+            Self::import(Some(DynamicImage::new_rgb8(4, 3)), path, metadata)
+        }
+        fn save(&mut self, _path: Option<PathBuf>) -> Result<(), RusimgError> {
+            Err(RusimgError::UnsupportedFeature)
+        }
+        fn compress(&mut self, _quality: Option<f32>) -> Result<(), RusimgError> {
+            Err(RusimgError::ImageFormatCannotBeCompressed)
+        }
+        fn resize(&mut self, _resize_ratio: f32) -> Result<ImgSize, RusimgError> {
+            Err(RusimgError::UnsupportedFeature)
+        }
+        fn trim(&mut self, _trim: Rect) -> Result<ImgSize, RusimgError> {
+            Err(RusimgError::UnsupportedFeature)
+        }
+        fn grayscale(&mut self) {
+            self.image = self.image.grayscale();
+        }
+        fn set_dynamic_image(&mut self, image: DynamicImage) -> Result<(), RusimgError> {
+            self.image = image;
+            Ok(())
+        }
+        fn get_dynamic_image(&self) -> Result<DynamicImage, RusimgError> {
+            Ok(self.image.clone())
+        }
+        fn get_source_filepath(&self) -> Option<PathBuf> {
+            self.filepath_input.clone()
+        }
+        fn get_destination_filepath(&self) -> Result<Option<PathBuf>, RusimgError> {
+            Ok(None)
+        }
+        fn get_metadata_src(&self) -> Option<std::fs::Metadata> {
+            self.metadata_input.clone()
+        }
+        fn get_metadata_dest(&self) -> Option<std::fs::Metadata> {
+            None
+        }
+        fn get_size(&self) -> Result<ImgSize, RusimgError> {
+            Ok(ImgSize::new(self.image.width() as usize, self.image.height() as usize))
+        }
+    }
+    fn open_test_external_image(path: Option<PathBuf>, buf: Vec<u8>, metadata: Option<std::fs::Metadata>) -> Result<Box<dyn BackendTrait>, RusimgError> {
+        Ok(Box::new(TestExternalImage::open(path, Some(buf), metadata)?))
+    }
+
+    #[test]
+    fn test_register_backend() {
+        let filename = "test_image24.rusimgtest";
+        std::fs::write(filename, b"RUSIMGTEST external format").unwrap();
+        register_backend(b"RUSIMGTEST", open_test_external_image);
+        let img = RusImg::open(Path::new(filename)).unwrap();
+        assert_eq!(img.get_extension(), Extension::ExternalFormat("rusimgtest".to_string()));
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(4, 3));
+        // Bytes in memory are opened by the registered backend too, without a file extension.
+        let img = RusImg::from_bytes(b"RUSIMGTEST in memory").unwrap();
+        assert_eq!(img.get_extension(), Extension::ExternalFormat(String::new()));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_convert_directory() {
+        let src_dir = Path::new("test_dir1");
+        let dst_dir = Path::new("test_dir1_out");
+        std::fs::create_dir_all(src_dir.join("sub")).unwrap();
+        generate_test_image("test_dir1/a.png", 100, 100);
+        generate_test_image("test_dir1/b.png", 100, 100);
+        generate_test_image("test_dir1/sub/c.png", 100, 100);
+        generate_test_image("test_dir1/d.bmp", 100, 100);
+        std::fs::write("test_dir1/e.txt", "This is a test file.").unwrap();
+
+        let results = convert_directory(src_dir, dst_dir, &Extension::Webp, Some(75.0), Some(&[Extension::Png]));
+        assert_eq!(results.len(), 3);
+        for result in &results {
+            assert!(result.is_ok());
+        }
+        for output in ["test_dir1_out/a.webp", "test_dir1_out/b.webp", "test_dir1_out/sub/c.webp"] {
+            let output_image_binary = std::fs::read(output).unwrap();
+            assert_eq!(image::guess_format(&output_image_binary).unwrap(), image::ImageFormat::WebP);
+            assert!(RusImg::open(Path::new(output)).is_ok());
+        }
+        // Filtered out by the source format
+        assert!(!Path::new("test_dir1_out/d.webp").exists());
+
+        std::fs::remove_dir_all(src_dir).unwrap();
+        std::fs::remove_dir_all(dst_dir).unwrap();
+    }
+
+    #[test]
+    fn test_background_color() {
+        let filename = "test_image25.png";
+        let saved_filename = "test_image25_saved.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        assert_eq!(img.get_background_color(), None);
+        img.set_background_color([10, 20, 30, 255]).unwrap();
+        img.save_image(Some(saved_filename)).unwrap();
+        // Read back the bKGD chunk
+        let saved = RusImg::open(Path::new(saved_filename)).unwrap();
+        assert_eq!(saved.get_background_color(), Some([10, 20, 30, 255]));
+        assert_eq!(saved.get_image_size().unwrap(), ImgSize::new(100, 100));
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(saved_filename).unwrap();
+    }
+
+    // Set the global maximum number of pixels for a test, and restore the previous limit when dropped, even if the test panics.
+    // The tests setting the limit hold MAX_PIXELS_LOCK, so that they do not restore each other's limits.
+    static MAX_PIXELS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    struct MaxPixelsGuard {
+        previous: u64,
+        _lock: std::sync::MutexGuard<'static, ()>,
+    }
+    impl MaxPixelsGuard {
+        fn set(limit: u64) -> Self {
+            let lock = MAX_PIXELS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let previous = max_pixels();
+            set_max_pixels(limit);
+            Self { previous, _lock: lock }
+        }
+    }
+    impl Drop for MaxPixelsGuard {
+        fn drop(&mut self) {
+            set_max_pixels(self.previous);
+        }
+    }
+
+    #[test]
+    fn test_err_image_too_large() {
+        let filename = "test_image26.bmp";
+        // BMP header claiming 50000x50000 pixels without pixel data
+        let mut header = Vec::new();
+        header.extend_from_slice(b"BM");
+        header.extend_from_slice(&54u32.to_le_bytes());         // file size
+        header.extend_from_slice(&0u32.to_le_bytes());          // reserved
+        header.extend_from_slice(&54u32.to_le_bytes());         // pixel data offset
+        header.extend_from_slice(&40u32.to_le_bytes());         // DIB header size
+        header.extend_from_slice(&50000i32.to_le_bytes());      // width
+        header.extend_from_slice(&50000i32.to_le_bytes());      // height
+        header.extend_from_slice(&1u16.to_le_bytes());          // planes
+        header.extend_from_slice(&24u16.to_le_bytes());         // bits per pixel
+        header.extend_from_slice(&[0u8; 24]);                   // compression, sizes, resolution, colors
+        std::fs::write(filename, &header).unwrap();
+        let result = {
+            let _limit = MaxPixelsGuard::set(100_000_000);
+            RusImg::open(Path::new(filename))
+        };
+        assert_eq!(max_pixels(), 0);
+        std::fs::remove_file(filename).unwrap();
+        assert!(result.is_err());
+        if let Err(e) = result {
+            if let RusimgError::ImageTooLarge = e.without_path() {
+                // Expected error
+            } else {
+                panic!("Unexpected error: {:?}", e);
+            }
+        } else {
+            panic!("Expected an error, but got Ok");
+        }
+    }
+
+    #[test]
+    fn test_frame_count() {
+        // Still WebP
+        let filename = "test_image27.png";
+        let still_filename = "test_image27.webp";
+        generate_test_image(filename, 100, 100);
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        assert_eq!(img.frame_count().unwrap(), 1);
+        img.convert(&Extension::Webp).unwrap();
+        img.save_image(Some(still_filename)).unwrap();
+        let still = RusImg::open(Path::new(still_filename)).unwrap();
+        assert_eq!(still.frame_count().unwrap(), 1);
+        // Animated WebP
+        let animated_filename = "test_image27_animated.webp";
+        generate_test_animation(animated_filename, 32, 32, 3);
+        let animated = RusImg::open(Path::new(animated_filename)).unwrap();
+        assert_eq!(animated.frame_count().unwrap(), 3);
+        assert_eq!(animated.get_image_size().unwrap(), ImgSize::new(32, 32));
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(still_filename).unwrap();
+        std::fs::remove_file(animated_filename).unwrap();
+    }
+
+    #[test]
+    fn test_resize_xy_image() {
+        let filename = "test_image28.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut img = RusImg::open(path).unwrap();
+        let size = img.resize_xy(50.0, 200.0).unwrap();
+        assert_eq!(size, ImgSize::new(50, 200));
+        let dynamic_image = img.get_dynamic_image().unwrap();
+        assert_eq!((dynamic_image.width(), dynamic_image.height()), (50, 200));
+        assert_eq!(img.resize_xy(0.0, 100.0), Err(RusimgError::InvalidResizeRatio));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_source_bytes() {
+        let filename = "test_image29.png";
+        let width = 100;
         let height = 100;
         generate_test_image(filename, width, height);
         let path = Path::new(filename);
         let img = RusImg::open(path).unwrap();
-        let size = img.get_image_size().unwrap();
-        assert_eq!(size.width, 100);
-        assert_eq!(size.height, 100);
+        let file_bytes = std::fs::read(path).unwrap();
+        assert_eq!(img.source_bytes(), Some(file_bytes.as_slice()));
+        // Images not opened from a file have no source bytes
+        let new_img = RusImg::new(&Extension::Png, DynamicImage::new_rgb8(10, 10)).unwrap();
+        assert_eq!(new_img.source_bytes(), None);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_jpeg_quality() {
+        let filename = "test_image30.png";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let mut filesizes = Vec::new();
+        for quality in [50.0, 100.0] {
+            let mut img = RusImg::open(path).unwrap();
+            img.convert(&Extension::Jpg).unwrap();
+            img.compress(Some(quality)).unwrap();
+            let output_filename = format!("test_image30_q{}.jpg", quality);
+            let status = img.save_image(Some(&output_filename)).unwrap();
+            filesizes.push(status.after_filesize.unwrap());
+            std::fs::remove_file(&output_filename).unwrap();
+        }
+        // Quality 100 keeps more detail than quality 50
+        assert!(filesizes[1] > filesizes[0]);
+        // Out of range quality is rejected
+        let mut img = RusImg::open(path).unwrap();
+        img.convert(&Extension::Jpg).unwrap();
+        assert_eq!(img.compress(Some(100.5)), Err(RusimgError::InvalidCompressionLevel));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_converted_image() {
+        let filename = "test_image31.png";
+        let output_filename = "test_image31_converted.webp";
+        let width = 100;
+        let height = 100;
+        generate_test_image(filename, width, height);
+        let path = Path::new(filename);
+        let img = RusImg::open(path).unwrap();
+        let mut converted = img.converted(&Extension::Webp).unwrap();
+        // The original is still a PNG
+        assert_eq!(img.get_extension(), Extension::Png);
+        assert_eq!(converted.get_extension(), Extension::Webp);
+        converted.save_image(Some(output_filename)).unwrap();
+        let output_image_binary = std::fs::read(output_filename).unwrap();
+        assert_eq!(image::guess_format(&output_image_binary).unwrap(), image::ImageFormat::WebP);
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(output_filename).unwrap();
+    }
+
+    #[test]
+    fn test_smart_crop() {
+        // Flat gray image with a high-detail checkerboard at (140, 20) - (180, 60)
+        let mut image: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_pixel(200, 100, Rgb([128, 128, 128]));
+        for y in 20..60 {
+            for x in 140..180 {
+                let v = if (x + y) % 2 == 0 { 255 } else { 0 };
+                image.put_pixel(x, y, Rgb([v, v, v]));
+            }
+        }
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(image)).unwrap();
+        let size = img.smart_crop(60, 60).unwrap();
+        assert_eq!(size, ImgSize::new(60, 60));
+        // The whole checkerboard is in the crop
+        let cropped = img.get_dynamic_image().unwrap().to_rgb8();
+        let detail_pixels = cropped.pixels().filter(|p| p[0] == 0 || p[0] == 255).count();
+        assert_eq!(detail_pixels, 40 * 40);
+        // The window must fit in the image
+        assert_eq!(img.smart_crop(100, 10), Err(RusimgError::InvalidTrimXY));
+    }
+
+    #[test]
+    fn test_png_row_filters() {
+        // Horizontal gradient: Sub filtering makes every row almost constant, so it usually compresses better than no filtering.
+        let filename = "test_image32.png";
+        let gradient: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(256, 64, |x, y| Rgb([x as u8, (x + y) as u8, 255 - x as u8]));
+        let mut gradient_image = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(gradient)).unwrap();
+        gradient_image.save_image(Some(filename)).unwrap();
+        for filter in [PngRowFilter::None, PngRowFilter::Paeth] {
+            let mut img = RusImg::open(Path::new(filename)).unwrap();
+            img.set_png_optimize_options(PngOptimizeOptions { filters: vec![filter] }).unwrap();
+            img.compress(Some(50.0)).unwrap();
+            let output_filename = format!("test_image32_{:?}.png", filter);
+            img.save_image(Some(&output_filename)).unwrap();
+            let output_image_binary = std::fs::read(&output_filename).unwrap();
+            assert_eq!(image::guess_format(&output_image_binary).unwrap(), image::ImageFormat::Png);
+            assert!(RusImg::open(Path::new(&output_filename)).is_ok());
+            std::fs::remove_file(&output_filename).unwrap();
+        }
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_iter_images() {
+        let dir = Path::new("test_dir2");
+        std::fs::create_dir_all(dir).unwrap();
+        generate_test_image("test_dir2/a.png", 100, 100);
+        generate_test_image("test_dir2/b.png", 100, 100);
+        generate_test_image("test_dir2/c.png", 100, 100);
+        std::fs::write("test_dir2/d.txt", "This is a test file.").unwrap();
+
+        let mut count = 0;
+        for img in iter_images(dir).unwrap() {
+            let img = img.unwrap();
+            assert_eq!(img.get_extension(), Extension::Png);
+            count += 1;
+        }
+        assert_eq!(count, 3);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resize_even_image() {
+        let filename = "test_image33.png";
+        generate_test_image(filename, 99, 75);
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        // 99x75 * 50% = 49x37, rounded up to 50x38
+        let size = img.resize_even(50.0).unwrap();
+        assert_eq!(size, ImgSize::new(50, 38));
+        let image = img.get_dynamic_image().unwrap();
+        assert_eq!((image.width(), image.height()), (50, 38));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_linear_resize() {
+        // 1px black-and-white checkerboard: half of the light is white, so the correct downscale is sRGB ~188, not 128.
+        let checkerboard: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(64, 64, |x, y| if (x + y) % 2 == 0 { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) });
+        let average = |img: &mut RusImg| -> f32 {
+            let image = img.get_dynamic_image().unwrap().to_luma8();
+            image.pixels().map(|p| p[0] as f32).sum::<f32>() / (image.width() * image.height()) as f32
+        };
+
+        let mut naive = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(checkerboard.clone())).unwrap();
+        naive.resize(50.0).unwrap();
+        let naive_average = average(&mut naive);
+        assert!((naive_average - 128.0).abs() < 10.0, "naive average: {}", naive_average);
+
+        let mut linear = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(checkerboard)).unwrap();
+        linear.set_linear_resize(true);
+        let size = linear.resize(50.0).unwrap();
+        assert_eq!(size, ImgSize::new(32, 32));
+        let linear_average = average(&mut linear);
+        assert!((linear_average - 188.0).abs() < 10.0, "linear average: {}", linear_average);
+        assert_eq!(linear.get_dynamic_image().unwrap().color(), image::ColorType::Rgb8);
+    }
+
+    #[test]
+    fn test_trim_anchored_image() {
+        // Each pixel encodes its own coordinates, so the offset of the trim area can be read back.
+        let coordinates: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(100, 80, |x, y| Rgb([x as u8, y as u8, 0]));
+
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(coordinates.clone())).unwrap();
+        let size = img.trim_anchored(Anchor::TopLeft, 30, 20).unwrap();
+        assert_eq!(size, ImgSize::new(30, 20));
+        let image = img.get_dynamic_image().unwrap().to_rgb8();
+        assert_eq!(image.get_pixel(0, 0), &Rgb([0, 0, 0]));
+
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(coordinates)).unwrap();
+        let size = img.trim_anchored(Anchor::Center, 30, 20).unwrap();
+        assert_eq!(size, ImgSize::new(30, 20));
+        let image = img.get_dynamic_image().unwrap().to_rgb8();
+        // (100 - 30) / 2 = 35, (80 - 20) / 2 = 30
+        assert_eq!(image.get_pixel(0, 0), &Rgb([35, 30, 0]));
+    }
+
+    #[test]
+    fn test_metadata() {
+        let filename = "test_image34.png";
+        generate_test_image(filename, 120, 80);
+        let img = RusImg::open(Path::new(filename)).unwrap();
+        let metadata = img.metadata();
+        assert_eq!((metadata.width, metadata.height), (120, 80));
+        assert_eq!(metadata.color_type, image::ColorType::Rgb8);
+        assert_eq!(metadata.bit_depth, 8);
+        assert!(!metadata.has_alpha);
+        assert_eq!(metadata.orientation, None);
+        assert_eq!(metadata.frame_count, 1);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_save_image_extension_mismatch() {
+        let filename = "test_image35.png";
+        generate_test_image(filename, 100, 100);
+
+        // Convert (default)
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        img.save_image(Some("test_image35.jpg")).unwrap();
+        let output_image_binary = std::fs::read("test_image35.jpg").unwrap();
+        assert_eq!(image::guess_format(&output_image_binary).unwrap(), image::ImageFormat::Jpeg);
+        assert_eq!(img.get_extension(), Extension::Jpg);
+
+        // Error
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        img.set_extension_mismatch_policy(ExtensionMismatchPolicy::Error);
+        assert_eq!(img.save_image(Some("test_image35_2.jpg")), Err(RusimgError::ExtensionMismatch(Extension::Png, Extension::Jpg)));
+        assert!(!Path::new("test_image35_2.jpg").exists());
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file("test_image35.jpg").unwrap();
+    }
+
+    #[test]
+    fn test_threshold() {
+        let gradient: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(256, 16, |x, _| Rgb([x as u8, x as u8, x as u8]));
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(gradient)).unwrap();
+        img.threshold(128).unwrap();
+        let image = img.get_dynamic_image().unwrap().to_luma8();
+        assert!(image.pixels().all(|p| p[0] == 0 || p[0] == 255));
+        assert_eq!(image.get_pixel(127, 0)[0], 0);
+        assert_eq!(image.get_pixel(128, 0)[0], 255);
+
+        // Saved as 1-bit PNG
+        let filename = "test_image36.png";
+        img.save_image(Some(filename)).unwrap();
+        let output_image_binary = std::fs::read(filename).unwrap();
+        assert_eq!(image::guess_format(&output_image_binary).unwrap(), image::ImageFormat::Png);
+        assert_eq!(output_image_binary[24], 1);     // bit depth in the IHDR chunk
+        let reopened = RusImg::open(Path::new(filename)).unwrap().get_dynamic_image().unwrap().to_luma8();
+        assert_eq!(reopened, image);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_threshold_otsu() {
+        // Bimodal image: dark (around 50) on the left half and light (around 200) on the right half.
+        let bimodal: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(100, 100, |x, y| {
+            let v = if x < 50 { 50 + (y % 10) as u8 } else { 200 - (y % 10) as u8 };
+            Rgb([v, v, v])
+        });
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(bimodal)).unwrap();
+        let level = img.threshold_otsu().unwrap();
+        assert!(level > 59 && level <= 191, "level: {}", level);
+        let image = img.get_dynamic_image().unwrap().to_luma8();
+        assert!(image.pixels().all(|p| p[0] == 0 || p[0] == 255));
+        assert_eq!(image.get_pixel(0, 0)[0], 0);
+        assert_eq!(image.get_pixel(99, 0)[0], 255);
+    }
+
+    #[test]
+    fn test_encode_animated_webp() {
+        let colors = [Rgb([255, 0, 0]), Rgb([0, 255, 0]), Rgb([0, 0, 255])];
+        let frames: Vec<RusImg> = colors.iter()
+            .map(|color| RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(32, 32, *color))).unwrap())
+            .collect();
+        let delays = [100, 200, 300];
+        let encoded = encode_animated_webp(&frames, &delays, 0, 90.0).unwrap();
+
+        let decoded = dep_webp::AnimDecoder::new(&encoded).decode().unwrap();
+        assert_eq!(decoded.len(), 3);
+        // The decoder reports the end time of each frame.
+        let end_times: Vec<i32> = (0..3).map(|i| decoded.get_frame(i).unwrap().get_time_ms()).collect();
+        assert_eq!(end_times, vec![100, 300, 600]);
+
+        // Invalid arguments
+        assert_eq!(encode_animated_webp(&frames, &delays[0..2], 0, 90.0), Err(RusimgError::FrameDelaysMismatch));
+        let mut frames = frames;
+        frames.push(RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(16, 16, Rgb([0, 0, 0])))).unwrap());
+        assert_eq!(encode_animated_webp(&frames, &[100, 200, 300, 400], 0, 90.0), Err(RusimgError::FrameSizeMismatch));
+    }
+
+    #[test]
+    fn test_limit_megapixels() {
+        // Same proportions as a 12MP (4000x3000) image limited to 8MP, scaled down by 10 on each side to keep the test fast.
+        let mut img = RusImg::new(&Extension::Bmp, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(400, 300, Rgb([64, 128, 192])))).unwrap();
+        let size = img.limit_megapixels(0.08).unwrap();
+        assert!(size.width * size.height <= 80_000);
+        assert!(size.width * size.height > 79_000);
+        assert!((size.width as f32 / size.height as f32 - 4.0 / 3.0).abs() < 0.01);
+        assert_eq!(img.get_image_size().unwrap(), size);
+
+        // Already within the limit
+        let size = img.limit_megapixels(0.12).unwrap();
+        assert!(size.width * size.height <= 80_000);
+
+        // Zero, negative, NaN and infinite limits are rejected and the image is unchanged.
+        for max_mp in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(img.limit_megapixels(max_mp), Err(RusimgError::InvalidResizeRatio));
+        }
+        assert_eq!(img.get_image_size().unwrap(), size);
+    }
+
+    #[test]
+    fn test_animation_info() {
+        let filename = "test_image37.webp";
+        let frames: Vec<RusImg> = [Rgb([255, 0, 0]), Rgb([0, 0, 255])].iter()
+            .map(|color| RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(32, 32, *color))).unwrap())
+            .collect();
+        std::fs::write(filename, encode_animated_webp(&frames, &[150, 250], 3, 90.0).unwrap()).unwrap();
+
+        let img = RusImg::open(Path::new(filename)).unwrap();
+        let info = img.animation_info().unwrap();
+        assert_eq!(info.loop_count, 3);
+        assert_eq!(info.frames.len(), 2);
+        assert_eq!(info.frames[0].delay_ms, 150);
+        assert_eq!(info.frames[1].delay_ms, 250);
+
+        // Still image
+        let still = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(32, 32, Rgb([0, 0, 0])))).unwrap();
+        assert_eq!(still.animation_info().unwrap(), AnimationInfo::still());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_posterize() {
+        let gradient: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(256, 4, |x, y| Rgb([x as u8, 255 - x as u8, (x as u8).wrapping_add(y as u8 * 60)]));
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(gradient)).unwrap();
+        img.posterize(4).unwrap();
+        let image = img.get_dynamic_image().unwrap().to_rgb8();
+        for channel in 0..3 {
+            let values: std::collections::HashSet<u8> = image.pixels().map(|p| p[channel]).collect();
+            assert!(values.len() <= 4, "channel {}: {:?}", channel, values);
+        }
+
+        assert_eq!(img.posterize(1), Err(RusimgError::InvalidPosterizeLevels));
+    }
+
+    #[test]
+    fn test_map_pixels() {
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(8, 8, Rgb([10, 20, 30])))).unwrap();
+        img.map_pixels(|[r, g, b, a]| [b, g, r, a]).unwrap();
+        let image = img.get_dynamic_image().unwrap().to_rgba8();
+        assert!(image.pixels().all(|p| p.0 == [30, 20, 10, 255]));
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let filename = "test_image38.png";
+        generate_test_image(filename, 100, 100);
+        let original = std::fs::read(filename).unwrap();
+
+        // A save that fails after a partial write leaves the original file intact.
+        let result = backend::write_atomically(Path::new(filename), true, |file| {
+            std::io::Write::write_all(file, &original[..original.len() / 2]).unwrap();
+            Err(RusimgError::FailedToSaveImage("interrupted".to_string()))
+        });
+        assert_eq!(result, Err(RusimgError::FailedToSaveImage("interrupted".to_string())));
+        assert_eq!(std::fs::read(filename).unwrap(), original);
+        // The temporary file is removed.
+        let leftovers = std::fs::read_dir(".").unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(".test_image38."))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        // Saves from several threads at once use their own temporary files, so none of them fails.
+        let handles: Vec<_> = (0..4).map(|_| {
+            let original = original.clone();
+            std::thread::spawn(move || backend::write_atomically(Path::new(filename), true, |file| {
+                std::thread::sleep(std::time::Duration::from_millis(20));
+                std::io::Write::write_all(file, &original).map_err(|e| RusimgError::FailedToSaveImage(e.to_string()))
+            }))
+        }).collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), Ok(()));
+        }
+        assert_eq!(std::fs::read(filename).unwrap(), original);
+
+        // Overwriting in place replaces the file.
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        img.resize(50.0).unwrap();
+        img.save_image(None).unwrap();
+        assert_eq!(RusImg::open(Path::new(filename)).unwrap().get_image_size().unwrap(), ImgSize::new(50, 50));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_luma_alpha_image() {
+        let filename = "test_image39.png";
+        let luma_alpha: ImageBuffer<image::LumaA<u8>, Vec<u8>> = ImageBuffer::from_fn(100, 100, |x, y| image::LumaA([(x * 2) as u8, (y * 2) as u8]));
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageLumaA8(luma_alpha)).unwrap();
+        img.save_image(Some(filename)).unwrap();
+
+        // Resize and trim keep LumaA
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        assert_eq!(img.get_dynamic_image().unwrap().color(), image::ColorType::La8);
+        img.resize(50.0).unwrap();
+        img.trim(0, 0, 40, 40).unwrap();
+        assert_eq!(img.get_dynamic_image().unwrap().color(), image::ColorType::La8);
+
+        // Remove alpha
+        img.remove_alpha_channel().unwrap();
+        assert_eq!(img.get_dynamic_image().unwrap().color(), image::ColorType::L8);
+
+        // Saved as grayscale JPEG
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        img.convert(&Extension::Jpeg).unwrap();
+        img.save_image(Some("test_image39.jpeg")).unwrap();
+        let jpeg = RusImg::open(Path::new("test_image39.jpeg")).unwrap().get_dynamic_image().unwrap();
+        assert_eq!(jpeg.color(), image::ColorType::L8);
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file("test_image39.jpeg").unwrap();
+    }
+
+    #[test]
+    fn test_save_image_no_overwrite() {
+        let filename = "test_image40.png";
+        let fresh_filename = "test_image40_fresh.png";
+        generate_test_image(filename, 100, 100);
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+
+        // Overwriting the source file
+        assert_eq!(img.save_image_no_overwrite(None), Err(RusimgError::FileAlreadyExists(PathBuf::from(filename))));
+        assert_eq!(img.save_image_no_overwrite(Some(filename)), Err(RusimgError::FileAlreadyExists(PathBuf::from(filename))));
+
+        // Fresh path
+        let status = img.save_image_no_overwrite(Some(fresh_filename)).unwrap();
+        assert_eq!(status.output_path, Some(PathBuf::from(fresh_filename)));
+        assert!(Path::new(fresh_filename).exists());
+        std::fs::remove_file(fresh_filename).unwrap();
+
+        // Of concurrent saves to the same fresh path, exactly one succeeds and the others do not replace its file.
+        let handles: Vec<_> = (0..8).map(|_| std::thread::spawn(move || {
+            let mut img = RusImg::open(Path::new(filename)).unwrap();
+            img.save_image_no_overwrite(Some(fresh_filename))
+        })).collect();
+        let results: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results.iter().filter(|r| r.is_err()).all(|r| r == &Err(RusimgError::FileAlreadyExists(PathBuf::from(fresh_filename)))));
+        let leftovers = std::fs::read_dir(".").unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().starts_with(".test_image40_fresh."))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(fresh_filename).unwrap();
+    }
+
+    #[test]
+    fn test_source_modified() {
+        let filename = "test_image41.png";
+        generate_test_image(filename, 100, 100);
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        let modified = img.source_modified().unwrap();
+        let elapsed = SystemTime::now().duration_since(modified).unwrap_or_default();
+        assert!(elapsed.as_secs() < 60);
+
+        let status = img.save_image(Some("test_image41_out.png")).unwrap();
+        assert_eq!(status.source_modified, Some(modified));
+
+        // Images without a source file
+        let new_img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(8, 8, Rgb([0, 0, 0])))).unwrap();
+        assert_eq!(new_img.source_modified(), None);
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file("test_image41_out.png").unwrap();
+    }
+
+    #[test]
+    fn test_save_preserving_mtime() {
+        let filename = "test_image42.png";
+        let output_filename = "test_image42_out.webp";
+        generate_test_image(filename, 100, 100);
+        // Make the source file clearly older than the output file.
+        let source_mtime = filetime::FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(filename, source_mtime).unwrap();
+
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        img.save_preserving_mtime(Some(output_filename)).unwrap();
+        let output_mtime = filetime::FileTime::from_last_modification_time(&std::fs::metadata(output_filename).unwrap());
+        assert_eq!(output_mtime, source_mtime);
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(output_filename).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let filename = "test_image43.png";
+        generate_test_image(filename, 100, 100);
+        let img = RusImg::open(Path::new(filename)).unwrap();
+        assert!(img.validate().is_ok());
+
+        // Truncated
+        let data = std::fs::read(filename).unwrap();
+        assert_eq!(validate_image_bytes(&data[..data.len() / 2]), Err(RusimgError::ImageTruncated));
+        // Corrupt chunk
+        let mut corrupt = data.clone();
+        corrupt[40] ^= 0xFF;
+        if let Err(RusimgError::CorruptImage(message)) = validate_image_bytes(&corrupt) {
+            assert!(message.contains("CRC"), "{}", message);
+        }
+        else {
+            panic!("Corrupt image data should return CorruptImage");
+        }
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_from_rgba8() {
+        let bytes = vec![
+            255, 0, 0, 255,     0, 255, 0, 255,
+            0, 0, 255, 255,     255, 255, 255, 128,
+        ];
+        let mut img = RusImg::from_rgba8(bytes, 2, 2, &Extension::Png).unwrap();
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(2, 2));
+        let image = img.get_dynamic_image().unwrap().to_rgba8();
+        assert_eq!(image.get_pixel(0, 1).0, [0, 0, 255, 255]);
+        assert_eq!(image.get_pixel(1, 1).0, [255, 255, 255, 128]);
+
+        // Size mismatch
+        assert_eq!(RusImg::from_rgba8(vec![0; 15], 2, 2, &Extension::Png).err(), Some(RusimgError::InvalidBufferLength(16, 15)));
+    }
+
+    #[cfg(feature="parallel")]
+    #[test]
+    fn test_parallel_pixel_ops() {
+        // The parallel loop must give byte-identical results to the serial loop.
+        let (width, height) = (1024usize, 768usize);
+        let source: Vec<u8> = (0..width * height * 4).map(|i| (i * 7 % 251) as u8).collect();
+        let posterize = |pixel: &mut [u8]| {
+            for v in pixel.iter_mut().take(3) {
+                *v = (*v / 64) * 85;
+            }
+        };
+        let mut serial = source.clone();
+        operations::for_each_pixel_serial(&mut serial, 4, posterize);
+        let mut parallel = source.clone();
+        operations::for_each_pixel_parallel(&mut parallel, width * 4, 4, posterize);
+        assert_eq!(serial, parallel);
+
+        // Operations built on the loop
+        let image = DynamicImage::ImageRgba8(image::RgbaImage::from_raw(width as u32, height as u32, source).unwrap());
+        let thresholded = operations::threshold(&image, 100);
+        let mut expected = image.to_luma8();
+        for pixel in expected.pixels_mut() {
+            pixel[0] = if pixel[0] >= 100 { 255 } else { 0 };
+        }
+        assert_eq!(thresholded, expected);
+        assert_eq!(operations::grayscale(&image, false), DynamicImage::ImageLumaA8(image.to_luma_alpha8()));
+        let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+        assert_eq!(operations::grayscale(&rgb, false), DynamicImage::ImageLuma8(rgb.to_luma8()));
+    }
+
+    // Timing of the grayscale conversion with and without the parallel feature:
+    // cargo test --release test_grayscale_timing -- --ignored --nocapture [--features parallel]
+    #[test]
+    #[ignore]
+    fn test_grayscale_timing() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(4096, 4096, |x, y| Rgb([x as u8, y as u8, (x ^ y) as u8])));
+        let start = std::time::Instant::now();
+        for _ in 0..10 {
+            operations::grayscale(&source, false);
+        }
+        println!("grayscale 4096x4096 (parallel: {}): {:.1} ms", cfg!(feature="parallel"), start.elapsed().as_secs_f64() * 100.0);
+    }
+
+    #[test]
+    fn test_rotate_arbitrary() {
+        let rectangle: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_pixel(100, 50, Rgb([0, 0, 0]));
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(rectangle)).unwrap();
+        let fill = [255, 0, 0, 255];
+        let size = img.rotate_arbitrary(45.0, fill).unwrap();
+
+        // The bounding box of a 100x50 rectangle rotated by 45 degrees is about 106.1x106.1.
+        assert_eq!(size, ImgSize::new(107, 107));
+        assert_eq!(img.get_image_size().unwrap(), size);
+        let image = img.get_dynamic_image().unwrap().to_rgba8();
+        for (x, y) in [(0, 0), (106, 0), (0, 106), (106, 106)] {
+            assert_eq!(image.get_pixel(x, y).0, fill, "corner ({}, {})", x, y);
+        }
+        assert_eq!(image.get_pixel(53, 53).0, [0, 0, 0, 255]);
+
+        // A right angle swaps the width and height without growing the canvas.
+        let mut img = RusImg::new(&Extension::Bmp, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(40, 20, Rgb([0, 0, 0])))).unwrap();
+        assert_eq!(img.rotate_arbitrary_with(90.0, fill, RotateInterpolation::Nearest).unwrap(), ImgSize::new(20, 40));
+
+        assert_eq!(img.rotate_arbitrary(f32::NAN, fill), Err(RusimgError::InvalidRotationAngle));
+    }
+
+    #[test]
+    fn test_deskew() {
+        // Black text-like lines on a white page, skewed by 3 degrees.
+        let page: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(240, 160, |x, y| {
+            if (20..220).contains(&x) && y % 16 < 3 && (16..144).contains(&y) { Rgb([0, 0, 0]) } else { Rgb([255, 255, 255]) }
+        });
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(page)).unwrap();
+        img.rotate_arbitrary(3.0, [255, 255, 255, 255]).unwrap();
+        img.threshold(128).unwrap();
+
+        let angle = img.deskew(10.0).unwrap();
+        assert!((angle - 3.0).abs() < 0.2, "detected angle: {}", angle);
+
+        assert_eq!(img.deskew(0.0), Err(RusimgError::InvalidRotationAngle));
+    }
+
+    #[test]
+    fn test_open_path() {
+        let filename = "test_image44.png";
+        generate_test_image(filename, 100, 100);
+        assert!(RusImg::open_path(filename).is_ok());
+        let string_path: String = filename.to_string();
+        assert!(RusImg::open_path(string_path).is_ok());
+        assert!(RusImg::open_path(PathBuf::from(filename)).is_ok());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[cfg(feature="png")]
+    #[test]
+    fn test_compress_with_shared_options() {
+        let options = PngCompressOptions::from_preset(2);
+        for (i, size) in [(45, 60), (46, 80), (47, 100)] {
+            let filename = format!("test_image{}.png", i);
+            let output = format!("test_image{}_out.png", i);
+            generate_test_image(&filename, size, size);
+            let mut img = RusImg::open_path(&filename).unwrap();
+            img.compress_with_options(&options).unwrap();
+            let status = img.save_image(Some(&output)).unwrap();
+            assert!(status.after_filesize.unwrap() <= status.before_filesize.unwrap());
+            assert_eq!(RusImg::open_path(&output).unwrap().get_image_size().unwrap(), ImgSize::new(size as usize, size as usize));
+            std::fs::remove_file(&filename).unwrap();
+            std::fs::remove_file(&output).unwrap();
+        }
+
+        let mut bmp = RusImg::new(&Extension::Bmp, DynamicImage::ImageRgb8(ImageBuffer::new(8, 8))).unwrap();
+        assert_eq!(bmp.compress_with_options(&options), Err(RusimgError::UnsupportedFeature));
+
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::new(8, 8))).unwrap();
+        let options = PngCompressOptions::from(oxipng::Options::from_preset(1)).with_filters(&[PngRowFilter::Paeth]).strip_metadata();
+        img.compress_with_options(&options).unwrap();
+        assert_eq!(img.get_dynamic_image().unwrap().width(), 8);
+    }
+
+    #[test]
+    fn test_resize_to_width_and_height() {
+        let mut img = RusImg::new(&Extension::Bmp, DynamicImage::ImageRgb8(ImageBuffer::new(1000, 500))).unwrap();
+        assert_eq!(img.resize_to_width(200).unwrap(), ImgSize::new(200, 100));
+        assert_eq!(img.get_dynamic_image().unwrap().width(), 200);
+
+        let mut img = RusImg::new(&Extension::Bmp, DynamicImage::ImageRgb8(ImageBuffer::new(1000, 500))).unwrap();
+        assert_eq!(img.resize_to_height(100).unwrap(), ImgSize::new(200, 100));
+        assert_eq!(img.get_dynamic_image().unwrap().height(), 100);
+
+        assert_eq!(img.resize_to_width(0), Err(RusimgError::InvalidResizeRatio));
+    }
+
+    #[cfg(feature="logging")]
+    #[test]
+    fn test_logging() {
+        // Logger capturing the messages, shared by all tests of the process.
+        struct CapturingLogger;
+        static RECORDS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+        impl log::Log for CapturingLogger {
+            fn enabled(&self, _metadata: &log::Metadata) -> bool { true }
+            fn log(&self, record: &log::Record) {
+                RECORDS.lock().unwrap().push(format!("{}", record.args()));
+            }
+            fn flush(&self) {}
+        }
+        static LOGGER: CapturingLogger = CapturingLogger;
+        let _ = log::set_logger(&LOGGER);
+        log::set_max_level(log::LevelFilter::Debug);
+
+        let filename = "test_image48.png";
+        generate_test_image(filename, 40, 40);
+        RECORDS.lock().unwrap().clear();
+        let mut img = RusImg::open_path(filename).unwrap();
+        img.resize(50.0).unwrap();
+        img.save_image(None).unwrap();
+
+        let records = RECORDS.lock().unwrap().clone();
+        assert!(records.iter().any(|r| r.starts_with("opened") && r.contains(filename)), "{:?}", records);
+        assert!(records.iter().any(|r| r.starts_with("resize:")), "{:?}", records);
+        assert!(records.iter().any(|r| r.starts_with("saved") && r.contains(filename)), "{:?}", records);
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let filename = "test_image49.png";
+        generate_test_image(filename, 50, 40);
+        let png = RusImg::open_path(filename).unwrap();
+        let bmp = png.converted(&Extension::Bmp).unwrap();
+        assert_eq!(png.content_hash().unwrap(), bmp.content_hash().unwrap());
+
+        let mut resized = png.converted(&Extension::Png).unwrap();
+        resized.resize(50.0).unwrap();
+        assert_ne!(png.content_hash().unwrap(), resized.content_hash().unwrap());
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_save_webp_target_size() {
+        // A photo-like image: smooth gradients with pseudo-random noise.
+        let mut seed = 12345u32;
+        let photo: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(256, 256, |x, y| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let noise = (seed >> 24) as u8 / 8;
+            Rgb([(x as u8).saturating_add(noise), (y as u8).saturating_add(noise), ((x + y) / 2) as u8])
+        });
+        let filename = "test_image50.webp";
+        let target = 12_000;
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(photo)).unwrap();
+        let status = img.save_webp_target_size(filename, target, 0.1).unwrap();
+        let size = std::fs::metadata(filename).unwrap().len();
+        assert_eq!(status.after_filesize, Some(size));
+        assert!((size as f64 - target as f64).abs() <= target as f64 * 0.1, "size: {}", size);
+        assert_eq!(img.get_extension(), Extension::Webp);
+
+        assert_eq!(img.save_webp_target_size(filename, 0, 0.1), Err(RusimgError::InvalidTargetSize));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_source_is_lossy() {
+        let image = DynamicImage::ImageRgba8(ImageBuffer::from_fn(32, 32, |x, y| image::Rgba([x as u8 * 8, y as u8 * 8, 0, 255])));
+
+        // Lossless WebP encoded by the image crate.
+        let lossless = "test_image51.webp";
+        image.save_with_format(lossless, image::ImageFormat::WebP).unwrap();
+        assert_eq!(RusImg::open_path(lossless).unwrap().source_is_lossy(), Some(false));
+
+        // Lossy WebP encoded by libwebp.
+        let lossy = "test_image52.webp";
+        let mut img = RusImg::new(&Extension::Webp, image.clone()).unwrap();
+        assert_eq!(img.source_is_lossy(), None);
+        img.save_image(Some(lossy)).unwrap();
+        assert_eq!(RusImg::open_path(lossy).unwrap().source_is_lossy(), Some(true));
+
+        assert_eq!(RusImg::new(&Extension::Jpeg, image.clone()).unwrap().source_is_lossy(), Some(true));
+        assert_eq!(RusImg::new(&Extension::Png, image).unwrap().source_is_lossy(), Some(false));
+        std::fs::remove_file(lossless).unwrap();
+        std::fs::remove_file(lossy).unwrap();
+    }
+
+    #[test]
+    fn test_webp_decode_fallback() {
+        // Valid WebP files still open.
+        let filename = "test_image53.webp";
+        let image = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(16, 16, Rgb([10, 200, 30])));
+        image.save_with_format(filename, image::ImageFormat::WebP).unwrap();
+        assert_eq!(RusImg::open_path(filename).unwrap().get_image_size().unwrap(), ImgSize::new(16, 16));
+
+        // Data that no decoder can read is still an error.
+        let mut broken = b"RIFF".to_vec();
+        broken.extend_from_slice(&20u32.to_le_bytes());
+        broken.extend_from_slice(b"WEBPVP8 ");
+        broken.extend_from_slice(&8u32.to_le_bytes());
+        broken.extend_from_slice(&[0xAB; 8]);
+        std::fs::write(filename, &broken).unwrap();
+        assert_eq!(RusImg::open_path(filename).err().as_ref().map(RusimgError::without_path), Some(&RusimgError::FailedToDecodeWebp));
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_img_size_helpers() {
+        let landscape = ImgSize::new(1920, 1080);
+        assert!((landscape.aspect_ratio() - 16.0 / 9.0).abs() < 1e-6);
+        assert!(landscape.is_landscape() && !landscape.is_portrait());
+        assert!((landscape.megapixels() - 2.0736).abs() < 1e-6);
+
+        let portrait = ImgSize::new(3, 4);
+        assert_eq!(portrait.aspect_ratio(), 0.75);
+        assert!(portrait.is_portrait() && !portrait.is_landscape());
+
+        let square = ImgSize::new(500, 500);
+        assert_eq!(square.aspect_ratio(), 1.0);
+        assert!(!square.is_landscape() && !square.is_portrait());
+        assert_eq!(square.megapixels(), 0.25);
+
+        assert!(ImgSize::new(10, 0).aspect_ratio().is_nan());
+        assert_eq!(ImgSize::new(10, 0).megapixels(), 0.0);
+    }
+
+    #[test]
+    fn test_bmp_rle() {
+        // Flat-color stripes with a few single-pixel details and a row of alternating colors.
+        let colors = [Rgb([255, 0, 0]), Rgb([0, 128, 255]), Rgb([250, 250, 250])];
+        let graphic: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(99, 60, |x, y| {
+            if y == 59 { colors[(x % 3) as usize] }
+            else if x % 17 == 3 && y % 5 == 0 { Rgb([0, 0, 0]) }
+            else { colors[(y / 20) as usize] }
+        });
+        let (uncompressed, compressed) = ("test_image54.bmp", "test_image55.bmp");
+        let mut img = RusImg::new(&Extension::Bmp, DynamicImage::ImageRgb8(graphic.clone())).unwrap();
+        img.save_image(Some(uncompressed)).unwrap();
+        img.set_bmp_rle(true).unwrap();
+        img.save_image(Some(compressed)).unwrap();
+
+        assert!(std::fs::metadata(compressed).unwrap().len() < std::fs::metadata(uncompressed).unwrap().len());
+        let decoded = RusImg::open_path(compressed).unwrap().get_dynamic_image().unwrap().to_rgb8();
+        assert_eq!(decoded, graphic);
+
+        assert_eq!(RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(graphic)).unwrap().set_bmp_rle(true), Err(RusimgError::UnsupportedFeature));
+        std::fs::remove_file(uncompressed).unwrap();
+        std::fs::remove_file(compressed).unwrap();
+    }
+
+    #[test]
+    fn test_open_with_config() {
+        let filename = "test_image56.png";
+        generate_test_image(filename, 100, 100);
+        let config = RusImgConfig { resize_filter: ResizeFilter::Nearest, ..Default::default() };
+
+        let mut img = RusImg::open_with_config(Path::new(filename), &config).unwrap();
+        assert_eq!(img.config(), &config);
+        let original = img.get_dynamic_image().unwrap();
+        img.resize(50.0).unwrap();
+        let expected = original.resize_exact(50, 50, image::imageops::FilterType::Nearest);
+        assert_eq!(img.get_dynamic_image().unwrap(), expected);
+
+        // The config is kept after converting the image.
+        img.convert(&Extension::Bmp).unwrap();
+        img.resize(50.0).unwrap();
+        assert_eq!(img.get_dynamic_image().unwrap(), expected.resize_exact(25, 25, image::imageops::FilterType::Nearest));
+
+        // Out-of-range qualities and levels are rejected and the config is unchanged.
+        for invalid in [
+            RusImgConfig { jpeg_quality: 101.0, ..Default::default() },
+            RusImgConfig { webp_quality: -1.0, ..Default::default() },
+            RusImgConfig { webp_quality: f32::NAN, ..Default::default() },
+            RusImgConfig { png_level: 0, ..Default::default() },
+            RusImgConfig { png_level: 7, ..Default::default() },
+        ] {
+            assert_eq!(img.set_config(&invalid), Err(RusimgError::InvalidCompressionLevel));
+            assert!(RusImg::open_with_config(Path::new(filename), &invalid).is_err());
+            assert_eq!(set_default_config(invalid), Err(RusimgError::InvalidCompressionLevel));
+        }
+        assert_eq!(img.config(), &config);
         std::fs::remove_file(filename).unwrap();
     }
 
     #[test]
-    fn test_resize_image() {
-        let filename = "test_image3.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let size = img.resize(50.0).unwrap();
-        assert_eq!(size.width, 50);
-        assert_eq!(size.height, 50);
+    fn test_crop_region() {
+        let gradient: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(100, 60, |x, y| Rgb([x as u8, y as u8, 0]));
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(gradient.clone())).unwrap();
+
+        let left = img.crop_region(Rect { x: 0, y: 0, w: 50, h: 60 }).unwrap();
+        let mut right = img.crop_region(Rect { x: 50, y: 10, w: 50, h: 30 }).unwrap();
+        assert_eq!(left.get_image_size().unwrap(), ImgSize::new(50, 60));
+        assert_eq!(right.get_image_size().unwrap(), ImgSize::new(50, 30));
+        assert_eq!(left.get_extension(), Extension::Png);
+        assert_eq!(right.get_dynamic_image().unwrap().to_rgb8().get_pixel(0, 0), &Rgb([50, 10, 0]));
+        assert_eq!(right.get_dynamic_image().unwrap().to_rgb8().get_pixel(49, 29), &Rgb([99, 39, 0]));
+
+        // The original is unchanged.
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(100, 60));
+        assert_eq!(img.get_dynamic_image().unwrap().to_rgb8(), gradient);
+
+        assert!(img.crop_region(Rect { x: 60, y: 0, w: 50, h: 10 }).is_err());
+        assert!(img.crop_region(Rect { x: 0, y: 0, w: 0, h: 10 }).is_err());
+    }
+
+    #[test]
+    fn test_split_tiles() {
+        let gradient: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(100, 100, |x, y| Rgb([x as u8, y as u8, 0]));
+        let img = RusImg::new(&Extension::Bmp, DynamicImage::ImageRgb8(gradient)).unwrap();
+
+        let mut tiles = img.split_tiles(2, 2).unwrap();
+        assert_eq!(tiles.len(), 4);
+        assert!(tiles.iter().all(|t| t.get_image_size().unwrap() == ImgSize::new(50, 50)));
+        // Row-major order: the second tile is the top right one.
+        assert_eq!(tiles[1].get_dynamic_image().unwrap().to_rgb8().get_pixel(0, 0), &Rgb([50, 0, 0]));
+        assert_eq!(tiles[2].get_dynamic_image().unwrap().to_rgb8().get_pixel(0, 0), &Rgb([0, 50, 0]));
+
+        // 100 / 3 = 33, so the last column and row take the remaining 34 pixels.
+        let tiles = img.split_tiles(3, 3).unwrap();
+        let sizes: Vec<ImgSize> = tiles.iter().map(|t| t.get_image_size().unwrap()).collect();
+        assert_eq!(sizes[0], ImgSize::new(33, 33));
+        assert_eq!(sizes[2], ImgSize::new(34, 33));
+        assert_eq!(sizes[6], ImgSize::new(33, 34));
+        assert_eq!(sizes[8], ImgSize::new(34, 34));
+        assert_eq!(sizes.iter().map(|s| s.width * s.height).sum::<usize>(), 100 * 100);
+
+        assert_eq!(img.split_tiles(0, 2).err(), Some(RusimgError::InvalidTileGrid));
+        assert_eq!(img.split_tiles(101, 1).err(), Some(RusimgError::InvalidTileGrid));
+    }
+
+    #[test]
+    fn test_gps_location() {
+        // EXIF data (little-endian TIFF) with a GPS IFD: 35°39'31.5"N, 139°44'28.5"W.
+        let mut tiff: Vec<u8> = b"II\x2a\x00".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        let entry = |tag: u16, format: u16, count: u32, value: [u8; 4]| -> Vec<u8> {
+            [tag.to_le_bytes().as_slice(), &format.to_le_bytes(), &count.to_le_bytes(), &value].concat()
+        };
+        // IFD0 with the GPS IFD pointer.
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend(entry(0x8825, 4, 1, 26u32.to_le_bytes()));
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        // GPS IFD at 26; the rationals follow it at 80 and 104.
+        tiff.extend_from_slice(&4u16.to_le_bytes());
+        tiff.extend(entry(1, 2, 2, *b"N\0\0\0"));
+        tiff.extend(entry(2, 5, 3, 80u32.to_le_bytes()));
+        tiff.extend(entry(3, 2, 2, *b"W\0\0\0"));
+        tiff.extend(entry(4, 5, 3, 104u32.to_le_bytes()));
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        for (numerator, denominator) in [(35u32, 1u32), (39, 1), (315, 10), (139, 1), (44, 1), (2850, 100)] {
+            tiff.extend_from_slice(&numerator.to_le_bytes());
+            tiff.extend_from_slice(&denominator.to_le_bytes());
+        }
+
+        // Insert the EXIF data as an APP1 segment after the SOI marker of a JPEG file.
+        let filename = "test_image57.jpg";
+        let mut img = RusImg::new(&Extension::Jpg, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(16, 16, Rgb([90, 120, 200])))).unwrap();
+        img.save_image(Some(filename)).unwrap();
+        let jpeg = std::fs::read(filename).unwrap();
+        let app1 = [b"Exif\0\0".as_slice(), &tiff].concat();
+        let geotagged = [&jpeg[0..2], &[0xFF, 0xE1], &((app1.len() + 2) as u16).to_be_bytes(), &app1, &jpeg[2..]].concat();
+        std::fs::write(filename, geotagged).unwrap();
+
+        let (latitude, longitude) = RusImg::open_path(filename).unwrap().gps_location().unwrap();
+        assert!((latitude - 35.65875).abs() < 1e-6, "latitude: {}", latitude);
+        assert!((longitude + 139.74125).abs() < 1e-6, "longitude: {}", longitude);
+        assert_eq!(img.gps_location(), None);
         std::fs::remove_file(filename).unwrap();
     }
 
     #[test]
-    fn test_trim_image() {
-        let filename = "test_image4.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let size = img.trim(10, 10, 50, 50).unwrap();
-        assert_eq!(size.width, 50);
-        assert_eq!(size.height, 50);
+    fn test_dominant_colors() {
+        // 70% red and 30% blue, with a little noise.
+        let image: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_fn(200, 100, |x, y| {
+            let noise = ((x * 7 + y * 13) % 9) as u8;
+            if x < 140 { Rgb([240 + noise, noise, noise]) } else { Rgb([noise, noise, 240 + noise]) }
+        });
+        let img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(image)).unwrap();
+        let colors = img.dominant_colors(2).unwrap();
+        assert_eq!(colors.len(), 2);
+        let near = |a: [u8; 3], b: [u8; 3]| (0..3).all(|i| (a[i] as i32 - b[i] as i32).abs() < 20);
+        assert!(near(colors[0], [244, 4, 4]), "{:?}", colors);
+        assert!(near(colors[1], [4, 4, 244]), "{:?}", colors);
+
+        // A single-color image has only one dominant color.
+        let flat = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(10, 10, Rgb([1, 2, 3])))).unwrap();
+        assert_eq!(flat.dominant_colors(3).unwrap(), vec![[1, 2, 3]]);
+    }
+
+    #[test]
+    fn test_embedded_thumbnail() {
+        // An 8x8 JPEG thumbnail.
+        let mut thumbnail = Vec::new();
+        DynamicImage::ImageRgb8(ImageBuffer::from_pixel(8, 8, Rgb([200, 40, 40])))
+            .write_to(&mut std::io::Cursor::new(&mut thumbnail), image::ImageFormat::Jpeg).unwrap();
+
+        // EXIF data (little-endian TIFF) with an empty IFD0 and IFD1 pointing to the thumbnail at 44.
+        let mut tiff: Vec<u8> = b"II\x2a\x00".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&0u16.to_le_bytes());
+        tiff.extend_from_slice(&14u32.to_le_bytes());
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        for (tag, value) in [(0x0201u16, 44u32), (0x0202, thumbnail.len() as u32)] {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&4u16.to_le_bytes());
+            tiff.extend_from_slice(&1u32.to_le_bytes());
+            tiff.extend_from_slice(&value.to_le_bytes());
+        }
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(&thumbnail);
+
+        // Insert the EXIF data as an APP1 segment after the SOI marker of a JPEG file.
+        let filename = "test_image58.jpg";
+        let mut img = RusImg::new(&Extension::Jpg, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(64, 48, Rgb([200, 40, 40])))).unwrap();
+        img.save_image(Some(filename)).unwrap();
+        let jpeg = std::fs::read(filename).unwrap();
+        let app1 = [b"Exif\0\0".as_slice(), &tiff].concat();
+        let with_thumbnail = [&jpeg[0..2], &[0xFF, 0xE1], &((app1.len() + 2) as u16).to_be_bytes(), &app1, &jpeg[2..]].concat();
+        std::fs::write(filename, with_thumbnail).unwrap();
+
+        let extracted = RusImg::open_path(filename).unwrap().embedded_thumbnail().unwrap();
+        assert_eq!(extracted, thumbnail);
+        let decoded = image::load_from_memory(&extracted).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (8, 8));
+        assert_eq!(img.embedded_thumbnail(), None);
         std::fs::remove_file(filename).unwrap();
     }
 
     #[test]
-    fn test_trim_rect_image() {
-        let filename = "test_image5.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let rect = Rect { x: 10, y: 10, w: 50, h: 50 };
-        let size = img.trim_rect(rect).unwrap();
-        assert_eq!(size.width, 50);
-        assert_eq!(size.height, 50);
+    fn test_supported_extensions() {
+        let extensions = supported_extensions();
+        assert_eq!(extensions.contains(&Extension::Png), cfg!(feature="png"));
+        assert_eq!(extensions.contains(&Extension::Bmp), cfg!(feature="bmp"));
+        assert_eq!(extensions.contains(&Extension::Jpg), cfg!(feature="jpeg"));
+        assert_eq!(extensions.contains(&Extension::Webp), cfg!(feature="webp"));
+        assert!(!extensions.iter().any(|e| matches!(e, Extension::ExternalFormat(_))));
+    }
+
+    #[test]
+    fn test_set_opacity() {
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(8, 8, Rgb([0, 0, 0])))).unwrap();
+        img.set_opacity(0.5).unwrap();
+        let overlay = img.get_dynamic_image().unwrap();
+        assert!(overlay.color().has_alpha());
+        assert!(overlay.to_rgba8().pixels().all(|p| p[3] == 128));
+
+        // Overlaying the half-transparent black image on white gives mid gray.
+        let mut background = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(8, 8, image::Rgba([255, 255, 255, 255])));
+        image::imageops::overlay(&mut background, &overlay, 0, 0);
+        let blended = background.to_rgba8().get_pixel(4, 4).0;
+        assert!(blended[0].abs_diff(127) <= 2, "{:?}", blended);
+
+        assert_eq!(img.set_opacity(1.5), Err(RusimgError::InvalidFilterParameter));
+        assert_eq!(img.set_opacity(-0.1), Err(RusimgError::InvalidFilterParameter));
+    }
+
+    #[test]
+    fn test_apply_masks() {
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(64, 64, Rgb([10, 200, 30])))).unwrap();
+        img.apply_circle_mask().unwrap();
+        let masked = img.get_dynamic_image().unwrap().to_rgba8();
+        for (x, y) in [(0, 0), (63, 0), (0, 63), (63, 63)] {
+            assert_eq!(masked.get_pixel(x, y)[3], 0);
+        }
+        assert_eq!(masked.get_pixel(32, 32).0, [10, 200, 30, 255]);
+        assert_eq!(masked.get_pixel(32, 2)[3], 255);
+
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(64, 32, Rgb([10, 200, 30])))).unwrap();
+        img.apply_rounded_corners(8).unwrap();
+        let rounded = img.get_dynamic_image().unwrap().to_rgba8();
+        assert_eq!(rounded.get_pixel(0, 0)[3], 0);
+        assert_eq!(rounded.get_pixel(63, 31)[3], 0);
+        assert_eq!(rounded.get_pixel(8, 0)[3], 255);
+        assert_eq!(rounded.get_pixel(0, 16)[3], 255);
+    }
+
+    #[test]
+    fn test_unmodified_save_passthrough() {
+        // Unmodified JPEG and PNG files are written verbatim instead of being re-encoded.
+        let jpeg_filename = "test_image59.jpg";
+        let mut img = RusImg::new(&Extension::Jpg, DynamicImage::ImageRgb8(ImageBuffer::from_fn(32, 32, |x, y| Rgb([x as u8 * 8, y as u8 * 8, 128])))).unwrap();
+        img.compress(Some(60.0)).unwrap();
+        img.save_image(Some(jpeg_filename)).unwrap();
+        let source = std::fs::read(jpeg_filename).unwrap();
+        let saved_filename = "test_image59_saved.jpg";
+        RusImg::open_path(jpeg_filename).unwrap().save_image(Some(saved_filename)).unwrap();
+        assert_eq!(std::fs::read(saved_filename).unwrap(), source);
+
+        let png_filename = "test_image59.png";
+        generate_test_image(png_filename, 32, 32);
+        let source = std::fs::read(png_filename).unwrap();
+        let saved_png_filename = "test_image59_saved.png";
+        RusImg::open_path(png_filename).unwrap().save_image(Some(saved_png_filename)).unwrap();
+        assert_eq!(std::fs::read(saved_png_filename).unwrap(), source);
+
+        // Modified images are re-encoded.
+        let mut modified = RusImg::open_path(jpeg_filename).unwrap();
+        modified.trim(0, 0, 16, 16).unwrap();
+        modified.save_image(Some(saved_filename)).unwrap();
+        assert_eq!(image::open(saved_filename).unwrap().width(), 16);
+
+        for filename in [jpeg_filename, saved_filename, png_filename, saved_png_filename] {
+            std::fs::remove_file(filename).unwrap();
+        }
+    }
+
+    #[cfg(feature="base64")]
+    #[test]
+    fn test_to_data_url() {
+        use base64::Engine;
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(4, 3, |x, y| Rgb([x as u8 * 60, y as u8 * 80, 200])));
+        let img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        let data_url = img.to_data_url(&Extension::Png, None).unwrap();
+        let encoded = data_url.strip_prefix("data:image/png;base64,").unwrap();
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+        assert_eq!(image::load_from_memory(&decoded).unwrap().to_rgb8(), source.to_rgb8());
+
+        let jpeg_url = img.to_data_url(&Extension::Jpg, Some(80.0)).unwrap();
+        assert!(jpeg_url.starts_with("data:image/jpeg;base64,"));
+        assert_eq!(img.get_extension(), Extension::Png);
+        assert_eq!(img.to_data_url(&Extension::ExternalFormat("tiff".to_string()), None), Err(RusimgError::UnsupportedFileExtension));
+    }
+
+    #[test]
+    fn test_premultiply_alpha() {
+        let source: ImageBuffer<image::Rgba<u8>, Vec<u8>> = ImageBuffer::from_fn(32, 32, |x, y| {
+            image::Rgba([(x * 8) as u8, (y * 8) as u8, 200, ((x + y) * 4) as u8])
+        });
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgba8(source.clone())).unwrap();
+        img.premultiply_alpha().unwrap();
+        let premultiplied = img.get_dynamic_image().unwrap().to_rgba8();
+        // e.g. 200 * 128 / 255 = 100.4
+        assert_eq!(premultiplied.get_pixel(16, 16).0, [64, 64, 100, 128]);
+        assert_eq!(premultiplied.get_pixel(0, 0).0, [0, 0, 0, 0]);
+
+        img.unpremultiply_alpha().unwrap();
+        let restored = img.get_dynamic_image().unwrap().to_rgba8();
+        for (original, restored) in source.pixels().zip(restored.pixels()) {
+            assert_eq!(original[3], restored[3]);
+            if original[3] == 0 {
+                assert_eq!(restored.0, [0, 0, 0, 0]);
+                continue;
+            }
+            // Premultiplying loses precision at low alpha values: up to about 255 / alpha / 2 per channel.
+            let tolerance = (255.0 / original[3] as f32 / 2.0).ceil() as u8 + 1;
+            for c in 0..3 {
+                assert!(original[c].abs_diff(restored[c]) <= tolerance, "{:?} -> {:?}", original, restored);
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_image_mkdir() {
+        let dir = Path::new("test_dir3");
+        let _ = std::fs::remove_dir_all(dir);
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 4, Rgb([1, 2, 3])))).unwrap();
+        let status = img.save_image_mkdir("test_dir3/a/b/c/out.png").unwrap();
+        assert_eq!(status.output_path, Some(PathBuf::from("test_dir3/a/b/c/out.png")));
+        assert!(Path::new("test_dir3/a/b/c").is_dir());
+        assert_eq!(image::open("test_dir3/a/b/c/out.png").unwrap().width(), 4);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resize_with_kernel() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(64, 64, |x, y| if (x / 4 + y / 4) % 2 == 0 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) }));
+        let resize = |kernel: ResampleKernel| -> image::RgbImage {
+            let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+            assert_eq!(img.resize_with_kernel(37.5, kernel).unwrap(), ImgSize::new(24, 24));
+            img.get_dynamic_image().unwrap().to_rgb8()
+        };
+        let lanczos2 = resize(ResampleKernel::Lanczos { a: 2 });
+        let lanczos5 = resize(ResampleKernel::Lanczos { a: 5 });
+        let triangle = resize(ResampleKernel::Triangle);
+        assert_ne!(lanczos2, lanczos5);
+        assert_ne!(lanczos2, triangle);
+
+        // A flat image stays flat with any kernel.
+        let mut flat = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(30, 20, Rgb([10, 100, 200])))).unwrap();
+        flat.resize_with_kernel(250.0, ResampleKernel::CatmullRom).unwrap();
+        assert!(flat.get_dynamic_image().unwrap().to_rgb8().pixels().all(|p| p.0 == [10, 100, 200]));
+
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        assert_eq!(img.resize_with_kernel(50.0, ResampleKernel::Lanczos { a: 0 }), Err(RusimgError::InvalidFilterParameter));
+        assert_eq!(img.resize_with_kernel(50.0, ResampleKernel::Gaussian { sigma: -1.0 }), Err(RusimgError::InvalidFilterParameter));
+        assert_eq!(img.resize_with_kernel(0.0, ResampleKernel::Triangle), Err(RusimgError::InvalidResizeRatio));
+        assert_eq!(img.resize_with_kernel(f32::NAN, ResampleKernel::Triangle), Err(RusimgError::InvalidResizeRatio));
+        assert_eq!(img.resize_with_kernel(f32::INFINITY, ResampleKernel::Triangle), Err(RusimgError::InvalidResizeRatio));
+        assert_eq!(img.resize_cancellable(f32::NAN, &CancelToken::new()), Err(RusimgError::InvalidResizeRatio));
+        assert_eq!(img.resize_xy(50.0, f32::INFINITY), Err(RusimgError::InvalidResizeRatio));
+        // The kernel itself rejects empty axes and a support that is not finite.
+        let token = CancelToken::new();
+        assert_eq!(operations::resample(&DynamicImage::new_rgb8(0, 4), 2, 2, ResampleKernel::Triangle, &token).err(), Some(RusimgError::InvalidResizeRatio));
+        assert_eq!(operations::resample(&source, 2, 0, ResampleKernel::Triangle, &token).err(), Some(RusimgError::InvalidResizeRatio));
+        assert_eq!(operations::resample(&source, 2, 2, ResampleKernel::Gaussian { sigma: f32::INFINITY }, &token).err(), Some(RusimgError::InvalidResizeRatio));
+    }
+
+    #[test]
+    fn test_grayscale_with_depth() {
+        let filename = "test_image60.png";
+        let source: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::from_fn(16, 16, |x, y| Rgb([x as u16 * 4000 + 1, y as u16 * 4000 + 3, 30001]));
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb16(source)).unwrap();
+        img.save_image(Some(filename)).unwrap();
+
+        let mut img = RusImg::open_path(filename).unwrap();
+        img.grayscale_with_depth(true).unwrap();
+        let gray = img.get_dynamic_image().unwrap();
+        assert_eq!(gray.color(), image::ColorType::L16);
+        // Values that do not fit in 8 bits are kept.
+        assert!(gray.to_luma16().pixels().any(|p| p[0] % 257 != 0));
+
+        let mut img = RusImg::open_path(filename).unwrap();
+        img.grayscale_with_depth(false).unwrap();
+        assert_eq!(img.get_dynamic_image().unwrap().color(), image::ColorType::L8);
+
+        // 8-bit sources stay 8-bit.
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgba8(ImageBuffer::from_pixel(4, 4, image::Rgba([1, 2, 3, 4])))).unwrap();
+        img.grayscale_with_depth(true).unwrap();
+        assert_eq!(img.get_dynamic_image().unwrap().color(), image::ColorType::La8);
         std::fs::remove_file(filename).unwrap();
     }
 
     #[test]
-    fn test_grayscale_image() {
-        let filename = "test_image6.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result = img.grayscale();
-        assert!(result.is_ok());
-        // color check
-        let dynamic_image = img.get_dynamic_image().unwrap();
-        let img_data = dynamic_image.to_rgb8();
-        for pixel in img_data.pixels() {
-            assert_eq!(pixel[0], pixel[1]);
-            assert_eq!(pixel[1], pixel[2]);
+    fn test_quality_sweep() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, ((x * y) % 256) as u8])));
+        let img = RusImg::new(&Extension::Png, source).unwrap();
+        let sweep = img.quality_sweep(&Extension::Jpg, &[20.0, 50.0, 90.0]).unwrap();
+        assert_eq!(sweep.iter().map(|(q, _, _)| *q).collect::<Vec<f32>>(), vec![20.0, 50.0, 90.0]);
+        for pair in sweep.windows(2) {
+            assert!(pair[0].1 < pair[1].1, "size: {:?}", sweep);
+            assert!(pair[0].2 < pair[1].2, "PSNR: {:?}", sweep);
         }
+        assert_eq!(img.get_extension(), Extension::Png);
+        assert_eq!(img.quality_sweep(&Extension::Jpg, &[120.0]), Err(RusimgError::InvalidCompressionLevel));
+    }
+
+    #[cfg(feature="mmap")]
+    #[test]
+    fn test_open_mmap() {
+        let png_filename = "test_image61.png";
+        generate_test_image(png_filename, 1200, 900);
+        let bmp_filename = "test_image61.bmp";
+        RusImg::open_path(png_filename).unwrap().converted(&Extension::Bmp).unwrap().save_image(Some(bmp_filename)).unwrap();
+
+        for filename in [png_filename, bmp_filename] {
+            let opened = RusImg::open(Path::new(filename)).unwrap();
+            let mapped = RusImg::open_mmap(Path::new(filename)).unwrap();
+            assert_eq!(mapped.get_extension(), opened.get_extension());
+            assert_eq!(mapped.get_image_size().unwrap(), ImgSize::new(1200, 900));
+            assert_eq!(mapped.content_hash().unwrap(), opened.content_hash().unwrap());
+        }
+        assert!(matches!(RusImg::open_mmap(Path::new("test_image61_missing.png")).err().as_ref().map(RusimgError::without_path), Some(RusimgError::FailedToOpenFile(_))));
+        std::fs::remove_file(png_filename).unwrap();
+        std::fs::remove_file(bmp_filename).unwrap();
+    }
+
+    #[test]
+    fn test_error_with_path() {
+        let filename = "test_image62.png";
+        std::fs::write(filename, b"not an image").unwrap();
+        let error = RusImg::open_path(filename).err().unwrap();
+        assert_eq!(error.path(), Some(Path::new(filename)));
+        assert!(matches!(error.without_path(), RusimgError::FailedToOpenImage(_)));
+        assert!(error.to_string().starts_with("test_image62.png: Failed to open image"), "{}", error);
+        // The path is attached only once.
+        assert_eq!(error.clone().with_path(Path::new("other.png")), error);
+
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 4, Rgb([1, 2, 3])))).unwrap();
+        let error = img.save_image(Some("test_image62_missing_dir/out.png")).err().unwrap();
+        assert_eq!(error.path(), Some(Path::new("test_image62_missing_dir/out.png")));
         std::fs::remove_file(filename).unwrap();
     }
 
     #[test]
-    fn test_compress_image() {
-        let filename = "test_image7.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result = img.compress(Some(30.0));
-        assert!(result.is_ok());
-        // size check
-        img.save_image(None).unwrap();
-        let before_size = img.data.get_metadata_src().unwrap().len();
-        let after_size = img.data.get_metadata_dest().unwrap().len();
-        assert!(after_size < before_size);
+    fn test_resize_to_breakpoint() {
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(1000, 500, Rgb([1, 2, 3])))).unwrap();
+        assert_eq!(img.resize_to_breakpoint(&[320, 640, 1280]).unwrap(), ImgSize::new(640, 320));
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(640, 320));
+        // The same width is kept as it is, and no breakpoint fits a smaller image.
+        assert_eq!(img.resize_to_breakpoint(&[640, 1280]).unwrap(), ImgSize::new(640, 320));
+        assert_eq!(img.resize_to_breakpoint(&[1280, 0]), Err(RusimgError::InvalidResizeRatio));
+        assert_eq!(img.resize_to_breakpoint(&[]), Err(RusimgError::InvalidResizeRatio));
+    }
+
+    #[test]
+    fn test_generate_srcset() {
+        let filename = "test_image63.png";
+        let dir = Path::new("test_dir4");
+        let _ = std::fs::remove_dir_all(dir);
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(800, 400, |x, y| Rgb([(x % 256) as u8, (y % 256) as u8, 128]))).save(filename).unwrap();
+        let img = RusImg::open(Path::new(filename)).unwrap();
+
+        // 1600 is larger than the source and skipped.
+        let statuses = img.generate_srcset(&[200, 400, 800, 1600], &Extension::Webp, Some(80.0), dir).unwrap();
+        assert_eq!(statuses.len(), 3);
+        for (status, width) in statuses.iter().zip([200, 400, 800]) {
+            let path = dir.join(format!("test_image63-{}w.webp", width));
+            assert_eq!(status.output_path.as_deref(), Some(path.as_path()));
+            let output = image::open(&path).unwrap();
+            assert_eq!((output.width(), output.height()), (width, width / 2));
+        }
+        assert!(!dir.join("test_image63-1600w.webp").exists());
+        // PNG files are compressed after resizing.
+        let statuses = img.generate_srcset(&[300], &Extension::Png, Some(50.0), dir).unwrap();
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(image::open(dir.join("test_image63-300w.png")).unwrap().width(), 300);
+        // The image itself is left untouched.
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(800, 400));
+        assert_eq!(img.get_extension(), Extension::Png);
+
+        // A width of 0 is rejected before any file is written.
+        std::fs::remove_dir_all(dir).unwrap();
+        assert_eq!(img.generate_srcset(&[200, 0, 400], &Extension::Webp, None, dir).err(), Some(RusimgError::InvalidResizeRatio));
+        assert!(!dir.exists());
+
         std::fs::remove_file(filename).unwrap();
     }
 
     #[test]
-    fn test_convert_image() {
-        let file_names = vec![
-            "test_image8.bmp",
-            "test_image8.jpeg",
-            "test_image8.jpg",
-            "test_image8.png",
-            "test_image8.webp",
+    fn test_import_invalid_image_buffer() {
+        // A 3x2 RGB image whose rows are padded to 12 bytes instead of 9 bytes.
+        let buffer = image::RgbImage::from_raw(3, 2, vec![0; 24]).unwrap();
+        let image = DynamicImage::ImageRgb8(buffer);
+        for extension in [Extension::Bmp, Extension::Jpeg, Extension::Png, Extension::Webp] {
+            assert_eq!(RusImg::new(&extension, image.clone()).err(), Some(RusimgError::InvalidImageBuffer(18, 24)), "{}", extension);
+        }
+        // A buffer of the right length is accepted.
+        let buffer = image::RgbImage::from_raw(3, 2, vec![0; 18]).unwrap();
+        assert!(RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(buffer)).is_ok());
+    }
+
+    #[test]
+    fn test_save_status_human_summary() {
+        let status = |before: Option<u64>, after: Option<u64>| SaveStatus { output_path: None, before_filesize: before, after_filesize: after, source_modified: None };
+        assert_eq!(status(Some(1_200_000), Some(340_000)).human_summary(), "1.2 MB → 340 KB (−72%)");
+        // Byte, KB and MB thresholds
+        assert_eq!(status(Some(999), Some(1000)).human_summary(), "999 B → 1.0 KB (0%)");
+        assert_eq!(status(Some(9_949), Some(9_950)).human_summary(), "9.9 KB → 10 KB (0%)");
+        assert_eq!(status(Some(999_499), Some(999_500)).human_summary(), "999 KB → 1.0 MB (0%)");
+        assert_eq!(status(Some(2_000), Some(3_000)).human_summary(), "2.0 KB → 3.0 KB (+50%)");
+        assert_eq!(status(Some(5_000_000_000), Some(512)).human_summary(), "5.0 GB → 512 B (−100%)");
+        // Missing sizes
+        assert_eq!(status(Some(1_200_000), None).human_summary(), "1.2 MB → ?");
+        assert_eq!(status(None, Some(340_000)).human_summary(), "340 KB");
+        assert_eq!(status(None, None).human_summary(), "?");
+        assert_eq!(status(Some(0), Some(10)).human_summary(), "0 B → 10 B");
+    }
+
+    #[test]
+    fn test_trim_multiple_of() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(200, 200, |x, y| Rgb([x as u8, y as u8, 0])));
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        assert_eq!(img.trim_multiple_of(Rect { x: 50, y: 20, w: 100, h: 100 }, 16).unwrap(), ImgSize::new(96, 96));
+        // The 96x96 area is centered in the requested area: (50 + 2, 20 + 2)
+        let trimmed = img.get_dynamic_image().unwrap().to_rgb8();
+        assert_eq!(trimmed.get_pixel(0, 0), &Rgb([52, 22, 0]));
+        assert_eq!(trimmed.get_pixel(95, 95), &Rgb([147, 117, 0]));
+
+        // An area reaching past the image is clipped to it (140..200 = 60 pixels wide) before rounding, so the result is still a multiple.
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        assert_eq!(img.trim_multiple_of(Rect { x: 140, y: 0, w: 100, h: 100 }, 16).unwrap(), ImgSize::new(48, 96));
+        assert_eq!(img.get_dynamic_image().unwrap().to_rgb8().get_pixel(0, 0), &Rgb([146, 2, 0]));
+
+        let mut img = RusImg::new(&Extension::Png, source).unwrap();
+        assert_eq!(img.trim_multiple_of(Rect { x: 0, y: 0, w: 100, h: 100 }, 0), Err(RusimgError::InvalidTrimMultiple));
+        assert_eq!(img.trim_multiple_of(Rect { x: 0, y: 0, w: 10, h: 100 }, 16), Err(RusimgError::InvalidTrimXY));
+        assert_eq!(img.trim_multiple_of(Rect { x: 0, y: 0, w: 100, h: 100 }, 1).unwrap(), ImgSize::new(100, 100));
+    }
+
+    #[test]
+    fn test_to_indexed() {
+        let colors = [Rgb([200, 30, 30]), Rgb([30, 200, 30]), Rgb([30, 30, 200]), Rgb([240, 240, 20])];
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(128, 128, |x, y| colors[((x / 16 + y / 8) % 4) as usize]));
+        let (truecolor_filename, indexed_filename, webp_filename) = ("test_image64_truecolor.png", "test_image64_indexed.png", "test_image64_indexed.webp");
+
+        RusImg::new(&Extension::Png, source.clone()).unwrap().save_image(Some(truecolor_filename)).unwrap();
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        img.to_indexed(4).unwrap();
+        img.save_image(Some(indexed_filename)).unwrap();
+        let indexed = std::fs::read(indexed_filename).unwrap();
+        assert!(indexed.windows(4).any(|w| w == b"PLTE"));
+        assert!(indexed.len() < std::fs::metadata(truecolor_filename).unwrap().len() as usize);
+        assert_eq!(image::load_from_memory(&indexed).unwrap().to_rgb8(), source.to_rgb8());
+
+        // WebP uses the lossless palette mode, so the colors also round-trip.
+        let mut img = RusImg::new(&Extension::Webp, source.clone()).unwrap();
+        img.to_indexed(4).unwrap();
+        img.save_image(Some(webp_filename)).unwrap();
+        assert_eq!(image::open(webp_filename).unwrap().to_rgb8(), source.to_rgb8());
+
+        // Images with more colors are quantized.
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_fn(64, 64, |x, y| Rgb([x as u8 * 4, y as u8 * 4, 128])))).unwrap();
+        img.to_indexed(16).unwrap();
+        let quantized: std::collections::HashSet<_> = img.get_dynamic_image().unwrap().to_rgb8().pixels().map(|p| p.0).collect();
+        assert!(quantized.len() <= 16);
+
+        assert_eq!(img.to_indexed(0), Err(RusimgError::InvalidPaletteSize));
+        assert_eq!(img.to_indexed(257), Err(RusimgError::InvalidPaletteSize));
+        let mut img = RusImg::new(&Extension::Jpeg, source).unwrap();
+        assert_eq!(img.to_indexed(4), Err(RusimgError::UnsupportedFeature));
+
+        std::fs::remove_file(truecolor_filename).unwrap();
+        std::fs::remove_file(indexed_filename).unwrap();
+        std::fs::remove_file(webp_filename).unwrap();
+    }
+
+    #[test]
+    fn test_batch_report() {
+        let status = |before: Option<u64>, after: Option<u64>| -> Result<SaveStatus, RusimgError> {
+            Ok(SaveStatus { output_path: None, before_filesize: before, after_filesize: after, source_modified: None })
+        };
+        let results = vec![
+            status(Some(1000), Some(400)),
+            Err(RusimgError::UnsupportedFileExtension),
+            status(Some(3000), Some(1600)),
+            status(None, Some(500)),        // not counted in the byte totals
+            Err(RusimgError::ImageTruncated),
         ];
-        for filename in &file_names {
-            let width = 100;
-            let height = 100;
-            generate_test_image(filename, width, height);
-            let path = Path::new(filename);
-            let mut img = RusImg::open(path).unwrap();
-            let result = img.convert(&Extension::Webp);
-            assert!(result.is_ok());
-            // file types
-            let rusimg_extensions = vec![Extension::Bmp, Extension::Jpeg, Extension::Jpg, Extension::Png, Extension::Webp];
-            let image_extensions = vec![image::ImageFormat::Bmp, image::ImageFormat::Jpeg, image::ImageFormat::Jpeg, image::ImageFormat::Png, image::ImageFormat::WebP];
-            for (ext, image_ext) in rusimg_extensions.iter().zip(image_extensions.iter()) {
-                // Convert the image to the new format.
-                let new_filename = filename.replace(format!(".{}", filename.split('.').last().unwrap()).as_str(), format!("_output.{}", ext.to_string()).as_str());
-                let new_path = Path::new(&new_filename);
-                let mut image_cloned = RusImg::open(&PathBuf::from(filename)).unwrap();
-                image_cloned.convert(&ext).unwrap();
-                image_cloned.save_image(new_path.to_str()).unwrap();
-                // Check if the file extension is correct.
-                let output_image_binary = std::fs::read(new_path).unwrap();
-                let guessed_format = image::guess_format(&output_image_binary).unwrap();
-                assert_eq!(guessed_format, *image_ext);
-                // Clean up the test image file.
-                std::fs::remove_file(new_path).unwrap();
+        let report = BatchReport::from_results(&results);
+        assert_eq!(report.succeeded(), 3);
+        assert_eq!(report.failed(), 2);
+        assert_eq!(report.processed(), 5);
+        assert_eq!(report.total_input_bytes(), 4000);
+        assert_eq!(report.total_output_bytes(), 2000);
+        assert_eq!(report.savings_bytes(), 2000);
+        assert_eq!(report.savings_percent(), Some(50.0));
+
+        // Adding the results one by one gives the same report.
+        let mut incremental = BatchReport::default();
+        results.iter().for_each(|result| incremental.add(result));
+        assert_eq!(incremental, report);
+
+        let report = BatchReport::from_results(&[status(Some(100), Some(150))]);
+        assert_eq!(report.savings_bytes(), -50);
+        assert_eq!(report.savings_percent(), Some(-50.0));
+        assert_eq!(BatchReport::from_results(&[]).savings_percent(), None);
+    }
+
+    #[test]
+    fn test_dedupe_frames() {
+        let frame = |color: u8| -> RusImg {
+            RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_fn(32, 32, |x, _| if x < 16 { Rgb([color, color, color]) } else { Rgb([255 - color, 0, 0]) }))).unwrap()
+        };
+        let (frames, delays) = dedupe_frames(vec![frame(0), frame(0), frame(0), frame(255)], vec![100, 50, 30, 200], 1).unwrap();
+        assert_eq!(delays, vec![180, 200]);
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].perceptual_hash().unwrap(), frame(0).perceptual_hash().unwrap());
+
+        // A threshold of 0 keeps every frame.
+        let (frames, delays) = dedupe_frames(vec![frame(0), frame(0), frame(0)], vec![100, 50, 30], 0).unwrap();
+        assert_eq!((frames.len(), delays), (3, vec![100, 50, 30]));
+
+        // The delays must match the frames.
+        assert_eq!(dedupe_frames(vec![frame(0), frame(0)], vec![100], 1).err(), Some(RusimgError::FrameDelaysMismatch));
+    }
+
+    #[test]
+    fn test_png_gamma() {
+        let filename = "test_image65.png";
+        let output_filename = "test_image65_output.png";
+        let rgb = ImageBuffer::from_fn(16, 16, |x, y| Rgb([x as u8 * 16, y as u8 * 16, 0]));
+        let mut raw = oxipng::RawImage::new(16, 16, oxipng::ColorType::RGB { transparent_color: None }, oxipng::BitDepth::Eight, rgb.into_raw()).unwrap();
+        raw.add_png_chunk(*b"gAMA", 45455u32.to_be_bytes().to_vec());
+        std::fs::write(filename, raw.create_optimized_png(&oxipng::Options::from_preset(1)).unwrap()).unwrap();
+
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        assert_eq!(img.get_png_gamma(), Some(0.45455));
+        assert!(!img.has_srgb_chunk());
+        // The gamma is kept when the image is re-encoded.
+        img.resize(50.0).unwrap();
+        img.save_image(Some(output_filename)).unwrap();
+        assert_eq!(RusImg::open(Path::new(output_filename)).unwrap().get_png_gamma(), Some(0.45455));
+
+        let mut img = RusImg::open(Path::new(output_filename)).unwrap();
+        img.set_png_gamma(1.0 / 1.8).unwrap();
+        img.save_image(Some(output_filename)).unwrap();
+        let saved = RusImg::open(Path::new(output_filename)).unwrap();
+        assert_eq!(saved.get_png_gamma(), Some(0.55556));
+        assert!(image::open(output_filename).is_ok());
+
+        assert_eq!(img.set_png_gamma(0.0), Err(RusimgError::InvalidFilterParameter));
+        let mut jpeg = RusImg::new(&Extension::Jpeg, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(4, 4, Rgb([0, 0, 0])))).unwrap();
+        assert_eq!(jpeg.get_png_gamma(), None);
+        assert_eq!(jpeg.set_png_gamma(0.45455), Err(RusimgError::UnsupportedFeature));
+
+        // The sRGB chunk is also kept.
+        let mut raw = oxipng::RawImage::new(2, 2, oxipng::ColorType::RGB { transparent_color: None }, oxipng::BitDepth::Eight, vec![0; 12]).unwrap();
+        raw.add_png_chunk(*b"sRGB", vec![0]);
+        std::fs::write(filename, raw.create_optimized_png(&oxipng::Options::from_preset(1)).unwrap()).unwrap();
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        assert!(img.has_srgb_chunk());
+        img.grayscale().unwrap();
+        img.save_image(Some(output_filename)).unwrap();
+        assert!(RusImg::open(Path::new(output_filename)).unwrap().has_srgb_chunk());
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(output_filename).unwrap();
+    }
+
+    #[test]
+    fn test_sobel_edges() {
+        // A white 20x20 rectangle at (20, 20) on a black 60x60 background.
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(60, 60, |x, y| {
+            if (20..40).contains(&x) && (20..40).contains(&y) { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) }
+        }));
+        let mut img = RusImg::new(&Extension::Png, source).unwrap();
+        img.sobel_edges().unwrap();
+        let edges = img.get_dynamic_image().unwrap().to_luma8();
+        // Edges of the rectangle
+        for (x, y) in [(20, 30), (39, 30), (30, 20), (30, 39), (19, 30)] {
+            assert_eq!(edges.get_pixel(x, y)[0], 255, "({}, {})", x, y);
+        }
+        // Flat regions inside and outside of the rectangle
+        for (x, y) in [(30, 30), (5, 5), (50, 50), (22, 30)] {
+            assert_eq!(edges.get_pixel(x, y)[0], 0, "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn test_convolve() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(32, 24, |x, y| Rgb([(x * 8) as u8, (y * 10) as u8, ((x * y) % 256) as u8])));
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        img.convolve(&[1.0; 9], 3, 3, 9.0, 0.0).unwrap();
+        let blurred = img.get_dynamic_image().unwrap().to_rgb8();
+        // Reference: the 3x3 box blur of the image crate, which does not extend the edges, so only the inner pixels are compared
+        let reference = image::imageops::filter3x3(&source.to_rgb8(), &[1.0 / 9.0; 9]);
+        for y in 1..23 {
+            for x in 1..31 {
+                let (a, b) = (blurred.get_pixel(x, y), reference.get_pixel(x, y));
+                for c in 0..3 {
+                    assert!(a[c].abs_diff(b[c]) <= 1, "({}, {}): {:?} != {:?}", x, y, a, b);
+                }
             }
-            std::fs::remove_file(filename).unwrap();
         }
+        // The corner takes the outside pixels from the edges: R = (0 + 0 + 8) * 3 / 9, G = (0 + 0 + 10) * 3 / 9
+        assert_eq!(blurred.get_pixel(0, 0), &Rgb([3, 3, 0]));
+
+        // The identity kernel with a bias shifts the values.
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        img.convolve(&[0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0], 3, 3, 1.0, 10.0).unwrap();
+        assert_eq!(img.get_dynamic_image().unwrap().to_rgb8().get_pixel(1, 1), &Rgb([18, 20, 11]));
+
+        assert_eq!(img.convolve(&[1.0; 8], 3, 3, 9.0, 0.0), Err(RusimgError::InvalidFilterParameter));
+        assert_eq!(img.convolve(&[1.0; 9], 3, 3, 0.0, 0.0), Err(RusimgError::InvalidFilterParameter));
+        assert_eq!(img.convolve(&[], 0, 0, 1.0, 0.0), Err(RusimgError::InvalidFilterParameter));
+    }
+
+    #[test]
+    fn test_compress_png_level() {
+        // A photo-like image: smooth gradients with some noise
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(128, 96, |x, y| {
+            let noise = ((x * 7919 + y * 104729) % 13) as u8;
+            Rgb([(x * 2) as u8 ^ noise, (y * 2 + x / 3) as u8, (x + y) as u8 / 2 + noise])
+        }));
+        let encoded_size = |level: u8| -> usize {
+            let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+            img.compress_png_level(level).unwrap();
+            let data = img.data.encode_to_memory().unwrap();
+            assert_eq!(image::load_from_memory(&data).unwrap().to_rgb8(), source.to_rgb8());
+            data.len()
+        };
+        assert_ne!(encoded_size(1), encoded_size(6));
+
+        // The compression applies to the image when it is encoded, so operations after compress_png_level() are kept.
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        img.compress_png_level(6).unwrap();
+        img.resize(50.0).unwrap();
+        let data = img.data.encode_to_memory().unwrap();
+        let decoded = image::load_from_memory(&data).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 48));
+        assert_eq!(decoded.to_rgb8(), img.get_dynamic_image().unwrap().to_rgb8());
+
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        assert_eq!(img.compress_png_level(7), Err(RusimgError::InvalidCompressionLevel));
+        let mut img = RusImg::new(&Extension::Jpeg, source).unwrap();
+        assert_eq!(img.compress_png_level(3), Err(RusimgError::UnsupportedFeature));
+    }
+
+    #[test]
+    fn test_webp_exif_xmp() {
+        let filename = "test_image66.webp";
+        let output_filename = "test_image66_output.webp";
+        // EXIF data (TIFF structure) with orientation 6
+        let exif: Vec<u8> = vec![0x49, 0x49, 42, 0, 8, 0, 0, 0, 1, 0, 0x12, 0x01, 3, 0, 1, 0, 0, 0, 6, 0, 0, 0, 0, 0, 0, 0];
+        let xmp = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><dc:rights>(c) librusimg</dc:rights></x:xmpmeta>".to_vec();
+        // Add a VP8X chunk and the EXIF and XMP chunks to an encoded 40x30 WebP image.
+        let img = RusImg::new(&Extension::Webp, DynamicImage::ImageRgb8(ImageBuffer::from_fn(40, 30, |x, y| Rgb([x as u8 * 6, y as u8 * 8, 0])))).unwrap();
+        let encoded = img.data.encode_to_memory().unwrap();
+        let mut body = b"WEBP".to_vec();
+        let mut add_chunk = |fourcc: &[u8], data: &[u8]| {
+            body.extend_from_slice(fourcc);
+            body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            body.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        };
+        add_chunk(b"VP8X", &[0x08 | 0x04, 0, 0, 0, 39, 0, 0, 29, 0, 0]);
+        add_chunk(&encoded[12..16], &encoded[20..]);
+        add_chunk(b"EXIF", &exif);
+        add_chunk(b"XMP ", &xmp);
+        let mut file = b"RIFF".to_vec();
+        file.extend_from_slice(&(body.len() as u32).to_le_bytes());
+        file.extend_from_slice(&body);
+        std::fs::write(filename, file).unwrap();
+
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        assert_eq!(img.metadata().orientation, Some(6));
+        img.resize(50.0).unwrap();
+        img.save_image(Some(output_filename)).unwrap();
+
+        let output = std::fs::read(output_filename).unwrap();
+        let reopened = RusImg::open(Path::new(output_filename)).unwrap();
+        assert_eq!(reopened.get_image_size().unwrap(), ImgSize::new(20, 15));
+        assert_eq!(reopened.metadata().orientation, Some(6));
+        assert!(output.windows(exif.len()).any(|w| w == exif.as_slice()));
+        assert!(output.windows(xmp.len()).any(|w| w == xmp.as_slice()));
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(output_filename).unwrap();
+    }
+
+    #[test]
+    fn test_optimize() {
+        let filename = "test_image67.png";
+        let output_filename = "test_image67_output.png";
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(96, 64, |x, y| Rgb([(x * 2) as u8, (y * 3) as u8, ((x + y) % 7) as u8 * 30])));
+        RusImg::new(&Extension::Png, source.clone()).unwrap().save_image(Some(filename)).unwrap();
+
+        let mut img = RusImg::open(Path::new(filename)).unwrap();
+        img.optimize().unwrap();
+        img.save_image(Some(output_filename)).unwrap();
+        assert!(std::fs::metadata(output_filename).unwrap().len() <= std::fs::metadata(filename).unwrap().len());
+        assert_eq!(image::open(output_filename).unwrap().to_rgb8(), source.to_rgb8());
+
+        // WebP is encoded losslessly.
+        let mut img = RusImg::new(&Extension::Webp, source.clone()).unwrap();
+        img.optimize().unwrap();
+        let encoded = img.data.encode_to_memory().unwrap();
+        assert_eq!(image::load_from_memory(&encoded).unwrap().to_rgb8(), source.to_rgb8());
+
+        let mut img = RusImg::new(&Extension::Bmp, source.clone()).unwrap();
+        assert!(matches!(img.optimize(), Err(RusimgError::CannotOptimize(_))));
+        let mut img = RusImg::new(&Extension::Jpeg, source).unwrap();
+        assert!(matches!(img.optimize(), Err(RusimgError::CannotOptimize(_))));
+
+        std::fs::remove_file(filename).unwrap();
+        std::fs::remove_file(output_filename).unwrap();
+    }
+
+    #[test]
+    fn test_content_bounds() {
+        // A red blob around (30..=41, 50..=57) with a soft edge on white
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(100, 80, |x, y| {
+            if (30..42).contains(&x) && (50..58).contains(&y) { Rgb([200, 20, 20]) }
+            else if x == 42 && y == 52 { Rgb([250, 250, 250]) }
+            else { Rgb([255, 255, 255]) }
+        }));
+        let img = RusImg::new(&Extension::Png, source).unwrap();
+        assert_eq!(img.content_bounds(10).unwrap(), Rect { x: 30, y: 50, w: 12, h: 8 });
+        // The faint pixel is content with a smaller tolerance.
+        assert_eq!(img.content_bounds(0).unwrap(), Rect { x: 30, y: 50, w: 13, h: 8 });
+        // The image is left untouched.
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(100, 80));
+
+        let blank = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(10, 10, Rgb([255, 255, 255])))).unwrap();
+        assert_eq!(blank.content_bounds(0), Err(RusimgError::ContentNotFound));
     }
 
     #[test]
-    fn test_set_dynamic_image() {
-        let filename = "test_image9.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let dynamic_image = image::open(path).unwrap();
-        let result = img.set_dynamic_image(dynamic_image);
-        assert!(result.is_ok());
-        std::fs::remove_file(filename).unwrap();
+    fn test_trim_original_coords() {
+        // Each 10x10 block of the 200x100 image has its own color.
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(200, 100, |x, y| Rgb([(x / 10 * 10) as u8, (y / 10 * 10) as u8, 0])));
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        img.resize(50.0).unwrap();
+        // The format conversion keeps the original size.
+        img.convert(&Extension::Bmp).unwrap();
+        assert_eq!(img.trim_original_coords(Rect { x: 40, y: 20, w: 60, h: 40 }).unwrap(), ImgSize::new(30, 20));
+        let trimmed = img.get_dynamic_image().unwrap().to_rgb8();
+        // The blocks at the corners of the area, checked away from the block edges blended by the resize filter
+        assert_eq!(trimmed.get_pixel(2, 2), &Rgb([40, 20, 0]));
+        assert_eq!(trimmed.get_pixel(27, 17), &Rgb([90, 50, 0]));
+
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        assert_eq!(img.trim_original_coords(Rect { x: 200, y: 0, w: 10, h: 10 }), Err(RusimgError::InvalidTrimXY));
+        // Without resizing, the coordinates are used as they are; the width reaching past the image is shrunk to fit.
+        assert_eq!(img.trim_original_coords(Rect { x: 150, y: 0, w: 60, h: 10 }).unwrap(), ImgSize::new(50, 10));
+
+        // Trims are followed too: after trimming and resizing, the same original region is cropped.
+        let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        img.trim_rect(Rect { x: 100, y: 0, w: 100, h: 100 }).unwrap();
+        img.resize(50.0).unwrap();
+        assert_eq!(img.coordinate_space().unwrap().to_current(140.0, 20.0), (20.0, 10.0));
+        assert_eq!(img.trim_original_coords(Rect { x: 140, y: 20, w: 40, h: 40 }).unwrap(), ImgSize::new(20, 20));
+        assert_eq!(img.get_dynamic_image().unwrap().to_rgb8().get_pixel(2, 2), &Rgb([140, 20, 0]));
+
+        // An image shrunk to nothing has no area to trim.
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::new_rgb8(50, 50)).unwrap();
+        img.resize(1.0).unwrap();
+        assert_eq!(img.trim_original_coords(Rect { x: 0, y: 0, w: 10, h: 10 }), Err(RusimgError::InvalidTrimXY));
+
+        // The mapping is lost after rotating by an arbitrary angle.
+        let mut img = RusImg::new(&Extension::Png, source).unwrap();
+        img.rotate_arbitrary(10.0, [0, 0, 0, 0]).unwrap();
+        assert_eq!(img.coordinate_space(), None);
+        assert_eq!(img.trim_original_coords(Rect { x: 0, y: 0, w: 10, h: 10 }), Err(RusimgError::OriginalCoordinatesUnavailable));
     }
 
     #[test]
-    fn test_get_dynamic_image() {
-        let filename = "test_image10.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result = img.get_dynamic_image();
-        assert!(result.is_ok());
-        std::fs::remove_file(filename).unwrap();
+    fn test_drop_redundant_alpha() {
+        let (rgba_filename, rgb_filename) = ("test_image68_rgba.png", "test_image68_rgb.png");
+        let opaque = DynamicImage::ImageRgba8(ImageBuffer::from_fn(64, 64, |x, y| image::Rgba([(x * 4) as u8, (y * 4) as u8, 0, 255])));
+        let mut img = RusImg::new(&Extension::Png, opaque.clone()).unwrap();
+        img.save_image(Some(rgba_filename)).unwrap();
+        assert!(img.drop_redundant_alpha());
+        assert_eq!(img.get_dynamic_image().unwrap().color(), image::ColorType::Rgb8);
+        img.save_image(Some(rgb_filename)).unwrap();
+        assert!(std::fs::metadata(rgb_filename).unwrap().len() < std::fs::metadata(rgba_filename).unwrap().len());
+        assert_eq!(image::open(rgb_filename).unwrap().to_rgba8(), opaque.to_rgba8());
+        // Images without an alpha channel are left as they are.
+        assert!(!img.drop_redundant_alpha());
+
+        let mut transparent = opaque.to_rgba8();
+        transparent.put_pixel(3, 3, image::Rgba([0, 0, 0, 254]));
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgba8(transparent)).unwrap();
+        assert!(!img.drop_redundant_alpha());
+        assert_eq!(img.get_dynamic_image().unwrap().color(), image::ColorType::Rgba8);
+
+        std::fs::remove_file(rgba_filename).unwrap();
+        std::fs::remove_file(rgb_filename).unwrap();
     }
 
     #[test]
-    fn test_get_extension() {
-        let filename = "test_image12.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let img = RusImg::open(path).unwrap();
-        let extension = img.get_extension();
-        assert_eq!(extension, Extension::Png);
-        std::fs::remove_file(filename).unwrap();
+    fn test_quality_range() {
+        let default = RusImgConfig::default();
+        assert_eq!(quality_range(&Extension::Jpeg), Some((0.0, 100.0, default.jpeg_quality)));
+        assert_eq!(quality_range(&Extension::Jpg), quality_range(&Extension::Jpeg));
+        assert_eq!(quality_range(&Extension::Webp), Some((0.0, 100.0, default.webp_quality)));
+        assert_eq!(quality_range(&Extension::Png), None);
+        assert_eq!(quality_range(&Extension::Bmp), None);
     }
 
     #[test]
-    fn test_get_input_filepath() {
-        let filename = "test_image13.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let img = RusImg::open(path).unwrap();
-        let input_filepath = img.get_input_filepath().unwrap();
-        assert_eq!(input_filepath, Path::new(filename));
+    fn test_difference_image() {
+        // The left half is flat gray, the right half is a one-pixel checkerboard.
+        let source = ImageBuffer::from_fn(64, 32, |x, y| {
+            if x < 32 { Rgb([128u8, 128, 128]) } else if (x + y) % 2 == 0 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) }
+        });
+        let original = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(source.clone())).unwrap();
+        let mut jpeg = RusImg::new(&Extension::Jpeg, DynamicImage::ImageRgb8(source)).unwrap();
+        jpeg.compress(Some(5.0)).unwrap();
+        let decoded = image::load_from_memory(&jpeg.data.encode_to_memory().unwrap()).unwrap();
+        let compressed = RusImg::new(&Extension::Png, decoded).unwrap();
+
+        let diff = original.difference_image(&compressed, 4.0).unwrap();
+        assert_eq!(diff.extension, Extension::Png);
+        let diff = diff.data.get_dynamic_image().unwrap().to_luma8();
+        let mean = |x0: u32| (x0..x0 + 16).flat_map(|x| (8..24).map(move |y| (x, y))).map(|(x, y)| diff.get_pixel(x, y)[0] as u32).sum::<u32>() / 256;
+        assert!(mean(8) < 16);
+        assert!(mean(40) > 64);
+
+        let small = RusImg::new(&Extension::Png, DynamicImage::new_rgb8(8, 8)).unwrap();
+        assert!(matches!(original.difference_image(&small, 1.0), Err(RusimgError::ImageSizeMismatch)));
+        assert!(matches!(original.difference_image(&original, -1.0), Err(RusimgError::InvalidFilterParameter)));
+    }
+
+    #[test]
+    fn test_generate_srcset_resize_filter() {
+        // Nearest is meant for fast jobs and Lanczos3 for the best quality; the config governs every copy of the set.
+        let dir = Path::new("test_dir5");
+        let _ = std::fs::remove_dir_all(dir);
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(300, 200, |x, y| if (x + y) % 3 == 0 { Rgb([255u8, 255, 255]) } else { Rgb([0, 0, 0]) }));
+        let mut img = RusImg::new(&Extension::Png, source).unwrap();
+
+        let mut render = |filter: ResizeFilter| -> Vec<image::RgbImage> {
+            img.set_config(&RusImgConfig { resize_filter: filter, ..Default::default() }).unwrap();
+            let statuses = img.generate_srcset(&[100, 200], &Extension::Png, None, dir).unwrap();
+            statuses.iter().map(|status| image::open(status.output_path.as_ref().unwrap()).unwrap().to_rgb8()).collect()
+        };
+        let fast = render(ResizeFilter::Nearest);
+        let best = render(ResizeFilter::Lanczos3);
+        for (fast, best) in fast.iter().zip(&best) {
+            assert_eq!(fast.dimensions(), best.dimensions());
+            assert_ne!(fast, best);
+            // Nearest neighbor only picks source pixels, while Lanczos3 blends them.
+            assert!(fast.pixels().all(|p| p[0] == 0 || p[0] == 255));
+            assert!(best.pixels().any(|p| p[0] != 0 && p[0] != 255));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_xmp() {
+        let xmp = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\"><rdf:Description xmlns:xmpRights=\"http://ns.adobe.com/xap/1.0/rights/\" xmpRights:Marked=\"True\"/></rdf:RDF></x:xmpmeta>";
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(16, 16, |x, y| Rgb([(x * 16) as u8, (y * 16) as u8, 64])));
+
+        // PNG: the packet is written to an iTXt chunk and read back from the saved file.
+        let filename = "test_image69.png";
+        let mut png = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        assert_eq!(png.get_xmp(), None);
+        png.set_xmp(xmp).unwrap();
+        png.save_image(Some(filename)).unwrap();
+        let mut reopened = RusImg::open(Path::new(filename)).unwrap();
+        assert_eq!(reopened.get_xmp().as_deref(), Some(xmp));
+        // Replacing the packet of an unmodified file keeps a single packet.
+        reopened.set_xmp("<x:xmpmeta/>").unwrap();
+        reopened.save_image(Some(filename)).unwrap();
+        assert_eq!(RusImg::open(Path::new(filename)).unwrap().get_xmp().as_deref(), Some("<x:xmpmeta/>"));
+        assert_eq!(image::open(filename).unwrap().to_rgb8(), source.to_rgb8());
         std::fs::remove_file(filename).unwrap();
+
+        // JPEG (APP1 segment) and WebP ("XMP " chunk).
+        for (extension, filename) in [(Extension::Jpeg, "test_image69.jpg"), (Extension::Webp, "test_image69.webp")] {
+            let mut img = RusImg::new(&extension, source.clone()).unwrap();
+            img.set_xmp(xmp).unwrap();
+            img.save_image(Some(filename)).unwrap();
+            let mut reopened = RusImg::open(Path::new(filename)).unwrap();
+            assert_eq!(reopened.get_xmp().as_deref(), Some(xmp));
+            reopened.set_xmp("<x:xmpmeta/>").unwrap();
+            reopened.save_image(Some(filename)).unwrap();
+            assert_eq!(RusImg::open(Path::new(filename)).unwrap().get_xmp().as_deref(), Some("<x:xmpmeta/>"));
+            std::fs::remove_file(filename).unwrap();
+        }
+
+        let mut jpeg = RusImg::new(&Extension::Jpeg, source.clone()).unwrap();
+        assert_eq!(jpeg.set_xmp(&"a".repeat(70000)), Err(RusimgError::MetadataTooLarge));
+        let mut bmp = RusImg::new(&Extension::Bmp, source).unwrap();
+        assert_eq!(bmp.set_xmp(xmp), Err(RusimgError::UnsupportedFeature));
     }
 
     #[test]
-    fn test_save_image() {
-        let filename = "test_image14.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result = img.save_image(Some("test_image_saved.png"));
-        assert!(result.is_ok());
+    fn test_decode_region() {
+        let filename = "test_image70.png";
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(2000, 1500, |x, y| Rgb([(x % 251) as u8, (y % 241) as u8, ((x * y) % 239) as u8])));
+        source.save(filename).unwrap();
+
+        let rect = Rect { x: 1234, y: 567, w: 300, h: 200 };
+        let region = RusImg::decode_region(Path::new(filename), rect.clone()).unwrap();
+        assert_eq!(region.get_image_size().unwrap(), ImgSize::new(300, 200));
+        assert_eq!(region.get_extension(), Extension::Png);
+        assert_eq!(region.data.get_source_filepath().as_deref(), Some(Path::new(filename)));
+        let mut full = RusImg::open(Path::new(filename)).unwrap();
+        full.trim_rect(rect).unwrap();
+        assert_eq!(region.data.get_dynamic_image().unwrap().to_rgb8(), full.get_dynamic_image().unwrap().to_rgb8());
+
+        assert_eq!(region.coordinate_space().unwrap().to_current(1234.0, 567.0), (0.0, 0.0));
+
+        assert_eq!(RusImg::decode_region(Path::new(filename), Rect { x: 1900, y: 0, w: 200, h: 10 }).err(), Some(RusimgError::InvalidTrimXY));
         std::fs::remove_file(filename).unwrap();
-        std::fs::remove_file("test_image_saved.png").unwrap();
     }
 
     #[test]
-    fn test_err_failed_to_open_file() {
-        let path = Path::new("non_existent_file.png");
-        let result = RusImg::open(path);
-        assert!(result.is_err());
-        if let Err(e) = result {
-            if let RusimgError::FailedToOpenFile(_) = e {
-                // Expected error
-            } else {
-                panic!("Unexpected error: {:?}", e);
-            }
-        } else {
-            panic!("Expected an error, but got Ok");
+    fn test_decode_region_formats() {
+        // The region read row by row (24-bit BMP, 16-bit PNG with alpha) or decoded whole (RGBA BMP, interlaced PNG) equals the trimmed image.
+        let rgb = DynamicImage::ImageRgb8(ImageBuffer::from_fn(301, 203, |x, y| Rgb([(x % 251) as u8, (y % 241) as u8, ((x * y) % 239) as u8])));
+        let rgba16 = DynamicImage::ImageRgba16(ImageBuffer::from_fn(301, 203, |x, y| image::Rgba([x as u16 * 200, y as u16 * 300, 65535 - x as u16, (x + y) as u16 * 100])));
+        let mut interlaced = oxipng::Options::from_preset(0);
+        interlaced.interlace = Some(oxipng::Interlacing::Adam7);
+        let raw = oxipng::RawImage::new(301, 203, oxipng::ColorType::RGB { transparent_color: None }, oxipng::BitDepth::Eight, rgb.to_rgb8().into_raw()).unwrap();
+        std::fs::write("test_image77.png", raw.create_optimized_png(&interlaced).unwrap()).unwrap();
+        rgb.save("test_image74.bmp").unwrap();
+        rgba16.save("test_image75.png").unwrap();
+        DynamicImage::ImageRgba8(rgb.to_rgba8()).save("test_image76.bmp").unwrap();
+
+        let rect = Rect { x: 17, y: 150, w: 250, h: 53 };
+        for filename in ["test_image74.bmp", "test_image75.png", "test_image76.bmp", "test_image77.png"] {
+            let mut region = RusImg::decode_region(Path::new(filename), rect.clone()).unwrap();
+            let mut full = RusImg::open(Path::new(filename)).unwrap();
+            full.trim_rect(rect.clone()).unwrap();
+            assert_eq!(region.get_dynamic_image().unwrap(), full.get_dynamic_image().unwrap(), "{}", filename);
+            assert_eq!(region.coordinate_space().unwrap().to_current(17.0, 150.0), (0.0, 0.0));
+            assert_eq!(RusImg::decode_region(Path::new(filename), Rect { x: 0, y: 200, w: 10, h: 4 }).err(), Some(RusimgError::InvalidTrimXY));
+            std::fs::remove_file(filename).unwrap();
         }
     }
 
     #[test]
-    fn test_err_failed_to_open_image() {
-        // Not supported image format
-        let path = Path::new("test_image1.txt");
-        // Create a dummy text file
-        std::fs::write(path, "This is a test file.").unwrap();
-        // Attempt to open the text file as an image
-        let result = RusImg::open(path);
-        // Remove the dummy text file
-        std::fs::remove_file(path).unwrap();
-        // Check if the error is as expected
-        assert!(result.is_err());
-        if let Err(e) = result {
-            if let RusimgError::FailedToOpenImage(_) = e {
-                // Expected error
-            } else {
-                panic!("Unexpected error: {:?}", e);
-            }
-        } else {
-            panic!("Expected an error, but got Ok");
-        }
+    fn test_try_from() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(24, 16, |x, y| Rgb([(x * 10) as u8, (y * 15) as u8, 200])));
+
+        // From encoded bytes: the format is detected from the data.
+        let png_bytes = RusImg::new(&Extension::Png, source.clone()).unwrap().data.encode_to_memory().unwrap();
+        let img: RusImg = png_bytes.as_slice().try_into().unwrap();
+        assert_eq!(img.get_extension(), Extension::Png);
+        assert_eq!(img.data.get_dynamic_image().unwrap().to_rgb8(), source.to_rgb8());
+        let webp_bytes = RusImg::new(&Extension::Webp, source.clone()).unwrap().data.encode_to_memory().unwrap();
+        let img = RusImg::try_from(webp_bytes.as_slice()).unwrap();
+        assert_eq!(img.get_extension(), Extension::Webp);
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(24, 16));
+
+        // The bytes are opened by the backend like a file: the PNG chunks are read, and an unmodified image is encoded as it is.
+        let mut raw = oxipng::RawImage::new(16, 16, oxipng::ColorType::RGB { transparent_color: None }, oxipng::BitDepth::Eight, vec![128; 768]).unwrap();
+        raw.add_png_chunk(*b"gAMA", 45455u32.to_be_bytes().to_vec());
+        let gamma_bytes = raw.create_optimized_png(&oxipng::Options::from_preset(1)).unwrap();
+        let img = RusImg::from_bytes(&gamma_bytes).unwrap();
+        assert_eq!(img.get_png_gamma(), Some(0.45455));
+        assert_eq!(img.data.encode_to_memory().unwrap(), gamma_bytes);
+        // The frames of an animated WebP are kept.
+        let frames: Vec<RusImg> = [Rgb([255, 0, 0]), Rgb([0, 0, 255])].iter()
+            .map(|color| RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(ImageBuffer::from_pixel(8, 8, *color))).unwrap())
+            .collect();
+        let animated = encode_animated_webp(&frames, &[150, 250], 0, 90.0).unwrap();
+        let img = RusImg::from_bytes(&animated).unwrap();
+        assert_eq!(img.animation_info().unwrap().frames.len(), 2);
+        assert_eq!(img.data.encode_to_memory().unwrap(), animated);
+
+        // Invalid bytes.
+        assert!(matches!(RusImg::try_from(&b"not an image"[..]), Err(RusimgError::FailedToOpenImage(_))));
+        let mut truncated = png_bytes.clone();
+        truncated.truncate(png_bytes.len() / 2);
+        assert!(RusImg::from_bytes(&truncated).is_err());
+
+        // From a DynamicImage: imported into the PNG backend.
+        let img: RusImg = source.clone().try_into().unwrap();
+        assert_eq!(img.get_extension(), Extension::Png);
+        assert_eq!(img.data.get_dynamic_image().unwrap().to_rgb8(), source.to_rgb8());
     }
 
     #[test]
-    fn test_err_failed_to_save_image() {
-        let filename = "test_image15.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result = img.save_image(Some("test_image/invalid_path/test_image_saved.png"));
-        assert!(result.is_err());
-        if let Err(e) = result {
-            if let RusimgError::FailedToSaveImage(_) = e {
-                // Expected error
-            } else {
-                panic!("Unexpected error: {:?}", e);
+    fn test_equalize_histogram() {
+        // A low-contrast image: the brightness only varies between 100 and 131, with a reddish tint.
+        let source = ImageBuffer::from_fn(64, 64, |x, y| {
+            let v = 100 + ((x + y) / 4) as u8;
+            Rgb([v + 20, v, v - 10])
+        });
+        // Entropy of the luminance histogram in 16 bins of 16 levels: a flat histogram over the full range gives 4 bits.
+        let entropy = |image: &DynamicImage| -> f64 {
+            let mut bins = [0u32; 16];
+            for p in image.to_luma8().pixels() {
+                bins[p[0] as usize / 16] += 1;
             }
-        } else {
-            panic!("Expected an error, but got Ok");
+            let total = bins.iter().sum::<u32>() as f64;
+            bins.iter().filter(|&&n| n > 0).map(|&n| { let p = n as f64 / total; -p * p.log2() }).sum()
+        };
+
+        let mut img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(source.clone())).unwrap();
+        img.equalize_histogram().unwrap();
+        let equalized = img.get_dynamic_image().unwrap();
+        let before = entropy(&DynamicImage::ImageRgb8(source));
+        let after = entropy(&equalized);
+        assert!(before < 1.5, "before: {}", before);
+        assert!(after > 3.5, "after: {}", after);
+        // The full range is used, and the tint is kept.
+        let luma = equalized.to_luma8();
+        assert!(luma.pixels().any(|p| p[0] < 10) && luma.pixels().any(|p| p[0] > 245));
+        let middle = equalized.to_rgb8().get_pixel(32, 32).0;
+        assert!(middle[0] > middle[1] && middle[1] > middle[2]);
+
+        // Grayscale images stay grayscale.
+        let mut gray = RusImg::new(&Extension::Png, DynamicImage::ImageLuma8(ImageBuffer::from_fn(16, 16, |x, _| image::Luma([120 + x as u8])))).unwrap();
+        gray.equalize_histogram().unwrap();
+        assert_eq!(gray.get_dynamic_image().unwrap().color(), image::ColorType::L8);
+    }
+
+    #[test]
+    fn test_save_image_verified() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(40, 30, |x, y| Rgb([(x * 6) as u8, (y * 8) as u8, 90])));
+        for filename in ["test_image71.png", "test_image71.jpg", "test_image71.webp", "test_image71.bmp"] {
+            let mut img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+            let status = img.save_image_verified(Some(filename)).unwrap();
+            assert_eq!(status.output_path.as_deref(), Some(Path::new(filename)));
+            assert_eq!(image::image_dimensions(filename).unwrap(), (40, 30));
+            std::fs::remove_file(filename).unwrap();
         }
-        // Clean up the test image file
-        std::fs::remove_file(filename).unwrap();
     }
 
     #[test]
-    fn test_err_invalid_compression_level() {
-        let filename = "test_image16.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result1 = img.compress(Some(150.0));
-        let result2 = img.compress(Some(-10.0));
-        assert!(result1.is_err());
-        assert!(result2.is_err());
-        if let Err(e) = result1 {
-            if let RusimgError::InvalidCompressionLevel = e {
-                // Expected error
-            } else {
-                panic!("Unexpected error: {:?}", e);
+    fn test_strict_trim() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(50, 40, |x, y| Rgb([x as u8, y as u8, 0])));
+
+        // By default, an oversized width is shrunk to fit.
+        let mut lenient = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        assert_eq!(lenient.trim_rect(Rect { x: 30, y: 10, w: 100, h: 20 }).unwrap(), ImgSize::new(20, 20));
+        // In strict mode, it is an error and the image is left untouched.
+        let mut strict = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        strict.set_strict_trim(true);
+        assert_eq!(strict.trim_rect(Rect { x: 30, y: 10, w: 100, h: 20 }), Err(RusimgError::InvalidTrimXY));
+        assert_eq!(strict.trim(30, 10, 20, 31), Err(RusimgError::InvalidTrimXY));
+        assert_eq!(strict.trim_anchored(Anchor::Center, 51, 10), Err(RusimgError::InvalidTrimXY));
+        assert_eq!(strict.trim_multiple_of(Rect { x: 0, y: 0, w: 64, h: 32 }, 16), Err(RusimgError::InvalidTrimXY));
+        assert_eq!(strict.trim_original_coords(Rect { x: 30, y: 10, w: 100, h: 20 }), Err(RusimgError::InvalidTrimXY));
+        assert_eq!(strict.get_image_size().unwrap(), ImgSize::new(50, 40));
+        let mut lenient_original = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        assert_eq!(lenient_original.trim_original_coords(Rect { x: 30, y: 10, w: 100, h: 20 }).unwrap(), ImgSize::new(20, 20));
+        // Areas inside the image are trimmed as usual.
+        assert_eq!(strict.trim_rect(Rect { x: 30, y: 10, w: 20, h: 30 }).unwrap(), ImgSize::new(20, 30));
+        assert_eq!(strict.get_dynamic_image().unwrap().to_rgb8().get_pixel(0, 0).0, [30, 10, 0]);
+        // An x or y outside the image is an error in both modes.
+        assert_eq!(lenient.trim_rect(Rect { x: 25, y: 0, w: 1, h: 1 }), Err(RusimgError::InvalidTrimXY));
+    }
+
+    #[test]
+    fn test_process_in_bands() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(37, 53, |x, y| Rgb([(x * 5) as u8, (y * 4) as u8, ((x + y) * 2) as u8])));
+        let brighten = |buffer: &mut image::RgbaImage| {
+            for pixel in buffer.pixels_mut() {
+                for v in pixel.0.iter_mut().take(3) {
+                    *v = v.saturating_add(40);
+                }
             }
-        } else {
-            panic!("Expected an error, but got Ok");
-        }
-        if let Err(e) = result2 {
-            if let RusimgError::InvalidCompressionLevel = e {
-                // Expected error
-            } else {
-                panic!("Unexpected error: {:?}", e);
+        };
+
+        // 53 rows in bands of 16 rows: the last band has 5 rows.
+        let mut banded = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        let mut band_rows = Vec::new();
+        banded.process_in_bands(16, |band| {
+            band_rows.push(band.height());
+            brighten(band);
+        }).unwrap();
+        assert_eq!(band_rows, vec![16, 16, 16, 5]);
+
+        let mut whole = source.to_rgba8();
+        brighten(&mut whole);
+        let result = banded.get_dynamic_image().unwrap();
+        assert_eq!(result.color(), image::ColorType::Rgb8);
+        assert_eq!(result.to_rgba8(), whole);
+
+        assert_eq!(banded.process_in_bands(0, |_| {}), Err(RusimgError::InvalidBandHeight));
+
+        // 16-bit bands left untouched keep their full depth; only the changed band is rounded to 8 bits.
+        let deep = DynamicImage::ImageRgb16(ImageBuffer::from_fn(8, 8, |x, y| Rgb([x as u16 * 1001 + 7, y as u16 * 1003 + 5, 12345])));
+        let mut img = RusImg::new(&Extension::Png, deep.clone()).unwrap();
+        let mut band_index = 0;
+        img.process_in_bands(4, |band| {
+            if band_index == 1 {
+                brighten(band);
             }
-        } else {
-            panic!("Expected an error, but got Ok");
-        }
-        std::fs::remove_file(filename).unwrap();
+            band_index += 1;
+        }).unwrap();
+        let result = img.get_dynamic_image().unwrap();
+        assert_eq!(result.color(), image::ColorType::Rgb16);
+        let (result, deep) = (result.to_rgb16(), deep.to_rgb16());
+        assert!((0..8).all(|x| (0..4).all(|y| result.get_pixel(x, y) == deep.get_pixel(x, y))));
+        assert!((0..8).all(|x| (4..8).all(|y| result.get_pixel(x, y)[2] % 257 == 0)));
     }
 
     #[test]
-    fn test_err_invalid_trim_xy() {
-        let filename = "test_image17.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result = img.trim(150, 150, 50, 50);
-        assert!(result.is_err());
-        if let Err(e) = result {
-            if let RusimgError::InvalidTrimXY = e {
-                // Expected error
-            } else {
-                panic!("Unexpected error: {:?}", e);
-            }
-        } else {
-            panic!("Expected an error, but got Ok");
-        }
-        std::fs::remove_file(filename).unwrap();
+    fn test_unique_color_count() {
+        // A 4-color graphic: quadrants of red, green, blue and transparent white.
+        let graphic = ImageBuffer::from_fn(32, 32, |x, y| match (x < 16, y < 16) {
+            (true, true) => image::Rgba([255u8, 0, 0, 255]),
+            (false, true) => image::Rgba([0, 255, 0, 255]),
+            (true, false) => image::Rgba([0, 0, 255, 255]),
+            (false, false) => image::Rgba([255, 255, 255, 0]),
+        });
+        let img = RusImg::new(&Extension::Png, DynamicImage::ImageRgba8(graphic)).unwrap();
+        assert_eq!(img.unique_color_count(None).unwrap(), 4);
+        assert_eq!(img.unique_color_count(Some(256)).unwrap(), 4);
+        assert_eq!(img.unique_color_count(Some(3)).unwrap(), 3);
+
+        // A photographic image with far more than 256 colors.
+        let photo = ImageBuffer::from_fn(64, 64, |x, y| Rgb([(x * 4) as u8, (y * 4) as u8, ((x * y) % 256) as u8]));
+        let img = RusImg::new(&Extension::Png, DynamicImage::ImageRgb8(photo)).unwrap();
+        assert_eq!(img.unique_color_count(Some(256)).unwrap(), 256);
+        assert_eq!(img.unique_color_count(None).unwrap(), 64 * 64);
     }
 
     #[test]
-    fn test_err_invalid_resize_ratio() {
-        let filename = "test_image18.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result = img.resize(0.0);
-        assert!(result.is_err());
-        if let Err(e) = result {
-            if let RusimgError::InvalidResizeRatio = e {
-                // Expected error
-            } else {
-                panic!("Unexpected error: {:?}", e);
-            }
-        } else {
-            panic!("Expected an error, but got Ok");
-        }
+    fn test_cancel_token() {
+        let filename = "test_image72.png";
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(512, 512, |x, y| Rgb([(x ^ y) as u8, (x * y) as u8, (x + y) as u8])));
+        let mut img = RusImg::new(&Extension::Png, source).unwrap();
+
+        // A token cancelled (e.g. from another thread through a clone) stops the operations before they start.
+        let token = CancelToken::new();
+        let ui_token = token.clone();
+        std::thread::spawn(move || ui_token.cancel()).join().unwrap();
+        assert!(token.is_cancelled());
+        assert_eq!(img.compress_cancellable(Some(100.0), &token), Err(RusimgError::Cancelled));
+        assert_eq!(img.resize_cancellable(50.0, &token), Err(RusimgError::Cancelled));
+        assert_eq!(img.save_image_cancellable(Some(filename), &token), Err(RusimgError::Cancelled));
+        assert!(!Path::new(filename).exists());
+        assert_eq!(img.get_image_size().unwrap(), ImgSize::new(512, 512));
+
+        // A token cancelled while the operation is running stops it at the next check, leaving the image and the output path untouched.
+        let mut state = 1u32;
+        let noise = DynamicImage::ImageRgb8(ImageBuffer::from_fn(1024, 1024, |_, _| {
+            state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+            let [r, g, b, _] = state.to_le_bytes();
+            Rgb([r, g, b])
+        }));
+        let mut large = RusImg::new(&Extension::Png, noise).unwrap();
+        let cancel_soon = || {
+            let token = CancelToken::new();
+            let ui_token = token.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(std::time::Duration::from_millis(10));
+                ui_token.cancel();
+            });
+            token
+        };
+        assert_eq!(large.resize_cancellable(50.0, &cancel_soon()), Err(RusimgError::Cancelled));
+        assert_eq!(large.get_image_size().unwrap(), ImgSize::new(1024, 1024));
+        large.compress(Some(100.0)).unwrap();
+        assert_eq!(large.save_image_cancellable(Some(filename), &cancel_soon()), Err(RusimgError::Cancelled));
+        assert!(!Path::new(filename).exists());
+
+        // A token that is not cancelled does not change anything.
+        let token = CancelToken::new();
+        assert_eq!(img.resize_cancellable(50.0, &token).unwrap(), ImgSize::new(256, 256));
+        img.compress_cancellable(Some(20.0), &token).unwrap();
+        img.save_image_cancellable(Some(filename), &token).unwrap();
+        assert_eq!(image::image_dimensions(filename).unwrap(), (256, 256));
         std::fs::remove_file(filename).unwrap();
     }
 
     #[test]
-    fn test_err_image_format_cannot_be_compressed() {
-        let filename = "test_image19.bmp";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let mut img = RusImg::open(path).unwrap();
-        let result = img.compress(Some(50.0));
-        assert!(result.is_err());
-        if let Err(e) = result {
-            if let RusimgError::ImageFormatCannotBeCompressed = e {
-                // Expected error
-            } else {
-                panic!("Unexpected error: {:?}", e);
-            }
-        } else {
-            panic!("Expected an error, but got Ok");
+    fn test_sharpness_score() {
+        let checkerboard = DynamicImage::ImageRgb8(ImageBuffer::from_fn(64, 64, |x, y| if (x / 4 + y / 4) % 2 == 0 { Rgb([255u8, 255, 255]) } else { Rgb([0, 0, 0]) }));
+        let sharp = RusImg::new(&Extension::Png, checkerboard.clone()).unwrap();
+        let blurred = RusImg::new(&Extension::Png, checkerboard.blur(3.0)).unwrap();
+        let (sharp_score, blurred_score) = (sharp.sharpness_score().unwrap(), blurred.sharpness_score().unwrap());
+        assert!(sharp_score > blurred_score * 10.0, "sharp: {}, blurred: {}", sharp_score, blurred_score);
+
+        let flat = RusImg::new(&Extension::Png, DynamicImage::new_rgb8(16, 16)).unwrap();
+        assert_eq!(flat.sharpness_score().unwrap(), 0.0);
+    }
+
+    #[test]
+    #[cfg(feature="base64")]
+    fn test_from_data_url() {
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(12, 9, |x, y| Rgb([x as u8 * 20, y as u8 * 25, 100])));
+        let img = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        for extension in [Extension::Png, Extension::Webp] {
+            let data_url = img.to_data_url(&extension, None).unwrap();
+            let decoded = RusImg::from_data_url(&data_url).unwrap();
+            assert_eq!(decoded.get_extension(), extension);
+            assert_eq!(decoded.get_image_size().unwrap(), ImgSize::new(12, 9));
         }
-        std::fs::remove_file(filename).unwrap();
+        let decoded = RusImg::from_data_url(&img.to_data_url(&Extension::Png, None).unwrap()).unwrap();
+        assert_eq!(decoded.data.get_dynamic_image().unwrap().to_rgb8(), source.to_rgb8());
+
+        let invalid = |s: &str| matches!(RusImg::from_data_url(s), Err(RusimgError::InvalidDataUrl(_)));
+        assert!(invalid("image/png;base64,iVBORw0KGgo="));
+        assert!(invalid("data:image/png;base64"));
+        assert!(invalid("data:image/png,iVBORw0KGgo="));
+        assert!(invalid("data:text/plain;base64,aGVsbG8="));
+        assert!(invalid("data:image/png;base64,@@not base64@@"));
+        assert!(matches!(RusImg::from_data_url("data:image/png;base64,aGVsbG8="), Err(RusimgError::FailedToOpenImage(_))));
     }
 
     #[test]
-    fn test_err_source_path_must_be_specified() {
-        let filename = "test_image20.png";
-        let width = 100;
-        let height = 100;
-        generate_test_image(filename, width, height);
-        let path = Path::new(filename);
-        let img = RusImg::open(path).unwrap();
-        let result = img.get_input_filepath();
-        assert!(result.is_ok());
+    fn test_tonemap() {
+        // An HDR image: the left half is dark, and the right half is bright (2.0 to 8.0) with detail.
+        let filename = "test_image73.hdr";
+        let (width, height) = (64u32, 32u32);
+        let pixels: Vec<image::Rgb<f32>> = (0..height).flat_map(|y| (0..width).map(move |x| {
+            if x < width / 2 {
+                image::Rgb([x as f32 / 64.0, y as f32 / 64.0, 0.25])
+            }
+            else {
+                let v = 2.0 + 6.0 * ((x - width / 2) as f32 / 32.0) + if (x + y) % 2 == 0 { 0.5 } else { 0.0 };
+                image::Rgb([v, v * 0.8, v * 0.6])
+            }
+        })).collect();
+        let file = std::io::BufWriter::new(std::fs::File::create(filename).unwrap());
+        image::codecs::hdr::HdrEncoder::new(file).encode(&pixels, width as usize, height as usize).unwrap();
+        let hdr = image::open(filename).unwrap();
         std::fs::remove_file(filename).unwrap();
+        assert_eq!(hdr.color(), image::ColorType::Rgb32F);
+
+        // Clamping blows out the bright half to pure white.
+        let clamped = hdr.to_rgb8();
+        assert!((width / 2..width).all(|x| clamped.get_pixel(x, 0).0 == [255, 255, 255]));
+
+        let mut img = RusImg::new(&Extension::Jpeg, hdr.clone()).unwrap();
+        img.tonemap(ToneMapMethod::Reinhard).unwrap();
+        let mapped = img.get_dynamic_image().unwrap();
+        assert_eq!(mapped.color(), image::ColorType::Rgb8);
+        let mapped = mapped.to_rgb8();
+        // No clipping to white, and the bright area keeps its gradient and detail.
+        assert!(mapped.pixels().all(|p| p.0 != [255, 255, 255]));
+        let bright: std::collections::HashSet<u8> = (width / 2..width).map(|x| mapped.get_pixel(x, 0)[0]).collect();
+        assert!(bright.len() > 10);
+        assert!(mapped.get_pixel(width / 2, 0)[0] < mapped.get_pixel(width - 1, 0)[0]);
+        assert!(mapped.get_pixel(0, 0)[0] < mapped.get_pixel(width / 2, 0)[0]);
+        img.save_image(Some("test_image73.jpg")).unwrap();
+        std::fs::remove_file("test_image73.jpg").unwrap();
+
+        let mut aces = RusImg::new(&Extension::Jpeg, hdr).unwrap();
+        aces.tonemap(ToneMapMethod::Aces).unwrap();
+        assert_eq!(aces.get_dynamic_image().unwrap().color(), image::ColorType::Rgb8);
+
+        // Infinite and huge values map to white, and NaN and negative values to black.
+        let extreme = DynamicImage::ImageRgb32F(ImageBuffer::from_fn(4, 1, |x, _| match x {
+            0 => image::Rgb([f32::INFINITY, f32::MAX, 1e30]),
+            1 => image::Rgb([f32::NAN, f32::NEG_INFINITY, -1.0]),
+            2 => image::Rgb([1e4, 1e5, 1e6]),
+            _ => image::Rgb([0.0, 0.0, 0.0]),
+        }));
+        for method in [ToneMapMethod::Reinhard, ToneMapMethod::Aces] {
+            let mapped = operations::tonemap(&extreme, method).to_rgb8();
+            assert_eq!(mapped.get_pixel(0, 0).0, [255, 255, 255], "{:?}", method);
+            assert_eq!(mapped.get_pixel(1, 0).0, [0, 0, 0], "{:?}", method);
+            assert_eq!(mapped.get_pixel(2, 0).0, [255, 255, 255], "{:?}", method);
+        }
+
+        // 8-bit images are left unchanged.
+        let source = DynamicImage::ImageRgb8(ImageBuffer::from_fn(4, 4, |x, y| Rgb([x as u8 * 60, y as u8 * 60, 0])));
+        let mut sdr = RusImg::new(&Extension::Png, source.clone()).unwrap();
+        sdr.tonemap(ToneMapMethod::Reinhard).unwrap();
+        assert_eq!(sdr.get_dynamic_image().unwrap(), source);
     }
 }
@@ -0,0 +1,46 @@
+// Optional logging through the log crate.
+// With the logging feature disabled, the macros and the timer compile to nothing,
+// but the arguments of the macros are still type-checked.
+
+#[cfg(feature="logging")]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { log::debug!(target: "librusimg", $($arg)*) };
+}
+#[cfg(not(feature="logging"))]
+macro_rules! log_debug {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
+#[cfg(feature="logging")]
+macro_rules! log_info {
+    ($($arg:tt)*) => { log::info!(target: "librusimg", $($arg)*) };
+}
+#[cfg(not(feature="logging"))]
+macro_rules! log_info {
+    ($($arg:tt)*) => { if false { let _ = format_args!($($arg)*); } };
+}
+
+pub(crate) use {log_debug, log_info};
+
+/// Timer for the durations in the log messages.
+/// Without the logging feature, it does not read the clock.
+pub struct Timer {
+    #[cfg(feature="logging")]
+    start: std::time::Instant,
+}
+impl Timer {
+    pub fn start() -> Self {
+        Self {
+            #[cfg(feature="logging")]
+            start: std::time::Instant::now(),
+        }
+    }
+
+    /// Elapsed time in milliseconds.
+    pub fn elapsed_ms(&self) -> f64 {
+        #[cfg(feature="logging")]
+        return self.start.elapsed().as_secs_f64() * 1000.0;
+        #[cfg(not(feature="logging"))]
+        return 0.0;
+    }
+}
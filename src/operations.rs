@@ -0,0 +1,834 @@
+// Image processing algorithms shared by RusImg and the backends.
+// These functions work on image::DynamicImage objects and do not depend on the image format.
+// With the parallel feature, the per-pixel loops run on multiple threads with rayon.
+
+use image::{ColorType, DynamicImage, GrayImage, Rgba, RgbaImage};
+
+use super::{CancelToken, Rect, ResampleKernel, RotateInterpolation, RusimgError, ToneMapMethod};
+
+// Compute the edge strength (sum of absolute horizontal and vertical differences) of each pixel.
+fn edge_map(image: &GrayImage) -> Vec<u64> {
+    let (width, height) = image.dimensions();
+    let mut edges = vec![0u64; (width * height) as usize];
+    for y in 0..height {
+        for x in 0..width {
+            let p = image.get_pixel(x, y)[0] as i32;
+            let dx = if x + 1 < width { (image.get_pixel(x + 1, y)[0] as i32 - p).unsigned_abs() } else { 0 };
+            let dy = if y + 1 < height { (image.get_pixel(x, y + 1)[0] as i32 - p).unsigned_abs() } else { 0 };
+            edges[(y * width + x) as usize] = (dx + dy) as u64;
+        }
+    }
+    edges
+}
+
+/// Find the crop window of the given size with the highest edge density.
+/// Candidate windows are scored with a summed-area table, so each score is computed in constant time.
+/// Returns None if the window does not fit in the image.
+pub fn salient_window(image: &DynamicImage, window_w: u32, window_h: u32) -> Option<Rect> {
+    let (width, height) = (image.width(), image.height());
+    if window_w == 0 || window_h == 0 || window_w > width || window_h > height {
+        return None;
+    }
+
+    // Summed-area table of the edge map with a zero row and column.
+    let edges = edge_map(&image.to_luma8());
+    let stride = (width + 1) as usize;
+    let mut table = vec![0u64; stride * (height + 1) as usize];
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            table[(y + 1) * stride + x + 1] = edges[y * width as usize + x]
+                + table[y * stride + x + 1] + table[(y + 1) * stride + x] - table[y * stride + x];
+        }
+    }
+    let window_sum = |x: usize, y: usize| -> u64 {
+        let (x2, y2) = (x + window_w as usize, y + window_h as usize);
+        table[y2 * stride + x2] + table[y * stride + x] - table[y * stride + x2] - table[y2 * stride + x]
+    };
+
+    let mut best = (0, 0, 0u64);
+    for y in 0..=(height - window_h) as usize {
+        for x in 0..=(width - window_w) as usize {
+            let score = window_sum(x, y);
+            if score > best.2 {
+                best = (x, y, score);
+            }
+        }
+    }
+    Some(Rect { x: best.0 as u32, y: best.1 as u32, w: window_w, h: window_h })
+}
+
+/// Find the bounding box of the pixels that differ from the background.
+/// The background is the most common color of the border pixels, and a pixel differs from it if any RGBA channel differs by more than ``tolerance``.
+/// Returns None if every pixel is background.
+pub fn content_bounds(image: &DynamicImage, tolerance: u8) -> Option<Rect> {
+    let rgba = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    let mut histogram = std::collections::HashMap::new();
+    let border = (0..width).flat_map(|x| [(x, 0), (x, height - 1)])
+        .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]));
+    for (x, y) in border {
+        *histogram.entry(rgba.get_pixel(x, y).0).or_insert(0usize) += 1;
+    }
+    let background = histogram.into_iter().max_by_key(|(color, n)| (*n, std::cmp::Reverse(*color)))?.0;
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in rgba.enumerate_pixels() {
+        if (0..4).any(|c| pixel[c].abs_diff(background[c]) > tolerance) {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    if min_x > max_x {
+        return None;
+    }
+    Some(Rect { x: min_x, y: min_y, w: max_x - min_x + 1, h: max_y - min_y + 1 })
+}
+
+/// Apply a function to each pixel of a raw image buffer.
+/// ``row_len`` is the number of values in a row and ``channels`` the number of values in a pixel.
+/// With the parallel feature, the rows are processed in parallel; the result is the same as the serial loop.
+pub fn for_each_pixel<T, F>(data: &mut [T], row_len: usize, channels: usize, f: F)
+where T: Send, F: Fn(&mut [T]) + Sync + Send {
+    #[cfg(feature="parallel")]
+    for_each_pixel_parallel(data, row_len, channels, f);
+    #[cfg(not(feature="parallel"))]
+    {
+        let _ = row_len;
+        for_each_pixel_serial(data, channels, f);
+    }
+}
+
+/// Apply a function to each pixel of a raw image buffer on the current thread.
+#[cfg(any(not(feature="parallel"), test))]
+pub fn for_each_pixel_serial<T, F>(data: &mut [T], channels: usize, f: F)
+where F: Fn(&mut [T]) {
+    data.chunks_exact_mut(channels).for_each(f);
+}
+
+/// Apply a function to each pixel of a raw image buffer, processing the rows in parallel with rayon.
+#[cfg(feature="parallel")]
+pub fn for_each_pixel_parallel<T, F>(data: &mut [T], row_len: usize, channels: usize, f: F)
+where T: Send, F: Fn(&mut [T]) + Sync + Send {
+    use rayon::prelude::*;
+    data.par_chunks_mut(row_len.max(channels)).for_each(|row| row.chunks_exact_mut(channels).for_each(&f));
+}
+
+// sRGB transfer functions on values in [0, 1].
+fn srgb_to_linear_value(v: f32) -> f32 {
+    if v <= 0.04045 { v / 12.92 } else { ((v + 0.055) / 1.055).powf(2.4) }
+}
+fn linear_to_srgb_value(v: f32) -> f32 {
+    if v <= 0.0031308 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 }
+}
+
+/// Convert an sRGB image to a linear RGB image with 32-bit float RGBA pixels.
+/// The alpha channel is kept as it is.
+pub fn srgb_to_linear(image: &DynamicImage) -> DynamicImage {
+    let mut buffer = image.to_rgba32f();
+    let row_len = buffer.width() as usize * 4;
+    for_each_pixel(&mut buffer, row_len, 4, |pixel| {
+        for v in pixel.iter_mut().take(3) {
+            *v = srgb_to_linear_value(*v);
+        }
+    });
+    DynamicImage::ImageRgba32F(buffer)
+}
+
+/// Convert a linear RGB image back to sRGB in the given color type.
+pub fn linear_to_srgb(image: &DynamicImage, color: ColorType) -> DynamicImage {
+    let mut buffer = image.to_rgba32f();
+    let row_len = buffer.width() as usize * 4;
+    for_each_pixel(&mut buffer, row_len, 4, |pixel| {
+        for v in pixel.iter_mut().take(3) {
+            *v = linear_to_srgb_value(v.clamp(0.0, 1.0));
+        }
+    });
+    to_color_type(DynamicImage::ImageRgba32F(buffer), color)
+}
+
+// Convert an image to the given color type.
+fn to_color_type(image: DynamicImage, color: ColorType) -> DynamicImage {
+    match color {
+        ColorType::L8 => DynamicImage::ImageLuma8(image.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(image.to_rgb8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(image.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(image.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(image.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        ColorType::Rgba32F => DynamicImage::ImageRgba32F(image.to_rgba32f()),
+        _ => DynamicImage::ImageRgba8(image.to_rgba8()),
+    }
+}
+
+/// Convert an image to grayscale, keeping the alpha channel.
+/// With ``preserve_depth``, 16-bit and floating-point sources become 16-bit grayscale (Luma16 or LumaA16); otherwise the result is 8-bit.
+/// 8-bit RGB sources are converted with ``for_each_pixel()``; the result is the same as the image crate's conversion.
+pub fn grayscale(image: &DynamicImage, preserve_depth: bool) -> DynamicImage {
+    match image {
+        DynamicImage::ImageRgb8(rgb) => return rgb8_to_luma(rgb.as_raw(), rgb.width(), rgb.height(), 3),
+        DynamicImage::ImageRgba8(rgba) => return rgb8_to_luma(rgba.as_raw(), rgba.width(), rgba.height(), 4),
+        _ => {},
+    }
+    let high_depth = !matches!(image.color(), ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8);
+    match (preserve_depth && high_depth, image.color().has_alpha()) {
+        (true, true) => DynamicImage::ImageLumaA16(image.to_luma_alpha16()),
+        (true, false) => DynamicImage::ImageLuma16(image.to_luma16()),
+        (false, true) => DynamicImage::ImageLumaA8(image.to_luma_alpha8()),
+        (false, false) => DynamicImage::ImageLuma8(image.to_luma8()),
+    }
+}
+
+// Convert 8-bit RGB or RGBA pixels (``channels`` = 3 or 4) to Luma8 or LumaA8.
+// The luma is computed in place on the first channel with the Rec. 709 integer weights of the image crate, and the other channels are dropped.
+fn rgb8_to_luma(raw: &[u8], width: u32, height: u32, channels: usize) -> DynamicImage {
+    let mut data = raw.to_vec();
+    for_each_pixel(&mut data, width as usize * channels, channels, |pixel| {
+        pixel[0] = ((2126 * pixel[0] as u32 + 7152 * pixel[1] as u32 + 722 * pixel[2] as u32) / 10000) as u8;
+    });
+    if channels == 4 {
+        let luma_alpha = data.chunks_exact(4).flat_map(|pixel| [pixel[0], pixel[3]]).collect();
+        DynamicImage::ImageLumaA8(image::GrayAlphaImage::from_raw(width, height, luma_alpha).unwrap())
+    }
+    else {
+        let luma = data.chunks_exact(3).map(|pixel| pixel[0]).collect();
+        DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, luma).unwrap())
+    }
+}
+
+/// Convert an image to grayscale and threshold each pixel to 0 or 255.
+/// Pixels at or above the level become white (255), and the others become black (0).
+pub fn threshold(image: &DynamicImage, level: u8) -> GrayImage {
+    let mut gray = image.to_luma8();
+    let row_len = gray.width() as usize;
+    for_each_pixel(&mut gray, row_len, 1, |pixel| {
+        pixel[0] = if pixel[0] >= level { 255 } else { 0 };
+    });
+    gray
+}
+
+/// Convert an image to grayscale and compute the Sobel gradient magnitude of each pixel.
+/// Edges become bright and flat regions become black; magnitudes above 255 are clamped.
+/// The border pixels are computed by extending the image edges.
+pub fn sobel_edges(image: &DynamicImage) -> GrayImage {
+    let gray = image.to_luma8();
+    let (width, height) = gray.dimensions();
+    GrayImage::from_fn(width, height, |x, y| {
+        // The value of the neighbor at (x + dx, y + dy), extending the image edges.
+        let at = |dx: i64, dy: i64| -> i32 {
+            let nx = (x as i64 + dx).clamp(0, width as i64 - 1) as u32;
+            let ny = (y as i64 + dy).clamp(0, height as i64 - 1) as u32;
+            gray.get_pixel(nx, ny)[0] as i32
+        };
+        let gx = at(1, -1) + 2 * at(1, 0) + at(1, 1) - at(-1, -1) - 2 * at(-1, 0) - at(-1, 1);
+        let gy = at(-1, 1) + 2 * at(0, 1) + at(1, 1) - at(-1, -1) - 2 * at(0, -1) - at(1, -1);
+        image::Luma([((gx * gx + gy * gy) as f32).sqrt().min(255.0) as u8])
+    })
+}
+
+/// Compute the threshold level of a grayscale image with Otsu's method.
+/// The level maximizes the between-class variance of the histogram when the pixels are split into
+/// the ones below it and the ones at or above it, matching ``threshold()``.
+pub fn otsu_level(image: &GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in image.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let total = image.pixels().len() as f64;
+    let total_sum: f64 = histogram.iter().enumerate().map(|(v, &n)| v as f64 * n as f64).sum();
+
+    let (mut best_level, mut best_variance) = (0u8, -1.0f64);
+    let (mut below_count, mut below_sum) = (0.0f64, 0.0f64);
+    // Level 0 puts every pixel in the upper class, so start from 1.
+    for level in 1..=255usize {
+        below_count += histogram[level - 1] as f64;
+        below_sum += (level - 1) as f64 * histogram[level - 1] as f64;
+        let above_count = total - below_count;
+        if below_count == 0.0 || above_count == 0.0 {
+            continue;
+        }
+        let below_mean = below_sum / below_count;
+        let above_mean = (total_sum - below_sum) / above_count;
+        let variance = below_count * above_count * (below_mean - above_mean).powi(2);
+        if variance > best_variance {
+            best_level = level as u8;
+            best_variance = variance;
+        }
+    }
+    best_level
+}
+
+/// Equalize the histogram of the luminance (the Y channel of YCbCr), spreading the brightness levels over the full range.
+/// Cb and Cr are kept, so the colors are preserved, and the alpha channel is kept as it is.
+/// The image is processed in 8 bits: grayscale images become Luma8 or LumaA8, images with an alpha channel RGBA8, and the others RGB8.
+/// Images with a single brightness level are returned unchanged (in the same color types).
+pub fn equalize_histogram(image: &DynamicImage) -> DynamicImage {
+    let luma = |p: &[u8]| -> f32 { 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32 };
+    let mut buffer = image.to_rgba8();
+    let mut histogram = [0u64; 256];
+    for pixel in buffer.pixels() {
+        histogram[luma(&pixel.0).round() as usize] += 1;
+    }
+
+    // Map each level through the cumulative histogram, so that the darkest level becomes 0 and the brightest 255.
+    let total: u64 = histogram.iter().sum();
+    let cdf_min = histogram.iter().copied().find(|&n| n > 0).unwrap_or(0);
+    let mut mapping = [0f32; 256];
+    let mut cumulative = 0u64;
+    for (level, &n) in histogram.iter().enumerate() {
+        cumulative += n;
+        mapping[level] = if total > cdf_min { (cumulative.saturating_sub(cdf_min)) as f32 / (total - cdf_min) as f32 * 255.0 } else { level as f32 };
+    }
+
+    let row_len = buffer.width() as usize * 4;
+    for_each_pixel(&mut buffer, row_len, 4, |pixel| {
+        let y = luma(pixel);
+        let cb = -0.168736 * pixel[0] as f32 - 0.331264 * pixel[1] as f32 + 0.5 * pixel[2] as f32;
+        let cr = 0.5 * pixel[0] as f32 - 0.418688 * pixel[1] as f32 - 0.081312 * pixel[2] as f32;
+        let y = mapping[y.round() as usize];
+        pixel[0] = (y + 1.402 * cr).round().clamp(0.0, 255.0) as u8;
+        pixel[1] = (y - 0.344136 * cb - 0.714136 * cr).round().clamp(0.0, 255.0) as u8;
+        pixel[2] = (y + 1.772 * cb).round().clamp(0.0, 255.0) as u8;
+    });
+    let equalized = DynamicImage::ImageRgba8(buffer);
+    match image.color() {
+        ColorType::L8 | ColorType::L16 => DynamicImage::ImageLuma8(equalized.to_luma8()),
+        ColorType::La8 | ColorType::La16 => DynamicImage::ImageLumaA8(equalized.to_luma_alpha8()),
+        color if color.has_alpha() => equalized,
+        _ => DynamicImage::ImageRgb8(equalized.to_rgb8()),
+    }
+}
+
+/// Quantize each RGB channel to the given number of evenly spaced levels.
+/// The alpha channel is kept as it is. Images with an alpha channel become RGBA8, and the others RGB8.
+pub fn posterize(image: &DynamicImage, levels: u8) -> DynamicImage {
+    let steps = (levels.max(2) - 1) as f32;
+    let quantize = |v: u8| -> u8 { ((v as f32 / 255.0 * steps).round() / steps * 255.0).round() as u8 };
+    let mut buffer = image.to_rgba8();
+    let row_len = buffer.width() as usize * 4;
+    for_each_pixel(&mut buffer, row_len, 4, |pixel| {
+        for v in pixel.iter_mut().take(3) {
+            *v = quantize(*v);
+        }
+    });
+    if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(buffer)
+    }
+    else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(buffer).to_rgb8())
+    }
+}
+
+// Largest input value of the tone-mapping operators; both map it to white, and its square does not overflow f32.
+const TONEMAP_MAX_INPUT: f32 = 1e4;
+
+/// Tone-map a floating-point (HDR) image with linear RGB values to an 8-bit sRGB image, compressing values above 1.0 instead of clipping them.
+/// Each color channel is mapped by the operator and then encoded with the sRGB transfer function; the alpha channel is kept.
+/// The result is RGBA8 if the image has an alpha channel, and RGB8 otherwise.
+/// Values are clamped to ``TONEMAP_MAX_INPUT`` first (NaN becomes 0), so infinite and huge values map to white instead of overflowing the operator.
+pub fn tonemap(image: &DynamicImage, method: ToneMapMethod) -> DynamicImage {
+    let operator = |c: f32| -> f32 {
+        let c = if c.is_nan() { 0.0 } else { c.clamp(0.0, TONEMAP_MAX_INPUT) };
+        match method {
+            ToneMapMethod::Reinhard => c / (1.0 + c),
+            ToneMapMethod::Aces => ((c * (2.51 * c + 0.03)) / (c * (2.43 * c + 0.59) + 0.14)).clamp(0.0, 1.0),
+        }
+    };
+    let source = image.to_rgba32f();
+    let mut buffer = RgbaImage::new(source.width(), source.height());
+    for (output, input) in buffer.pixels_mut().zip(source.pixels()) {
+        for i in 0..3 {
+            output[i] = (linear_to_srgb_value(operator(input[i])) * 255.0).round() as u8;
+        }
+        output[3] = (input[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+    if image.color().has_alpha() {
+        DynamicImage::ImageRgba8(buffer)
+    }
+    else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(buffer).to_rgb8())
+    }
+}
+
+/// Drop the alpha channel of an image, keeping the color channels and the bit depth.
+/// Images without an alpha channel are returned as they are.
+pub fn remove_alpha(image: &DynamicImage) -> DynamicImage {
+    match image {
+        DynamicImage::ImageLumaA8(_) => DynamicImage::ImageLuma8(image.to_luma8()),
+        DynamicImage::ImageLumaA16(_) => DynamicImage::ImageLuma16(image.to_luma16()),
+        DynamicImage::ImageRgba8(_) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        DynamicImage::ImageRgba16(_) => DynamicImage::ImageRgb16(image.to_rgb16()),
+        DynamicImage::ImageRgba32F(_) => DynamicImage::ImageRgb32F(image.to_rgb32f()),
+        _ => image.clone(),
+    }
+}
+
+/// Check whether every pixel of an image is fully opaque.
+/// Images without an alpha channel are always opaque.
+pub fn is_opaque(image: &DynamicImage) -> bool {
+    match image {
+        DynamicImage::ImageLumaA8(buffer) => buffer.pixels().all(|p| p[1] == u8::MAX),
+        DynamicImage::ImageLumaA16(buffer) => buffer.pixels().all(|p| p[1] == u16::MAX),
+        DynamicImage::ImageRgba8(buffer) => buffer.pixels().all(|p| p[3] == u8::MAX),
+        DynamicImage::ImageRgba16(buffer) => buffer.pixels().all(|p| p[3] == u16::MAX),
+        DynamicImage::ImageRgba32F(buffer) => buffer.pixels().all(|p| p[3] >= 1.0),
+        _ => !image.color().has_alpha(),
+    }
+}
+
+/// Multiply the alpha channel of an image by ``factor`` (0.0 - 1.0).
+/// The image becomes RGBA8; images without an alpha channel are treated as fully opaque.
+pub fn scale_alpha(image: &DynamicImage, factor: f32) -> DynamicImage {
+    let mut buffer = image.to_rgba8();
+    let row_len = buffer.width() as usize * 4;
+    for_each_pixel(&mut buffer, row_len, 4, |pixel| {
+        pixel[3] = (pixel[3] as f32 * factor).round() as u8;
+    });
+    DynamicImage::ImageRgba8(buffer)
+}
+
+/// Multiply the color channels of each pixel by its alpha (straight to premultiplied alpha).
+/// The image becomes RGBA8.
+pub fn premultiply_alpha(image: &DynamicImage) -> DynamicImage {
+    let mut buffer = image.to_rgba8();
+    let row_len = buffer.width() as usize * 4;
+    for_each_pixel(&mut buffer, row_len, 4, |pixel| {
+        let alpha = pixel[3] as u32;
+        for v in pixel.iter_mut().take(3) {
+            *v = ((*v as u32 * alpha + 127) / 255) as u8;
+        }
+    });
+    DynamicImage::ImageRgba8(buffer)
+}
+
+/// Divide the color channels of each pixel by its alpha (premultiplied to straight alpha).
+/// Fully transparent pixels have no color to recover, so they become transparent black.
+/// The image becomes RGBA8.
+pub fn unpremultiply_alpha(image: &DynamicImage) -> DynamicImage {
+    let mut buffer = image.to_rgba8();
+    let row_len = buffer.width() as usize * 4;
+    for_each_pixel(&mut buffer, row_len, 4, |pixel| {
+        let alpha = pixel[3] as u32;
+        for v in pixel.iter_mut().take(3) {
+            *v = (*v as u32 * 255 + alpha / 2).checked_div(alpha).unwrap_or(0).min(255) as u8;
+        }
+    });
+    DynamicImage::ImageRgba8(buffer)
+}
+
+/// Make the pixels outside a rounded rectangle transparent.
+/// The rectangle is given by its center, half width, half height and corner radius, in pixels.
+/// The edge is antialiased by the coverage of each pixel, and the image becomes RGBA8.
+pub fn rounded_rect_mask(image: &DynamicImage, center: (f32, f32), half_size: (f32, f32), radius: f32) -> DynamicImage {
+    let mut buffer = image.to_rgba8();
+    let radius = radius.min(half_size.0).min(half_size.1).max(0.0);
+    for (x, y, pixel) in buffer.enumerate_pixels_mut() {
+        // Signed distance from the pixel center to the edge of the rounded rectangle (negative inside).
+        let qx = (x as f32 + 0.5 - center.0).abs() - (half_size.0 - radius);
+        let qy = (y as f32 + 0.5 - center.1).abs() - (half_size.1 - radius);
+        let distance = (qx.max(0.0).powi(2) + qy.max(0.0).powi(2)).sqrt() + qx.max(qy).min(0.0) - radius;
+        let coverage = (0.5 - distance).clamp(0.0, 1.0);
+        pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+    }
+    DynamicImage::ImageRgba8(buffer)
+}
+
+// Sample the image at (x, y) with the interpolation, where integer coordinates are the pixels.
+// Positions whose neighbors needed by the interpolation are not all inside the image get ``fill``.
+fn sample(image: &RgbaImage, x: f32, y: f32, interpolation: RotateInterpolation, fill: Rgba<u8>) -> Rgba<u8> {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    // Check that the pixels from (left, top) to (right, bottom) are inside the image; NaN coordinates are rejected.
+    let inside = |left: f32, top: f32, right: f32, bottom: f32| left >= 0.0 && top >= 0.0 && right < width && bottom < height;
+    match interpolation {
+        RotateInterpolation::Nearest => {
+            let (nx, ny) = ((x + 0.5).floor(), (y + 0.5).floor());
+            if inside(nx, ny, nx, ny) { *image.get_pixel(nx as u32, ny as u32) } else { fill }
+        },
+        RotateInterpolation::Bilinear => {
+            let (left, top) = (x.floor(), y.floor());
+            if !inside(left, top, left + 1.0, top + 1.0) {
+                return fill;
+            }
+            let (tx, ty) = (x - left, y - top);
+            let (l, t) = (left as u32, top as u32);
+            let lerp = |a: f32, b: f32, t: f32| (1.0 - t) * a + t * b;
+            let mut pixel = fill;
+            for c in 0..4 {
+                let upper = lerp(image.get_pixel(l, t)[c] as f32, image.get_pixel(l + 1, t)[c] as f32, tx).clamp(0.0, 255.0).trunc();
+                let lower = lerp(image.get_pixel(l, t + 1)[c] as f32, image.get_pixel(l + 1, t + 1)[c] as f32, tx).clamp(0.0, 255.0).trunc();
+                pixel[c] = lerp(upper, lower, ty).clamp(0.0, 255.0) as u8;
+            }
+            pixel
+        },
+        RotateInterpolation::Bicubic => {
+            let (left, top) = (x.floor() - 1.0, y.floor() - 1.0);
+            if !inside(left, top, left + 3.0, top + 3.0) {
+                return fill;
+            }
+            let (tx, ty) = (x - left - 1.0, y - top - 1.0);
+            let (l, t) = (left as u32, top as u32);
+            // Catmull-Rom spline through p0..p3, evaluated at t between p1 and p2.
+            let cubic = |p: [f32; 4], t: f32| {
+                p[1] + 0.5 * t * (p[2] - p[0] + t * (2.0 * p[0] - 5.0 * p[1] + 4.0 * p[2] - p[3] + t * (3.0 * (p[1] - p[2]) + p[3] - p[0])))
+            };
+            let mut pixel = fill;
+            for c in 0..4 {
+                let rows = [0, 1, 2, 3].map(|r| {
+                    let p = [0, 1, 2, 3].map(|i| image.get_pixel(l + i, t + r)[c] as f32);
+                    cubic(p, tx).clamp(0.0, 255.0).trunc()
+                });
+                pixel[c] = cubic(rows, ty).clamp(0.0, 255.0) as u8;
+            }
+            pixel
+        },
+    }
+}
+
+/// Rotate an image clockwise by an arbitrary angle around its center.
+/// The canvas is expanded to the bounding box of the rotated image, and the uncovered corners are filled with ``fill``.
+/// Images with an alpha channel, or rotated with a transparent fill, become RGBA8, and the others RGB8.
+pub fn rotate(image: &DynamicImage, degrees: f32, fill: [u8; 4], interpolation: RotateInterpolation) -> DynamicImage {
+    let source = image.to_rgba8();
+    let (width, height) = (source.width() as f32, source.height() as f32);
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    // Round away tiny float errors (e.g. at 90 degrees) before taking the ceiling.
+    let fit = |v: f32| -> u32 { ((v * 1000.0).round() / 1000.0).ceil().max(1.0) as u32 };
+    let new_width = fit(width * cos.abs() + height * sin.abs());
+    let new_height = fit(width * sin.abs() + height * cos.abs());
+
+    // Map each pixel of the new canvas back to the source: move the center of the canvas to the origin,
+    // rotate it counterclockwise, and move it to the center of the source image.
+    let (canvas_cx, canvas_cy) = (new_width as f32 / 2.0, new_height as f32 / 2.0);
+    let rotated = RgbaImage::from_fn(new_width, new_height, |x, y| {
+        let (dx, dy) = (x as f32 - canvas_cx, y as f32 - canvas_cy);
+        let (sx, sy) = (cos * dx + sin * dy + width / 2.0, -sin * dx + cos * dy + height / 2.0);
+        sample(&source, sx, sy, interpolation, Rgba(fill))
+    });
+
+    if image.color().has_alpha() || fill[3] != 255 {
+        DynamicImage::ImageRgba8(rotated)
+    }
+    else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rotated).to_rgb8())
+    }
+}
+
+/// Estimate the clockwise skew angle (in degrees) of the text lines of a document image within ±``max_angle``.
+/// The image is binarized with Otsu's method, and the dark pixels are projected onto the vertical axis at each candidate angle.
+/// The angle whose projection profile is the sharpest (the largest sum of squared row counts) is the one aligning the text lines.
+pub fn skew_angle(image: &GrayImage, max_angle: f32) -> f32 {
+    let level = otsu_level(image);
+    let (cx, cy) = (image.width() as f32 / 2.0, image.height() as f32 / 2.0);
+    let points: Vec<(f32, f32)> = image.enumerate_pixels()
+        .filter(|(_, _, p)| p[0] < level)
+        .map(|(x, y, _)| (x as f32 - cx, y as f32 - cy))
+        .collect();
+    if points.is_empty() {
+        return 0.0;
+    }
+
+    let offset = (cx * cx + cy * cy).sqrt().ceil();
+    let score = |degrees: f32| -> u64 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let rows = (offset * 2.0) as usize + 2;
+        let mut profile = vec![0u64; rows];
+        for (x, y) in &points {
+            let row = (y * cos - x * sin + offset) as usize;
+            profile[row.min(rows - 1)] += 1;
+        }
+        profile.iter().map(|n| n * n).sum()
+    };
+    let best_in = |from: f32, to: f32, step: f32| -> f32 {
+        let steps = ((to - from) / step).round() as i32;
+        (0..=steps).map(|i| (from + i as f32 * step).clamp(-max_angle, max_angle))
+            .max_by_key(|&degrees| score(degrees))
+            .unwrap_or(0.0)
+    };
+
+    // Search coarsely over the whole range, then finely around the best candidate.
+    let coarse = best_in(-max_angle, max_angle, 0.5);
+    best_in(coarse - 0.5, coarse + 0.5, 0.05)
+}
+
+/// Compute the peak signal-to-noise ratio (in dB) between two images of the same size, compared in RGB8.
+/// Identical images return infinity.
+pub fn psnr(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    let squared_error: f64 = a.as_raw().iter().zip(b.as_raw()).map(|(x, y)| (*x as f64 - *y as f64).powi(2)).sum();
+    let mse = squared_error / a.as_raw().len().max(1) as f64;
+    if mse == 0.0 {
+        return f64::INFINITY;
+    }
+    10.0 * (255.0 * 255.0 / mse).log10()
+}
+
+/// Make an image of the per-pixel absolute difference between two images of the same size, compared in RGB8.
+/// Each difference is multiplied by ``amplify`` and clamped to 255, so that small compression artifacts become visible.
+pub fn difference_image(a: &DynamicImage, b: &DynamicImage, amplify: f32) -> DynamicImage {
+    let (a, b) = (a.to_rgb8(), b.to_rgb8());
+    let data = a.as_raw().iter().zip(b.as_raw()).map(|(x, y)| ((x.abs_diff(*y) as f32 * amplify).round()).min(255.0) as u8).collect();
+    DynamicImage::ImageRgb8(image::RgbImage::from_raw(a.width(), a.height(), data).unwrap())
+}
+
+/// Compute the variance of the Laplacian of the grayscale image, a common blur metric: sharp images have strong edges and a high variance.
+/// The 3x3 Laplacian (4-neighbor) is computed on the interior pixels; images smaller than 3x3 return 0.
+pub fn laplacian_variance(image: &DynamicImage) -> f64 {
+    let gray = image.to_luma8();
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+    let laplacians: Vec<f64> = (1..height - 1)
+        .flat_map(|y| (1..width - 1).map(move |x| (x, y)))
+        .map(|(x, y)| at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y))
+        .collect();
+    let mean = laplacians.iter().sum::<f64>() / laplacians.len() as f64;
+    laplacians.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / laplacians.len() as f64
+}
+
+/// Find the most prominent colors of an image with k-means clustering, sorted by the number of pixels they cover.
+/// The image is downsampled to at most 64x64 pixels first, so the result is fast to compute even for large images.
+/// The initial centers are chosen deterministically (the most common color, then the farthest colors),
+/// and fewer colors are returned if the image has fewer distinct colors than ``count``.
+pub fn dominant_colors(image: &DynamicImage, count: usize) -> Vec<[u8; 3]> {
+    let small = if image.width() > 64 || image.height() > 64 {
+        image.resize(64, 64, image::imageops::FilterType::Nearest).to_rgb8()
+    }
+    else {
+        image.to_rgb8()
+    };
+    let pixels: Vec<[f32; 3]> = small.pixels().map(|p| [p[0] as f32, p[1] as f32, p[2] as f32]).collect();
+    if pixels.is_empty() || count == 0 {
+        return Vec::new();
+    }
+    let distance = |a: &[f32; 3], b: &[f32; 3]| -> f32 { (0..3).map(|i| (a[i] - b[i]).powi(2)).sum() };
+
+    // Start from the most common color, and add the color farthest from the chosen centers one by one.
+    let mut histogram = std::collections::HashMap::new();
+    for p in small.pixels() {
+        *histogram.entry(p.0).or_insert(0usize) += 1;
+    }
+    let most_common = histogram.iter().max_by_key(|(color, &n)| (n, std::cmp::Reverse(**color))).map(|(color, _)| *color).unwrap_or([0; 3]);
+    let mut centers = vec![[most_common[0] as f32, most_common[1] as f32, most_common[2] as f32]];
+    while centers.len() < count {
+        let farthest = pixels.iter()
+            .map(|p| (p, centers.iter().map(|c| distance(p, c)).fold(f32::MAX, f32::min)))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        match farthest {
+            Some((p, d)) if d > 0.0 => centers.push(*p),
+            _ => break,
+        }
+    }
+
+    let mut assignment = vec![0usize; pixels.len()];
+    for _ in 0..10 {
+        for (i, p) in pixels.iter().enumerate() {
+            assignment[i] = (0..centers.len()).min_by(|&a, &b| distance(p, &centers[a]).total_cmp(&distance(p, &centers[b]))).unwrap_or(0);
+        }
+        let mut sums = vec![[0.0f32; 3]; centers.len()];
+        let mut counts = vec![0usize; centers.len()];
+        for (p, &k) in pixels.iter().zip(&assignment) {
+            (0..3).for_each(|i| sums[k][i] += p[i]);
+            counts[k] += 1;
+        }
+        for k in 0..centers.len() {
+            if counts[k] > 0 {
+                centers[k] = [sums[k][0] / counts[k] as f32, sums[k][1] / counts[k] as f32, sums[k][2] / counts[k] as f32];
+            }
+        }
+    }
+
+    let mut coverage = vec![0usize; centers.len()];
+    for &k in &assignment {
+        coverage[k] += 1;
+    }
+    let mut clusters: Vec<(usize, [u8; 3])> = centers.iter().zip(coverage)
+        .filter(|(_, n)| *n > 0)
+        .map(|(c, n)| (n, [c[0].round() as u8, c[1].round() as u8, c[2].round() as u8]))
+        .collect();
+    clusters.sort_by_key(|cluster| std::cmp::Reverse(cluster.0));
+    clusters.into_iter().map(|(_, color)| color).collect()
+}
+
+/// Count the distinct RGBA8 colors of an image.
+/// With ``cap``, counting stops as soon as more than ``cap`` colors are found and ``cap`` is returned.
+pub fn unique_color_count(image: &DynamicImage, cap: Option<usize>) -> usize {
+    let cap = cap.unwrap_or(usize::MAX);
+    let mut colors = std::collections::HashSet::new();
+    for p in image.to_rgba8().pixels() {
+        colors.insert(u32::from_le_bytes(p.0));
+        if colors.len() > cap {
+            return cap;
+        }
+    }
+    colors.len()
+}
+
+/// Reduce the RGB colors of an image to at most ``max_colors`` colors, keeping the alpha channel.
+/// The palette is made by ``dominant_colors()``, and each pixel is replaced by the nearest palette color.
+/// Images that already have ``max_colors`` or fewer colors are returned unchanged (as RGBA8).
+pub fn quantize(image: &DynamicImage, max_colors: usize) -> DynamicImage {
+    let mut rgba = image.to_rgba8();
+    let mut colors = std::collections::HashSet::new();
+    let few_colors = rgba.pixels().all(|p| {
+        colors.insert([p[0], p[1], p[2]]);
+        colors.len() <= max_colors
+    });
+    if few_colors {
+        return DynamicImage::ImageRgba8(rgba);
+    }
+
+    let palette = dominant_colors(image, max_colors);
+    let distance = |a: &[u8; 3], b: &[u8; 3]| -> i32 { (0..3).map(|i| (a[i] as i32 - b[i] as i32).pow(2)).sum() };
+    let mut nearest = std::collections::HashMap::new();
+    for p in rgba.pixels_mut() {
+        let color = [p[0], p[1], p[2]];
+        let mapped = *nearest.entry(color).or_insert_with(|| {
+            palette.iter().copied().min_by_key(|c| distance(c, &color)).unwrap_or(color)
+        });
+        p.0 = [mapped[0], mapped[1], mapped[2], p[3]];
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// Apply a convolution kernel of ``kernel_width`` x ``kernel_height`` (row-major) to the RGB channels of an image; the alpha channel is kept.
+/// Each channel becomes ``sum(kernel * pixels) / divisor + bias``, where ``bias`` is in 8-bit units (e.g. 128 for emboss), clamped to the valid range.
+/// The kernel is applied without flipping (like ``image::imageops::filter3x3()``), centered at ``((kernel_width - 1) / 2, (kernel_height - 1) / 2)``,
+/// and the pixels outside the image are taken from the nearest edge.
+/// The kernel size must be ``kernel.len()``, and the result has the color type of the input.
+pub fn convolve(image: &DynamicImage, kernel: &[f32], kernel_width: u32, kernel_height: u32, divisor: f32, bias: f32) -> DynamicImage {
+    let source = image.to_rgba32f();
+    let (width, height) = source.dimensions();
+    let (center_x, center_y) = (((kernel_width - 1) / 2) as i64, ((kernel_height - 1) / 2) as i64);
+    let bias = bias / 255.0;
+
+    let mut output = image::Rgba32FImage::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum = [0.0f32; 3];
+            for ky in 0..kernel_height {
+                let sy = (y as i64 + ky as i64 - center_y).clamp(0, height as i64 - 1) as u32;
+                for kx in 0..kernel_width {
+                    let sx = (x as i64 + kx as i64 - center_x).clamp(0, width as i64 - 1) as u32;
+                    let weight = kernel[(ky * kernel_width + kx) as usize];
+                    let pixel = source.get_pixel(sx, sy);
+                    (0..3).for_each(|c| sum[c] += pixel[c] * weight);
+                }
+            }
+            let value = |v: f32| (v / divisor + bias).clamp(0.0, 1.0);
+            output.put_pixel(x, y, Rgba([value(sum[0]), value(sum[1]), value(sum[2]), source.get_pixel(x, y)[3]]));
+        }
+    }
+    to_color_type(DynamicImage::ImageRgba32F(output), image.color())
+}
+
+// Support radius and value of a resampling kernel at x (in source pixels, before scaling for downsampling).
+fn kernel_support(kernel: ResampleKernel) -> f32 {
+    match kernel {
+        ResampleKernel::Nearest => 0.5,
+        ResampleKernel::Triangle => 1.0,
+        ResampleKernel::CatmullRom => 2.0,
+        ResampleKernel::Gaussian { sigma } => 3.0 * sigma.max(0.5),
+        ResampleKernel::Lanczos { a } => a as f32,
+    }
+}
+fn kernel_weight(kernel: ResampleKernel, x: f32) -> f32 {
+    let x = x.abs();
+    let sinc = |t: f32| if t == 0.0 { 1.0 } else { (std::f32::consts::PI * t).sin() / (std::f32::consts::PI * t) };
+    match kernel {
+        ResampleKernel::Nearest => if x < 0.5 { 1.0 } else { 0.0 },
+        ResampleKernel::Triangle => (1.0 - x).max(0.0),
+        ResampleKernel::CatmullRom => {
+            if x < 1.0 { 1.5 * x.powi(3) - 2.5 * x.powi(2) + 1.0 }
+            else if x < 2.0 { -0.5 * x.powi(3) + 2.5 * x.powi(2) - 4.0 * x + 2.0 }
+            else { 0.0 }
+        },
+        ResampleKernel::Gaussian { sigma } => (-x * x / (2.0 * sigma * sigma)).exp(),
+        ResampleKernel::Lanczos { a } => if x < a as f32 { sinc(x) * sinc(x / a as f32) } else { 0.0 },
+    }
+}
+
+// Compute the first source index and the normalized weights of each destination pixel on one axis.
+// An empty axis or a scale or kernel support that is not finite returns an ``InvalidResizeRatio`` error.
+fn resample_weights(src_len: u32, dst_len: u32, kernel: ResampleKernel) -> Result<Vec<(usize, Vec<f32>)>, RusimgError> {
+    if src_len == 0 || dst_len == 0 {
+        return Err(RusimgError::InvalidResizeRatio);
+    }
+    let scale = src_len as f32 / dst_len as f32;
+    // When downsampling, the kernel is stretched to cover all the source pixels.
+    let filter_scale = scale.max(1.0);
+    let support = kernel_support(kernel) * filter_scale;
+    if !scale.is_finite() || !support.is_finite() {
+        return Err(RusimgError::InvalidResizeRatio);
+    }
+    Ok((0..dst_len).map(|i| {
+        let center = (i as f32 + 0.5) * scale;
+        let left = ((center - support).floor().max(0.0) as usize).min(src_len as usize - 1);
+        let right = ((center + support).ceil() as usize).clamp(left + 1, src_len as usize);
+        let mut weights: Vec<f32> = (left..right).map(|j| kernel_weight(kernel, (j as f32 + 0.5 - center) / filter_scale)).collect();
+        let sum: f32 = weights.iter().sum();
+        if sum.abs() > f32::EPSILON {
+            weights.iter_mut().for_each(|w| *w /= sum);
+        }
+        else {
+            // No source pixel is under the kernel (e.g. a narrow Gaussian); use the nearest one.
+            weights = vec![0.0; right - left];
+            weights[((center as usize).clamp(left, right - 1)) - left] = 1.0;
+        }
+        (left, weights)
+    }).collect())
+}
+
+// Number of rows resampled between the checks of the cancel token.
+const RESAMPLE_ROWS_PER_CHECK: usize = 16;
+
+/// Resize an image to the exact size with a resampling kernel.
+/// The image is filtered horizontally and then vertically in 32-bit float, and converted back to its color type.
+/// ``token`` is checked every few rows of both passes, and a ``Cancelled`` error is returned as soon as it is found cancelled.
+pub fn resample(image: &DynamicImage, nwidth: u32, nheight: u32, kernel: ResampleKernel, token: &CancelToken) -> Result<DynamicImage, RusimgError> {
+    token.check()?;
+    let source = image.to_rgba32f();
+    let (width, height) = source.dimensions();
+
+    let horizontal_weights = resample_weights(width, nwidth, kernel)?;
+    let mut horizontal = image::Rgba32FImage::new(nwidth, height);
+    for batch_top in (0..height).step_by(RESAMPLE_ROWS_PER_CHECK) {
+        token.check()?;
+        for y in batch_top..(batch_top + RESAMPLE_ROWS_PER_CHECK as u32).min(height) {
+            for (x, (left, weights)) in horizontal_weights.iter().enumerate() {
+                let mut sum = [0.0f32; 4];
+                for (k, w) in weights.iter().enumerate() {
+                    let pixel = source.get_pixel((left + k) as u32, y);
+                    (0..4).for_each(|c| sum[c] += pixel[c] * w);
+                }
+                horizontal.put_pixel(x as u32, y, Rgba(sum));
+            }
+        }
+    }
+
+    let vertical_weights = resample_weights(height, nheight, kernel)?;
+    let mut output = image::Rgba32FImage::new(nwidth, nheight);
+    for (batch, batch_weights) in vertical_weights.chunks(RESAMPLE_ROWS_PER_CHECK).enumerate() {
+        token.check()?;
+        for (i, (top, weights)) in batch_weights.iter().enumerate() {
+            let y = (batch * RESAMPLE_ROWS_PER_CHECK + i) as u32;
+            for x in 0..nwidth {
+                let mut sum = [0.0f32; 4];
+                for (k, w) in weights.iter().enumerate() {
+                    let pixel = horizontal.get_pixel(x, (top + k) as u32);
+                    (0..4).for_each(|c| sum[c] += pixel[c] * w);
+                }
+                output.put_pixel(x, y, Rgba(sum.map(|v| v.clamp(0.0, 1.0))));
+            }
+        }
+    }
+    Ok(to_color_type(DynamicImage::ImageRgba32F(output), image.color()))
+}
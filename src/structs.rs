@@ -1,4 +1,11 @@
 use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+use image::{ColorType, DynamicImage};
+use image::imageops::FilterType;
+
+use super::RusimgError;
 
 /// Rectangle object for rusimg.
 /// This object is used for trimming an image.
@@ -10,6 +17,21 @@ pub struct Rect {
     pub h: u32,
 }
 
+/// Anchor position for trimming an image.
+/// The trim area is placed at this position of the image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
 /// Image size object.
 #[derive(Debug, Clone, PartialEq, Copy, Default)]
 pub struct ImgSize {
@@ -23,15 +45,455 @@ impl ImgSize {
             height,
         }
     }
+
+    /// Width divided by height.
+    /// Returns ``f32::NAN`` if the height is 0.
+    pub fn aspect_ratio(&self) -> f32 {
+        if self.height == 0 {
+            return f32::NAN;
+        }
+        (self.width as f64 / self.height as f64) as f32
+    }
+
+    /// Check if the image is wider than it is tall. Square images are neither landscape nor portrait.
+    pub fn is_landscape(&self) -> bool {
+        self.width > self.height
+    }
+
+    /// Check if the image is taller than it is wide. Square images are neither landscape nor portrait.
+    pub fn is_portrait(&self) -> bool {
+        self.height > self.width
+    }
+
+    /// Number of pixels in millions.
+    pub fn megapixels(&self) -> f32 {
+        (self.width as f64 * self.height as f64 / 1_000_000.0) as f32
+    }
+}
+
+/// Mapping from the coordinates of the original image (when it was opened or created) to the current image.
+/// It follows resizing and trimming, so an area chosen on the original image can be found in the current one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CoordinateSpace {
+    /// Size of the original image.
+    pub original: ImgSize,
+    /// Position of the top-left corner of the current image in the original coordinates.
+    pub origin: (f64, f64),
+    /// Pixels of the current image per pixel of the original image (horizontal, vertical).
+    pub scale: (f64, f64),
+}
+impl CoordinateSpace {
+    /// Make a coordinate space in which the current image is the original one.
+    pub fn new(original: ImgSize) -> Self {
+        Self {
+            original,
+            origin: (0.0, 0.0),
+            scale: (1.0, 1.0),
+        }
+    }
+
+    /// Map a point in the original coordinates to the current image.
+    pub fn to_current(&self, x: f64, y: f64) -> (f64, f64) {
+        ((x - self.origin.0) * self.scale.0, (y - self.origin.1) * self.scale.1)
+    }
+
+    // Follow a resize from ``from`` to ``to``. Returns None if ``from`` is empty, because the mapping is lost.
+    pub(crate) fn resized(self, from: ImgSize, to: ImgSize) -> Option<Self> {
+        if from.width == 0 || from.height == 0 {
+            return None;
+        }
+        let scale = (
+            self.scale.0 * to.width as f64 / from.width as f64,
+            self.scale.1 * to.height as f64 / from.height as f64,
+        );
+        Some(Self { scale, ..self })
+    }
+
+    // Follow a trim whose top-left corner is at (x, y) of the current image.
+    pub(crate) fn trimmed(self, x: u32, y: u32) -> Self {
+        let origin = (self.origin.0 + x as f64 / self.scale.0, self.origin.1 + y as f64 / self.scale.1);
+        Self { origin, ..self }
+    }
 }
 
 /// Save status object.
 /// This object is used for tracking the status of saving an image.
-/// It contains the output file path, the file size before saving, and the file size after saving.
+/// It contains the output file path, the file size before saving, the file size after saving, and the modification time of the source file.
 /// If the image has compression, the file size after saving will be different from the file size before saving.
 #[derive(Debug, Clone, PartialEq)]
 pub struct SaveStatus {
     pub output_path: Option<PathBuf>,
     pub before_filesize: Option<u64>,
     pub after_filesize: Option<u64>,
+    pub source_modified: Option<SystemTime>,
+}
+
+impl SaveStatus {
+    /// A human-readable summary of the file sizes for display, e.g. "1.2 MB → 340 KB (−72%)".
+    /// Sizes use decimal units (1 KB = 1000 bytes). Unknown sizes are shown as "?", and the change ratio is omitted if either size is unknown.
+    pub fn human_summary(&self) -> String {
+        let format_size = |size: Option<u64>| size.map(format_bytes).unwrap_or_else(|| "?".to_string());
+        match (self.before_filesize, self.after_filesize) {
+            (None, after) => format_size(after),
+            (Some(before), Some(after)) if before > 0 => {
+                let percent = ((after as f64 - before as f64) / before as f64 * 100.0).round() as i64;
+                let sign = if percent < 0 { "−" } else if percent > 0 { "+" } else { "" };
+                format!("{} → {} ({}{}%)", format_bytes(before), format_bytes(after), sign, percent.abs())
+            },
+            (before, after) => format!("{} → {}", format_size(before), format_size(after)),
+        }
+    }
+}
+
+// Format a byte count with a decimal unit, e.g. "512 B", "3.4 KB" or "340 KB".
+// Values under 10 in their unit keep one decimal place.
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while value >= 999.5 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+    if value < 9.95 {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+    else {
+        format!("{:.0} {}", value, UNITS[unit])
+    }
+}
+
+/// What ``save_image()`` does when the extension of the output path differs from the image format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExtensionMismatchPolicy {
+    /// Convert the image to the format of the output path before saving.
+    #[default]
+    Convert,
+    /// Return an ``ExtensionMismatch`` error.
+    Error,
+}
+
+/// Common image metadata.
+/// This object consolidates the basic properties of an image.
+/// The fields that are not available for the image format are None.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageMetadata {
+    pub width: usize,
+    pub height: usize,
+    pub color_type: ColorType,
+    /// Bits per channel.
+    pub bit_depth: u8,
+    /// Horizontal and vertical resolution in dots per inch.
+    pub dpi: Option<(f64, f64)>,
+    /// EXIF orientation (1-8).
+    pub orientation: Option<u8>,
+    pub has_alpha: bool,
+    pub frame_count: usize,
+}
+impl ImageMetadata {
+    /// Make an ImageMetadata object from the properties of a DynamicImage object.
+    /// DPI and orientation are None, and the frame count is 1.
+    pub fn from_image(image: &DynamicImage) -> Self {
+        let color_type = image.color();
+        Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            color_type,
+            bit_depth: (color_type.bits_per_pixel() / color_type.channel_count() as u16) as u8,
+            dpi: None,
+            orientation: None,
+            has_alpha: color_type.has_alpha(),
+            frame_count: 1,
+        }
+    }
+}
+
+/// How the area of an animation frame is treated before the next frame is rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDisposal {
+    /// Leave the frame as it is.
+    None,
+    /// Clear the frame area to the background.
+    Background,
+    /// Restore the frame area to the previous contents (APNG only).
+    Previous,
+}
+
+/// How an animation frame is drawn onto the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameBlend {
+    /// Replace the canvas area with the frame.
+    Source,
+    /// Alpha-blend the frame over the canvas.
+    Over,
+}
+
+/// Timing and compositing of an animation frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameInfo {
+    pub delay_ms: u32,
+    pub disposal: FrameDisposal,
+    pub blend: FrameBlend,
+}
+
+/// Animation information read from the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationInfo {
+    /// Number of times to play the animation (0 = infinite).
+    pub loop_count: u32,
+    pub frames: Vec<FrameInfo>,
+}
+impl AnimationInfo {
+    /// Make an AnimationInfo object describing a still image (a single frame without delay).
+    pub fn still() -> Self {
+        Self {
+            loop_count: 0,
+            frames: vec![FrameInfo { delay_ms: 0, disposal: FrameDisposal::None, blend: FrameBlend::Source }],
+        }
+    }
+}
+
+/// Row filter strategy for PNG optimization.
+/// ``None``, ``Sub``, ``Up``, ``Average`` and ``Paeth`` apply the same filter to every row,
+/// and the others are heuristics that choose a filter for each row.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PngRowFilter {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+    MinSum,
+    Entropy,
+    Bigrams,
+    BigEnt,
+    Brute,
+}
+
+/// Options for optimizing PNG images with oxipng.
+/// The options are used by ``compress()`` of PNG images.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PngOptimizeOptions {
+    /// Row filters to try. oxipng keeps the one giving the smallest output.
+    /// If empty, the filters of the compression level preset are used.
+    pub filters: Vec<PngRowFilter>,
+}
+
+/// Prebuilt options for compressing PNG images, shared by many images in a batch (see ``compress_with_options()``).
+/// Without the ``png`` feature, the options hold nothing and every backend returns ``UnsupportedFeature`` for them.
+#[derive(Debug, Clone)]
+pub struct PngCompressOptions {
+    #[cfg(feature="png")]
+    pub(crate) oxipng: oxipng::Options,
+}
+
+impl PngCompressOptions {
+    /// Options of an oxipng preset level (0: fastest - 6: best compression).
+    /// Levels above 6 are treated as 6.
+    pub fn from_preset(level: u8) -> Self {
+        #[cfg(not(feature="png"))]
+        let _ = level;
+        Self {
+            #[cfg(feature="png")]
+            oxipng: oxipng::Options::from_preset(level.min(6)),
+        }
+    }
+
+    /// Set the row filters to try instead of the ones of the preset.
+    /// If empty, the filters of the preset are kept.
+    #[cfg_attr(not(feature="png"), allow(unused_mut))]
+    pub fn with_filters(mut self, filters: &[PngRowFilter]) -> Self {
+        #[cfg(feature="png")]
+        if !filters.is_empty() {
+            self.oxipng.filter = filters.iter().map(oxipng_row_filter).collect();
+        }
+        #[cfg(not(feature="png"))]
+        let _ = filters;
+        self
+    }
+
+    /// Strip the metadata chunks that do not affect rendering, as ``optimize()`` does.
+    #[cfg_attr(not(feature="png"), allow(unused_mut))]
+    pub fn strip_metadata(mut self) -> Self {
+        #[cfg(feature="png")]
+        {
+            self.oxipng.strip = oxipng::StripChunks::Safe;
+        }
+        self
+    }
+}
+
+// Convert a PngRowFilter to the oxipng row filter.
+#[cfg(feature="png")]
+fn oxipng_row_filter(filter: &PngRowFilter) -> oxipng::RowFilter {
+    match filter {
+        PngRowFilter::None => oxipng::RowFilter::None,
+        PngRowFilter::Sub => oxipng::RowFilter::Sub,
+        PngRowFilter::Up => oxipng::RowFilter::Up,
+        PngRowFilter::Average => oxipng::RowFilter::Average,
+        PngRowFilter::Paeth => oxipng::RowFilter::Paeth,
+        PngRowFilter::MinSum => oxipng::RowFilter::MinSum,
+        PngRowFilter::Entropy => oxipng::RowFilter::Entropy,
+        PngRowFilter::Bigrams => oxipng::RowFilter::Bigrams,
+        PngRowFilter::BigEnt => oxipng::RowFilter::BigEnt,
+        PngRowFilter::Brute => oxipng::RowFilter::Brute,
+    }
+}
+
+#[cfg(feature="png")]
+impl From<oxipng::Options> for PngCompressOptions {
+    /// Wrap oxipng options built by the caller, for settings not covered by ``PngCompressOptions``.
+    fn from(options: oxipng::Options) -> Self {
+        Self { oxipng: options }
+    }
+}
+
+/// Interpolation used for rotating an image by an arbitrary angle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RotateInterpolation {
+    /// Use the nearest source pixel. Fast, but edges become jagged.
+    Nearest,
+    /// Interpolate between the 4 nearest source pixels.
+    #[default]
+    Bilinear,
+    /// Interpolate between the 16 nearest source pixels. Slowest, but sharpest.
+    Bicubic,
+}
+
+/// Tone-mapping operator used by ``tonemap()`` to convert HDR (floating-point) pixels to displayable 8-bit values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMapMethod {
+    /// ``c / (1 + c)`` per channel. Compresses the highlights smoothly, keeping the detail of bright areas; only extreme values come close to white.
+    #[default]
+    Reinhard,
+    /// The ACES filmic curve (Narkowicz's fit). More contrast than Reinhard; very bright values still reach white.
+    Aces,
+}
+
+/// Resampling filter used for resizing images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    #[default]
+    Lanczos3,
+}
+impl ResizeFilter {
+    /// Get the corresponding filter type of the image crate.
+    pub fn filter_type(&self) -> FilterType {
+        match self {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+
+    /// Get the corresponding resampling kernel of ``operations::resample()``, used by the cancellable resize.
+    pub fn resample_kernel(&self) -> ResampleKernel {
+        match self {
+            ResizeFilter::Nearest => ResampleKernel::Nearest,
+            ResizeFilter::Triangle => ResampleKernel::Triangle,
+            ResizeFilter::CatmullRom => ResampleKernel::CatmullRom,
+            ResizeFilter::Gaussian => ResampleKernel::Gaussian { sigma: 0.5 },
+            ResizeFilter::Lanczos3 => ResampleKernel::Lanczos { a: 3 },
+        }
+    }
+}
+
+/// Resampling kernel used by ``resize_with_kernel()``.
+/// In addition to the filters of ``ResizeFilter``, the kernel parameters can be adjusted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResampleKernel {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    /// Gaussian kernel with the standard deviation in pixels (must be positive). ``ResizeFilter::Gaussian`` uses 0.5.
+    Gaussian { sigma: f32 },
+    /// Lanczos kernel with the window size ``a`` (1-8). ``ResizeFilter::Lanczos3`` uses 3.
+    Lanczos { a: u32 },
+}
+impl ResampleKernel {
+    /// Check if the kernel parameters are valid.
+    pub fn is_valid(&self) -> bool {
+        match self {
+            ResampleKernel::Gaussian { sigma } => sigma.is_finite() && *sigma > 0.0,
+            ResampleKernel::Lanczos { a } => (1..=8).contains(a),
+            _ => true,
+        }
+    }
+}
+
+/// Default settings of image operations.
+/// Set it with ``RusImg::open_with_config()`` or ``set_config()``, or globally with ``set_default_config()``.
+/// The defaults match the behavior without a config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RusImgConfig {
+    /// Filter used by the resize functions, including the copies made by ``generate_srcset()``.
+    /// ``ResizeFilter::Nearest`` is the fastest and ``ResizeFilter::Lanczos3`` gives the best quality.
+    pub resize_filter: ResizeFilter,
+    /// JPEG quality (0-100) used when no quality is given to ``compress()``.
+    pub jpeg_quality: f32,
+    /// oxipng optimization level (1-6) used when no quality is given to ``compress()``.
+    pub png_level: u8,
+    /// WebP quality (0-100) used when no quality is given to ``compress()``.
+    pub webp_quality: f32,
+}
+impl Default for RusImgConfig {
+    fn default() -> Self {
+        Self {
+            resize_filter: ResizeFilter::default(),
+            jpeg_quality: 100.0,
+            png_level: 5,
+            webp_quality: 75.0,
+        }
+    }
+}
+impl RusImgConfig {
+    /// Check that the qualities are within 0-100 and the PNG level within 1-6.
+    /// Returns ``InvalidCompressionLevel`` otherwise, as ``compress()`` does for an out-of-range quality.
+    pub fn validate(&self) -> Result<(), RusimgError> {
+        if !(0.0..=100.0).contains(&self.jpeg_quality) || !(0.0..=100.0).contains(&self.webp_quality) || !(1..=6).contains(&self.png_level) {
+            return Err(RusimgError::InvalidCompressionLevel);
+        }
+        Ok(())
+    }
+}
+
+/// Token for cancelling long operations (e.g. from a UI thread), passed to the ``*_cancellable()`` functions of RusImg.
+/// Clones share the same flag, so a clone can be cancelled from another thread.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+impl CancelToken {
+    /// Create a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the operations using this token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Check whether the token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Return a ``Cancelled`` error if the token has been cancelled.
+    /// Long operations call this between their steps.
+    pub fn check(&self) -> Result<(), RusimgError> {
+        if self.is_cancelled() {
+            return Err(RusimgError::Cancelled);
+        }
+        Ok(())
+    }
 }